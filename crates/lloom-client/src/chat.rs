@@ -0,0 +1,336 @@
+//! Interactive multi-turn chat sessions.
+//!
+//! A [`ChatSession`] keeps the conversation history, the generation settings that
+//! slash commands can change, and the running cost of the session. The REPL in the
+//! `lloom-client chat` binary drives it; everything here is I/O-free apart from
+//! saving and loading transcripts.
+
+use crate::{ledger::parse_wei, validation::validate_temperature};
+use alloy::primitives::U256;
+use anyhow::{Result, anyhow};
+use lloom_core::protocol::ChatMessage;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Transcript format version written by [`ChatSession::save`]
+pub const TRANSCRIPT_VERSION: u32 = 1;
+
+/// Help text listing the supported slash commands
+pub const HELP_TEXT: &str = "\
+Commands:
+  /model <name>         Switch to another model
+  /temperature <value>  Set the sampling temperature (0.0 to 2.0)
+  /system <prompt>      Set the system prompt (empty to clear)
+  /cost                 Show tokens and cost so far
+  /save <path>          Save the transcript as JSON
+  /load <path>          Load a transcript saved with /save
+  /clear                Forget the conversation history
+  /help                 Show this help
+  /quit                 Leave the chat";
+
+/// Author of a chat turn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    User,
+    Assistant,
+}
+
+/// A single message in the conversation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatTurn {
+    pub role: ChatRole,
+    pub content: String,
+}
+
+/// A line of REPL input
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatCommand {
+    /// A message to send to the model
+    Message(String),
+    Model(String),
+    Temperature(f32),
+    System(Option<String>),
+    Cost,
+    Save(String),
+    Load(String),
+    Clear,
+    Help,
+    Quit,
+}
+
+/// Parse a line of REPL input into a command.
+///
+/// Lines that do not start with `/` are messages. Blank lines yield `None`.
+pub fn parse_command(line: &str) -> Option<std::result::Result<ChatCommand, String>> {
+    let line = line.trim();
+    if line.is_empty() {
+        return None;
+    }
+    let Some(command) = line.strip_prefix('/') else {
+        return Some(Ok(ChatCommand::Message(line.to_string())));
+    };
+
+    let (name, arg) = match command.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (command, ""),
+    };
+    let require_arg = |usage: &str| {
+        if arg.is_empty() {
+            Err(format!("Usage: {}", usage))
+        } else {
+            Ok(arg.to_string())
+        }
+    };
+
+    let parsed = match name {
+        "model" => require_arg("/model <name>").map(ChatCommand::Model),
+        "temperature" | "temp" => require_arg("/temperature <value>").and_then(|value| {
+            let temperature: f32 = value.parse().map_err(|_| format!("Invalid temperature: {}", value))?;
+            if validate_temperature(temperature) {
+                Ok(ChatCommand::Temperature(temperature))
+            } else {
+                Err(format!("Temperature must be between 0.0 and 2.0, got {}", temperature))
+            }
+        }),
+        "system" => Ok(ChatCommand::System((!arg.is_empty()).then(|| arg.to_string()))),
+        "cost" => Ok(ChatCommand::Cost),
+        "save" => require_arg("/save <path>").map(ChatCommand::Save),
+        "load" => require_arg("/load <path>").map(ChatCommand::Load),
+        "clear" => Ok(ChatCommand::Clear),
+        "help" | "?" => Ok(ChatCommand::Help),
+        "quit" | "exit" => Ok(ChatCommand::Quit),
+        other => Err(format!("Unknown command /{} (try /help)", other)),
+    };
+    Some(parsed)
+}
+
+/// Saved form of a chat session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatTranscript {
+    pub version: u32,
+    pub model: String,
+    pub temperature: Option<f32>,
+    pub system_prompt: Option<String>,
+    pub turns: Vec<ChatTurn>,
+}
+
+/// State of an interactive chat
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatSession {
+    pub model: String,
+    pub temperature: Option<f32>,
+    pub system_prompt: Option<String>,
+    pub turns: Vec<ChatTurn>,
+    pub inbound_tokens: u64,
+    pub outbound_tokens: u64,
+    pub total_cost: U256,
+}
+
+impl ChatSession {
+    /// Start an empty session
+    pub fn new(model: String, temperature: Option<f32>, system_prompt: Option<String>) -> Self {
+        Self {
+            model,
+            temperature,
+            system_prompt,
+            turns: Vec::new(),
+            inbound_tokens: 0,
+            outbound_tokens: 0,
+            total_cost: U256::ZERO,
+        }
+    }
+
+    /// Render the prompt for the next request: the whole history followed by `message`
+    pub fn render_prompt(&self, message: &str) -> String {
+        if self.turns.is_empty() {
+            return message.to_string();
+        }
+        let mut prompt = String::new();
+        for turn in &self.turns {
+            let speaker = match turn.role {
+                ChatRole::User => "User",
+                ChatRole::Assistant => "Assistant",
+            };
+            prompt.push_str(&format!("{}: {}\n\n", speaker, turn.content));
+        }
+        prompt.push_str(&format!("User: {}\n\nAssistant:", message));
+        prompt
+    }
+
+    /// Conversation for the next request: system prompt, history, then `message`
+    pub fn messages_for(&self, message: &str) -> Vec<ChatMessage> {
+        let mut messages: Vec<ChatMessage> = self.system_prompt.iter().map(ChatMessage::system).collect();
+        messages.extend(self.turns.iter().map(|turn| match turn.role {
+            ChatRole::User => ChatMessage::user(&turn.content),
+            ChatRole::Assistant => ChatMessage::assistant(&turn.content),
+        }));
+        messages.push(ChatMessage::user(message));
+        messages
+    }
+
+    /// Append a completed exchange and account for its cost
+    pub fn record_exchange(
+        &mut self,
+        message: &str,
+        reply: &str,
+        inbound_tokens: u64,
+        outbound_tokens: u64,
+        cost: &str,
+    ) -> Result<()> {
+        self.total_cost += parse_wei(cost)?;
+        self.inbound_tokens += inbound_tokens;
+        self.outbound_tokens += outbound_tokens;
+        self.turns.push(ChatTurn { role: ChatRole::User, content: message.to_string() });
+        self.turns.push(ChatTurn { role: ChatRole::Assistant, content: reply.to_string() });
+        Ok(())
+    }
+
+    /// Number of completed exchanges
+    pub fn exchanges(&self) -> usize {
+        self.turns.iter().filter(|turn| turn.role == ChatRole::Assistant).count()
+    }
+
+    /// One-line summary of tokens and cost so far
+    pub fn cost_summary(&self) -> String {
+        format!(
+            "{} exchanges, {} inbound + {} outbound tokens, {} wei",
+            self.exchanges(),
+            self.inbound_tokens,
+            self.outbound_tokens,
+            self.total_cost
+        )
+    }
+
+    /// Snapshot of the session for saving
+    pub fn transcript(&self) -> ChatTranscript {
+        ChatTranscript {
+            version: TRANSCRIPT_VERSION,
+            model: self.model.clone(),
+            temperature: self.temperature,
+            system_prompt: self.system_prompt.clone(),
+            turns: self.turns.clone(),
+        }
+    }
+
+    /// Save the transcript as pretty-printed JSON
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(&self.transcript())?;
+        std::fs::write(path, json)
+            .map_err(|e| anyhow!("Failed to write transcript {}: {}", path.display(), e))
+    }
+
+    /// Replace the conversation and settings with a saved transcript.
+    ///
+    /// Cost counters are kept: they track what this session has spent.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read transcript {}: {}", path.display(), e))?;
+        let transcript: ChatTranscript = serde_json::from_str(&content)
+            .map_err(|e| anyhow!("Failed to parse transcript {}: {}", path.display(), e))?;
+        if transcript.version > TRANSCRIPT_VERSION {
+            return Err(anyhow!("Unsupported transcript version {}", transcript.version));
+        }
+        self.model = transcript.model;
+        self.temperature = transcript.temperature;
+        self.system_prompt = transcript.system_prompt;
+        self.turns = transcript.turns;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_messages_and_commands() {
+        assert_eq!(parse_command("   "), None);
+        assert_eq!(parse_command("hello there"), Some(Ok(ChatCommand::Message("hello there".to_string()))));
+        assert_eq!(parse_command("/model llama3"), Some(Ok(ChatCommand::Model("llama3".to_string()))));
+        assert_eq!(parse_command("/temperature 0.5"), Some(Ok(ChatCommand::Temperature(0.5))));
+        assert_eq!(parse_command("/system be brief"), Some(Ok(ChatCommand::System(Some("be brief".to_string())))));
+        assert_eq!(parse_command("/system"), Some(Ok(ChatCommand::System(None))));
+        assert_eq!(parse_command("/cost"), Some(Ok(ChatCommand::Cost)));
+        assert_eq!(parse_command("/save chat.json"), Some(Ok(ChatCommand::Save("chat.json".to_string()))));
+        assert_eq!(parse_command("/load chat.json"), Some(Ok(ChatCommand::Load("chat.json".to_string()))));
+        assert_eq!(parse_command("/clear"), Some(Ok(ChatCommand::Clear)));
+        assert_eq!(parse_command("/help"), Some(Ok(ChatCommand::Help)));
+        assert_eq!(parse_command("/exit"), Some(Ok(ChatCommand::Quit)));
+    }
+
+    #[test]
+    fn test_parse_command_errors() {
+        assert!(matches!(parse_command("/model"), Some(Err(_))));
+        assert!(matches!(parse_command("/temperature hot"), Some(Err(_))));
+        assert!(matches!(parse_command("/temperature 3.0"), Some(Err(_))));
+        assert!(matches!(parse_command("/save"), Some(Err(_))));
+        assert!(matches!(parse_command("/frobnicate"), Some(Err(_))));
+    }
+
+    #[test]
+    fn test_render_prompt_includes_history() {
+        let mut session = ChatSession::new("gpt-4".to_string(), None, None);
+        assert_eq!(session.render_prompt("Hi"), "Hi");
+
+        session.record_exchange("Hi", "Hello!", 3, 2, "2500").unwrap();
+        let prompt = session.render_prompt("How are you?");
+        assert_eq!(prompt, "User: Hi\n\nAssistant: Hello!\n\nUser: How are you?\n\nAssistant:");
+    }
+
+    #[test]
+    fn test_messages_for_keeps_roles() {
+        let mut session = ChatSession::new("gpt-4".to_string(), None, Some("Be terse".to_string()));
+        assert_eq!(
+            session.messages_for("Hi"),
+            vec![ChatMessage::system("Be terse"), ChatMessage::user("Hi")]
+        );
+
+        session.record_exchange("Hi", "Hello!", 3, 2, "2500").unwrap();
+        assert_eq!(
+            session.messages_for("How are you?"),
+            vec![
+                ChatMessage::system("Be terse"),
+                ChatMessage::user("Hi"),
+                ChatMessage::assistant("Hello!"),
+                ChatMessage::user("How are you?"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cost_accumulates() {
+        let mut session = ChatSession::new("gpt-4".to_string(), Some(0.7), None);
+        session.record_exchange("a", "b", 10, 5, "1000").unwrap();
+        session.record_exchange("c", "d", 20, 10, "2000").unwrap();
+
+        assert_eq!(session.exchanges(), 2);
+        assert_eq!(session.total_cost, U256::from(3000));
+        assert_eq!(session.cost_summary(), "2 exchanges, 30 inbound + 15 outbound tokens, 3000 wei");
+        assert!(session.record_exchange("e", "f", 1, 1, "lots").is_err());
+        assert_eq!(session.exchanges(), 2);
+    }
+
+    #[test]
+    fn test_save_and_load_transcript() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("chat.json");
+
+        let mut session = ChatSession::new("gpt-4".to_string(), Some(0.3), Some("Be terse".to_string()));
+        session.record_exchange("Hi", "Hello!", 3, 2, "2500").unwrap();
+        session.save(&path).unwrap();
+
+        let mut loaded = ChatSession::new("other".to_string(), None, None);
+        loaded.load(&path).unwrap();
+        assert_eq!(loaded.model, "gpt-4");
+        assert_eq!(loaded.temperature, Some(0.3));
+        assert_eq!(loaded.system_prompt.as_deref(), Some("Be terse"));
+        assert_eq!(loaded.turns, session.turns);
+        assert_eq!(loaded.total_cost, U256::ZERO);
+
+        assert!(loaded.load(dir.path().join("missing.json")).is_err());
+    }
+}
@@ -0,0 +1,645 @@
+//! Client spending ledger and budget caps.
+//!
+//! Every signed request and the response it receives are appended to a JSON Lines
+//! file under the client data directory. The ledger is used to enforce daily budget
+//! limits (overall, per model and per executor) before a request is signed, and to
+//! flag responses whose charged cost does not match the prices committed in the
+//! request. Requests the client gave up on are flagged as cancelled. Until the
+//! executor reports what it charged, a request counts at its worst case unless it
+//! provably never reached the executor.
+
+use alloy::primitives::U256;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tracing::{debug, warn};
+
+/// File name of the ledger inside the data directory
+pub const LEDGER_FILE_NAME: &str = "ledger.jsonl";
+
+/// Output tokens assumed for budgeting when a request does not set `max_tokens`
+pub const DEFAULT_BUDGET_MAX_TOKENS: u32 = 4096;
+
+/// Parse a decimal wei amount as used for prices and costs in the protocol
+pub fn parse_wei(value: &str) -> Result<U256> {
+    U256::from_str_radix(value.trim(), 10)
+        .map_err(|e| anyhow!("Invalid wei amount '{}': {}", value, e))
+}
+
+/// Worst-case cost of a request: every allowed output token at the outbound price
+pub fn worst_case_cost(max_tokens: Option<u32>, outbound_price: &str) -> Result<U256> {
+    let max_tokens = max_tokens.unwrap_or(DEFAULT_BUDGET_MAX_TOKENS);
+    Ok(U256::from(max_tokens) * parse_wei(outbound_price)?)
+}
+
+/// Cost implied by the committed prices for the reported token counts
+pub fn expected_cost(
+    inbound_tokens: u64,
+    outbound_tokens: u64,
+    inbound_price: &str,
+    outbound_price: &str,
+) -> Result<U256> {
+    Ok(U256::from(inbound_tokens) * parse_wei(inbound_price)?
+        + U256::from(outbound_tokens) * parse_wei(outbound_price)?)
+}
+
+/// A signed request as recorded in the ledger
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestEntry {
+    pub id: String,
+    /// `request_id` sent to the executor, shared by every failover attempt
+    #[serde(default)]
+    pub request_id: Option<String>,
+    pub timestamp: u64,
+    pub executor: String,
+    pub model: String,
+    pub nonce: u64,
+    pub inbound_price: String,
+    pub outbound_price: String,
+    pub max_tokens: Option<u32>,
+    /// Worst-case cost reserved against the budget until the response arrives
+    pub max_cost: String,
+}
+
+/// The outcome of a recorded request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseEntry {
+    pub id: String,
+    pub timestamp: u64,
+    pub inbound_tokens: u64,
+    pub outbound_tokens: u64,
+    /// Cost reported by the executor
+    pub charged_cost: String,
+    /// Cost implied by the committed prices
+    pub expected_cost: String,
+    pub cost_mismatch: bool,
+    pub error: Option<String>,
+}
+
+/// A single line of the ledger file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LedgerEvent {
+    Request(RequestEntry),
+    Response(ResponseEntry),
+    /// The request never produced a response (transport failure, timeout).
+    /// `maybe_billed` is unset only if the request never reached the executor.
+    Failed {
+        id: String,
+        timestamp: u64,
+        error: String,
+        #[serde(default)]
+        maybe_billed: bool,
+    },
+    /// The client cancelled the request (deadline passed, user interrupt). A
+    /// `Response` recorded afterwards holds what the executor billed for it.
+    Cancelled { id: String, timestamp: u64, reason: String },
+}
+
+/// A request joined with its outcome
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedgerRecord {
+    pub request: RequestEntry,
+    pub response: Option<ResponseEntry>,
+    pub failed: bool,
+    /// Whether a failed request may still have been billed
+    pub maybe_billed: bool,
+    pub cancelled: bool,
+}
+
+impl LedgerRecord {
+    /// Amount counted against the budget: the charged cost once settled,
+    /// nothing if it failed without reaching the executor, and otherwise the
+    /// reserved worst case, since the executor may still bill it.
+    pub fn committed_cost(&self) -> U256 {
+        if let Some(response) = &self.response {
+            parse_wei(&response.charged_cost).unwrap_or(U256::ZERO)
+        } else if self.failed && !self.maybe_billed {
+            U256::ZERO
+        } else {
+            parse_wei(&self.request.max_cost).unwrap_or(U256::ZERO)
+        }
+    }
+
+    /// UTC day the request was signed
+    pub fn day(&self) -> Option<NaiveDate> {
+        DateTime::<Utc>::from_timestamp(self.request.timestamp as i64, 0).map(|dt| dt.date_naive())
+    }
+}
+
+/// Daily budget limits, in wei as decimal strings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BudgetLimits {
+    /// Maximum total spend per UTC day
+    #[serde(default)]
+    pub daily_limit: Option<String>,
+
+    /// Maximum spend per UTC day for each model
+    #[serde(default)]
+    pub per_model: HashMap<String, String>,
+
+    /// Maximum spend per UTC day for each executor (peer ID)
+    #[serde(default)]
+    pub per_executor: HashMap<String, String>,
+}
+
+/// Which budget a request would exceed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BudgetScope {
+    Daily,
+    Model(String),
+    Executor(String),
+}
+
+impl fmt::Display for BudgetScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetScope::Daily => write!(f, "daily budget"),
+            BudgetScope::Model(model) => write!(f, "daily budget for model {}", model),
+            BudgetScope::Executor(executor) => write!(f, "daily budget for executor {}", executor),
+        }
+    }
+}
+
+/// A request that would exceed a budget limit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BudgetViolation {
+    pub scope: BudgetScope,
+    pub limit: U256,
+    pub spent: U256,
+    pub requested: U256,
+}
+
+impl fmt::Display for BudgetViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "request could cost up to {} wei but only {} wei of the {} ({} wei) remains",
+            self.requested,
+            self.limit.saturating_sub(self.spent),
+            self.scope,
+            self.limit
+        )
+    }
+}
+
+impl BudgetLimits {
+    /// Check that every configured amount is a valid wei value
+    pub fn validate(&self) -> Result<()> {
+        if let Some(limit) = &self.daily_limit {
+            parse_wei(limit)?;
+        }
+        for limit in self.per_model.values().chain(self.per_executor.values()) {
+            parse_wei(limit)?;
+        }
+        Ok(())
+    }
+
+    /// Whether any limit is configured
+    pub fn is_empty(&self) -> bool {
+        self.daily_limit.is_none() && self.per_model.is_empty() && self.per_executor.is_empty()
+    }
+}
+
+/// Spend totals for one UTC day
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpendingSummary {
+    pub total: U256,
+    pub requests: usize,
+    pub by_model: HashMap<String, U256>,
+    pub by_executor: HashMap<String, U256>,
+    pub cost_mismatches: usize,
+    pub cancelled: usize,
+}
+
+/// Append-only spending ledger stored as JSON Lines
+#[derive(Debug)]
+pub struct SpendingLedger {
+    path: PathBuf,
+    guard: Mutex<()>,
+}
+
+impl SpendingLedger {
+    /// Open (or create) the ledger in the given data directory
+    pub fn new(data_dir: impl AsRef<Path>) -> Result<Self> {
+        let data_dir = data_dir.as_ref();
+        fs::create_dir_all(data_dir)
+            .map_err(|e| anyhow!("Failed to create data directory {}: {}", data_dir.display(), e))?;
+        Ok(Self {
+            path: data_dir.join(LEDGER_FILE_NAME),
+            guard: Mutex::new(()),
+        })
+    }
+
+    /// Path of the backing file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record a signed request and return its ledger id
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_request(
+        &self,
+        request_id: &str,
+        executor: &str,
+        model: &str,
+        nonce: u64,
+        inbound_price: &str,
+        outbound_price: &str,
+        max_tokens: Option<u32>,
+    ) -> Result<String> {
+        let entry = RequestEntry {
+            id: uuid::Uuid::new_v4().to_string(),
+            request_id: Some(request_id.to_string()),
+            timestamp: now_secs(),
+            executor: executor.to_string(),
+            model: model.to_string(),
+            nonce,
+            inbound_price: inbound_price.to_string(),
+            outbound_price: outbound_price.to_string(),
+            max_tokens,
+            max_cost: worst_case_cost(max_tokens, outbound_price)?.to_string(),
+        };
+        let id = entry.id.clone();
+        self.append(&LedgerEvent::Request(entry))?;
+        Ok(id)
+    }
+
+    /// Record the response to a request, flagging cost mismatches.
+    ///
+    /// Returns the stored entry so callers can report a mismatch.
+    pub fn record_response(
+        &self,
+        id: &str,
+        inbound_tokens: u64,
+        outbound_tokens: u64,
+        charged_cost: &str,
+        error: Option<String>,
+    ) -> Result<ResponseEntry> {
+        let request = self.records()?
+            .into_iter()
+            .find(|record| record.request.id == id)
+            .map(|record| record.request)
+            .ok_or_else(|| anyhow!("Unknown ledger entry {}", id))?;
+
+        let expected = expected_cost(inbound_tokens, outbound_tokens, &request.inbound_price, &request.outbound_price)?;
+        let cost_mismatch = match parse_wei(charged_cost) {
+            Ok(charged) => charged != expected,
+            Err(_) => true,
+        };
+        if cost_mismatch {
+            warn!(
+                "Executor {} charged {} wei but committed prices imply {} wei",
+                request.executor, charged_cost, expected
+            );
+        }
+
+        let entry = ResponseEntry {
+            id: id.to_string(),
+            timestamp: now_secs(),
+            inbound_tokens,
+            outbound_tokens,
+            charged_cost: charged_cost.to_string(),
+            expected_cost: expected.to_string(),
+            cost_mismatch,
+            error,
+        };
+        self.append(&LedgerEvent::Response(entry.clone()))?;
+        Ok(entry)
+    }
+
+    /// Record that a request never received a response.
+    ///
+    /// `maybe_billed` is whether the request may have reached the executor,
+    /// which can then still bill it.
+    pub fn record_failure(&self, id: &str, error: &str, maybe_billed: bool) -> Result<()> {
+        self.append(&LedgerEvent::Failed {
+            id: id.to_string(),
+            timestamp: now_secs(),
+            error: error.to_string(),
+            maybe_billed,
+        })
+    }
+
+    /// Record that the client cancelled a request
+    pub fn record_cancellation(&self, id: &str, reason: &str) -> Result<()> {
+        self.append(&LedgerEvent::Cancelled {
+            id: id.to_string(),
+            timestamp: now_secs(),
+            reason: reason.to_string(),
+        })
+    }
+
+    /// All requests with their outcomes, in the order they were signed
+    pub fn records(&self) -> Result<Vec<LedgerRecord>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(anyhow!("Failed to read ledger {}: {}", self.path.display(), e)),
+        };
+
+        let mut records: Vec<LedgerRecord> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        for (line_no, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event: LedgerEvent = match serde_json::from_str(line) {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("Skipping malformed ledger line {}: {}", line_no + 1, e);
+                    continue;
+                }
+            };
+            match event {
+                LedgerEvent::Request(request) => {
+                    index.insert(request.id.clone(), records.len());
+                    records.push(LedgerRecord { request, response: None, failed: false, maybe_billed: false, cancelled: false });
+                }
+                LedgerEvent::Response(response) => {
+                    if let Some(&i) = index.get(&response.id) {
+                        records[i].response = Some(response);
+                    }
+                }
+                LedgerEvent::Failed { id, maybe_billed, .. } => {
+                    if let Some(&i) = index.get(&id) {
+                        records[i].failed = true;
+                        records[i].maybe_billed |= maybe_billed;
+                    }
+                }
+                LedgerEvent::Cancelled { id, .. } => {
+                    if let Some(&i) = index.get(&id) {
+                        records[i].cancelled = true;
+                    }
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Spend totals for the given UTC day
+    pub fn summary_for_day(&self, day: NaiveDate) -> Result<SpendingSummary> {
+        let mut summary = SpendingSummary::default();
+        for record in self.records()?.into_iter().filter(|record| record.day() == Some(day)) {
+            let cost = record.committed_cost();
+            summary.total += cost;
+            summary.requests += 1;
+            *summary.by_model.entry(record.request.model.clone()).or_default() += cost;
+            *summary.by_executor.entry(record.request.executor.clone()).or_default() += cost;
+            if record.response.as_ref().is_some_and(|response| response.cost_mismatch) {
+                summary.cost_mismatches += 1;
+            }
+            if record.cancelled {
+                summary.cancelled += 1;
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Spend totals for the current UTC day
+    pub fn today(&self) -> Result<SpendingSummary> {
+        self.summary_for_day(Utc::now().date_naive())
+    }
+
+    /// Check whether a request costing up to `max_cost` fits in today's budgets
+    pub fn check_budget(
+        &self,
+        limits: &BudgetLimits,
+        model: &str,
+        executor: &str,
+        max_cost: U256,
+    ) -> Result<Option<BudgetViolation>> {
+        if limits.is_empty() {
+            return Ok(None);
+        }
+        let today = self.today()?;
+
+        let mut checks = Vec::new();
+        if let Some(limit) = &limits.daily_limit {
+            checks.push((BudgetScope::Daily, limit, today.total));
+        }
+        if let Some(limit) = limits.per_model.get(model) {
+            let spent = today.by_model.get(model).copied().unwrap_or_default();
+            checks.push((BudgetScope::Model(model.to_string()), limit, spent));
+        }
+        if let Some(limit) = limits.per_executor.get(executor) {
+            let spent = today.by_executor.get(executor).copied().unwrap_or_default();
+            checks.push((BudgetScope::Executor(executor.to_string()), limit, spent));
+        }
+
+        for (scope, limit, spent) in checks {
+            let limit = parse_wei(limit)?;
+            if spent.saturating_add(max_cost) > limit {
+                return Ok(Some(BudgetViolation { scope, limit, spent, requested: max_cost }));
+            }
+        }
+        Ok(None)
+    }
+
+    fn append(&self, event: &LedgerEvent) -> Result<()> {
+        let _guard = self.guard.lock().map_err(|_| anyhow!("Ledger mutex poisoned"))?;
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| anyhow!("Failed to open ledger {}: {}", self.path.display(), e))?;
+        file.write_all(&line)
+            .map_err(|e| anyhow!("Failed to write ledger {}: {}", self.path.display(), e))?;
+        debug!("Ledger event recorded in {}", self.path.display());
+        Ok(())
+    }
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const INBOUND: &str = "500000000000000";
+    const OUTBOUND: &str = "1000000000000000";
+
+    fn wei(value: &str) -> U256 {
+        parse_wei(value).unwrap()
+    }
+
+    #[test]
+    fn test_cost_helpers() {
+        assert_eq!(worst_case_cost(Some(10), OUTBOUND).unwrap(), wei("10000000000000000"));
+        assert_eq!(
+            worst_case_cost(None, "1").unwrap(),
+            U256::from(DEFAULT_BUDGET_MAX_TOKENS)
+        );
+        assert_eq!(expected_cost(10, 5, INBOUND, OUTBOUND).unwrap(), wei("10000000000000000"));
+        assert!(parse_wei("not-a-number").is_err());
+    }
+
+    #[test]
+    fn test_record_request_and_response() {
+        let dir = TempDir::new().unwrap();
+        let ledger = SpendingLedger::new(dir.path()).unwrap();
+
+        let id = ledger.record_request("req-1", "executor-a", "gpt-4", 1, INBOUND, OUTBOUND, Some(100)).unwrap();
+
+        // While in flight the worst case is reserved
+        let today = ledger.today().unwrap();
+        assert_eq!(today.total, wei("100000000000000000"));
+        assert_eq!(today.requests, 1);
+
+        let response = ledger.record_response(&id, 10, 5, "10000000000000000", None).unwrap();
+        assert!(!response.cost_mismatch);
+
+        let today = ledger.today().unwrap();
+        assert_eq!(today.total, wei("10000000000000000"));
+        assert_eq!(today.by_model["gpt-4"], wei("10000000000000000"));
+        assert_eq!(today.by_executor["executor-a"], wei("10000000000000000"));
+        assert_eq!(today.cost_mismatches, 0);
+
+        let records = ledger.records().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].request.nonce, 1);
+        assert_eq!(records[0].request.request_id.as_deref(), Some("req-1"));
+        assert_eq!(records[0].response.as_ref().unwrap().outbound_tokens, 5);
+    }
+
+    #[test]
+    fn test_cost_mismatch_detected() {
+        let dir = TempDir::new().unwrap();
+        let ledger = SpendingLedger::new(dir.path()).unwrap();
+
+        let id = ledger.record_request("req-1", "executor-a", "gpt-4", 1, INBOUND, OUTBOUND, Some(100)).unwrap();
+        let response = ledger.record_response(&id, 10, 5, "15000000000000000", None).unwrap();
+
+        assert!(response.cost_mismatch);
+        assert_eq!(response.expected_cost, "10000000000000000");
+        assert_eq!(ledger.today().unwrap().cost_mismatches, 1);
+    }
+
+    #[test]
+    fn test_undelivered_request_costs_nothing() {
+        let dir = TempDir::new().unwrap();
+        let ledger = SpendingLedger::new(dir.path()).unwrap();
+
+        let id = ledger.record_request("req-1", "executor-a", "gpt-4", 1, INBOUND, OUTBOUND, Some(100)).unwrap();
+        ledger.record_failure(&id, "Dial failure", false).unwrap();
+
+        let today = ledger.today().unwrap();
+        assert_eq!(today.total, U256::ZERO);
+        assert!(ledger.records().unwrap()[0].failed);
+        assert!(ledger.record_response("missing", 1, 1, "0", None).is_err());
+    }
+
+    #[test]
+    fn test_failed_request_may_still_be_billed() {
+        let dir = TempDir::new().unwrap();
+        let ledger = SpendingLedger::new(dir.path()).unwrap();
+        let max_cost = worst_case_cost(Some(100), OUTBOUND).unwrap();
+
+        // The executor may have received the request before the failure
+        let id = ledger.record_request("req-1", "executor-a", "gpt-4", 1, INBOUND, OUTBOUND, Some(100)).unwrap();
+        ledger.record_failure(&id, "Timeout", true).unwrap();
+        assert_eq!(ledger.today().unwrap().total, max_cost);
+
+        let limits = BudgetLimits { daily_limit: Some(max_cost.to_string()), ..Default::default() };
+        let violation = ledger.check_budget(&limits, "gpt-4", "executor-b", U256::from(1)).unwrap();
+        assert_eq!(violation.map(|violation| violation.scope), Some(BudgetScope::Daily));
+
+        // Ledgers written before the flag existed keep counting such failures as free
+        let line = r#"{"event":"failed","id":"old","timestamp":0,"error":"Timeout"}"#;
+        let event: LedgerEvent = serde_json::from_str(line).unwrap();
+        assert!(matches!(event, LedgerEvent::Failed { maybe_billed: false, .. }));
+    }
+
+    #[test]
+    fn test_cancelled_request() {
+        let dir = TempDir::new().unwrap();
+        let ledger = SpendingLedger::new(dir.path()).unwrap();
+
+        // Cancelled without hearing back from the executor, which may still bill it
+        let unanswered = ledger.record_request("req-1", "executor-a", "gpt-4", 1, INBOUND, OUTBOUND, Some(100)).unwrap();
+        ledger.record_cancellation(&unanswered, "Interrupted").unwrap();
+        let today = ledger.today().unwrap();
+        let max_cost = worst_case_cost(Some(100), OUTBOUND).unwrap();
+        assert_eq!(today.total, max_cost);
+        assert_eq!(today.cancelled, 1);
+
+        // The executor reported the tokens it produced before stopping
+        let billed = ledger.record_request("req-1", "executor-a", "gpt-4", 2, INBOUND, OUTBOUND, Some(100)).unwrap();
+        ledger.record_cancellation(&billed, "Deadline passed").unwrap();
+        ledger.record_response(&billed, 10, 5, "10000000000000000", Some("Request cancelled".to_string())).unwrap();
+
+        let today = ledger.today().unwrap();
+        assert_eq!(today.total, max_cost + wei("10000000000000000"));
+        assert_eq!(today.cancelled, 2);
+        let records = ledger.records().unwrap();
+        assert!(records.iter().all(|record| record.cancelled && !record.failed));
+    }
+
+    #[test]
+    fn test_budget_limits() {
+        let dir = TempDir::new().unwrap();
+        let ledger = SpendingLedger::new(dir.path()).unwrap();
+        let id = ledger.record_request("req-1", "executor-a", "gpt-4", 1, INBOUND, OUTBOUND, Some(100)).unwrap();
+        ledger.record_response(&id, 10, 5, "10000000000000000", None).unwrap();
+
+        let max_cost = worst_case_cost(Some(10), OUTBOUND).unwrap();
+
+        // No limits configured
+        assert!(ledger.check_budget(&BudgetLimits::default(), "gpt-4", "executor-a", max_cost).unwrap().is_none());
+
+        // Daily limit with room for exactly one more request of this size
+        let limits = BudgetLimits {
+            daily_limit: Some("20000000000000000".to_string()),
+            ..Default::default()
+        };
+        assert!(limits.validate().is_ok());
+        assert!(ledger.check_budget(&limits, "gpt-4", "executor-a", max_cost).unwrap().is_none());
+        let violation = ledger.check_budget(&limits, "gpt-4", "executor-a", max_cost + U256::from(1)).unwrap().unwrap();
+        assert_eq!(violation.scope, BudgetScope::Daily);
+        assert!(violation.to_string().contains("daily budget"));
+
+        // Per-model limit only applies to that model
+        let limits = BudgetLimits {
+            per_model: HashMap::from([("gpt-4".to_string(), "15000000000000000".to_string())]),
+            ..Default::default()
+        };
+        let violation = ledger.check_budget(&limits, "gpt-4", "executor-a", max_cost).unwrap().unwrap();
+        assert_eq!(violation.scope, BudgetScope::Model("gpt-4".to_string()));
+        assert_eq!(violation.spent, wei("10000000000000000"));
+        assert!(ledger.check_budget(&limits, "llama", "executor-a", max_cost).unwrap().is_none());
+
+        // Per-executor limit only applies to that executor
+        let limits = BudgetLimits {
+            per_executor: HashMap::from([("executor-a".to_string(), "15000000000000000".to_string())]),
+            ..Default::default()
+        };
+        let violation = ledger.check_budget(&limits, "gpt-4", "executor-a", max_cost).unwrap().unwrap();
+        assert_eq!(violation.scope, BudgetScope::Executor("executor-a".to_string()));
+        assert!(ledger.check_budget(&limits, "gpt-4", "executor-b", max_cost).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_limits_rejected() {
+        let limits = BudgetLimits {
+            per_model: HashMap::from([("gpt-4".to_string(), "ten".to_string())]),
+            ..Default::default()
+        };
+        assert!(limits.validate().is_err());
+    }
+}
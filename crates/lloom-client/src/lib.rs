@@ -38,16 +38,23 @@
 //! lloom-client --bootstrap-nodes /ip4/127.0.0.1/tcp/9000 --prompt "Hello world"
 //! ```
 
+pub mod chat;
+pub mod ledger;
+pub mod nonce;
+
 /// Network and protocol utilities for client operations
 pub mod network {
-    /// Validate and parse bootstrap node addresses
+    use libp2p::{multiaddr::Protocol, Multiaddr};
+    use lloom_core::network::is_quic;
+
+    /// Validate and parse bootstrap node addresses, reached over TCP or QUIC
+    /// (`/udp/<port>/quic-v1`)
     pub fn parse_bootstrap_nodes(addrs: &[String]) -> std::result::Result<Vec<String>, String> {
         for addr_str in addrs {
-            // Basic validation - check if it looks like a multiaddr
-            if !addr_str.starts_with('/') {
-                return Err(format!("Invalid multiaddr format: {}", addr_str));
-            }
-            if !addr_str.contains("/tcp/") && !addr_str.contains("/udp/") {
+            let addr: Multiaddr = addr_str.parse()
+                .map_err(|e| format!("Invalid multiaddr format: {} ({})", addr_str, e))?;
+            let tcp = addr.iter().any(|protocol| matches!(protocol, Protocol::Tcp(_)));
+            if !tcp && !is_quic(&addr) {
                 return Err(format!("Missing transport protocol in: {}", addr_str));
             }
         }
@@ -68,9 +75,15 @@ pub mod network {
 
 /// Request creation and management utilities
 pub mod request {
-    use lloom_core::protocol::LlmRequest;
+    use anyhow::{Result, anyhow};
+    use lloom_core::protocol::{
+        EmbeddingRequest, ImageInput, LlmRequest, Quote, QuoteRequest,
+        constants::{LLM_REQUEST_VERSION, MAX_IMAGE_BYTES},
+    };
+    use std::path::Path;
 
     /// Create an LLM request from command line arguments
+    #[allow(clippy::too_many_arguments)]
     pub fn create_llm_request(
         model: String,
         prompt: String,
@@ -94,25 +107,208 @@ pub mod request {
             outbound_price,
             nonce,
             deadline,
+            messages: None,
+            version: 1,
+            request_id: None,
+            tools: None,
+            tool_choice: None,
+            response_format: None,
+            sampling: Default::default(),
+            quote_id: None,
+            session: None,
+            credential: None,
+        }
+    }
+
+    /// Create an embedding request for a batch of texts
+    pub fn create_embedding_request(
+        model: String,
+        input: Vec<String>,
+        executor_address: String,
+        price_per_token: String,
+        nonce: u64,
+        deadline: u64,
+    ) -> EmbeddingRequest {
+        EmbeddingRequest {
+            model,
+            input,
+            executor_address,
+            price_per_token,
+            nonce,
+            deadline,
+            request_id: None,
+            session: None,
+            credential: None,
+        }
+    }
+
+    /// Upper bound on the input tokens of an embedding request.
+    ///
+    /// Byte-level tokenizers never produce more tokens than the UTF-8 text has
+    /// bytes, while a single character can become several tokens, so the byte
+    /// count never underestimates what an executor can bill.
+    pub fn max_embedding_tokens(input: &[String]) -> u32 {
+        input.iter().map(|text| text.len() as u32).sum()
+    }
+
+    /// Ask the request's executor to quote it
+    pub fn create_quote_request(request: &LlmRequest) -> QuoteRequest {
+        QuoteRequest {
+            model: request.model.clone(),
+            messages: request.chat_messages(),
+            max_tokens: request.max_tokens,
+            executor_address: request.executor_address.clone(),
+        }
+    }
+
+    /// Accept `quote` for `request`, taking over its prices.
+    ///
+    /// Fails if the executor could not give a quote, or gave one for another
+    /// request or that has expired at unix time `now`.
+    pub fn apply_quote(request: &mut LlmRequest, quote: &Quote, now: u64) -> Result<()> {
+        if let Some(error) = &quote.error {
+            return Err(anyhow!("Executor refused to quote: {}", error));
+        }
+        if quote.model != request.model || quote.executor_address != request.executor_address {
+            return Err(anyhow!("Quote {} is for another model or executor", quote.quote_id));
+        }
+        if request.max_tokens.is_some_and(|max_tokens| max_tokens > quote.max_tokens) {
+            return Err(anyhow!("Quote {} covers only {} output tokens", quote.quote_id, quote.max_tokens));
+        }
+        if quote.is_expired(now) {
+            return Err(anyhow!("Quote {} has expired", quote.quote_id));
+        }
+        request.inbound_price = quote.inbound_price.clone();
+        request.outbound_price = quote.outbound_price.clone();
+        request.quote_id = Some(quote.quote_id.clone());
+        request.version = request.version.max(LLM_REQUEST_VERSION);
+        Ok(())
+    }
+
+    /// MIME type of an image file, inferred from its extension
+    pub fn image_media_type(path: &Path) -> Option<&'static str> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some("image/png"),
+            "jpg" | "jpeg" => Some("image/jpeg"),
+            "gif" => Some("image/gif"),
+            "webp" => Some("image/webp"),
+            _ => None,
+        }
+    }
+
+    /// Read an image file to send inline with a request
+    pub fn load_image(path: &str) -> Result<ImageInput> {
+        let media_type = image_media_type(Path::new(path))
+            .ok_or_else(|| anyhow!("Unsupported image type {}, expected png, jpeg, gif or webp", path))?;
+        let bytes = std::fs::read(path).map_err(|e| anyhow!("Failed to read image {}: {}", path, e))?;
+        if bytes.len() > MAX_IMAGE_BYTES {
+            return Err(anyhow!("Image {} is {} bytes, the limit is {}", path, bytes.len(), MAX_IMAGE_BYTES));
         }
+        Ok(ImageInput::inline(media_type, &bytes))
     }
 }
 
 /// Parameter validation utilities
 pub mod validation {
+    use lloom_core::protocol::SamplingParams;
+
+    /// Most choices a single request may ask for
+    pub const MAX_CHOICES: u32 = 8;
+
+    /// Most stop sequences a single request may carry
+    pub const MAX_STOP_SEQUENCES: usize = 4;
+
     /// Validate temperature parameter
     pub fn validate_temperature(temp: f32) -> bool {
-        temp >= 0.0 && temp <= 2.0
+        (0.0..=2.0).contains(&temp)
     }
 
     /// Validate max_tokens parameter
     pub fn validate_max_tokens(max_tokens: u32) -> bool {
         max_tokens > 0 && max_tokens <= 4096
     }
+
+    /// Validate top_p parameter
+    pub fn validate_top_p(top_p: f32) -> bool {
+        top_p > 0.0 && top_p <= 1.0
+    }
+
+    /// Validate top_k parameter
+    pub fn validate_top_k(top_k: u32) -> bool {
+        top_k > 0
+    }
+
+    /// Validate presence_penalty or frequency_penalty parameter
+    pub fn validate_penalty(penalty: f32) -> bool {
+        (-2.0..=2.0).contains(&penalty)
+    }
+
+    /// Validate stop sequences
+    pub fn validate_stop(stop: &[String]) -> bool {
+        !stop.is_empty() && stop.len() <= MAX_STOP_SEQUENCES && stop.iter().all(|sequence| !sequence.is_empty())
+    }
+
+    /// Validate the number of choices
+    pub fn validate_choices(n: u32) -> bool {
+        n > 0 && n <= MAX_CHOICES
+    }
+
+    /// Validate every set sampling parameter, naming the first invalid one
+    pub fn validate_sampling(sampling: &SamplingParams) -> Result<(), String> {
+        if let Some(top_p) = sampling.top_p {
+            if !validate_top_p(top_p) {
+                return Err(format!("top_p must be in (0, 1], got {}", top_p));
+            }
+        }
+        if sampling.top_k.is_some_and(|top_k| !validate_top_k(top_k)) {
+            return Err("top_k must be at least 1".to_string());
+        }
+        if let Some(stop) = &sampling.stop {
+            if !validate_stop(stop) {
+                return Err(format!("stop must hold 1 to {} non-empty sequences", MAX_STOP_SEQUENCES));
+            }
+        }
+        for (name, penalty) in [("presence_penalty", sampling.presence_penalty), ("frequency_penalty", sampling.frequency_penalty)] {
+            if let Some(penalty) = penalty {
+                if !validate_penalty(penalty) {
+                    return Err(format!("{} must be in [-2, 2], got {}", name, penalty));
+                }
+            }
+        }
+        if let Some(n) = sampling.n {
+            if !validate_choices(n) {
+                return Err(format!("n must be in [1, {}], got {}", MAX_CHOICES, n));
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Response formatting utilities
 pub mod response {
+    use lloom_core::protocol::{EmbeddingResponse, ToolCall};
+
+    /// Format the tool calls of a response, one `id: name(arguments)` per line
+    pub fn format_tool_calls(tool_calls: &[ToolCall]) -> String {
+        tool_calls
+            .iter()
+            .map(|call| format!("Tool call {}: {}({})", call.id, call.function.name, call.function.arguments))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Format embedding vectors and their usage as pretty-printed JSON
+    pub fn format_embeddings(response: &EmbeddingResponse) -> String {
+        let output = serde_json::json!({
+            "model": response.model_used,
+            "input_tokens": response.input_tokens,
+            "total_cost": response.total_cost,
+            "embeddings": response.embeddings,
+        });
+        serde_json::to_string_pretty(&output).unwrap_or_default()
+    }
+
     /// Format response for display
     pub fn format_response(
         content: &str,
@@ -128,6 +324,282 @@ pub mod response {
     }
 }
 
+/// Executor failover and retry policy
+pub mod retry {
+    use lloom_core::{LlmErrorCode, LlmResponse, PeerId};
+    use std::time::{Duration, Instant};
+
+    /// Error fragments that will fail the same way on every executor.
+    const FATAL_ERROR_PATTERNS: &[&str] = &[
+        "signature verification failed",
+        "invalid request",
+        "invalid nonce",
+        "deadline",
+    ];
+
+    /// Whether a failed attempt is worth retrying on another executor
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum FailureKind {
+        /// The failure is specific to the executor (unsupported model, backend down, transport error)
+        Retryable,
+        /// The request itself was rejected and would fail everywhere
+        Fatal,
+    }
+
+    /// Classify an error string returned by an executor
+    pub fn classify_error(error: &str) -> FailureKind {
+        let error = error.to_lowercase();
+        if FATAL_ERROR_PATTERNS.iter().any(|pattern| error.contains(pattern)) {
+            FailureKind::Fatal
+        } else {
+            FailureKind::Retryable
+        }
+    }
+
+    /// Classify a failed response, preferring its error code over its message.
+    ///
+    /// Responses from executors that predate error codes fall back to
+    /// [`classify_error`].
+    pub fn classify_response(response: &LlmResponse) -> FailureKind {
+        classify_failure(response.error.as_deref().unwrap_or_default(), response.error_code)
+    }
+
+    /// Classify an error message and its optional error code
+    pub fn classify_failure(error: &str, code: Option<LlmErrorCode>) -> FailureKind {
+        match code.and_then(LlmErrorCode::is_retryable) {
+            None => classify_error(error),
+            Some(true) => FailureKind::Retryable,
+            Some(false) => FailureKind::Fatal,
+        }
+    }
+
+    /// Limits applied across all attempts of a single request
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct RetryPolicy {
+        /// Maximum number of executors to try
+        pub max_attempts: u32,
+        /// Overall time budget for the request, including retries
+        pub time_budget: Duration,
+    }
+
+    impl Default for RetryPolicy {
+        fn default() -> Self {
+            Self {
+                max_attempts: 3,
+                time_budget: Duration::from_secs(120),
+            }
+        }
+    }
+
+    impl RetryPolicy {
+        /// Create a new retry policy
+        pub fn new(max_attempts: u32, time_budget: Duration) -> Self {
+            Self { max_attempts, time_budget }
+        }
+    }
+
+    /// Outcome of a single attempt against an executor
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum AttemptOutcome {
+        Success,
+        Failed { error: String, kind: FailureKind },
+    }
+
+    /// Record of an attempt against a specific executor
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct AttemptRecord {
+        pub executor: PeerId,
+        pub outcome: AttemptOutcome,
+    }
+
+    /// Tracks attempts for one request and decides whether to fail over
+    #[derive(Debug, Clone)]
+    pub struct FailoverTracker {
+        policy: RetryPolicy,
+        started_at: Instant,
+        attempts: Vec<AttemptRecord>,
+    }
+
+    impl Default for FailoverTracker {
+        fn default() -> Self {
+            Self::new(RetryPolicy::default())
+        }
+    }
+
+    impl FailoverTracker {
+        /// Start tracking a request; the time budget starts now
+        pub fn new(policy: RetryPolicy) -> Self {
+            Self {
+                policy,
+                started_at: Instant::now(),
+                attempts: Vec::new(),
+            }
+        }
+
+        /// The policy in effect
+        pub fn policy(&self) -> &RetryPolicy {
+            &self.policy
+        }
+
+        /// All attempts made so far, in order
+        pub fn attempts(&self) -> &[AttemptRecord] {
+            &self.attempts
+        }
+
+        /// Whether the executor has already been tried for this request
+        pub fn has_tried(&self, executor: &PeerId) -> bool {
+            self.attempts.iter().any(|attempt| attempt.executor == *executor)
+        }
+
+        /// Time left before the overall budget is exhausted
+        pub fn remaining(&self) -> Duration {
+            self.policy.time_budget.saturating_sub(self.started_at.elapsed())
+        }
+
+        /// Whether another attempt is allowed by the attempt count and time budget
+        pub fn can_attempt(&self) -> bool {
+            (self.attempts.len() as u32) < self.policy.max_attempts && !self.remaining().is_zero()
+        }
+
+        /// Pick the next-best executor that has not been tried yet.
+        ///
+        /// Candidates are expected in preference order; the first untried one wins.
+        pub fn next_executor<'a, I>(&self, candidates: I) -> Option<PeerId>
+        where
+            I: IntoIterator<Item = &'a PeerId>,
+        {
+            if !self.can_attempt() {
+                return None;
+            }
+            candidates.into_iter().find(|peer| !self.has_tried(peer)).copied()
+        }
+
+        /// Record a successful attempt
+        pub fn record_success(&mut self, executor: PeerId) {
+            self.attempts.push(AttemptRecord {
+                executor,
+                outcome: AttemptOutcome::Success,
+            });
+        }
+
+        /// Record a failed attempt and return its classification
+        pub fn record_failure(&mut self, executor: PeerId, error: &str) -> FailureKind {
+            let kind = classify_error(error);
+            self.record_failure_with_kind(executor, error, kind);
+            kind
+        }
+
+        /// Record a failed attempt whose classification is already known
+        pub fn record_failure_with_kind(&mut self, executor: PeerId, error: &str, kind: FailureKind) {
+            self.attempts.push(AttemptRecord {
+                executor,
+                outcome: AttemptOutcome::Failed {
+                    error: error.to_string(),
+                    kind,
+                },
+            });
+        }
+
+        /// Human-readable summary of the executors tried
+        pub fn summary(&self) -> String {
+            if self.attempts.is_empty() {
+                return "no executors tried".to_string();
+            }
+            self.attempts
+                .iter()
+                .enumerate()
+                .map(|(i, attempt)| match &attempt.outcome {
+                    AttemptOutcome::Success => format!("{}. {}: ok", i + 1, attempt.executor),
+                    AttemptOutcome::Failed { error, kind } => {
+                        format!("{}. {}: {:?} error: {}", i + 1, attempt.executor, kind, error)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    }
+}
+
+/// Executor prices, as announced on the network or quoted
+pub mod pricing {
+    use lloom_core::{PeerId, U256, protocol::{AnnouncementType, ModelAnnouncement, ModelPricing}};
+    use std::collections::HashMap;
+
+    /// Price per input token in wei offered to executors whose prices are unknown.
+    ///
+    /// Executors predating configurable prices announce and accept exactly this.
+    pub const DEFAULT_INBOUND_PRICE: &str = "500000000000000"; // 0.0005 ETH per token
+    /// Price per output token in wei offered to executors whose prices are unknown
+    pub const DEFAULT_OUTBOUND_PRICE: &str = "1000000000000000"; // 0.001 ETH per token
+
+    /// Per-model prices of the executors a client has heard from
+    #[derive(Debug, Clone, Default)]
+    pub struct ExecutorPrices {
+        /// Available models of each executor, from its latest model announcement
+        announced: HashMap<PeerId, HashMap<String, Option<ModelPricing>>>,
+        /// Prices executors quoted for models they have not announced
+        quoted: HashMap<(PeerId, String), ModelPricing>,
+    }
+
+    impl ExecutorPrices {
+        /// Take over the models of an announcement sent by `executor`.
+        ///
+        /// The caller verifies the announcement's signature and that it was
+        /// published by `executor`.
+        pub fn record_announcement(&mut self, executor: PeerId, announcement: &ModelAnnouncement) {
+            match announcement.announcement_type {
+                AnnouncementType::Removal => {
+                    self.announced.remove(&executor);
+                    self.quoted.retain(|(peer, _), _| *peer != executor);
+                }
+                // Nothing is known about what a newer announcement type means
+                AnnouncementType::Unknown => {}
+                AnnouncementType::Initial | AnnouncementType::Update | AnnouncementType::Heartbeat => {
+                    let models = announcement.models.iter()
+                        .filter(|model| model.is_available)
+                        .map(|model| (model.model_id.clone(), model.pricing.clone()))
+                        .collect();
+                    self.announced.insert(executor, models);
+                }
+            }
+        }
+
+        /// Remember the prices `executor` quoted for `model`
+        pub fn record_quote(&mut self, executor: PeerId, model: &str, pricing: ModelPricing) {
+            self.quoted.insert((executor, model.to_string()), pricing);
+        }
+
+        /// Prices `executor` charges for `model`, if it announced or quoted them
+        pub fn get(&self, executor: &PeerId, model: &str) -> Option<&ModelPricing> {
+            self.announced.get(executor)
+                .and_then(|models| models.get(model))
+                .and_then(Option::as_ref)
+                .or_else(|| self.quoted.get(&(*executor, model.to_string())))
+        }
+
+        /// Order `candidates` for a request for `model`, best first.
+        ///
+        /// The `preferred` executor comes first, then executors known to serve
+        /// the model, cheapest output and then input price first, then executors
+        /// that have not announced their models, and last those whose
+        /// announcements lack the model. Ties are broken by peer id.
+        pub fn rank(&self, candidates: &mut [PeerId], model: &str, preferred: Option<PeerId>) {
+            candidates.sort_by_cached_key(|peer| {
+                let price = |wei: &str| wei.parse::<U256>().unwrap_or(U256::MAX);
+                let (support, output_price, input_price) = match self.get(peer, model) {
+                    Some(pricing) => (0, price(&pricing.output_token_price), price(&pricing.input_token_price)),
+                    None => match self.announced.get(peer) {
+                        Some(models) if models.contains_key(model) => (0, U256::MAX, U256::MAX),
+                        Some(_) => (2, U256::MAX, U256::MAX),
+                        None => (1, U256::MAX, U256::MAX),
+                    },
+                };
+                (Some(*peer) != preferred, support, output_price, input_price, *peer)
+            });
+        }
+    }
+}
+
 // Backward compatibility - re-export under old module name
 #[deprecated(since = "0.1.0", note = "Use the new modular API instead")]
 pub mod client_utils {
@@ -139,7 +611,10 @@ pub mod client_utils {
 
 #[cfg(test)]
 mod tests {
-    use super::{network::*, request::*, validation::*, response::*};
+    use super::{network::*, request::*, validation::*, response::*, retry::*, pricing::*};
+    use lloom_core::{Address, PeerId};
+    use lloom_core::protocol::{AnnouncementType, ModelAnnouncement, ModelCapabilities, ModelDescriptor, ModelPricing};
+    use std::time::Duration;
 
     #[test]
     fn test_parse_bootstrap_nodes_valid() {
@@ -180,6 +655,18 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_parse_bootstrap_nodes_quic() {
+        let addrs = vec![
+            "/ip4/127.0.0.1/udp/9000/quic-v1".to_string(),
+            "/ip4/127.0.0.1/tcp/9000".to_string(),
+        ];
+        assert_eq!(parse_bootstrap_nodes(&addrs).unwrap().len(), 2);
+
+        // UDP alone is not a transport
+        assert!(parse_bootstrap_nodes(&["/ip4/127.0.0.1/udp/9000".to_string()]).is_err());
+    }
+
     #[test]
     fn test_create_llm_request_minimal() {
         let request = create_llm_request(
@@ -207,6 +694,112 @@ mod tests {
         assert_eq!(request.deadline, 1234567890);
     }
 
+    #[test]
+    fn test_create_embedding_request() {
+        let input = vec!["Hello".to_string(), "wörld".to_string()];
+        let request = create_embedding_request(
+            "text-embedding-3-small".to_string(),
+            input.clone(),
+            "12D3KooWBmwkafWE2fqfzS96VoTZgpGp6aFdD7zdBUyJ1BDdyWz4".to_string(),
+            "500000000000000".to_string(),
+            3,
+            1234567890,
+        );
+
+        assert_eq!(request.model, "text-embedding-3-small");
+        assert_eq!(request.input, input);
+        assert_eq!(request.price_per_token, "500000000000000");
+        assert_eq!(request.nonce, 3);
+        assert_eq!(request.deadline, 1234567890);
+        assert_eq!(max_embedding_tokens(&input), 11);
+        // Multi-byte characters can take a token per byte
+        assert_eq!(max_embedding_tokens(&["日本".to_string()]), 6);
+    }
+
+    #[test]
+    fn test_apply_quote() {
+        let mut request = create_llm_request(
+            "gpt-4".to_string(),
+            "Hello".to_string(),
+            Some("Be brief".to_string()),
+            None,
+            Some(100),
+            "12D3KooWBmwkafWE2fqfzS96VoTZgpGp6aFdD7zdBUyJ1BDdyWz4".to_string(),
+            "1".to_string(),
+            "1".to_string(),
+            1,
+            0,
+        );
+        let quote_request = create_quote_request(&request);
+        assert_eq!(quote_request.messages.len(), 2);
+        assert_eq!(quote_request.max_tokens, Some(100));
+
+        let quote = lloom_core::Quote {
+            quote_id: "quote-1".to_string(),
+            model: "gpt-4".to_string(),
+            executor_address: request.executor_address.clone(),
+            prompt_tokens: 5,
+            max_tokens: 100,
+            inbound_price: "500".to_string(),
+            outbound_price: "1000".to_string(),
+            max_cost: "102500".to_string(),
+            expires_at: 1000,
+            error: None,
+            error_code: None,
+        };
+        assert!(apply_quote(&mut request.clone(), &quote, 1001).is_err());
+        assert!(apply_quote(&mut request.clone(), &lloom_core::Quote { model: "gpt-3.5-turbo".to_string(), ..quote.clone() }, 0).is_err());
+        assert!(apply_quote(&mut request.clone(), &lloom_core::Quote { max_tokens: 50, ..quote.clone() }, 0).is_err());
+        let refused = lloom_core::Quote { error: Some("Model gpt-4 not supported".to_string()), ..quote.clone() };
+        assert!(apply_quote(&mut request.clone(), &refused, 0).unwrap_err().to_string().contains("not supported"));
+
+        apply_quote(&mut request, &quote, 1000).unwrap();
+        assert_eq!(request.inbound_price, "500");
+        assert_eq!(request.outbound_price, "1000");
+        assert_eq!(request.quote_id.as_deref(), Some("quote-1"));
+        assert_eq!(request.version, lloom_core::protocol::constants::LLM_REQUEST_VERSION);
+    }
+
+    #[test]
+    fn test_load_image() {
+        use std::path::Path;
+
+        assert_eq!(image_media_type(Path::new("cat.PNG")), Some("image/png"));
+        assert_eq!(image_media_type(Path::new("cat.jpeg")), Some("image/jpeg"));
+        assert_eq!(image_media_type(Path::new("cat.bmp")), None);
+        assert_eq!(image_media_type(Path::new("cat")), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cat.png");
+        std::fs::write(&path, b"png bytes").unwrap();
+        let image = load_image(path.to_str().unwrap()).unwrap();
+        assert_eq!(image.media_type, "image/png");
+        assert_eq!(image.decode().unwrap(), b"png bytes");
+
+        let large = dir.path().join("large.png");
+        std::fs::write(&large, vec![0u8; lloom_core::protocol::constants::MAX_IMAGE_BYTES + 1]).unwrap();
+        assert!(load_image(large.to_str().unwrap()).unwrap_err().to_string().contains("limit"));
+        assert!(load_image(dir.path().join("missing.png").to_str().unwrap()).is_err());
+        assert!(load_image(dir.path().join("cat.txt").to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_format_embeddings() {
+        let response = lloom_core::EmbeddingResponse {
+            embeddings: vec![vec![0.5, -1.0]],
+            input_tokens: 1,
+            total_cost: "500000000000000".to_string(),
+            model_used: "text-embedding-3-small".to_string(),
+            error: None,
+            error_code: None,
+            request_id: None,
+        };
+        let json: serde_json::Value = serde_json::from_str(&format_embeddings(&response)).unwrap();
+        assert_eq!(json["embeddings"], serde_json::json!([[0.5, -1.0]]));
+        assert_eq!(json["input_tokens"], 1);
+        assert_eq!(json["model"], "text-embedding-3-small");
+    }
+
     #[test]
     fn test_create_llm_request_full() {
         let request = create_llm_request(
@@ -259,6 +852,40 @@ mod tests {
         assert!(!validate_max_tokens(10000));
     }
 
+    #[test]
+    fn test_validate_sampling() {
+        use lloom_core::SamplingParams;
+
+        assert!(validate_sampling(&SamplingParams::default()).is_ok());
+        let sampling = SamplingParams {
+            top_p: Some(0.9),
+            top_k: Some(40),
+            stop: Some(vec!["\n\n".to_string()]),
+            seed: Some(7),
+            presence_penalty: Some(-2.0),
+            frequency_penalty: Some(2.0),
+            n: Some(MAX_CHOICES),
+        };
+        assert!(validate_sampling(&sampling).is_ok());
+
+        let invalid = [
+            SamplingParams { top_p: Some(0.0), ..sampling.clone() },
+            SamplingParams { top_p: Some(1.5), ..sampling.clone() },
+            SamplingParams { top_k: Some(0), ..sampling.clone() },
+            SamplingParams { stop: Some(vec![]), ..sampling.clone() },
+            SamplingParams { stop: Some(vec![String::new()]), ..sampling.clone() },
+            SamplingParams { stop: Some(vec!["x".to_string(); MAX_STOP_SEQUENCES + 1]), ..sampling.clone() },
+            SamplingParams { presence_penalty: Some(2.5), ..sampling.clone() },
+            SamplingParams { frequency_penalty: Some(-3.0), ..sampling.clone() },
+            SamplingParams { n: Some(0), ..sampling.clone() },
+            SamplingParams { n: Some(MAX_CHOICES + 1), ..sampling.clone() },
+        ];
+        for params in invalid {
+            assert!(validate_sampling(&params).is_err(), "{:?} should be rejected", params);
+        }
+        assert!(validate_sampling(&SamplingParams { n: Some(0), ..Default::default() }).unwrap_err().starts_with("n must"));
+    }
+
     #[test]
     fn test_select_executor_index() {
         // Empty set
@@ -304,4 +931,219 @@ mod tests {
         assert!(formatted.contains("Total Cost: 300000000000000000 wei"));
         assert!(formatted.contains(&long_content));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_format_tool_calls() {
+        let call = lloom_core::ToolCall {
+            id: "call_1".to_string(),
+            kind: "function".to_string(),
+            function: lloom_core::protocol::FunctionCall {
+                name: "get_weather".to_string(),
+                arguments: "{\"city\":\"Paris\"}".to_string(),
+            },
+        };
+        assert_eq!(format_tool_calls(&[call]), "Tool call call_1: get_weather({\"city\":\"Paris\"})");
+    }
+
+    #[test]
+    fn test_classify_error() {
+        assert_eq!(classify_error("Model gpt-5 not supported"), FailureKind::Retryable);
+        assert_eq!(classify_error("Backend openai not available"), FailureKind::Retryable);
+        assert_eq!(classify_error("HTTP error: 503 Service Unavailable"), FailureKind::Retryable);
+        assert_eq!(classify_error("Signature verification failed: expired"), FailureKind::Fatal);
+        assert_eq!(classify_error("Invalid nonce"), FailureKind::Fatal);
+    }
+
+    #[test]
+    fn test_classify_response() {
+        use lloom_core::{LlmErrorCode, LlmResponse};
+
+        let mut response = LlmResponse {
+            content: String::new(),
+            inbound_tokens: 0,
+            outbound_tokens: 0,
+            total_cost: "0".to_string(),
+            model_used: "gpt-4".to_string(),
+            error: Some("Too many requests".to_string()),
+            request_id: None,
+            error_code: Some(LlmErrorCode::RateLimited),
+            tool_calls: None,
+            choices: None,
+        };
+        assert_eq!(classify_response(&response), FailureKind::Retryable);
+
+        // The code wins over a message that looks fatal
+        response.error = Some("Request deadline passed".to_string());
+        response.error_code = Some(LlmErrorCode::Busy);
+        assert_eq!(classify_response(&response), FailureKind::Retryable);
+        response.error_code = Some(LlmErrorCode::ContentRejected);
+        assert_eq!(classify_response(&response), FailureKind::Fatal);
+
+        // Without a code the message is classified
+        response.error_code = None;
+        assert_eq!(classify_response(&response), FailureKind::Fatal);
+        response.error_code = Some(LlmErrorCode::Unknown);
+        assert_eq!(classify_response(&response), FailureKind::Fatal);
+    }
+
+    #[test]
+    fn test_failover_tracker_skips_tried_executors() {
+        let mut tracker = FailoverTracker::new(RetryPolicy::new(3, Duration::from_secs(60)));
+        let peers = [PeerId::random(), PeerId::random()];
+
+        assert_eq!(tracker.next_executor(&peers), Some(peers[0]));
+        assert_eq!(tracker.record_failure(peers[0], "Model x not supported"), FailureKind::Retryable);
+        assert!(tracker.has_tried(&peers[0]));
+
+        assert_eq!(tracker.next_executor(&peers), Some(peers[1]));
+        tracker.record_success(peers[1]);
+
+        assert_eq!(tracker.next_executor(&peers), None);
+        assert_eq!(tracker.attempts().len(), 2);
+        assert_eq!(tracker.attempts()[1].outcome, AttemptOutcome::Success);
+
+        let summary = tracker.summary();
+        assert!(summary.contains(&peers[0].to_string()));
+        assert!(summary.contains("not supported"));
+        assert!(summary.contains(&format!("{}: ok", peers[1])));
+    }
+
+    #[test]
+    fn test_failover_tracker_respects_max_attempts() {
+        let mut tracker = FailoverTracker::new(RetryPolicy::new(1, Duration::from_secs(60)));
+        let peers = [PeerId::random(), PeerId::random()];
+
+        assert!(tracker.can_attempt());
+        tracker.record_failure(peers[0], "Backend x not available");
+        assert!(!tracker.can_attempt());
+        assert_eq!(tracker.next_executor(&peers), None);
+    }
+
+    #[test]
+    fn test_failover_tracker_respects_time_budget() {
+        let tracker = FailoverTracker::new(RetryPolicy::new(3, Duration::ZERO));
+        let peers = [PeerId::random()];
+
+        assert_eq!(tracker.remaining(), Duration::ZERO);
+        assert!(!tracker.can_attempt());
+        assert_eq!(tracker.next_executor(&peers), None);
+    }
+
+    #[test]
+    fn test_failover_tracker_empty_summary() {
+        let tracker = FailoverTracker::default();
+        assert_eq!(tracker.summary(), "no executors tried");
+        assert_eq!(tracker.policy(), &RetryPolicy::default());
+    }
+
+    fn pricing(input: &str, output: &str) -> ModelPricing {
+        ModelPricing {
+            input_token_price: input.to_string(),
+            output_token_price: output.to_string(),
+            minimum_fee: None,
+        }
+    }
+
+    fn announcement(announcement_type: AnnouncementType, models: Vec<ModelDescriptor>) -> ModelAnnouncement {
+        ModelAnnouncement {
+            executor_peer_id: String::new(),
+            executor_address: Address::ZERO,
+            models,
+            announcement_type,
+            timestamp: 0,
+            nonce: 0,
+            protocol_version: 2,
+        }
+    }
+
+    fn descriptor(model_id: &str, is_available: bool, pricing: Option<ModelPricing>) -> ModelDescriptor {
+        ModelDescriptor {
+            model_id: model_id.to_string(),
+            backend_type: "openai".to_string(),
+            capabilities: ModelCapabilities {
+                max_context_length: 4096,
+                features: vec!["chat".to_string()],
+                architecture: None,
+                model_size: None,
+                performance: None,
+                metadata: Default::default(),
+            },
+            is_available,
+            pricing,
+            org: None,
+        }
+    }
+
+    #[test]
+    fn test_executor_prices_follow_announcements() {
+        let executor = PeerId::random();
+        let mut prices = ExecutorPrices::default();
+        assert!(prices.get(&executor, "gpt-4").is_none());
+
+        prices.record_announcement(executor, &announcement(AnnouncementType::Initial, vec![
+            descriptor("gpt-4", true, Some(pricing("10", "20"))),
+            descriptor("llama-2-7b", false, Some(pricing("1", "2"))),
+        ]));
+        assert_eq!(prices.get(&executor, "gpt-4"), Some(&pricing("10", "20")));
+        assert!(prices.get(&executor, "llama-2-7b").is_none());
+        assert!(prices.get(&PeerId::random(), "gpt-4").is_none());
+
+        // Heartbeats carry the current prices
+        prices.record_announcement(executor, &announcement(AnnouncementType::Heartbeat, vec![
+            descriptor("gpt-4", true, Some(pricing("30", "40"))),
+        ]));
+        assert_eq!(prices.get(&executor, "gpt-4"), Some(&pricing("30", "40")));
+
+        prices.record_announcement(executor, &announcement(AnnouncementType::Unknown, vec![]));
+        assert_eq!(prices.get(&executor, "gpt-4"), Some(&pricing("30", "40")));
+
+        prices.record_announcement(executor, &announcement(AnnouncementType::Removal, vec![]));
+        assert!(prices.get(&executor, "gpt-4").is_none());
+    }
+
+    #[test]
+    fn test_executor_prices_rank_candidates() {
+        let mut peers: Vec<PeerId> = (0..5).map(|_| PeerId::random()).collect();
+        peers.sort();
+        let [unknown, cheap, expensive, other_model, preferred] = peers[..] else { unreachable!() };
+        let mut prices = ExecutorPrices::default();
+        prices.record_announcement(cheap, &announcement(AnnouncementType::Initial, vec![
+            descriptor("gpt-4", true, Some(pricing("30", "10"))),
+        ]));
+        prices.record_announcement(expensive, &announcement(AnnouncementType::Initial, vec![
+            descriptor("gpt-4", true, Some(pricing("1", "20"))),
+        ]));
+        prices.record_announcement(other_model, &announcement(AnnouncementType::Initial, vec![
+            descriptor("llama-2-7b", true, Some(pricing("1", "1"))),
+        ]));
+        prices.record_announcement(preferred, &announcement(AnnouncementType::Initial, vec![
+            descriptor("llama-2-7b", true, Some(pricing("1", "1"))),
+        ]));
+
+        let mut candidates = vec![other_model, unknown, preferred, expensive, cheap];
+        prices.rank(&mut candidates, "gpt-4", Some(preferred));
+        assert_eq!(candidates, vec![preferred, cheap, expensive, unknown, other_model]);
+
+        // Without announcements only the peer id orders candidates
+        let mut candidates = vec![preferred, cheap, unknown];
+        ExecutorPrices::default().rank(&mut candidates, "gpt-4", None);
+        assert_eq!(candidates, vec![unknown, cheap, preferred]);
+    }
+
+    #[test]
+    fn test_executor_prices_prefer_announced_over_quoted() {
+        let executor = PeerId::random();
+        let mut prices = ExecutorPrices::default();
+        prices.record_quote(executor, "gpt-4", pricing("5", "6"));
+        assert_eq!(prices.get(&executor, "gpt-4"), Some(&pricing("5", "6")));
+
+        prices.record_announcement(executor, &announcement(AnnouncementType::Update, vec![
+            descriptor("gpt-4", true, Some(pricing("10", "20"))),
+        ]));
+        assert_eq!(prices.get(&executor, "gpt-4"), Some(&pricing("10", "20")));
+
+        // Removal forgets quoted prices too
+        prices.record_announcement(executor, &announcement(AnnouncementType::Removal, vec![]));
+        assert!(prices.get(&executor, "gpt-4").is_none());
+    }
+}
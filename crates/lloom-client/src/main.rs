@@ -3,41 +3,151 @@
 //! A CLI tool for interacting with the Lloom P2P network to request LLM services.
 
 use anyhow::{Result, anyhow};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use serde::Deserialize;
 use lloom_core::{
     identity::Identity,
-    network::{LloomBehaviour, LloomEvent, helpers},
+    network::{LloomBehaviour, LloomEvent, Transport, build_swarm, helpers},
     protocol::{
-        LlmRequest, LlmResponse, ServiceRole, RequestMessage, ResponseMessage,
-        constants::MAX_MESSAGE_AGE_SECS, ModelQuery, ModelQueryResponse, ModelQueryType,
-        QueryFilters, QueryResult as ProtocolQueryResult, ModelEntry, ExecutorEntry
+        CancelRequest, ChatMessage, EmbeddingResponse, Quote, ResponseFormat, SamplingParams, Tool, LlmRequest, LlmResponse, ServiceRole, RequestMessage, ResponseMessage,
+        constants::{LEGACY_LLM_REQUEST_VERSION, LLM_REQUEST_VERSION, MAX_IMAGES_PER_REQUEST, MAX_MESSAGE_AGE_SECS}, ModelQuery, ModelQueryResponse, ModelQueryType,
+        QueryFilters, QueryResult as ProtocolQueryResult, ModelEntry, ExecutorEntry, ModelPricing, QuoteRequest,
+        SignedModelAnnouncement, request_span
     },
-    signing::{SignableMessage},
+    signing::{SIGNATURE_VERSION, SignableMessage},
+    streaming::request_llm_stream,
+    org::load_credential,
+    Address, OrgCredential, SessionCertificate, SessionKey,
+};
+#[cfg(unix)]
+use lloom_core::signer::RemoteSigner;
+use lloom_client::{
+    chat::{ChatCommand, ChatSession, HELP_TEXT, parse_command},
+    ledger::{BudgetLimits, BudgetScope, SpendingLedger, SpendingSummary, worst_case_cost},
+    nonce::{NonceManager, default_data_dir},
+    request::{apply_quote, create_embedding_request, create_quote_request, load_image, max_embedding_tokens},
+    validation::validate_sampling,
+    pricing::{DEFAULT_INBOUND_PRICE, DEFAULT_OUTBOUND_PRICE, ExecutorPrices},
+    response::{format_embeddings, format_tool_calls},
+    retry::{FailoverTracker, FailureKind, RetryPolicy, classify_failure, classify_response},
 };
 use futures::StreamExt;
 use libp2p::{
     kad::{self},
     request_response::{self, OutboundRequestId},
     swarm::SwarmEvent,
-    Multiaddr, PeerId, Swarm, SwarmBuilder,
+    Multiaddr, PeerId, Swarm,
 };
 use std::{
     collections::HashSet,
     time::Duration,
 };
-use tokio::time::{timeout, sleep};
-use tracing::{debug, info, warn, error};
+use std::io::Write;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    sync::mpsc,
+    sync::oneshot,
+    task::JoinHandle,
+    time::{timeout, sleep},
+};
+use tracing::{debug, info, warn, error, Instrument};
 
 #[derive(Debug, Deserialize)]
 struct ClientConfig {
     identity: IdentityConfig,
     network: NetworkConfig,
+    #[serde(default)]
+    blockchain: Option<BlockchainConfig>,
+    #[serde(default)]
+    budget: BudgetLimits,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 struct IdentityConfig {
-    private_key: String,
+    /// Private key (hex encoded)
+    #[serde(default)]
+    private_key: Option<String>,
+    /// Encrypted keystore file, used instead of the private key
+    #[serde(default)]
+    keystore: Option<String>,
+    /// Unix socket of a signer daemon holding the EVM key
+    #[serde(default)]
+    signer_socket: Option<String>,
+    /// Session key file, signing in place of the main key
+    #[serde(default)]
+    session: Option<String>,
+    /// Org membership credential file
+    #[serde(default)]
+    credential: Option<String>,
+}
+
+/// Certificates attached to LLM and embedding requests
+#[derive(Debug, Clone, Default)]
+struct Credentials {
+    /// Certificate of the session key signing requests
+    session: Option<SessionCertificate>,
+    /// Membership of the billed address in an organization
+    membership: Option<OrgCredential>,
+}
+
+impl IdentityConfig {
+    /// Command-line arguments override config file values
+    fn merged(self, args: &Args) -> Self {
+        Self {
+            private_key: args.private_key.clone().or(self.private_key),
+            keystore: args.keystore.clone().or(self.keystore),
+            signer_socket: args.signer_socket.clone().or(self.signer_socket),
+            session: args.session.clone().or(self.session),
+            credential: args.credential.clone().or(self.credential),
+        }
+    }
+
+    /// Load the identity from the session key, keystore or private key, or
+    /// generate an ephemeral one, and sign through the signer daemon if one is
+    /// configured. Also loads the certificates its requests carry.
+    fn load(&self) -> Result<(Identity, Credentials)> {
+        let mut credentials = Credentials::default();
+        if let Some(path) = &self.credential {
+            let membership = load_credential(path)?;
+            info!("Membership issued by {} until {}", membership.signer, membership.payload.expires_at);
+            credentials.membership = Some(membership);
+        }
+        let identity = if let Some(path) = &self.session {
+            info!("Loading session key from {}", path);
+            let session = SessionKey::load(path)?;
+            info!("Session key authorized by {} until {}", session.certificate.signer, session.certificate.payload.expires_at);
+            credentials.session = Some(session.certificate.clone());
+            session.identity()?
+        } else if let Some(path) = &self.keystore {
+            info!("Loading identity from keystore {}", path);
+            Identity::from_keystore_prompt(path)?
+        } else if let Some(key) = &self.private_key {
+            info!("Loading identity from private key");
+            Identity::from_str(key)?
+        } else {
+            info!("Generating ephemeral identity");
+            Identity::generate()
+        };
+        let identity = match &self.signer_socket {
+            Some(path) => {
+                info!("Signing through signer daemon at {}", path);
+                with_signer_daemon(identity, path)?
+            }
+            None => identity,
+        };
+        Ok((identity, credentials))
+    }
+}
+
+/// Sign for `identity` through the signer daemon listening at `path`
+#[cfg(unix)]
+fn with_signer_daemon(identity: Identity, path: &str) -> Result<Identity> {
+    Ok(identity.with_signer(std::sync::Arc::new(RemoteSigner::connect(path, None)?)))
+}
+
+#[cfg(not(unix))]
+fn with_signer_daemon(_identity: Identity, _path: &str) -> Result<Identity> {
+    anyhow::bail!("Signer daemons are only supported on Unix")
 }
 
 #[derive(Debug, Deserialize)]
@@ -45,6 +155,12 @@ struct NetworkConfig {
     bootstrap_nodes: Vec<String>,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+struct BlockchainConfig {
+    rpc_url: String,
+    contract_address: String,
+}
+
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 struct Args {
@@ -56,6 +172,32 @@ struct Args {
     #[arg(long, env = "LLOOM_PRIVATE_KEY")]
     private_key: Option<String>,
     
+    /// Encrypted (Web3 Secret Storage) keystore file for identity. The password
+    /// is read from LLOOM_KEYSTORE_PASSWORD or prompted for.
+    #[arg(long, env = "LLOOM_KEYSTORE")]
+    keystore: Option<String>,
+    
+    /// Unix socket of a signer daemon that signs for the EVM address instead of
+    /// the local key. The network identity stays local.
+    #[arg(long, env = "LLOOM_SIGNER_SOCKET")]
+    signer_socket: Option<String>,
+    
+    /// Envelope version to sign messages with: 2, or 1 while peers that
+    /// predate version 2 still verify messages. Version 1 cannot be signed
+    /// through a signer daemon.
+    #[arg(long, env = "LLOOM_SIGNATURE_VERSION", default_value_t = SIGNATURE_VERSION)]
+    signature_version: u8,
+    
+    /// Session key file (from `lloom-helper new-session`) to sign with instead
+    /// of the main key. Requests carry its certificate and bill the main address.
+    #[arg(long, env = "LLOOM_SESSION")]
+    session: Option<String>,
+    
+    /// Org membership credential file (from `lloom-helper new-credential`),
+    /// for executors serving or discounting members of the org
+    #[arg(long, env = "LLOOM_CREDENTIAL")]
+    credential: Option<String>,
+    
     /// Bootstrap nodes to connect to (validator nodes)
     #[arg(long, value_delimiter = ',')]
     bootstrap_nodes: Vec<String>,
@@ -80,10 +222,92 @@ struct Args {
     #[arg(long)]
     max_tokens: Option<u32>,
     
+    /// Nucleus sampling probability mass (0.0 to 1.0)
+    #[arg(long)]
+    top_p: Option<f32>,
+    
+    /// Only sample from the k most likely tokens
+    #[arg(long)]
+    top_k: Option<u32>,
+    
+    /// Stop generating at this sequence (repeatable)
+    #[arg(long)]
+    stop: Vec<String>,
+    
+    /// Seed for reproducible sampling
+    #[arg(long)]
+    seed: Option<u64>,
+    
+    /// Penalty for tokens that already appeared (-2.0 to 2.0)
+    #[arg(long, allow_negative_numbers = true)]
+    presence_penalty: Option<f32>,
+    
+    /// Penalty for frequently repeated tokens (-2.0 to 2.0)
+    #[arg(long, allow_negative_numbers = true)]
+    frequency_penalty: Option<f32>,
+    
+    /// Number of choices to generate
+    #[arg(long = "choices")]
+    n: Option<u32>,
+    
     /// Timeout for the entire operation in seconds
     #[arg(long, default_value = "120")]
     timeout_secs: u64,
     
+    /// Maximum number of executors to try before giving up
+    #[arg(long, default_value = "3")]
+    max_attempts: u32,
+    
+    /// Directory for persistent client state such as the nonce store
+    #[arg(long, env = "LLOOM_DATA_DIR")]
+    data_dir: Option<String>,
+    
+    /// Ethereum RPC URL used to resync the request nonce
+    #[arg(long, env = "LLOOM_RPC_URL")]
+    rpc_url: Option<String>,
+    
+    /// Accounting contract address used to resync the request nonce
+    #[arg(long, env = "LLOOM_CONTRACT_ADDRESS")]
+    contract_address: Option<String>,
+    
+    /// Overwrite the local nonce with the contract's current nonce
+    #[arg(long)]
+    resync_nonce: bool,
+    
+    /// Maximum total spend per UTC day in wei (overrides the config file)
+    #[arg(long)]
+    daily_budget: Option<String>,
+    
+    /// Print today's spending from the local ledger and exit
+    #[arg(long)]
+    show_spending: bool,
+    
+    /// Stream the response and print tokens as they arrive
+    #[arg(long)]
+    stream: bool,
+    
+    /// Ask each executor for a signed price quote and send the request at the quoted prices.
+    /// Without it, requests offer the executor's announced prices, or quoted ones if it
+    /// announced none, and skip no executor for lack of a quote
+    #[arg(long)]
+    quote: bool,
+    
+    /// JSON file with the tools the model may call (OpenAI `tools` format)
+    #[arg(long)]
+    tools: Option<String>,
+    
+    /// Image to send with the prompt, for vision models (repeatable)
+    #[arg(long)]
+    image: Vec<String>,
+    
+    /// Require the output to be a JSON object
+    #[arg(long, conflicts_with = "json_schema")]
+    json: bool,
+    
+    /// JSON Schema file the output must match
+    #[arg(long)]
+    json_schema: Option<String>,
+    
     /// Enable debug logging
     #[arg(short = 'd', long)]
     debug: bool,
@@ -103,15 +327,121 @@ struct Args {
     /// Run a demo query with predefined settings (connects to default validator, uses gpt-oss:20b model)
     #[arg(long)]
     demo: bool,
+    
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+enum Command {
+    /// Start an interactive multi-turn chat (type /help inside for commands)
+    Chat,
+    /// Embed texts with --model and print the vectors as JSON
+    Embed {
+        /// Texts to embed; one vector is returned per text, in order
+        #[arg(required = true)]
+        input: Vec<String>,
+    },
+}
+
+/// How the in-flight attempt was sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PendingAttempt {
+    Request(OutboundRequestId),
+    /// Streamed attempt, numbered so events from abandoned streams are ignored
+    Stream(u64),
+}
+
+/// Progress of a streamed attempt
+#[derive(Debug)]
+enum StreamEvent {
+    Chunk(String),
+    Done(std::result::Result<Box<ResponseMessage>, String>),
+}
+
+/// How long to wait for an executor to answer a cancellation
+const CANCEL_ACK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Why the client gave up on a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CancelReason {
+    /// The operation timeout passed
+    Timeout,
+    /// The user pressed Ctrl+C
+    Interrupted,
+}
+
+impl std::fmt::Display for CancelReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CancelReason::Timeout => write!(f, "Deadline passed"),
+            CancelReason::Interrupted => write!(f, "Interrupted by user"),
+        }
+    }
 }
 
 /// Client state for tracking the request lifecycle
 #[derive(Default)]
 struct ClientState {
     discovered_executors: HashSet<PeerId>,
-    pending_request: Option<(OutboundRequestId, PeerId)>,
+    pending_request: Option<(PendingAttempt, PeerId)>,
+    /// `LlmRequest::request_id` of the in-flight attempt, used to cancel it
+    pending_request_id: Option<String>,
+    /// Task driving the in-flight streamed attempt
+    stream_task: Option<StreamTask>,
     response_received: Option<LlmResponse>,
+    outbound_failure: Option<String>,
+    ledger_entry: Option<String>,
     discovery_complete: bool,
+    failover: FailoverTracker,
+    preferred_executor: Option<PeerId>,
+    streams_opened: u64,
+    /// Whether the current attempt has printed streamed content
+    stream_printed: bool,
+    /// Whether any attempt may have reached an executor that can settle its nonce
+    maybe_settled: bool,
+    /// Prices executors announced or quoted, kept across requests
+    prices: ExecutorPrices,
+}
+
+impl ClientState {
+    /// Reset per-request state before sending a new request, keeping discovered executors
+    fn begin_request(&mut self, policy: RetryPolicy) {
+        self.pending_request = None;
+        self.pending_request_id = None;
+        self.stream_task = None;
+        self.response_received = None;
+        self.outbound_failure = None;
+        self.ledger_entry = None;
+        self.discovery_complete = false;
+        self.failover = FailoverTracker::new(policy);
+        self.stream_printed = false;
+        self.maybe_settled = false;
+    }
+    
+    /// Whether a failed attempt may be retried on another executor.
+    ///
+    /// Every attempt signs the request's single nonce, and only one commitment
+    /// per nonce can be settled on chain. Once an attempt may have reached an
+    /// executor able to settle it, no other executor is given the nonce.
+    fn may_fail_over(&self) -> bool {
+        self.failover.can_attempt() && !self.maybe_settled
+    }
+    
+    /// Stop preferring an executor once it fails
+    fn forget_preferred(&mut self, executor: &PeerId) {
+        if self.preferred_executor.as_ref() == Some(executor) {
+            self.preferred_executor = None;
+        }
+    }
+}
+
+/// Task driving a streamed attempt
+struct StreamTask {
+    /// Closes the client's side of the stream, asking the executor to stop
+    cancel: oneshot::Sender<()>,
+    /// Final response of the stream, carrying what the executor billed
+    handle: JoinHandle<Option<ResponseMessage>>,
 }
 
 /// Model discovery cache for client-side model information
@@ -315,7 +645,7 @@ async fn send_model_query(
     info!("Sending model query to {} connected peers", connected_peers.len());
 
     // Sign and prepare the query
-    let signed_query = query.sign_blocking(&identity.wallet)
+    let signed_query = query.sign_blocking(identity)
         .map_err(|e| anyhow!("Failed to sign model query: {}", e))?;
     
     let request_message = RequestMessage::ModelQuery(signed_query);
@@ -323,12 +653,9 @@ async fn send_model_query(
     // Send queries to all connected peers (assuming they are validators)
     let mut request_ids = Vec::new();
     for peer_id in &connected_peers {
-        match swarm.behaviour_mut().request_response.send_request(peer_id, request_message.clone()) {
-            request_id => {
-                info!("Sent model query to validator {}", peer_id);
-                request_ids.push((request_id, *peer_id));
-            }
-        }
+        let request_id = swarm.behaviour_mut().request_response.send_request(peer_id, request_message.clone());
+        info!("Sent model query to validator {}", peer_id);
+        request_ids.push((request_id, *peer_id));
     }
 
     if request_ids.is_empty() {
@@ -342,27 +669,26 @@ async fn send_model_query(
     let expected_responses = request_ids.len();
 
     while start_time.elapsed() < query_timeout && responses_received < expected_responses {
-        if let Ok(event) = tokio::time::timeout(Duration::from_millis(100), swarm.select_next_some()).await {
-            if let SwarmEvent::Behaviour(LloomEvent::RequestResponse(
-                libp2p::request_response::Event::Message {
-                    message: libp2p::request_response::Message::Response { response, .. },
-                    peer,
+        if let Ok(SwarmEvent::Behaviour(LloomEvent::RequestResponse(
+            libp2p::request_response::Event::Message {
+                message: libp2p::request_response::Message::Response {
+                    response: ResponseMessage::ModelQueryResponse(signed_response),
                     ..
+                },
+                peer,
+                ..
+            }
+        ))) = tokio::time::timeout(Duration::from_millis(100), swarm.select_next_some()).await {
+            info!("Received model query response from {}", peer);
+            
+            // Verify signature if signing is enabled
+            match signed_response.verify_with_time_window(MAX_MESSAGE_AGE_SECS) {
+                Ok(_) => {
+                    cache.update(&signed_response.payload);
+                    responses_received += 1;
                 }
-            )) = event {
-                if let ResponseMessage::ModelQueryResponse(signed_response) = response {
-                    info!("Received model query response from {}", peer);
-                    
-                    // Verify signature if signing is enabled
-                    match signed_response.verify_with_time_window(MAX_MESSAGE_AGE_SECS) {
-                        Ok(_) => {
-                            cache.update(&signed_response.payload);
-                            responses_received += 1;
-                        }
-                        Err(e) => {
-                            warn!("Model query response signature verification failed: {}", e);
-                        }
-                    }
+                Err(e) => {
+                    warn!("Model query response signature verification failed: {}", e);
                 }
             }
         }
@@ -507,7 +833,7 @@ async fn main() -> Result<()> {
         .init();
     
     // Load configuration from file if provided, or check for default config.toml
-    let (final_private_key, final_bootstrap_nodes) = if let Some(config_path) = &args.config {
+    let (identity_config, final_bootstrap_nodes, config_blockchain, mut budget) = if let Some(config_path) = &args.config {
         info!("Loading configuration from: {}", config_path);
         let config_content = std::fs::read_to_string(config_path)
             .map_err(|e| anyhow!("Failed to read config file {}: {}", config_path, e))?;
@@ -515,14 +841,14 @@ async fn main() -> Result<()> {
             .map_err(|e| anyhow!("Failed to parse TOML config: {}", e))?;
         
         // Command-line arguments override config file values
-        let private_key = args.private_key.clone().unwrap_or(config.identity.private_key);
+        let identity_config = config.identity.merged(&args);
         let bootstrap_nodes = if args.bootstrap_nodes.is_empty() {
             config.network.bootstrap_nodes
         } else {
             args.bootstrap_nodes.clone()
         };
         
-        (Some(private_key), bootstrap_nodes)
+        (identity_config, bootstrap_nodes, config.blockchain, config.budget)
     } else if std::path::Path::new("config.toml").exists() {
         info!("Automatically loading config from: config.toml");
         let config_content = std::fs::read_to_string("config.toml")
@@ -531,18 +857,24 @@ async fn main() -> Result<()> {
             .map_err(|e| anyhow!("Failed to parse TOML config: {}", e))?;
         
         // Command-line arguments override config file values
-        let private_key = args.private_key.clone().unwrap_or(config.identity.private_key);
+        let identity_config = config.identity.merged(&args);
         let bootstrap_nodes = if args.bootstrap_nodes.is_empty() {
             config.network.bootstrap_nodes
         } else {
             args.bootstrap_nodes.clone()
         };
         
-        (Some(private_key), bootstrap_nodes)
+        (identity_config, bootstrap_nodes, config.blockchain, config.budget)
     } else {
-        (args.private_key.clone(), args.bootstrap_nodes.clone())
+        (IdentityConfig::default().merged(&args), args.bootstrap_nodes.clone(), None, BudgetLimits::default())
     };
     
+    if let Some(daily_budget) = &args.daily_budget {
+        budget.daily_limit = Some(daily_budget.clone());
+    }
+    budget.validate()?;
+    validate_sampling(&sampling_params(&args)).map_err(|e| anyhow!("Invalid sampling parameters: {}", e))?;
+    
     // Handle demo mode - override settings with demo defaults
     let (final_bootstrap_nodes, final_model, final_prompt) = if args.demo {
         println!("🚀 Running Lloom Demo!");
@@ -562,7 +894,7 @@ async fn main() -> Result<()> {
     };
     
     // Validate bootstrap nodes are provided
-    if final_bootstrap_nodes.is_empty() {
+    if final_bootstrap_nodes.is_empty() && !args.show_spending {
         return Err(anyhow!("At least one bootstrap node is required (via --bootstrap-nodes or config file)"));
     }
     
@@ -573,20 +905,48 @@ async fn main() -> Result<()> {
     }
     
     // Load or generate identity
-    let identity = match &final_private_key {
-        Some(key) => {
-            info!("Loading identity from private key");
-            Identity::from_str(key)?
-        }
-        None => {
-            info!("Generating ephemeral identity");
-            Identity::generate()
-        }
-    };
+    let (identity, credentials) = identity_config.load()?;
+    let identity = identity.with_signature_version(args.signature_version)?;
     
     info!("Client identity: PeerId={}", identity.peer_id);
     info!("EVM address: {}", identity.evm_address);
     
+    // Open the nonce store and reconcile it with the contract when possible
+    let data_dir = match &args.data_dir {
+        Some(dir) => std::path::PathBuf::from(dir),
+        None => default_data_dir()
+            .ok_or_else(|| anyhow!("Could not determine a data directory, pass --data-dir"))?,
+    };
+    let nonce_manager = NonceManager::new(&data_dir)?;
+    debug!("Nonce store: {}", nonce_manager.path().display());
+    let ledger = SpendingLedger::new(&data_dir)?;
+    debug!("Spending ledger: {}", ledger.path().display());
+    
+    if args.show_spending {
+        display_spending(&ledger.today()?, &budget);
+        return Ok(());
+    }
+    
+    let rpc_url = args.rpc_url.clone()
+        .or_else(|| config_blockchain.as_ref().map(|c| c.rpc_url.clone()));
+    let contract_address = args.contract_address.clone()
+        .or_else(|| config_blockchain.as_ref().map(|c| c.contract_address.clone()));
+    match (rpc_url, contract_address) {
+        (Some(rpc_url), Some(contract_address)) => {
+            let contract_address: Address = contract_address.parse()
+                .map_err(|e| anyhow!("Invalid contract address {}: {}", contract_address, e))?;
+            match nonce_manager.resync(identity.evm_address, &rpc_url, contract_address, args.resync_nonce).await {
+                Ok(nonce) => info!("Nonce synchronized with contract, last used nonce: {}", nonce),
+                Err(e) if args.resync_nonce => return Err(e),
+                Err(e) => warn!("Could not sync nonce with contract, using local store: {}", e),
+            }
+        }
+        _ if args.resync_nonce => {
+            return Err(anyhow!("--resync-nonce requires --rpc-url and --contract-address (or a [blockchain] config section)"));
+        }
+        _ => debug!("No contract configured, using local nonce store only"),
+    }
+    
     // Parse bootstrap nodes
     let bootstrap_addrs: Result<Vec<Multiaddr>> = final_bootstrap_nodes
         .iter()
@@ -596,19 +956,8 @@ async fn main() -> Result<()> {
     
     info!("Bootstrap nodes: {:?}", bootstrap_addrs);
     
-    // Create network behaviour
-    let behaviour = LloomBehaviour::new(&identity)?;
-    
-    // Build swarm
-    let mut swarm = SwarmBuilder::with_existing_identity(identity.p2p_keypair.clone())
-        .with_tokio()
-        .with_tcp(
-            libp2p::tcp::Config::default(),
-            libp2p::noise::Config::new,
-            libp2p::yamux::Config::default,
-        )?
-        .with_behaviour(|_| behaviour)?
-        .build();
+    // Build swarm, dialing validators and executors over TCP or QUIC
+    let mut swarm = build_swarm(&identity, Transport::Dual)?;
     
     // Connect to bootstrap nodes
     for addr in &bootstrap_addrs {
@@ -622,6 +971,7 @@ async fn main() -> Result<()> {
     // Subscribe to gossipsub topics
     helpers::subscribe_topic(&mut swarm, "lloom/announcements")?;
     helpers::subscribe_topic(&mut swarm, "lloom/executor-announcements")?;
+    helpers::subscribe_topic(&mut swarm, "lloom/model-announcements")?;
     
     let mut client_state = ClientState::default();
     let mut discovery_cache = ModelDiscoveryCache::new();
@@ -646,6 +996,16 @@ async fn main() -> Result<()> {
         }
     }
 
+    match &args.command {
+        Some(Command::Chat) => {
+            return run_chat(&mut swarm, &args, &mut client_state, &identity, &credentials, &nonce_manager, &ledger, &budget).await;
+        }
+        Some(Command::Embed { input }) => {
+            return run_embed(&mut swarm, &args, &mut client_state, &identity, &credentials, &nonce_manager, &ledger, &budget, input).await;
+        }
+        None => {}
+    }
+    
     // Require prompt for normal operation (demo provides its own prompt)
     if final_prompt.is_none() && !args.discover_models && args.query_model.is_none() && !args.demo {
         return Err(anyhow!("Prompt is required when not using discovery commands (--discover-models, --query-model, or --demo)"));
@@ -658,17 +1018,59 @@ async fn main() -> Result<()> {
         runtime_args.prompt = final_prompt;
     }
     
-    // Run the client with timeout
-    let result = timeout(
+    // The retry budget shares the overall operation timeout
+    client_state.begin_request(RetryPolicy::new(
+        args.max_attempts,
         Duration::from_secs(args.timeout_secs),
-        run_client(&mut swarm, &runtime_args, &mut client_state, &identity)
-    ).await;
+    ));
+    
+    let nonce = nonce_manager.reserve(identity.evm_address).await?;
+    info!("Using request nonce {}", nonce);
+    let ctx = RequestContext {
+        identity: &identity,
+        credentials: credentials.clone(),
+        ledger: &ledger,
+        budget: &budget,
+        nonce,
+        request_id: uuid::Uuid::new_v4().to_string(),
+        messages: image_messages(&runtime_args)?,
+        tools: args.tools.as_deref().map(load_tools).transpose()?,
+        response_format: response_format(&args)?,
+    };
+    
+    // Run the client with timeout, cancelling the request if it passes or the user interrupts
+    info!("Request id: {}", ctx.request_id);
+    let span = request_span(&ctx.request_id);
+    let result = tokio::select! {
+        result = timeout(
+            Duration::from_secs(args.timeout_secs),
+            run_client(&mut swarm, &runtime_args, &mut client_state, &ctx)
+        ).instrument(span.clone()) => result.map_err(|_| CancelReason::Timeout),
+        _ = tokio::signal::ctrl_c() => Err(CancelReason::Interrupted),
+    };
+    
+    let mut served = matches!(&result, Ok(Ok(response)) if response.error.is_none());
+    if let Err(reason) = result {
+        served = cancel_pending_request(&mut swarm, &mut client_state, &runtime_args, &identity, &ledger, reason)
+            .instrument(span)
+            .await;
+    }
+    finish_request(&mut client_state, &ledger, &nonce_manager, identity.evm_address, nonce, served).await;
     
     match result {
         Ok(Ok(response)) => {
+            report_attempts(&client_state.failover);
             if let Some(error) = &response.error {
                 error!("Request failed: {}", error);
                 std::process::exit(1);
+            } else if args.stream {
+                // The content has already been printed as it arrived
+                println!();
+                println!("---");
+                println!("Model: {}", response.model_used);
+                println!("Inbound Tokens: {}", response.inbound_tokens);
+                println!("Outbound Tokens: {}", response.outbound_tokens);
+                println!("Total Cost: {}", response.total_cost);
             } else {
                 println!("Model: {}", response.model_used);
                 println!("Inbound Tokens: {}", response.inbound_tokens);
@@ -677,159 +1079,1083 @@ async fn main() -> Result<()> {
                 println!("---");
                 println!("{}", response.content);
             }
+            // The first choice is the content printed above
+            for (index, choice) in response.choices.iter().flatten().enumerate().skip(1) {
+                println!("--- Choice {} ---", index + 1);
+                println!("{}", choice);
+            }
+            if let Some(tool_calls) = &response.tool_calls {
+                println!("---");
+                println!("{}", format_tool_calls(tool_calls));
+            }
         }
         Ok(Err(e)) => {
             error!("Client error: {}", e);
+            report_attempts(&client_state.failover);
             std::process::exit(1);
         }
-        Err(_) => {
+        Err(CancelReason::Timeout) => {
             error!("Request timed out after {} seconds", args.timeout_secs);
+            report_attempts(&client_state.failover);
             std::process::exit(1);
         }
+        Err(CancelReason::Interrupted) => {
+            error!("Request interrupted");
+            std::process::exit(130);
+        }
     }
     
     Ok(())
 }
 
-/// Main client logic
-async fn run_client(
+/// Read the tools the model may call from a JSON file holding an array of tools
+fn load_tools(path: &str) -> Result<Vec<Tool>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read tools file {}: {}", path, e))?;
+    serde_json::from_str(&contents).map_err(|e| anyhow!("Invalid tools file {}: {}", path, e))
+}
+
+/// The conversation for a prompt with images attached, if any were given
+fn image_messages(args: &Args) -> Result<Option<Vec<ChatMessage>>> {
+    if args.image.is_empty() {
+        return Ok(None);
+    }
+    if args.image.len() > MAX_IMAGES_PER_REQUEST {
+        return Err(anyhow!("At most {} images can be sent with a request", MAX_IMAGES_PER_REQUEST));
+    }
+    let images = args.image.iter().map(|path| load_image(path)).collect::<Result<Vec<_>>>()?;
+    let mut messages: Vec<ChatMessage> = args.system_prompt.iter().map(ChatMessage::system).collect();
+    messages.push(ChatMessage::user_with_images(args.prompt.clone().unwrap_or_default(), images));
+    Ok(Some(messages))
+}
+
+/// Sampling parameters given on the command line
+fn sampling_params(args: &Args) -> SamplingParams {
+    SamplingParams {
+        top_p: args.top_p,
+        top_k: args.top_k,
+        stop: (!args.stop.is_empty()).then(|| args.stop.clone()),
+        seed: args.seed,
+        presence_penalty: args.presence_penalty,
+        frequency_penalty: args.frequency_penalty,
+        n: args.n,
+    }
+}
+
+/// Output format requested on the command line, reading the schema file if one is given
+fn response_format(args: &Args) -> Result<Option<ResponseFormat>> {
+    if args.json {
+        return Ok(Some(ResponseFormat::JsonObject));
+    }
+    let Some(path) = &args.json_schema else {
+        return Ok(None);
+    };
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read JSON Schema file {}: {}", path, e))?;
+    let schema = serde_json::from_str(&contents).map_err(|e| anyhow!("Invalid JSON Schema file {}: {}", path, e))?;
+    let name = std::path::Path::new(path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("output");
+    let format = ResponseFormat::json_schema(name, schema);
+    format.check().map_err(|e| anyhow!("Invalid JSON Schema file {}: {}", path, e))?;
+    Ok(Some(format))
+}
+
+/// Bookkeeping once a request is over, whatever its outcome
+async fn finish_request(
+    state: &mut ClientState,
+    ledger: &SpendingLedger,
+    nonce_manager: &NonceManager,
+    client: Address,
+    nonce: u64,
+    served: bool,
+) {
+    // A request still in flight when the timeout hit never got a response,
+    // but the executor may still be working on it
+    if let Some(entry_id) = state.ledger_entry.take() {
+        state.maybe_settled = true;
+        if let Err(e) = ledger.record_failure(&entry_id, "Request timed out", true) {
+            warn!("Failed to update spending ledger: {}", e);
+        }
+    }
+    
+    // Hand the nonce back only if no executor can have used it: releasing a
+    // nonce that gets settled later would make the next request reuse it
+    if !served && !state.maybe_settled {
+        if let Err(e) = nonce_manager.release(client, nonce).await {
+            warn!("Failed to release unused nonce {}: {}", nonce, e);
+        }
+    }
+}
+
+/// Cancel the in-flight attempt after the client gave up on it.
+///
+/// A streamed attempt is cancelled by closing the client's side of its stream,
+/// a request-response attempt by sending a signed `CancelRequest`. Either way the
+/// executor answers with the final response of the cancelled request, which is
+/// recorded in the ledger as what it billed. Returns whether the executor may still settle the request, i.e. use
+/// the nonce; without its final answer that cannot be ruled out.
+async fn cancel_pending_request(
     swarm: &mut Swarm<LloomBehaviour>,
-    args: &Args,
     state: &mut ClientState,
+    args: &Args,
     identity: &Identity,
-) -> Result<LlmResponse> {
-    info!("Phase 1: Discovering executors...");
-    
-    // Wait longer for initial connections and DHT to stabilize
-    info!("DEBUG: Waiting for DHT to stabilize...");
-    sleep(Duration::from_secs(5)).await;
-    
-    // Bootstrap Kademlia DHT first
-    info!("DEBUG: Bootstrapping Kademlia DHT...");
-    if let Err(e) = swarm.behaviour_mut().kademlia.bootstrap() {
-        warn!("DEBUG: Failed to bootstrap Kademlia: {:?}", e);
+    ledger: &SpendingLedger,
+    reason: CancelReason,
+) -> bool {
+    let Some((attempt, executor)) = state.pending_request.take() else {
+        return state.maybe_settled;
+    };
+    let request_id = state.pending_request_id.take();
+    let entry_id = state.ledger_entry.take();
+    if let Some(entry_id) = &entry_id {
+        if let Err(e) = ledger.record_cancellation(entry_id, &reason.to_string()) {
+            warn!("Failed to update spending ledger: {}", e);
+        }
+    }
+    if state.stream_printed {
+        println!();
     }
     
-    // Wait for bootstrap to complete
-    sleep(Duration::from_secs(3)).await;
-    
-    // Query for executors multiple times with delays - more attempts with longer intervals
-    let executor_key = ServiceRole::Executor.to_kad_key();
-    info!("DEBUG: Looking for executors using key: {:?} (as string: {})", executor_key, String::from_utf8_lossy(&executor_key));
-    
-    for attempt in 1..=8 {
-        info!("DEBUG: Attempt {}/8: Starting provider query for executor key", attempt);
-        swarm.behaviour_mut().kademlia.get_providers(executor_key.clone().into());
-        
-        // Also try to get records for backwards compatibility
-        info!("DEBUG: Attempt {}/8: Starting record query for executor key", attempt);
-        swarm.behaviour_mut().kademlia.get_record(executor_key.clone().into());
-        
-        // Process events immediately after each query
-        for _ in 0..10 {
-            if let Ok(event) = tokio::time::timeout(Duration::from_millis(100), swarm.select_next_some()).await {
-                handle_swarm_event(swarm, event, state, args, identity).await;
-                if !state.discovered_executors.is_empty() {
-                    info!("DEBUG: Early discovery success - found {} executors", state.discovered_executors.len());
-                    break;
-                }
+    let billed = match (attempt, request_id) {
+        (PendingAttempt::Stream(_), _) => match state.stream_task.take() {
+            Some(task) => {
+                info!("Closing stream to executor {} ({})", executor, reason);
+                close_stream(task, executor, args.enable_signing).await
             }
+            None => None,
+        },
+        (PendingAttempt::Request(_), Some(request_id)) => {
+            send_cancel_request(swarm, state, args, identity, executor, request_id, reason).await
         }
-        
-        if !state.discovered_executors.is_empty() {
-            break;
+        (PendingAttempt::Request(_), None) => None,
+    };
+    
+    let Some(response) = billed else {
+        // Without the executor's final answer the request may still be settled
+        state.maybe_settled = true;
+        return true;
+    };
+    if let Some(entry_id) = &entry_id {
+        if let Err(e) = ledger.record_response(
+            entry_id,
+            response.inbound_tokens,
+            response.outbound_tokens,
+            &response.total_cost,
+            response.error.clone(),
+        ) {
+            warn!("Failed to update spending ledger: {}", e);
         }
-        
-        if attempt < 8 {
-            info!("DEBUG: Waiting 2 seconds before next attempt...");
-            sleep(Duration::from_secs(2)).await;
+    }
+    info!("Request cancelled, executor billed {} wei for {} + {} tokens",
+          response.total_cost, response.inbound_tokens, response.outbound_tokens);
+    state.maybe_settled |= response.inbound_tokens + response.outbound_tokens > 0;
+    state.maybe_settled
+}
+
+/// Close the client's side of a streamed attempt and wait briefly for the
+/// executor's final response to the stopped completion
+async fn close_stream(task: StreamTask, executor: PeerId, enable_signing: bool) -> Option<LlmResponse> {
+    let StreamTask { cancel, mut handle } = task;
+    let _ = cancel.send(());
+    match timeout(CANCEL_ACK_TIMEOUT, &mut handle).await {
+        Ok(Ok(Some(response))) => extract_llm_response(&response, executor, enable_signing),
+        Ok(_) => None,
+        Err(_) => {
+            warn!("Executor {} did not answer the closed stream", executor);
+            handle.abort();
+            None
         }
     }
+}
+
+/// Send a signed cancellation to an executor and wait briefly for its answer
+async fn send_cancel_request(
+    swarm: &mut Swarm<LloomBehaviour>,
+    state: &mut ClientState,
+    args: &Args,
+    identity: &Identity,
+    executor: PeerId,
+    request_id: String,
+    reason: CancelReason,
+) -> Option<LlmResponse> {
+    let cancel = CancelRequest {
+        request_id,
+        executor_address: executor.to_string(),
+        reason: Some(reason.to_string()),
+    };
+    // Executors only honour signed cancellations, whatever the request signing mode
+    let signed_cancel = match cancel.sign_blocking(identity) {
+        Ok(signed_cancel) => signed_cancel,
+        Err(e) => {
+            error!("Failed to sign cancellation: {}", e);
+            return None;
+        }
+    };
+    info!("Cancelling request {} on executor {} ({})", signed_cancel.payload.request_id, executor, reason);
+    let cancel_id = swarm.behaviour_mut().request_response
+        .send_request(&executor, RequestMessage::CancelRequest(signed_cancel));
     
-    info!("DEBUG: Completed all discovery attempts, found {} executors so far",
-          state.discovered_executors.len());
-    
-    let mut discovery_timeout = tokio::time::interval(Duration::from_secs(60));
-    discovery_timeout.tick().await; // Skip first immediate tick
-    
-    // Discovery phase
+    let deadline = sleep(CANCEL_ACK_TIMEOUT);
+    tokio::pin!(deadline);
     loop {
         tokio::select! {
-            event = swarm.select_next_some() => {
-                handle_swarm_event(swarm, event, state, args, identity).await;
-                
-                // Check if we found executors and can proceed
-                if !state.discovered_executors.is_empty() && !state.discovery_complete {
-                    info!("Phase 2: Found {} executors, selecting one...", state.discovered_executors.len());
-                    
-                    // Select first available executor (could be improved with latency testing)
-                    let selected_executor = *state.discovered_executors.iter().next().unwrap();
-                    
-                    // Prepare LLM request
-                    let request = LlmRequest {
-                        model: args.model.clone(),
-                        prompt: args.prompt.as_ref().unwrap().clone(),
-                        system_prompt: args.system_prompt.clone(),
-                        temperature: args.temperature,
-                        max_tokens: args.max_tokens,
-                        executor_address: selected_executor.to_string(),
-                        inbound_price: "500000000000000".to_string(), // 0.0005 ETH per token
-                        outbound_price: "1000000000000000".to_string(), // 0.001 ETH per token
-                        nonce: 1,
-                        deadline: std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap()
-                            .as_secs() + 300, // 5 minutes from now
-                    };
-                    
-                    info!("Phase 3: Sending request to executor: {}", selected_executor);
-                    
-                    // Send the request (with or without signing based on configuration)
-                    let request_message = if args.enable_signing {
-                        // Sign the request before sending
-                        match request.sign_blocking(&identity.wallet) {
-                            Ok(signed_request) => {
-                                info!("Successfully signed request with timestamp: {}", signed_request.timestamp);
-                                RequestMessage::SignedLlmRequest(signed_request)
-                            }
-                            Err(e) => {
-                                error!("Failed to sign request: {}, falling back to unsigned", e);
-                                RequestMessage::LlmRequest(request)
-                            }
-                        }
-                    } else {
-                        info!("Signing disabled, sending unsigned request");
-                        RequestMessage::LlmRequest(request)
-                    };
-                    
-                    let request_id = swarm.behaviour_mut().request_response.send_request(&selected_executor, request_message);
-                    state.pending_request = Some((request_id, selected_executor));
-                    state.discovery_complete = true;
-                }
-                
-                // Check if we received a response
-                if let Some(response) = &state.response_received {
-                    return Ok(response.clone());
+            event = swarm.select_next_some() => match event {
+                SwarmEvent::Behaviour(LloomEvent::RequestResponse(request_response::Event::Message {
+                    message: request_response::Message::Response { response, request_id },
+                    peer,
+                    ..
+                })) if request_id == cancel_id => {
+                    if let ResponseMessage::AcknowledgmentResponse(ack) = &response {
+                        warn!("Executor {} refused the cancellation: {}",
+                              peer, ack.payload.message.as_deref().unwrap_or("no reason given"));
+                        return None;
+                    }
+                    return extract_llm_response(&response, peer, args.enable_signing);
                 }
-            }
-            _ = discovery_timeout.tick() => {
-                if state.discovered_executors.is_empty() {
-                    error!("DEBUG: ❌ DISCOVERY TIMEOUT - No executors found after 60 seconds");
-                    error!("DEBUG: This suggests either:");
-                    error!("DEBUG: 1. No executors are running or registered");
-                    error!("DEBUG: 2. Network connectivity issues prevent discovery");
-                    error!("DEBUG: 3. Executors are not advertising the '{}' model", args.model);
-                    return Err(anyhow!("No executors found after discovery timeout"));
-                } else {
-                    info!("DEBUG: Discovery timeout reached but we have {} executors", state.discovered_executors.len());
+                SwarmEvent::Behaviour(LloomEvent::RequestResponse(request_response::Event::OutboundFailure {
+                    request_id, error, ..
+                })) if request_id == cancel_id => {
+                    warn!("Failed to cancel request on executor {}: {:?}", executor, error);
+                    return None;
                 }
+                event => handle_swarm_event(swarm, event, state, args, identity).await,
+            },
+            _ = &mut deadline => {
+                warn!("Executor {} did not answer the cancellation", executor);
+                return None;
             }
         }
     }
 }
 
-/// Handle swarm events
-async fn handle_swarm_event(
+/// Interactive multi-turn chat REPL
+#[allow(clippy::too_many_arguments)]
+async fn run_chat(
+    swarm: &mut Swarm<LloomBehaviour>,
+    args: &Args,
+    state: &mut ClientState,
+    identity: &Identity,
+    credentials: &Credentials,
+    nonce_manager: &NonceManager,
+    ledger: &SpendingLedger,
+    budget: &BudgetLimits,
+) -> Result<()> {
+    let mut session = ChatSession::new(args.model.clone(), args.temperature, args.system_prompt.clone());
+    discover_executors(swarm, args, state, identity).await;
+    
+    println!("💬 Chatting with {} (type /help for commands, /quit to leave)", session.model);
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+        
+        // Keep the swarm alive while waiting for input
+        let line = loop {
+            tokio::select! {
+                line = lines.next_line() => break line?,
+                event = swarm.select_next_some() => {
+                    handle_swarm_event(swarm, event, state, args, identity).await;
+                }
+            }
+        };
+        let Some(line) = line else {
+            break; // EOF
+        };
+        
+        let command = match parse_command(&line) {
+            None => continue,
+            Some(Err(e)) => {
+                warn!("{}", e);
+                continue;
+            }
+            Some(Ok(command)) => command,
+        };
+        
+        match command {
+            ChatCommand::Message(message) => {
+                let mut turn_args = args.clone();
+                turn_args.model = session.model.clone();
+                turn_args.temperature = session.temperature;
+                turn_args.system_prompt = session.system_prompt.clone();
+                turn_args.prompt = Some(session.render_prompt(&message));
+                
+                state.begin_request(RetryPolicy::new(
+                    args.max_attempts,
+                    Duration::from_secs(args.timeout_secs),
+                ));
+                let nonce = nonce_manager.reserve(identity.evm_address).await?;
+                let ctx = RequestContext {
+                    identity,
+                    credentials: credentials.clone(),
+                    ledger,
+                    budget,
+                    nonce,
+                    request_id: uuid::Uuid::new_v4().to_string(),
+                    messages: Some(session.messages_for(&message)),
+                    tools: None,
+                    response_format: None,
+                };
+                
+                let span = request_span(&ctx.request_id);
+                let result = timeout(
+                    Duration::from_secs(args.timeout_secs),
+                    send_request(swarm, &turn_args, state, &ctx)
+                ).instrument(span.clone()).await;
+                let mut served = matches!(&result, Ok(Ok(response)) if response.error.is_none());
+                if result.is_err() {
+                    served = cancel_pending_request(swarm, state, &turn_args, identity, ledger, CancelReason::Timeout)
+                        .instrument(span)
+                        .await;
+                }
+                finish_request(state, ledger, nonce_manager, identity.evm_address, nonce, served).await;
+                
+                match result {
+                    Ok(Ok(response)) => {
+                        report_attempts(&state.failover);
+                        if let Some(error) = &response.error {
+                            error!("Request failed: {}", error);
+                            continue;
+                        }
+                        if args.stream {
+                            println!(); // The content was printed as it arrived
+                        } else {
+                            println!("{}", response.content);
+                        }
+                        if let Err(e) = session.record_exchange(
+                            &message,
+                            &response.content,
+                            response.inbound_tokens,
+                            response.outbound_tokens,
+                            &response.total_cost,
+                        ) {
+                            warn!("Could not account for response cost: {}", e);
+                        }
+                    }
+                    Ok(Err(e)) => {
+                        error!("Request failed: {}", e);
+                        report_attempts(&state.failover);
+                    }
+                    Err(_) => {
+                        error!("Request timed out after {} seconds", args.timeout_secs);
+                        report_attempts(&state.failover);
+                    }
+                }
+            }
+            ChatCommand::Model(model) => {
+                // The current executor may not serve the new model
+                state.preferred_executor = None;
+                println!("Model set to {}", model);
+                session.model = model;
+            }
+            ChatCommand::Temperature(temperature) => {
+                session.temperature = Some(temperature);
+                println!("Temperature set to {}", temperature);
+            }
+            ChatCommand::System(system_prompt) => {
+                match &system_prompt {
+                    Some(prompt) => println!("System prompt set to: {}", prompt),
+                    None => println!("System prompt cleared"),
+                }
+                session.system_prompt = system_prompt;
+            }
+            ChatCommand::Cost => println!("{}", session.cost_summary()),
+            ChatCommand::Save(path) => match session.save(&path) {
+                Ok(()) => println!("Transcript saved to {}", path),
+                Err(e) => warn!("{}", e),
+            },
+            ChatCommand::Load(path) => {
+                let previous_model = session.model.clone();
+                match session.load(&path) {
+                    Ok(()) => {
+                        if session.model != previous_model {
+                            state.preferred_executor = None;
+                        }
+                        println!("Loaded {} messages from {} (model {})", session.turns.len(), path, session.model);
+                    }
+                    Err(e) => warn!("{}", e),
+                }
+            }
+            ChatCommand::Clear => {
+                session.turns.clear();
+                println!("Conversation history cleared");
+            }
+            ChatCommand::Help => println!("{}", HELP_TEXT),
+            ChatCommand::Quit => break,
+        }
+    }
+    
+    println!("Session total: {}", session.cost_summary());
+    Ok(())
+}
+
+/// Embed `input` on the first executor that serves it and print the vectors as JSON
+#[allow(clippy::too_many_arguments)]
+async fn run_embed(
+    swarm: &mut Swarm<LloomBehaviour>,
+    args: &Args,
+    state: &mut ClientState,
+    identity: &Identity,
+    credentials: &Credentials,
+    nonce_manager: &NonceManager,
+    ledger: &SpendingLedger,
+    budget: &BudgetLimits,
+    input: &[String],
+) -> Result<()> {
+    state.begin_request(RetryPolicy::new(
+        args.max_attempts,
+        Duration::from_secs(args.timeout_secs),
+    ));
+    let nonce = nonce_manager.reserve(identity.evm_address).await?;
+    let ctx = RequestContext {
+        identity,
+        credentials: credentials.clone(),
+        ledger,
+        budget,
+        nonce,
+        request_id: uuid::Uuid::new_v4().to_string(),
+        messages: None,
+        tools: None,
+        response_format: None,
+    };
+    info!("Request id: {}", ctx.request_id);
+    
+    let result = timeout(
+        Duration::from_secs(args.timeout_secs),
+        send_embedding_request(swarm, args, state, &ctx, input),
+    ).instrument(request_span(&ctx.request_id)).await;
+    let served = matches!(&result, Ok(Ok(response)) if response.error.is_none());
+    finish_request(state, ledger, nonce_manager, identity.evm_address, nonce, served).await;
+    report_attempts(&state.failover);
+    
+    match result {
+        Ok(Ok(response)) => {
+            if let Some(error) = &response.error {
+                error!("Embedding request failed: {}", error);
+                std::process::exit(1);
+            }
+            println!("{}", format_embeddings(&response));
+            Ok(())
+        }
+        Ok(Err(e)) => {
+            error!("Client error: {}", e);
+            std::process::exit(1);
+        }
+        Err(_) => {
+            error!("Request timed out after {} seconds", args.timeout_secs);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Send an embedding request, failing over between executors until one answers
+async fn send_embedding_request(
+    swarm: &mut Swarm<LloomBehaviour>,
+    args: &Args,
+    state: &mut ClientState,
+    ctx: &RequestContext<'_>,
+    input: &[String],
+) -> Result<EmbeddingResponse> {
+    discover_executors(swarm, args, state, ctx.identity).await;
+    
+    loop {
+        // Executors announcing the model at the lowest prices come first
+        let mut candidates: Vec<PeerId> = state.discovered_executors.iter().copied().collect();
+        state.prices.rank(&mut candidates, &args.model, None);
+        let Some(executor) = state.failover.next_executor(&candidates) else {
+            return Err(anyhow!("No executor could serve the request ({})", state.failover.summary()));
+        };
+        
+        let price_per_token = match embedding_price(swarm, args, state, ctx.identity, executor).await {
+            Ok(price) => price,
+            Err(error) => {
+                // Legacy executors neither announce prices nor give quotes
+                warn!("No prices known for executor {} ({}), offering default prices", executor, error);
+                DEFAULT_INBOUND_PRICE.to_string()
+            }
+        };
+        let mut request = create_embedding_request(
+            args.model.clone(),
+            input.to_vec(),
+            executor.to_string(),
+            price_per_token,
+            ctx.nonce,
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() + 300, // 5 minutes from now
+        );
+        request.request_id = Some(ctx.request_id.clone());
+        request.session = ctx.credentials.session.clone();
+        request.credential = ctx.credentials.membership.clone();
+        
+        // Refuse to sign anything that could exceed the remaining budget
+        let max_tokens = max_embedding_tokens(input);
+        let max_cost = worst_case_cost(Some(max_tokens), &request.price_per_token)?;
+        if let Some(violation) = ctx.ledger.check_budget(ctx.budget, &request.model, &request.executor_address, max_cost)? {
+            if let BudgetScope::Executor(_) = violation.scope {
+                warn!("Skipping executor {}: {}", executor, violation);
+                state.failover.record_failure_with_kind(executor, &violation.to_string(), FailureKind::Retryable);
+                continue;
+            }
+            return Err(anyhow!("Refusing to sign request: {}", violation));
+        }
+        let entry_id = ctx.ledger.record_request(
+            &ctx.request_id,
+            &request.executor_address,
+            &request.model,
+            request.nonce,
+            &request.price_per_token,
+            &request.price_per_token,
+            Some(max_tokens),
+        )?;
+        state.ledger_entry = Some(entry_id.clone());
+        
+        // Embedding requests are always signed, like cancellations
+        let signed_request = request.sign_blocking(ctx.identity)
+            .map_err(|e| anyhow!("Failed to sign embedding request: {}", e))?;
+        info!("Sending embedding request for {} inputs to executor: {}", input.len(), executor);
+        let outbound_id = swarm.behaviour_mut().request_response
+            .send_request(&executor, RequestMessage::EmbeddingRequest(signed_request));
+        
+        let outcome = loop {
+            match swarm.select_next_some().await {
+                SwarmEvent::Behaviour(LloomEvent::RequestResponse(request_response::Event::Message {
+                    message: request_response::Message::Response { response, request_id },
+                    peer,
+                    ..
+                })) if request_id == outbound_id => {
+                    let response = extract_embedding_response(response, peer, args.enable_signing);
+                    // The executor received the request, and its answer does not say what it billed
+                    state.maybe_settled |= response.is_none();
+                    break response.ok_or_else(|| "Unexpected response to embedding request".to_string());
+                }
+                SwarmEvent::Behaviour(LloomEvent::RequestResponse(request_response::Event::OutboundFailure {
+                    request_id, error, ..
+                })) if request_id == outbound_id => {
+                    state.maybe_settled |= !never_delivered(&error);
+                    break Err(format!("Failed to reach executor: {:?}", error));
+                }
+                event => handle_swarm_event(swarm, event, state, args, ctx.identity).await,
+            }
+        };
+        state.ledger_entry = None;
+        
+        let response = match outcome {
+            Ok(response) => response,
+            Err(error) => {
+                warn!("Embedding request to {} failed: {}", executor, error);
+                // Attempts stop once one may have been settled, so this covers the current one
+                ctx.ledger.record_failure(&entry_id, &error, state.maybe_settled)?;
+                state.failover.record_failure_with_kind(executor, &error, FailureKind::Retryable);
+                if !state.may_fail_over() {
+                    return Err(anyhow!("Embedding request to executor {} failed: {}", executor, error));
+                }
+                continue;
+            }
+        };
+        state.maybe_settled |= response.input_tokens > 0;
+        ctx.ledger.record_response(&entry_id, response.input_tokens, 0, &response.total_cost, response.error.clone())?;
+        let Some(error) = response.error.clone() else {
+            state.failover.record_success(executor);
+            return Ok(response);
+        };
+        let kind = classify_failure(&error, response.error_code);
+        state.failover.record_failure_with_kind(executor, &error, kind);
+        if kind == FailureKind::Fatal || !state.may_fail_over() {
+            return Ok(response);
+        }
+        warn!("Executor {} failed with retryable error: {}; trying another executor", executor, error);
+    }
+}
+
+/// Print today's spending totals alongside the configured limits
+fn display_spending(summary: &SpendingSummary, budget: &BudgetLimits) {
+    println!("Spending today (UTC): {} wei across {} requests", summary.total, summary.requests);
+    if let Some(limit) = &budget.daily_limit {
+        println!("Daily limit: {} wei", limit);
+    }
+    
+    let mut models: Vec<_> = summary.by_model.iter().collect();
+    models.sort();
+    for (model, spent) in models {
+        match budget.per_model.get(model) {
+            Some(limit) => println!("  model {}: {} wei (limit {} wei)", model, spent, limit),
+            None => println!("  model {}: {} wei", model, spent),
+        }
+    }
+    
+    let mut executors: Vec<_> = summary.by_executor.iter().collect();
+    executors.sort();
+    for (executor, spent) in executors {
+        match budget.per_executor.get(executor) {
+            Some(limit) => println!("  executor {}: {} wei (limit {} wei)", executor, spent, limit),
+            None => println!("  executor {}: {} wei", executor, spent),
+        }
+    }
+    
+    if summary.cancelled > 0 {
+        println!("{} requests were cancelled", summary.cancelled);
+    }
+    
+    if summary.cost_mismatches > 0 {
+        println!("⚠️  {} responses charged a cost that differs from the committed prices", summary.cost_mismatches);
+    }
+}
+
+/// Log the executors tried for the request when more than one was needed or all failed
+fn report_attempts(failover: &FailoverTracker) {
+    let attempts = failover.attempts();
+    let succeeded = attempts.last()
+        .is_some_and(|attempt| attempt.outcome == lloom_client::retry::AttemptOutcome::Success);
+    if !succeeded {
+        warn!("Executors tried:\n{}", failover.summary());
+    } else if attempts.len() > 1 {
+        info!("Executors tried:\n{}", failover.summary());
+    }
+}
+
+/// Per-request context shared by every failover attempt
+struct RequestContext<'a> {
+    identity: &'a Identity,
+    /// Certificates attached to LLM and embedding requests
+    credentials: Credentials,
+    ledger: &'a SpendingLedger,
+    budget: &'a BudgetLimits,
+    /// One nonce per logical request, shared by every failover attempt until
+    /// one may have been settled
+    nonce: u64,
+    /// Client-generated id of the logical request, shared by every failover attempt
+    request_id: String,
+    /// Full conversation to send instead of the flattened prompt
+    messages: Option<Vec<ChatMessage>>,
+    /// Tools the model may call
+    tools: Option<Vec<Tool>>,
+    /// Format the output must follow
+    response_format: Option<ResponseFormat>,
+}
+
+/// Main client logic
+async fn run_client(
+    swarm: &mut Swarm<LloomBehaviour>,
+    args: &Args,
+    state: &mut ClientState,
+    ctx: &RequestContext<'_>,
+) -> Result<LlmResponse> {
+    discover_executors(swarm, args, state, ctx.identity).await;
+    send_request(swarm, args, state, ctx).await
+}
+
+/// Phase 1: find executors through the DHT and gossipsub
+async fn discover_executors(
+    swarm: &mut Swarm<LloomBehaviour>,
+    args: &Args,
+    state: &mut ClientState,
+    identity: &Identity,
+) {
+    info!("Phase 1: Discovering executors...");
+    
+    // Wait longer for initial connections and DHT to stabilize
+    info!("DEBUG: Waiting for DHT to stabilize...");
+    sleep(Duration::from_secs(5)).await;
+    
+    // Bootstrap Kademlia DHT first
+    info!("DEBUG: Bootstrapping Kademlia DHT...");
+    if let Err(e) = swarm.behaviour_mut().kademlia.bootstrap() {
+        warn!("DEBUG: Failed to bootstrap Kademlia: {:?}", e);
+    }
+    
+    // Wait for bootstrap to complete
+    sleep(Duration::from_secs(3)).await;
+    
+    // Query for executors multiple times with delays - more attempts with longer intervals
+    let executor_key = ServiceRole::Executor.to_kad_key();
+    info!("DEBUG: Looking for executors using key: {:?} (as string: {})", executor_key, String::from_utf8_lossy(&executor_key));
+    
+    for attempt in 1..=8 {
+        info!("DEBUG: Attempt {}/8: Starting provider query for executor key", attempt);
+        swarm.behaviour_mut().kademlia.get_providers(executor_key.clone().into());
+        
+        // Also try to get records for backwards compatibility
+        info!("DEBUG: Attempt {}/8: Starting record query for executor key", attempt);
+        swarm.behaviour_mut().kademlia.get_record(executor_key.clone().into());
+        
+        // Process events immediately after each query
+        for _ in 0..10 {
+            if let Ok(event) = tokio::time::timeout(Duration::from_millis(100), swarm.select_next_some()).await {
+                handle_swarm_event(swarm, event, state, args, identity).await;
+                if !state.discovered_executors.is_empty() {
+                    info!("DEBUG: Early discovery success - found {} executors", state.discovered_executors.len());
+                    break;
+                }
+            }
+        }
+        
+        if !state.discovered_executors.is_empty() {
+            break;
+        }
+        
+        if attempt < 8 {
+            info!("DEBUG: Waiting 2 seconds before next attempt...");
+            sleep(Duration::from_secs(2)).await;
+        }
+    }
+    
+    info!("DEBUG: Completed all discovery attempts, found {} executors so far",
+          state.discovered_executors.len());
+}
+
+/// Phases 2 and 3: send the request, failing over between executors until it settles
+async fn send_request(
+    swarm: &mut Swarm<LloomBehaviour>,
+    args: &Args,
+    state: &mut ClientState,
+    ctx: &RequestContext<'_>,
+) -> Result<LlmResponse> {
+    let mut discovery_timeout = tokio::time::interval(Duration::from_secs(60));
+    discovery_timeout.tick().await; // Skip first immediate tick
+    let (stream_tx, mut stream_rx) = mpsc::unbounded_channel();
+    
+    loop {
+        // Settle the outcome of the in-flight attempt, failing over when allowed
+        if let Some(response) = settle_attempt(state, ctx.ledger)? {
+            return Ok(response);
+        }
+        
+        // Check if we found executors and can proceed
+        if !state.discovered_executors.is_empty() && !state.discovery_complete {
+            dispatch_request(swarm, args, state, ctx, &stream_tx).await?;
+        }
+        
+        tokio::select! {
+            event = swarm.select_next_some() => {
+                handle_swarm_event(swarm, event, state, args, ctx.identity).await;
+            }
+            Some((stream_id, event)) = stream_rx.recv() => {
+                handle_stream_event(state, args, stream_id, event);
+            }
+            _ = discovery_timeout.tick() => {
+                if state.discovered_executors.is_empty() {
+                    error!("DEBUG: ❌ DISCOVERY TIMEOUT - No executors found after 60 seconds");
+                    error!("DEBUG: This suggests either:");
+                    error!("DEBUG: 1. No executors are running or registered");
+                    error!("DEBUG: 2. Network connectivity issues prevent discovery");
+                    error!("DEBUG: 3. Executors are not advertising the '{}' model", args.model);
+                    return Err(anyhow!("No executors found after discovery timeout"));
+                } else {
+                    info!("DEBUG: Discovery timeout reached but we have {} executors", state.discovered_executors.len());
+                }
+            }
+        }
+    }
+}
+
+/// Sign and send the request to the next-best executor that fits the budget
+async fn dispatch_request(
+    swarm: &mut Swarm<LloomBehaviour>,
+    args: &Args,
+    state: &mut ClientState,
+    ctx: &RequestContext<'_>,
+    stream_tx: &mpsc::UnboundedSender<(u64, StreamEvent)>,
+) -> Result<()> {
+    info!("Phase 2: Found {} executors, selecting one...", state.discovered_executors.len());
+    
+    // Prefer the executor that served the previous request while it stays healthy,
+    // then those announcing the model at the lowest prices
+    let mut candidates: Vec<PeerId> = state.discovered_executors.iter().copied().collect();
+    state.prices.rank(&mut candidates, &args.model, state.preferred_executor);
+    
+    loop {
+        // Select the next-best executor that has not been tried yet
+        let Some(selected_executor) = state.failover.next_executor(&candidates) else {
+            if !state.failover.can_attempt() {
+                return Err(anyhow!("Retry budget exhausted after {} attempts", state.failover.attempts().len()));
+            }
+            // Every known executor has been tried; wait for new ones to be discovered
+            debug!("All {} known executors tried, waiting for discovery", candidates.len());
+            return Ok(());
+        };
+        
+        // Prepare LLM request
+        let sampling = sampling_params(args);
+        let mut request = LlmRequest {
+            model: args.model.clone(),
+            prompt: args.prompt.as_ref().unwrap().clone(),
+            system_prompt: args.system_prompt.clone(),
+            temperature: args.temperature,
+            max_tokens: args.max_tokens,
+            executor_address: selected_executor.to_string(),
+            inbound_price: DEFAULT_INBOUND_PRICE.to_string(),
+            outbound_price: DEFAULT_OUTBOUND_PRICE.to_string(),
+            nonce: ctx.nonce,
+            deadline: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() + 300, // 5 minutes from now
+            messages: ctx.messages.clone(),
+            // Legacy executors only understand version 1, so plain prompts keep using it
+            version: if ctx.messages.is_some() || ctx.tools.is_some() || ctx.response_format.is_some() || !sampling.is_empty() {
+                LLM_REQUEST_VERSION
+            } else {
+                LEGACY_LLM_REQUEST_VERSION
+            },
+            request_id: Some(ctx.request_id.clone()),
+            tools: ctx.tools.clone(),
+            tool_choice: None,
+            response_format: ctx.response_format.clone(),
+            sampling,
+            quote_id: None,
+            session: ctx.credentials.session.clone(),
+            credential: ctx.credentials.membership.clone(),
+        };
+        
+        // Offer the prices the executor announced; without them, ask for a quote
+        let known_prices = if args.quote { None } else { state.prices.get(&selected_executor, &request.model).cloned() };
+        if let Some(pricing) = &known_prices {
+            request.inbound_price = pricing.input_token_price.clone();
+            request.outbound_price = pricing.output_token_price.clone();
+        }
+        
+        // Every choice may use up to max_tokens
+        let choices = alloy::primitives::U256::from(request.sampling.n.unwrap_or(1));
+        let mut max_cost = worst_case_cost(request.max_tokens, &request.outbound_price)? * choices;
+        if known_prices.is_none() {
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+            let quoted = fetch_quote(swarm, args, state, ctx.identity, selected_executor, create_quote_request(&request)).await
+                .and_then(|quote| apply_quote(&mut request, &quote, now).map_err(|e| e.to_string()).map(|_| quote));
+            match quoted {
+                Ok(quote) => {
+                    info!("Executor {} quoted {} prompt tokens, at most {} wei", selected_executor, quote.prompt_tokens, quote.max_cost);
+                    // The executor never bills more than it quoted
+                    max_cost = quote.max_cost.parse().map_err(|e| anyhow!("Invalid quoted cost {}: {}", quote.max_cost, e))?;
+                }
+                Err(error) if args.quote => {
+                    warn!("No quote from executor {}: {}", selected_executor, error);
+                    state.failover.record_failure_with_kind(selected_executor, &error, FailureKind::Retryable);
+                    continue;
+                }
+                Err(error) => {
+                    // Legacy executors neither announce prices nor give quotes
+                    warn!("No prices known for executor {} ({}), offering default prices", selected_executor, error);
+                }
+            }
+        }
+        
+        // Refuse to sign anything that could exceed the remaining budget
+        if let Some(violation) = ctx.ledger.check_budget(ctx.budget, &request.model, &request.executor_address, max_cost)? {
+            if let BudgetScope::Executor(_) = violation.scope {
+                // Another executor may still fit the budget
+                warn!("Skipping executor {}: {}", selected_executor, violation);
+                state.failover.record_failure_with_kind(selected_executor, &violation.to_string(), FailureKind::Retryable);
+                continue;
+            }
+            return Err(anyhow!("Refusing to sign request: {}", violation));
+        }
+        state.ledger_entry = Some(ctx.ledger.record_request(
+            &ctx.request_id,
+            &request.executor_address,
+            &request.model,
+            request.nonce,
+            &request.inbound_price,
+            &request.outbound_price,
+            request.max_tokens,
+        )?);
+        
+        info!("Phase 3: Sending request to executor: {}", selected_executor);
+        let request_id = request.id();
+        
+        // Send the request (with or without signing based on configuration)
+        let request_message = if args.enable_signing {
+            // Sign the request before sending
+            match request.sign_blocking(ctx.identity) {
+                Ok(signed_request) => {
+                    info!("Successfully signed request with timestamp: {}", signed_request.timestamp);
+                    RequestMessage::SignedLlmRequest(signed_request)
+                }
+                Err(e) => {
+                    error!("Failed to sign request: {}, falling back to unsigned", e);
+                    RequestMessage::LlmRequest(request)
+                }
+            }
+        } else {
+            info!("Signing disabled, sending unsigned request");
+            RequestMessage::LlmRequest(request)
+        };
+        
+        let attempt = if args.stream {
+            state.streams_opened += 1;
+            let stream_id = state.streams_opened;
+            let mut control = swarm.behaviour().stream.new_control();
+            let events = stream_tx.clone();
+            let (cancel, cancelled) = oneshot::channel();
+            let handle = tokio::spawn(async move {
+                let chunks = events.clone();
+                let cancelled = async {
+                    if cancelled.await.is_err() {
+                        // Dropping the handle leaves the stream running
+                        std::future::pending::<()>().await;
+                    }
+                };
+                let result = request_llm_stream(&mut control, selected_executor, &request_message, |chunk| {
+                    let _ = chunks.send((stream_id, StreamEvent::Chunk(chunk.to_string())));
+                }, cancelled).await;
+                let response = result.as_ref().ok().cloned();
+                let _ = events.send((stream_id, StreamEvent::Done(result.map(Box::new).map_err(|e| e.to_string()))));
+                response
+            });
+            state.stream_task = Some(StreamTask { cancel, handle });
+            PendingAttempt::Stream(stream_id)
+        } else {
+            PendingAttempt::Request(swarm.behaviour_mut().request_response.send_request(&selected_executor, request_message))
+        };
+        state.pending_request = Some((attempt, selected_executor));
+        state.pending_request_id = Some(request_id);
+        state.stream_printed = false;
+        state.discovery_complete = true;
+        return Ok(());
+    }
+}
+
+/// Price per input token `executor` charges for embeddings with the requested model.
+///
+/// Embedding requests cannot accept a quote, so unless the executor announced
+/// its prices, a quote for an empty prompt is only used to learn them.
+async fn embedding_price(
+    swarm: &mut Swarm<LloomBehaviour>,
+    args: &Args,
+    state: &mut ClientState,
+    identity: &Identity,
+    executor: PeerId,
+) -> std::result::Result<String, String> {
+    if let Some(pricing) = state.prices.get(&executor, &args.model) {
+        return Ok(pricing.input_token_price.clone());
+    }
+    let request = QuoteRequest {
+        model: args.model.clone(),
+        messages: Vec::new(),
+        max_tokens: Some(0),
+        executor_address: executor.to_string(),
+    };
+    let quote = fetch_quote(swarm, args, state, identity, executor, request).await?;
+    match quote.error {
+        Some(error) => Err(format!("Executor refused to quote: {}", error)),
+        None => Ok(quote.inbound_price),
+    }
+}
+
+/// Ask `executor` for a signed quote, remembering the prices it quotes
+async fn fetch_quote(
+    swarm: &mut Swarm<LloomBehaviour>,
+    args: &Args,
+    state: &mut ClientState,
+    identity: &Identity,
+    executor: PeerId,
+    request: QuoteRequest,
+) -> std::result::Result<Quote, String> {
+    // Quote requests are always signed, so the quote is tied to this client
+    let signed_request = request.sign_blocking(identity)
+        .map_err(|e| format!("Failed to sign quote request: {}", e))?;
+    let outbound_id = swarm.behaviour_mut().request_response
+        .send_request(&executor, RequestMessage::QuoteRequest(signed_request));
+    loop {
+        match swarm.select_next_some().await {
+            SwarmEvent::Behaviour(LloomEvent::RequestResponse(request_response::Event::Message {
+                message: request_response::Message::Response { response, request_id },
+                peer,
+                ..
+            })) if request_id == outbound_id => {
+                let ResponseMessage::Quote(signed_quote) = response else {
+                    return Err("Unexpected response to quote request".to_string());
+                };
+                if args.enable_signing {
+                    if let Err(e) = signed_quote.verify_with_time_window(MAX_MESSAGE_AGE_SECS) {
+                        return Err(format!("Quote signature verification failed: {}", e));
+                    }
+                }
+                info!("Received quote {} from {}", signed_quote.payload.quote_id, peer);
+                let quote = signed_quote.payload;
+                if quote.error.is_none() {
+                    state.prices.record_quote(peer, &quote.model, ModelPricing {
+                        input_token_price: quote.inbound_price.clone(),
+                        output_token_price: quote.outbound_price.clone(),
+                        minimum_fee: None,
+                    });
+                }
+                return Ok(quote);
+            }
+            SwarmEvent::Behaviour(LloomEvent::RequestResponse(request_response::Event::OutboundFailure {
+                request_id, error, ..
+            })) if request_id == outbound_id => {
+                return Err(format!("Failed to reach executor: {:?}", error));
+            }
+            event => handle_swarm_event(swarm, event, state, args, identity).await,
+        }
+    }
+}
+
+/// Settle the outcome of the current attempt.
+///
+/// Returns the response to hand back to the user, or `None` when the request is
+/// still in flight or has been scheduled for another executor.
+fn settle_attempt(state: &mut ClientState, ledger: &SpendingLedger) -> Result<Option<LlmResponse>> {
+    let Some((_, executor)) = state.pending_request else {
+        return Ok(None);
+    };
+    
+    if let Some(response) = state.response_received.take() {
+        state.pending_request = None;
+        state.maybe_settled |= response.inbound_tokens + response.outbound_tokens > 0;
+        if let Some(entry_id) = state.ledger_entry.take() {
+            // Cost mismatches are flagged and logged by the ledger
+            ledger.record_response(
+                &entry_id,
+                response.inbound_tokens,
+                response.outbound_tokens,
+                &response.total_cost,
+                response.error.clone(),
+            )?;
+        }
+        let Some(error) = response.error.clone() else {
+            state.failover.record_success(executor);
+            state.preferred_executor = Some(executor);
+            return Ok(Some(response));
+        };
+        
+        let kind = classify_response(&response);
+        state.failover.record_failure_with_kind(executor, &error, kind);
+        state.forget_preferred(&executor);
+        if kind == FailureKind::Fatal || !state.may_fail_over() {
+            return Ok(Some(response));
+        }
+        warn!("Executor {} failed with retryable error: {}; trying another executor", executor, error);
+        state.discovery_complete = false;
+    } else if let Some(error) = state.outbound_failure.take() {
+        state.pending_request = None;
+        if state.stream_printed {
+            // End the partial output before anything else is printed
+            println!();
+            warn!("Stream from {} interrupted", executor);
+        }
+        if let Some(entry_id) = state.ledger_entry.take() {
+            // Attempts stop once one may have been settled, so this covers the current one
+            ledger.record_failure(&entry_id, &error, state.maybe_settled)?;
+        }
+        state.failover.record_failure_with_kind(executor, &error, FailureKind::Retryable);
+        state.forget_preferred(&executor);
+        if !state.may_fail_over() {
+            return Err(anyhow!("Request to executor {} failed: {}", executor, error));
+        }
+        warn!("Request to executor {} failed: {}; trying another executor", executor, error);
+        state.discovery_complete = false;
+    }
+    
+    Ok(None)
+}
+
+/// Handle swarm events
+async fn handle_swarm_event(
     _swarm: &mut Swarm<LloomBehaviour>,
     event: SwarmEvent<LloomEvent>,
     state: &mut ClientState,
@@ -861,8 +2187,9 @@ async fn handle_swarm_event(
             result: kad::QueryResult::GetRecord(Ok(kad::GetRecordOk::FoundRecord(record))),
             ..
         })) => {
-            if record.record.key.as_ref() == ServiceRole::Executor.to_kad_key() {
-                if let Ok(peer_id) = libp2p::PeerId::from_bytes(&record.record.value) {
+            let record = record.record;
+            if record.key.as_ref() == ServiceRole::Executor.to_kad_key() {
+                if let Ok(peer_id) = libp2p::PeerId::from_bytes(&record.value) {
                     if state.discovered_executors.insert(peer_id) {
                         info!("DEBUG: Discovered executor from record: {}", peer_id);
                     }
@@ -882,53 +2209,9 @@ async fn handle_swarm_event(
                 connection_id: _,
             }
         )) => {
-            if let Some((pending_id, expected_peer)) = &state.pending_request {
+            if let Some((PendingAttempt::Request(pending_id), expected_peer)) = &state.pending_request {
                 if request_id == *pending_id && peer == *expected_peer {
-                    // Handle both signed and unsigned responses
-                    let llm_response = match &response {
-                        ResponseMessage::LlmResponse(resp) => {
-                            info!("Received unsigned response from {}: {} inbound + {} outbound tokens",
-                                  peer, resp.inbound_tokens, resp.outbound_tokens);
-                            Some(resp.clone())
-                        }
-                        ResponseMessage::SignedLlmResponse(signed_resp) => {
-                            info!("Received signed response from {}", peer);
-                            
-                            // Verify the signature if signing is enabled
-                            if args.enable_signing {
-                                match signed_resp.verify_with_time_window(MAX_MESSAGE_AGE_SECS) {
-                                    Ok(signer_address) => {
-                                        info!("✓ Response signature verified from signer: {}", signer_address);
-                                        info!("Response content: {} inbound + {} outbound tokens",
-                                              signed_resp.payload.inbound_tokens, signed_resp.payload.outbound_tokens);
-                                        Some(signed_resp.payload.clone())
-                                    }
-                                    Err(e) => {
-                                        error!("✗ Response signature verification failed: {}", e);
-                                        warn!("Response may be tampered with or from untrusted source");
-                                        // Still process the response but log the security issue
-                                        info!("Processing unverified response: {} inbound + {} outbound tokens",
-                                              signed_resp.payload.inbound_tokens, signed_resp.payload.outbound_tokens);
-                                        Some(signed_resp.payload.clone())
-                                    }
-                                }
-                            } else {
-                                info!("Signature verification disabled, processing response: {} inbound + {} outbound tokens",
-                                      signed_resp.payload.inbound_tokens, signed_resp.payload.outbound_tokens);
-                                Some(signed_resp.payload.clone())
-                            }
-                        }
-                        ResponseMessage::ModelQueryResponse(_) => {
-                            debug!("Received model query response from {}", peer);
-                            None // Not handled by client
-                        }
-                        ResponseMessage::AcknowledgmentResponse(_) => {
-                            debug!("Received acknowledgment response from {}", peer);
-                            None // Not handled by client
-                        }
-                    };
-                    
-                    if let Some(resp) = llm_response {
+                    if let Some(resp) = extract_llm_response(&response, peer, args.enable_signing) {
                         state.response_received = Some(resp);
                     }
                 }
@@ -937,13 +2220,12 @@ async fn handle_swarm_event(
         SwarmEvent::Behaviour(LloomEvent::RequestResponse(
             request_response::Event::OutboundFailure { request_id, error, peer, .. }
         )) => {
-            if let Some((pending_id, expected_peer)) = &state.pending_request {
+            if let Some((PendingAttempt::Request(pending_id), expected_peer)) = &state.pending_request {
                 if request_id == *pending_id && peer == *expected_peer {
                     error!("Request failed: {:?}", error);
-                    // Try next executor if available
-                    state.discovered_executors.remove(expected_peer);
-                    state.pending_request = None;
-                    state.discovery_complete = false; // Allow retry with different executor
+                    state.maybe_settled |= !never_delivered(&error);
+                    // The failover tracker decides whether to try another executor
+                    state.outbound_failure = Some(error.to_string());
                 }
             }
         }
@@ -962,11 +2244,148 @@ async fn handle_swarm_event(
                     }
                 }
             }
+            
+            // Model announcements carry the prices each executor charges
+            if message.topic.as_str() == "lloom/model-announcements" {
+                match serde_json::from_slice::<SignedModelAnnouncement>(&message.data) {
+                    Ok(signed_announcement) => {
+                        let announcement = &signed_announcement.payload;
+                        // Only the executor itself may announce its prices
+                        let Some(source) = message.source.filter(|source| source.to_string() == announcement.executor_peer_id) else {
+                            warn!("Ignoring model announcement for {} published by {:?}", announcement.executor_peer_id, message.source);
+                            return;
+                        };
+                        if let Err(e) = signed_announcement.verify_with_time_window(MAX_MESSAGE_AGE_SECS) {
+                            warn!("Ignoring model announcement from {}: {}", source, e);
+                            return;
+                        }
+                        debug!("Executor {} announced {} models ({:?})", source, announcement.models.len(), announcement.announcement_type);
+                        state.prices.record_announcement(source, announcement);
+                    }
+                    Err(e) => debug!("Failed to parse model announcement: {}", e),
+                }
+            }
         }
         _ => {}
     }
 }
 
+/// Handle progress of a streamed attempt
+fn handle_stream_event(state: &mut ClientState, args: &Args, stream_id: u64, event: StreamEvent) {
+    let Some((PendingAttempt::Stream(pending_id), peer)) = state.pending_request else {
+        return;
+    };
+    if stream_id != pending_id {
+        return; // Left over from an abandoned attempt
+    }
+    match event {
+        StreamEvent::Chunk(chunk) => {
+            print!("{}", chunk);
+            let _ = std::io::stdout().flush();
+            state.stream_printed = true;
+        }
+        StreamEvent::Done(Ok(response)) => {
+            match extract_llm_response(&response, peer, args.enable_signing) {
+                Some(resp) => state.response_received = Some(resp),
+                None => {
+                    state.maybe_settled = true;
+                    state.outbound_failure = Some("Stream ended with an unexpected message".to_string());
+                }
+            }
+        }
+        StreamEvent::Done(Err(error)) => {
+            error!("Stream request failed: {}", error);
+            // The request may have been written before the stream broke
+            state.maybe_settled = true;
+            state.outbound_failure = Some(error);
+        }
+    }
+}
+
+/// Whether an outbound failure proves the request never reached the executor
+fn never_delivered(error: &request_response::OutboundFailure) -> bool {
+    matches!(
+        error,
+        request_response::OutboundFailure::DialFailure | request_response::OutboundFailure::UnsupportedProtocols
+    )
+}
+
+/// Extract the LLM response from a response message, verifying its signature if enabled
+fn extract_llm_response(response: &ResponseMessage, peer: PeerId, enable_signing: bool) -> Option<LlmResponse> {
+    match response {
+        ResponseMessage::LlmResponse(resp) => {
+            info!("Received unsigned response from {}: {} inbound + {} outbound tokens",
+                  peer, resp.inbound_tokens, resp.outbound_tokens);
+            Some(resp.clone())
+        }
+        ResponseMessage::SignedLlmResponse(signed_resp) => {
+            info!("Received signed response from {}", peer);
+            
+            // Verify the signature if signing is enabled
+            if enable_signing {
+                match signed_resp.verify_with_time_window(MAX_MESSAGE_AGE_SECS) {
+                    Ok(signer_address) => {
+                        info!("✓ Response signature verified from signer: {}", signer_address);
+                        info!("Response content: {} inbound + {} outbound tokens",
+                              signed_resp.payload.inbound_tokens, signed_resp.payload.outbound_tokens);
+                        Some(signed_resp.payload.clone())
+                    }
+                    Err(e) => {
+                        error!("✗ Response signature verification failed: {}", e);
+                        warn!("Response may be tampered with or from untrusted source");
+                        // Still process the response but log the security issue
+                        info!("Processing unverified response: {} inbound + {} outbound tokens",
+                              signed_resp.payload.inbound_tokens, signed_resp.payload.outbound_tokens);
+                        Some(signed_resp.payload.clone())
+                    }
+                }
+            } else {
+                info!("Signature verification disabled, processing response: {} inbound + {} outbound tokens",
+                      signed_resp.payload.inbound_tokens, signed_resp.payload.outbound_tokens);
+                Some(signed_resp.payload.clone())
+            }
+        }
+        ResponseMessage::EmbeddingResponse(_) => {
+            debug!("Received embedding response from {}", peer);
+            None // Answered in send_embedding_request
+        }
+        ResponseMessage::Quote(_) => {
+            debug!("Received quote from {}", peer);
+            None // Answered in fetch_quote
+        }
+        ResponseMessage::ModelQueryResponse(_) => {
+            debug!("Received model query response from {}", peer);
+            None // Not handled by client
+        }
+        ResponseMessage::AcknowledgmentResponse(_) => {
+            debug!("Received acknowledgment response from {}", peer);
+            None // Not handled by client
+        }
+        ResponseMessage::Unknown => {
+            warn!("Received a response from {} this client does not know", peer);
+            None
+        }
+    }
+}
+
+/// Extract the embedding response, verifying its signature if signing is enabled
+fn extract_embedding_response(response: ResponseMessage, peer: PeerId, enable_signing: bool) -> Option<EmbeddingResponse> {
+    let ResponseMessage::EmbeddingResponse(signed_response) = response else {
+        return None;
+    };
+    if enable_signing {
+        match signed_response.verify_with_time_window(MAX_MESSAGE_AGE_SECS) {
+            Ok(signer_address) => info!("✓ Embedding response signature verified from signer: {}", signer_address),
+            Err(e) => {
+                error!("✗ Embedding response signature verification failed: {}", e);
+                warn!("Response may be tampered with or from untrusted source");
+            }
+        }
+    }
+    info!("Received embedding response from {}: {} input tokens", peer, signed_response.payload.input_tokens);
+    Some(signed_response.payload)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -977,7 +2396,7 @@ mod tests {
     #[test]
     fn test_args_parsing() {
         // Test minimal required args
-        let args = Args::try_parse_from(&[
+        let args = Args::try_parse_from([
             "client",
             "--bootstrap-nodes", "/ip4/127.0.0.1/tcp/9000",
             "--prompt", "Hello world"
@@ -996,7 +2415,7 @@ mod tests {
 
     #[test]
     fn test_args_parsing_full() {
-        let args = Args::try_parse_from(&[
+        let args = Args::try_parse_from([
             "client",
             "--bootstrap-nodes", "/ip4/127.0.0.1/tcp/9000,/ip4/192.168.1.1/tcp/8000",
             "--prompt", "Test prompt",
@@ -1025,27 +2444,92 @@ mod tests {
     #[test]
     fn test_args_missing_required() {
         // Missing prompt (should succeed because prompt is optional at parse time)
-        let result = Args::try_parse_from(&[
+        let result = Args::try_parse_from([
             "client",
             "--bootstrap-nodes", "/ip4/127.0.0.1/tcp/9000"
         ]);
         assert!(result.is_ok());
         
         // Missing bootstrap nodes (should succeed because bootstrap nodes can come from config)
-        let result = Args::try_parse_from(&[
+        let result = Args::try_parse_from([
             "client",
             "--prompt", "Hello world"
         ]);
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_client_config_optional_sections() {
+        let config: ClientConfig = toml::from_str(r#"
+            [identity]
+            private_key = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+
+            [network]
+            bootstrap_nodes = ["/ip4/127.0.0.1/tcp/9000"]
+
+            [blockchain]
+            rpc_url = "https://sepolia.base.org"
+            contract_address = "0x25e8c5878DdaA22d1753a9223f948B61AeAf47E6"
+
+            [budget]
+            daily_limit = "1000000000000000000"
+
+            [budget.per_model]
+            "gpt-4" = "500000000000000000"
+        "#).unwrap();
+        assert_eq!(config.budget.daily_limit.as_deref(), Some("1000000000000000000"));
+        assert_eq!(config.budget.per_model["gpt-4"], "500000000000000000");
+        assert!(config.budget.validate().is_ok());
+        let blockchain = config.blockchain.unwrap();
+        assert_eq!(blockchain.rpc_url, "https://sepolia.base.org");
+        assert_eq!(blockchain.contract_address, "0x25e8c5878DdaA22d1753a9223f948B61AeAf47E6");
+
+        // The section is optional
+        let config: ClientConfig = toml::from_str(r#"
+            [identity]
+            private_key = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+
+            [network]
+            bootstrap_nodes = []
+        "#).unwrap();
+        assert!(config.blockchain.is_none());
+        assert!(config.budget.is_empty());
+
+        // A keystore can replace the plaintext key
+        let config: ClientConfig = toml::from_str(r#"
+            [identity]
+            keystore = "client-keystore.json"
+
+            [network]
+            bootstrap_nodes = []
+        "#).unwrap();
+        assert_eq!(config.identity.keystore.as_deref(), Some("client-keystore.json"));
+        assert!(config.identity.private_key.is_none());
+
+        // So can a session key and an org credential
+        let config: ClientConfig = toml::from_str(r#"
+            [identity]
+            session = "session.json"
+            credential = "credential.json"
+
+            [network]
+            bootstrap_nodes = []
+        "#).unwrap();
+        assert_eq!(config.identity.session.as_deref(), Some("session.json"));
+        assert_eq!(config.identity.credential.as_deref(), Some("credential.json"));
+    }
+
     #[test]
     fn test_client_state_default() {
         let state = ClientState::default();
         assert!(state.discovered_executors.is_empty());
         assert_eq!(state.pending_request, None);
         assert_eq!(state.response_received, None);
+        assert_eq!(state.outbound_failure, None);
+        assert_eq!(state.ledger_entry, None);
         assert!(!state.discovery_complete);
+        assert!(state.failover.attempts().is_empty());
+        assert_eq!(state.preferred_executor, None);
     }
 
     #[test]
@@ -1072,6 +2556,10 @@ mod tests {
             total_cost: "10000000000000000".to_string(),
             model_used: "gpt-3.5-turbo".to_string(),
             error: None,
+            request_id: None,
+            error_code: None,
+            tool_calls: None,
+            choices: None,
         };
         state.response_received = Some(response.clone());
         assert_eq!(state.response_received, Some(response));
@@ -1106,11 +2594,9 @@ mod tests {
 
     #[test]
     fn test_bootstrap_addr_parsing() {
-        let valid_addrs = vec![
-            "/ip4/127.0.0.1/tcp/9000".to_string(),
+        let valid_addrs = ["/ip4/127.0.0.1/tcp/9000".to_string(),
             "/ip4/192.168.1.1/tcp/8000".to_string(),
-            "/ip6/::1/tcp/9000".to_string(),
-        ];
+            "/ip6/::1/tcp/9000".to_string()];
         
         let result: Result<Vec<libp2p::Multiaddr>, _> = valid_addrs
             .iter()
@@ -1124,10 +2610,8 @@ mod tests {
 
     #[test]
     fn test_bootstrap_addr_parsing_invalid() {
-        let invalid_addrs = vec![
-            "invalid-addr".to_string(),
-            "http://localhost:9000".to_string(),
-        ];
+        let invalid_addrs = ["invalid-addr".to_string(),
+            "http://localhost:9000".to_string()];
         
         let result: Result<Vec<libp2p::Multiaddr>, _> = invalid_addrs
             .iter()
@@ -1150,6 +2634,7 @@ mod tests {
             outbound_price: "1000000000000000".to_string(),
             nonce: 1,
             deadline: 1234567890,
+            ..Default::default()
         };
         
         assert_eq!(request.model, "gpt-4");
@@ -1168,6 +2653,10 @@ mod tests {
             total_cost: "25000000000000000".to_string(),
             model_used: "gpt-3.5-turbo".to_string(),
             error: None,
+            request_id: None,
+            error_code: None,
+            tool_calls: None,
+            choices: None,
         };
         
         assert_eq!(response.content, "Generated text");
@@ -1184,6 +2673,10 @@ mod tests {
             total_cost: "0".to_string(),
             model_used: "gpt-3.5-turbo".to_string(),
             error: Some("API error".to_string()),
+            request_id: None,
+            error_code: None,
+            tool_calls: None,
+            choices: None,
         };
         
         assert!(error_response.error.is_some());
@@ -1223,18 +2716,44 @@ mod tests {
         let args = Args {
             config: None,
             private_key: None,
+            keystore: None,
+            signer_socket: None,
+            signature_version: SIGNATURE_VERSION,
+            session: None,
+            credential: None,
             bootstrap_nodes: vec!["/ip4/127.0.0.1/tcp/9000".to_string()],
             model: "gpt-3.5-turbo".to_string(),
             prompt: Some("Hello".to_string()),
             system_prompt: None,
             temperature: None,
             max_tokens: None,
+            top_p: None,
+            top_k: None,
+            stop: Vec::new(),
+            seed: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            n: None,
             timeout_secs: 120,
+            max_attempts: 3,
+            data_dir: None,
+            rpc_url: None,
+            contract_address: None,
+            resync_nonce: false,
+            daily_budget: None,
+            show_spending: false,
+            stream: false,
+            quote: false,
+            tools: None,
+            image: vec![],
+            json: false,
+            json_schema: None,
             debug: false,
             enable_signing: true,
             discover_models: false,
             query_model: None,
             demo: false,
+            command: None,
         };
         
         let debug_str = format!("{:?}", args);
@@ -1243,9 +2762,319 @@ mod tests {
         assert!(debug_str.contains("Hello"));
     }
     
+    #[test]
+    fn test_chat_subcommand() {
+        let args = Args::try_parse_from([
+            "client",
+            "--model", "llama3",
+            "--temperature", "0.5",
+            "chat",
+        ]).unwrap();
+        assert_eq!(args.command, Some(Command::Chat));
+        assert_eq!(args.model, "llama3");
+        assert_eq!(args.temperature, Some(0.5));
+        assert!(args.prompt.is_none());
+
+        let args = Args::try_parse_from(["client", "--prompt", "Hello"]).unwrap();
+        assert_eq!(args.command, None);
+    }
+
+    #[test]
+    fn test_embed_subcommand() {
+        let args = Args::try_parse_from([
+            "client",
+            "--model", "text-embedding-3-small",
+            "embed", "first text", "second text",
+        ]).unwrap();
+        assert_eq!(args.command, Some(Command::Embed {
+            input: vec!["first text".to_string(), "second text".to_string()],
+        }));
+        assert_eq!(args.model, "text-embedding-3-small");
+
+        // At least one input is required
+        assert!(Args::try_parse_from(["client", "embed"]).is_err());
+    }
+
+    #[test]
+    fn test_client_state_begin_request_keeps_executors() {
+        let mut state = ClientState::default();
+        let peer = PeerId::random();
+        state.discovered_executors.insert(peer);
+        state.preferred_executor = Some(peer);
+        state.discovery_complete = true;
+        state.outbound_failure = Some("Timeout".to_string());
+        state.pending_request_id = Some("0xabc".to_string());
+        state.failover.record_failure(peer, "Timeout");
+
+        state.begin_request(RetryPolicy::new(2, Duration::from_secs(30)));
+        assert!(state.discovered_executors.contains(&peer));
+        assert_eq!(state.pending_request_id, None);
+        assert_eq!(state.preferred_executor, Some(peer));
+        assert!(!state.discovery_complete);
+        assert_eq!(state.outbound_failure, None);
+        assert!(state.failover.attempts().is_empty());
+        assert_eq!(state.failover.policy().max_attempts, 2);
+
+        state.forget_preferred(&PeerId::random());
+        assert_eq!(state.preferred_executor, Some(peer));
+        state.forget_preferred(&peer);
+        assert_eq!(state.preferred_executor, None);
+    }
+
+    #[test]
+    fn test_load_tools() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("tools.json");
+        std::fs::write(&path, r#"[{"type": "function", "function": {"name": "get_weather", "parameters": {"type": "object"}}}]"#).unwrap();
+
+        let tools = load_tools(path.to_str().unwrap()).unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].function.name, "get_weather");
+
+        std::fs::write(&path, "{}").unwrap();
+        assert!(load_tools(path.to_str().unwrap()).is_err());
+        assert!(load_tools(dir.path().join("missing.json").to_str().unwrap()).is_err());
+
+        let args = Args::try_parse_from(["lloom-client", "--prompt", "Hi", "--tools", "tools.json"]).unwrap();
+        assert_eq!(args.tools.as_deref(), Some("tools.json"));
+    }
+
+    #[test]
+    fn test_image_messages() {
+        let args = Args::try_parse_from(["lloom-client", "--prompt", "Hi"]).unwrap();
+        assert!(image_messages(&args).unwrap().is_none());
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("cat.png");
+        std::fs::write(&path, b"png bytes").unwrap();
+        let path = path.to_str().unwrap();
+        let args = Args::try_parse_from([
+            "lloom-client", "--prompt", "What is this?", "--system-prompt", "Be brief", "--image", path, "--image", path,
+        ]).unwrap();
+        let messages = image_messages(&args).unwrap().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0], ChatMessage::system("Be brief"));
+        assert_eq!(messages[1].content, "What is this?");
+        assert_eq!(messages[1].images.as_ref().unwrap().len(), 2);
+
+        let too_many: Vec<&str> = std::iter::repeat_n(["--image", path], MAX_IMAGES_PER_REQUEST + 1).flatten().collect();
+        let args = Args::try_parse_from(["lloom-client", "--prompt", "Hi"].into_iter().chain(too_many)).unwrap();
+        assert!(image_messages(&args).is_err());
+    }
+
+    #[test]
+    fn test_sampling_args() {
+        let args = Args::try_parse_from(["lloom-client", "--prompt", "Hi"]).unwrap();
+        assert!(sampling_params(&args).is_empty());
+
+        let args = Args::try_parse_from([
+            "lloom-client", "--prompt", "Hi",
+            "--top-p", "0.9",
+            "--top-k", "40",
+            "--stop", "END", "--stop", "STOP",
+            "--seed", "7",
+            "--presence-penalty", "-0.5",
+            "--frequency-penalty", "0.5",
+            "--choices", "3",
+        ]).unwrap();
+        let sampling = sampling_params(&args);
+        assert_eq!(sampling.top_p, Some(0.9));
+        assert_eq!(sampling.top_k, Some(40));
+        assert_eq!(sampling.stop, Some(vec!["END".to_string(), "STOP".to_string()]));
+        assert_eq!(sampling.seed, Some(7));
+        assert_eq!(sampling.presence_penalty, Some(-0.5));
+        assert_eq!(sampling.frequency_penalty, Some(0.5));
+        assert_eq!(sampling.n, Some(3));
+        assert!(validate_sampling(&sampling).is_ok());
+    }
+
+    #[test]
+    fn test_response_format_args() {
+        let args = Args::try_parse_from(["lloom-client", "--prompt", "Hi"]).unwrap();
+        assert_eq!(response_format(&args).unwrap(), None);
+
+        let args = Args::try_parse_from(["lloom-client", "--prompt", "Hi", "--json"]).unwrap();
+        assert_eq!(response_format(&args).unwrap(), Some(ResponseFormat::JsonObject));
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("person.json");
+        std::fs::write(&path, r#"{"type": "object", "required": ["name"]}"#).unwrap();
+        let args = Args::try_parse_from(["lloom-client", "--prompt", "Hi", "--json-schema", path.to_str().unwrap()]).unwrap();
+        assert_eq!(
+            response_format(&args).unwrap(),
+            Some(ResponseFormat::json_schema("person", serde_json::json!({"type": "object", "required": ["name"]})))
+        );
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(response_format(&args).is_err());
+        assert!(Args::try_parse_from(["lloom-client", "--prompt", "Hi", "--json", "--json-schema", "s.json"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stream_attempt() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let ledger = SpendingLedger::new(dir.path()).unwrap();
+        let identity = Identity::generate();
+        let mut swarm = build_swarm(&identity, Transport::Tcp).unwrap();
+        let args = Args::try_parse_from(["lloom-client", "--prompt", "Hi", "--stream"]).unwrap();
+
+        let mut state = ClientState::default();
+        // The executor answers a closed stream with what it billed so far
+        let (cancel, cancelled) = oneshot::channel::<()>();
+        let handle = tokio::spawn(async move {
+            cancelled.await.unwrap();
+            Some(ResponseMessage::LlmResponse(LlmResponse {
+                content: "Partial".to_string(),
+                inbound_tokens: 3,
+                outbound_tokens: 4,
+                total_cost: "7".to_string(),
+                model_used: "gpt-4".to_string(),
+                error: Some("Client cancelled the stream".to_string()),
+                request_id: Some("0xabc".to_string()),
+                error_code: Some(lloom_core::protocol::LlmErrorCode::Cancelled),
+                tool_calls: None,
+                choices: None,
+            }))
+        });
+        state.stream_task = Some(StreamTask { cancel, handle });
+        state.pending_request = Some((PendingAttempt::Stream(1), PeerId::random()));
+        state.pending_request_id = Some("0xabc".to_string());
+        state.ledger_entry = Some(ledger.record_request("req-1", "executor-a", "gpt-4", 1, "1", "1", Some(10)).unwrap());
+
+        let billed = cancel_pending_request(&mut swarm, &mut state, &args, &identity, &ledger, CancelReason::Interrupted).await;
+        assert!(billed);
+        assert!(state.pending_request.is_none());
+        assert!(state.ledger_entry.is_none());
+
+        let records = ledger.records().unwrap();
+        assert!(records[0].cancelled);
+        assert_eq!(ledger.today().unwrap().total, alloy::primitives::U256::from(7));
+
+        // A stream that never answers may still have been billed
+        let mut state = ClientState::default();
+        let (cancel, _cancelled) = oneshot::channel::<()>();
+        state.stream_task = Some(StreamTask { cancel, handle: tokio::spawn(async { None }) });
+        state.pending_request = Some((PendingAttempt::Stream(2), PeerId::random()));
+        let billed = cancel_pending_request(&mut swarm, &mut state, &args, &identity, &ledger, CancelReason::Interrupted).await;
+        assert!(billed);
+    }
+
+    #[tokio::test]
+    async fn test_finish_request_keeps_nonce_the_executor_may_settle() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let ledger = SpendingLedger::new(dir.path()).unwrap();
+        let nonce_manager = NonceManager::new(dir.path()).unwrap();
+        let identity = Identity::generate();
+        let mut swarm = build_swarm(&identity, Transport::Tcp).unwrap();
+        let args = Args::try_parse_from(["lloom-client", "--prompt", "Hi"]).unwrap();
+        let client = identity.evm_address;
+        
+        // Failing to dial proves the request never left the client
+        let mut state = ClientState::default();
+        let nonce = nonce_manager.reserve(client).await.unwrap();
+        assert!(never_delivered(&request_response::OutboundFailure::DialFailure));
+        finish_request(&mut state, &ledger, &nonce_manager, client, nonce, false).await;
+        assert_eq!(nonce_manager.current(client).await.unwrap(), nonce - 1);
+        
+        // A request-response attempt that cannot be cancelled may still be settled
+        let nonce = nonce_manager.reserve(client).await.unwrap();
+        state.begin_request(RetryPolicy::new(1, Duration::from_secs(30)));
+        let executor = PeerId::random();
+        let outbound_id = swarm.behaviour_mut().request_response.send_request(&executor, RequestMessage::Unknown);
+        state.pending_request = Some((PendingAttempt::Request(outbound_id), executor));
+        let billed = cancel_pending_request(&mut swarm, &mut state, &args, &identity, &ledger, CancelReason::Timeout).await;
+        assert!(billed);
+        finish_request(&mut state, &ledger, &nonce_manager, client, nonce, billed).await;
+        assert_eq!(nonce_manager.current(client).await.unwrap(), nonce);
+        
+        // So may one still in flight when the timeout hit
+        let nonce = nonce_manager.reserve(client).await.unwrap();
+        state.begin_request(RetryPolicy::new(1, Duration::from_secs(30)));
+        state.ledger_entry = Some(ledger.record_request("req-2", "executor-a", "gpt-4", nonce, "1", "1", Some(10)).unwrap());
+        finish_request(&mut state, &ledger, &nonce_manager, client, nonce, false).await;
+        assert_eq!(nonce_manager.current(client).await.unwrap(), nonce);
+        assert!(!never_delivered(&request_response::OutboundFailure::Timeout));
+    }
+
+    #[test]
+    fn test_no_failover_once_the_nonce_may_be_settled() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let ledger = SpendingLedger::new(dir.path()).unwrap();
+        let response = LlmResponse {
+            content: String::new(),
+            inbound_tokens: 0,
+            outbound_tokens: 0,
+            total_cost: "0".to_string(),
+            model_used: "gpt-4".to_string(),
+            error: Some("Backend unavailable".to_string()),
+            request_id: None,
+            error_code: Some(lloom_core::protocol::LlmErrorCode::BackendUnavailable),
+            tool_calls: None,
+            choices: None,
+        };
+        
+        // An unbilled retryable error moves on to another executor
+        let mut state = ClientState::default();
+        state.begin_request(RetryPolicy::new(3, Duration::from_secs(30)));
+        state.pending_request = Some((PendingAttempt::Stream(1), PeerId::random()));
+        state.response_received = Some(response.clone());
+        assert!(settle_attempt(&mut state, &ledger).unwrap().is_none());
+        assert!(state.may_fail_over());
+        
+        // One that billed tokens hands the nonce to that executor
+        state.pending_request = Some((PendingAttempt::Stream(2), PeerId::random()));
+        state.response_received = Some(LlmResponse { inbound_tokens: 3, total_cost: "3".to_string(), ..response });
+        let settled = settle_attempt(&mut state, &ledger).unwrap();
+        assert_eq!(settled.map(|response| response.inbound_tokens), Some(3));
+        assert!(!state.may_fail_over());
+        
+        // So does a delivered request that failed in transit
+        state.begin_request(RetryPolicy::new(3, Duration::from_secs(30)));
+        state.pending_request = Some((PendingAttempt::Stream(3), PeerId::random()));
+        state.maybe_settled = true;
+        state.outbound_failure = Some("Connection reset".to_string());
+        assert!(settle_attempt(&mut state, &ledger).is_err());
+        assert_eq!(state.failover.attempts().len(), 1);
+    }
+
+    #[test]
+    fn test_stream_events_only_settle_current_attempt() {
+        let args = Args::try_parse_from(["client", "--stream"]).unwrap();
+        assert!(args.stream);
+        
+        let mut state = ClientState::default();
+        let peer = PeerId::random();
+        state.pending_request = Some((PendingAttempt::Stream(2), peer));
+        let response = LlmResponse {
+            content: "Hi".to_string(),
+            inbound_tokens: 1,
+            outbound_tokens: 1,
+            total_cost: "2".to_string(),
+            model_used: "gpt-4".to_string(),
+            error: None,
+            request_id: None,
+            error_code: None,
+            tool_calls: None,
+            choices: None,
+        };
+        
+        // Events from an abandoned stream are ignored
+        handle_stream_event(&mut state, &args, 1, StreamEvent::Done(Ok(Box::new(ResponseMessage::LlmResponse(response.clone())))));
+        assert!(state.response_received.is_none());
+        
+        handle_stream_event(&mut state, &args, 2, StreamEvent::Chunk("Hi".to_string()));
+        assert!(state.stream_printed);
+        handle_stream_event(&mut state, &args, 2, StreamEvent::Done(Ok(Box::new(ResponseMessage::LlmResponse(response)))));
+        assert_eq!(state.response_received.as_ref().map(|r| r.content.as_str()), Some("Hi"));
+        
+        state.response_received = None;
+        handle_stream_event(&mut state, &args, 2, StreamEvent::Done(Err("reset".to_string())));
+        assert_eq!(state.outbound_failure.as_deref(), Some("reset"));
+    }
+
     #[test]
     fn test_demo_flag() {
-        let args = Args::try_parse_from(&[
+        let args = Args::try_parse_from([
             "client",
             "--demo"
         ]).unwrap();
@@ -1258,7 +3087,7 @@ mod tests {
     
     #[test]
     fn test_demo_with_other_args() {
-        let args = Args::try_parse_from(&[
+        let args = Args::try_parse_from([
             "client",
             "--demo",
             "--debug",
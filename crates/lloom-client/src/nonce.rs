@@ -0,0 +1,317 @@
+//! Client nonce management.
+//!
+//! The Accounting contract only accepts strictly sequential nonces per client
+//! (`validateAndIncrementNonce`), so the client keeps the last nonce it handed out
+//! in a small JSON file under its data directory. Reservations go through an
+//! async in-process mutex and a lock file, so concurrent requests (and concurrent client
+//! processes sharing a data directory) never receive the same nonce. The store can
+//! be resynchronized from the contract's `getCurrentNonce(address)`.
+
+use alloy::{
+    primitives::Address,
+    providers::ProviderBuilder,
+    sol,
+};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+sol! {
+    #[allow(missing_docs)]
+    #[sol(rpc)]
+    contract NonceContract {
+        function getCurrentNonce(address client) external view returns (uint64);
+    }
+}
+
+/// File name of the nonce store inside the data directory
+pub const NONCE_FILE_NAME: &str = "nonces.json";
+
+/// How long to wait for another process to release the store lock
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Lock files older than this are assumed to be left behind by a crashed process
+const STALE_LOCK_AGE: Duration = Duration::from_secs(30);
+
+/// Default client data directory (e.g. `~/.local/share/lloom/client` on Linux)
+pub fn default_data_dir() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "lloom")
+        .map(|dirs| dirs.data_dir().join("client"))
+}
+
+/// On-disk format of the nonce store: last nonce used per client address
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct NonceFile {
+    #[serde(default)]
+    nonces: BTreeMap<Address, u64>,
+}
+
+/// Lock file held while the store is being read and rewritten
+struct StoreLock {
+    path: PathBuf,
+}
+
+impl StoreLock {
+    /// Wait for the lock file without blocking the runtime
+    async fn acquire(path: PathBuf) -> Result<Self> {
+        let started = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(Self { path }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let stale = fs::metadata(&path)
+                        .and_then(|meta| meta.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok())
+                        .is_some_and(|age| age > STALE_LOCK_AGE);
+                    if stale {
+                        warn!("Removing stale nonce store lock: {}", path.display());
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                    if started.elapsed() > LOCK_TIMEOUT {
+                        return Err(anyhow!("Timed out waiting for nonce store lock: {}", path.display()));
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                Err(e) => return Err(anyhow!("Failed to create nonce store lock {}: {}", path.display(), e)),
+            }
+        }
+    }
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Persistent, concurrency-safe nonce allocator for client requests
+#[derive(Debug)]
+pub struct NonceManager {
+    path: PathBuf,
+    guard: Mutex<()>,
+}
+
+impl NonceManager {
+    /// Open (or create) the nonce store in the given data directory
+    pub fn new(data_dir: impl AsRef<Path>) -> Result<Self> {
+        let data_dir = data_dir.as_ref();
+        fs::create_dir_all(data_dir)
+            .map_err(|e| anyhow!("Failed to create data directory {}: {}", data_dir.display(), e))?;
+        Ok(Self {
+            path: data_dir.join(NONCE_FILE_NAME),
+            guard: Mutex::new(()),
+        })
+    }
+
+    /// Path of the backing JSON file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Last nonce used by the address (0 if it has never sent a request)
+    pub async fn current(&self, address: Address) -> Result<u64> {
+        self.with_store(|store| Ok(store.nonces.get(&address).copied().unwrap_or(0))).await
+    }
+
+    /// Reserve the next nonce for the address and persist it
+    pub async fn reserve(&self, address: Address) -> Result<u64> {
+        self.with_store(|store| {
+            let next = store.nonces.get(&address).copied().unwrap_or(0)
+                .checked_add(1)
+                .ok_or_else(|| anyhow!("Nonce overflow for {}", address))?;
+            store.nonces.insert(address, next);
+            debug!("Reserved nonce {} for {}", next, address);
+            Ok(next)
+        }).await
+    }
+
+    /// Give back a reserved nonce that was never used.
+    ///
+    /// Only the most recent reservation can be released; otherwise the sequence
+    /// would have a gap in the middle and `false` is returned. Such gaps are
+    /// repaired by resyncing from the contract.
+    pub async fn release(&self, address: Address, nonce: u64) -> Result<bool> {
+        self.with_store(|store| {
+            match store.nonces.get(&address) {
+                Some(&current) if current == nonce && nonce > 0 => {
+                    store.nonces.insert(address, nonce - 1);
+                    debug!("Released unused nonce {} for {}", nonce, address);
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        }).await
+    }
+
+    /// Reconcile the local store with the nonce recorded on-chain.
+    ///
+    /// If the local store is behind the contract it is moved forward. When it is
+    /// ahead (requests not yet settled on-chain) it is kept, unless `force` is set,
+    /// in which case the contract value wins. Returns the resulting current nonce.
+    pub async fn sync_with_chain_nonce(&self, address: Address, chain_nonce: u64, force: bool) -> Result<u64> {
+        self.with_store(|store| {
+            let local = store.nonces.get(&address).copied().unwrap_or(0);
+            let synced = if force { chain_nonce } else { local.max(chain_nonce) };
+            if synced != local {
+                info!("Nonce for {} resynced from {} to {} (on-chain: {})", address, local, synced, chain_nonce);
+            }
+            store.nonces.insert(address, synced);
+            Ok(synced)
+        }).await
+    }
+
+    /// Fetch the on-chain nonce from the Accounting contract and reconcile with it
+    pub async fn resync(&self, address: Address, rpc_url: &str, contract_address: Address, force: bool) -> Result<u64> {
+        let chain_nonce = fetch_contract_nonce(rpc_url, contract_address, address).await?;
+        self.sync_with_chain_nonce(address, chain_nonce, force).await
+    }
+
+    /// Run a read-modify-write cycle on the store while holding both locks
+    async fn with_store<T>(&self, f: impl FnOnce(&mut NonceFile) -> Result<T>) -> Result<T> {
+        let _guard = self.guard.lock().await;
+        let _lock = StoreLock::acquire(self.path.with_extension("json.lock")).await?;
+
+        let mut store = match fs::read_to_string(&self.path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| anyhow!("Failed to parse nonce store {}: {}", self.path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => NonceFile::default(),
+            Err(e) => return Err(anyhow!("Failed to read nonce store {}: {}", self.path.display(), e)),
+        };
+
+        let result = f(&mut store)?;
+
+        // Write to a temporary file and rename so a crash never leaves a truncated store
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(&store)?)
+            .map_err(|e| anyhow!("Failed to write nonce store {}: {}", tmp_path.display(), e))?;
+        fs::rename(&tmp_path, &self.path)
+            .map_err(|e| anyhow!("Failed to replace nonce store {}: {}", self.path.display(), e))?;
+
+        Ok(result)
+    }
+}
+
+/// Query `getCurrentNonce(client)` on the Accounting contract
+pub async fn fetch_contract_nonce(rpc_url: &str, contract_address: Address, client: Address) -> Result<u64> {
+    let provider = ProviderBuilder::new()
+        .connect_http(rpc_url.parse().map_err(|e| anyhow!("Invalid RPC URL {}: {}", rpc_url, e))?);
+    let contract = NonceContract::new(contract_address, provider);
+    let nonce = contract.getCurrentNonce(client).call().await
+        .map_err(|e| anyhow!("Failed to query contract nonce: {}", e))?;
+    Ok(nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn test_address() -> Address {
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reserve_is_sequential_and_persistent() {
+        let dir = TempDir::new().unwrap();
+        let address = test_address();
+
+        let manager = NonceManager::new(dir.path()).unwrap();
+        assert_eq!(manager.current(address).await.unwrap(), 0);
+        assert_eq!(manager.reserve(address).await.unwrap(), 1);
+        assert_eq!(manager.reserve(address).await.unwrap(), 2);
+
+        // A fresh manager on the same directory continues the sequence
+        let reopened = NonceManager::new(dir.path()).unwrap();
+        assert_eq!(reopened.current(address).await.unwrap(), 2);
+        assert_eq!(reopened.reserve(address).await.unwrap(), 3);
+        assert!(!dir.path().join("nonces.json.lock").exists());
+    }
+
+    #[tokio::test]
+    async fn test_nonces_are_per_address() {
+        let dir = TempDir::new().unwrap();
+        let manager = NonceManager::new(dir.path()).unwrap();
+        let other: Address = "0x70997970C51812dc3A010C7d01b50e0d17dc79C8".parse().unwrap();
+
+        assert_eq!(manager.reserve(test_address()).await.unwrap(), 1);
+        assert_eq!(manager.reserve(other).await.unwrap(), 1);
+        assert_eq!(manager.reserve(test_address()).await.unwrap(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_reservations_are_unique() {
+        let dir = TempDir::new().unwrap();
+        let manager = Arc::new(NonceManager::new(dir.path()).unwrap());
+        let address = test_address();
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let manager = Arc::clone(&manager);
+                tokio::spawn(async move {
+                    let mut nonces = Vec::new();
+                    for _ in 0..10 {
+                        nonces.push(manager.reserve(address).await.unwrap());
+                    }
+                    nonces
+                })
+            })
+            .collect();
+
+        let mut nonces = Vec::new();
+        for handle in handles {
+            nonces.extend(handle.await.unwrap());
+        }
+        nonces.sort_unstable();
+        assert_eq!(nonces, (1..=80).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_release_only_latest() {
+        let dir = TempDir::new().unwrap();
+        let manager = NonceManager::new(dir.path()).unwrap();
+        let address = test_address();
+
+        let first = manager.reserve(address).await.unwrap();
+        let second = manager.reserve(address).await.unwrap();
+
+        assert!(!manager.release(address, first).await.unwrap());
+        assert!(manager.release(address, second).await.unwrap());
+        assert_eq!(manager.current(address).await.unwrap(), first);
+        assert_eq!(manager.reserve(address).await.unwrap(), second);
+    }
+
+    #[tokio::test]
+    async fn test_sync_with_chain_nonce() {
+        let dir = TempDir::new().unwrap();
+        let manager = NonceManager::new(dir.path()).unwrap();
+        let address = test_address();
+
+        // Behind the chain: move forward
+        assert_eq!(manager.sync_with_chain_nonce(address, 5, false).await.unwrap(), 5);
+        assert_eq!(manager.reserve(address).await.unwrap(), 6);
+
+        // Ahead of the chain: keep local unless forced
+        assert_eq!(manager.sync_with_chain_nonce(address, 3, false).await.unwrap(), 6);
+        assert_eq!(manager.sync_with_chain_nonce(address, 3, true).await.unwrap(), 3);
+        assert_eq!(manager.reserve(address).await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_store_is_reported() {
+        let dir = TempDir::new().unwrap();
+        let manager = NonceManager::new(dir.path()).unwrap();
+        fs::write(manager.path(), "not json").unwrap();
+
+        assert!(manager.reserve(test_address()).await.is_err());
+    }
+}
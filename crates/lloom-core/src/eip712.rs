@@ -1,11 +1,20 @@
+//! EIP-712 typed data for on-chain accounting.
+//!
+//! Signed structs are declared once in [`sol!`] and get their type strings,
+//! struct hashes, wallet `TypedData` and signatures from [`Eip712Struct`].
+//! Adding a signed type means adding a struct to a `sol!` block.
+
 use crate::{
     error::{Error, Result},
-    protocol::{LlmRequest, LlmResponse},
+    protocol::{ChatMessage, LlmRequest, LlmResponse, SamplingParams},
+    signer::Signer,
 };
-use alloy::signers::local::PrivateKeySigner;
-use alloy::primitives::{Address, Signature, keccak256, B256};
+use alloy::primitives::{Address, Signature, U256, keccak256, B256};
+use alloy::sol;
+use alloy::sol_types::SolStruct;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+
+pub use alloy::dyn_abi::TypedData;
 
 /// EIP-712 Domain Separator structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,523 +37,341 @@ impl EIP712Domain {
             verifying_contract,
         }
     }
-}
 
-/// LLM Request Commitment for EIP-712 signing
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LlmRequestCommitment {
-    #[serde(rename = "requestId")]
-    pub request_id: String,
-    #[serde(rename = "clientAddress")]
-    pub client_address: String,
-    #[serde(rename = "executorAddress")]
-    pub executor_address: String,
-    #[serde(rename = "modelName")]
-    pub model_name: String,
-    #[serde(rename = "maxTokens")]
-    pub max_tokens: u32,
-    pub temperature: String, // f32 as string for precision
-    #[serde(rename = "promptHash")]
-    pub prompt_hash: String,
-    #[serde(rename = "maxPricePerToken")]
-    pub max_price_per_token: String, // UINT256 as string
-    #[serde(rename = "maxTotalCost")]
-    pub max_total_cost: String,      // UINT256 as string
-    pub timestamp: u64,
-    pub nonce: u64,
-}
+    /// The domain in alloy's representation
+    pub fn to_sol(&self) -> alloy::sol_types::Eip712Domain {
+        alloy::sol_types::Eip712Domain::new(
+            Some(self.name.clone().into()),
+            Some(self.version.clone().into()),
+            Some(U256::from(self.chain_id)),
+            Some(self.verifying_contract),
+            None,
+        )
+    }
 
-/// LLM Response Commitment for EIP-712 signing
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LlmResponseCommitment {
-    #[serde(rename = "requestId")]
-    pub request_id: String,
-    #[serde(rename = "executorAddress")]
-    pub executor_address: String,
-    #[serde(rename = "responseHash")]
-    pub response_hash: String,
-    #[serde(rename = "inputTokens")]
-    pub input_tokens: u32,
-    #[serde(rename = "outputTokens")]
-    pub output_tokens: u32,
-    #[serde(rename = "totalTokens")]
-    pub total_tokens: u32,
-    #[serde(rename = "pricePerToken")]
-    pub price_per_token: String, // UINT256 as string
-    #[serde(rename = "totalCost")]
-    pub total_cost: String,      // UINT256 as string
-    pub timestamp: u64,
+    /// The domain separator
+    pub fn separator(&self) -> B256 {
+        self.to_sol().separator()
+    }
 }
 
-/// EIP-712 TypedData structure
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TypedData {
-    pub types: HashMap<String, Vec<TypeField>>,
-    #[serde(rename = "primaryType")]
-    pub primary_type: String,
-    pub domain: EIP712Domain,
-    pub message: serde_json::Value,
-}
+sol! {
+    /// LLM Request Commitment for EIP-712 signing, as the accounting contract
+    /// verifies it
+    #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+    struct LlmRequestCommitment {
+        string requestId;
+        address executor;
+        string model;
+        bytes32 promptHash;
+        /// Zero when the request has no separate system prompt
+        bytes32 systemPromptHash;
+        uint32 maxTokens;
+        /// Temperature × 10000
+        uint32 temperature;
+        /// Zero when the request sets no other sampling parameters
+        bytes32 samplingHash;
+        uint256 inboundPrice;
+        uint256 outboundPrice;
+        uint64 nonce;
+        uint64 deadline;
+    }
 
-/// Field definition for EIP-712 types
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TypeField {
-    pub name: String,
-    #[serde(rename = "type")]
-    pub field_type: String,
+    /// LLM Response Commitment for EIP-712 signing, as the accounting contract
+    /// verifies it
+    #[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+    struct LlmResponseCommitment {
+        string requestId;
+        /// Signing hash of the answered request's commitment
+        bytes32 requestHash;
+        address client;
+        string model;
+        bytes32 contentHash;
+        uint32 inboundTokens;
+        uint32 outboundTokens;
+        uint256 inboundPrice;
+        uint256 outboundPrice;
+        uint64 timestamp;
+        bool success;
+    }
 }
 
-impl TypedData {
-    /// Create TypedData for LlmRequestCommitment
-    pub fn for_request_commitment(
-        domain: EIP712Domain,
-        commitment: LlmRequestCommitment,
-    ) -> Result<Self> {
-        let mut types = HashMap::new();
-        
-        // EIP712Domain type
-        types.insert("EIP712Domain".to_string(), vec![
-            TypeField { name: "name".to_string(), field_type: "string".to_string() },
-            TypeField { name: "version".to_string(), field_type: "string".to_string() },
-            TypeField { name: "chainId".to_string(), field_type: "uint256".to_string() },
-            TypeField { name: "verifyingContract".to_string(), field_type: "address".to_string() },
-        ]);
-
-        // LlmRequestCommitment type
-        types.insert("LlmRequestCommitment".to_string(), vec![
-            TypeField { name: "requestId".to_string(), field_type: "string".to_string() },
-            TypeField { name: "clientAddress".to_string(), field_type: "address".to_string() },
-            TypeField { name: "executorAddress".to_string(), field_type: "address".to_string() },
-            TypeField { name: "modelName".to_string(), field_type: "string".to_string() },
-            TypeField { name: "maxTokens".to_string(), field_type: "uint32".to_string() },
-            TypeField { name: "temperature".to_string(), field_type: "string".to_string() },
-            TypeField { name: "promptHash".to_string(), field_type: "bytes32".to_string() },
-            TypeField { name: "maxPricePerToken".to_string(), field_type: "uint256".to_string() },
-            TypeField { name: "maxTotalCost".to_string(), field_type: "uint256".to_string() },
-            TypeField { name: "timestamp".to_string(), field_type: "uint64".to_string() },
-            TypeField { name: "nonce".to_string(), field_type: "uint64".to_string() },
-        ]);
-
-        let message = serde_json::to_value(&commitment)
-            .map_err(|e| Error::Serialization(e))?;
-
-        Ok(TypedData {
-            types,
-            primary_type: "LlmRequestCommitment".to_string(),
-            domain,
-            message,
-        })
-    }
+/// Chat messages as committed in a request's prompt hash. The names are part
+/// of the type hashes, so they live apart from [`ChatMessage`].
+mod chat {
+    alloy::sol! {
+        struct ChatMessage {
+            string role;
+            string content;
+        }
 
-    /// Create TypedData for LlmResponseCommitment
-    pub fn for_response_commitment(
-        domain: EIP712Domain,
-        commitment: LlmResponseCommitment,
-    ) -> Result<Self> {
-        let mut types = HashMap::new();
-        
-        // EIP712Domain type
-        types.insert("EIP712Domain".to_string(), vec![
-            TypeField { name: "name".to_string(), field_type: "string".to_string() },
-            TypeField { name: "version".to_string(), field_type: "string".to_string() },
-            TypeField { name: "chainId".to_string(), field_type: "uint256".to_string() },
-            TypeField { name: "verifyingContract".to_string(), field_type: "address".to_string() },
-        ]);
-
-        // LlmResponseCommitment type
-        types.insert("LlmResponseCommitment".to_string(), vec![
-            TypeField { name: "requestId".to_string(), field_type: "string".to_string() },
-            TypeField { name: "executorAddress".to_string(), field_type: "address".to_string() },
-            TypeField { name: "responseHash".to_string(), field_type: "bytes32".to_string() },
-            TypeField { name: "inputTokens".to_string(), field_type: "uint32".to_string() },
-            TypeField { name: "outputTokens".to_string(), field_type: "uint32".to_string() },
-            TypeField { name: "totalTokens".to_string(), field_type: "uint32".to_string() },
-            TypeField { name: "pricePerToken".to_string(), field_type: "uint256".to_string() },
-            TypeField { name: "totalCost".to_string(), field_type: "uint256".to_string() },
-            TypeField { name: "timestamp".to_string(), field_type: "uint64".to_string() },
-        ]);
-
-        let message = serde_json::to_value(&commitment)
-            .map_err(|e| Error::Serialization(e))?;
-
-        Ok(TypedData {
-            types,
-            primary_type: "LlmResponseCommitment".to_string(),
-            domain,
-            message,
-        })
+        struct ChatMessageWithImages {
+            string role;
+            string content;
+            bytes32[] images;
+        }
     }
 }
 
-/// Calculate domain separator hash according to EIP-712
-pub fn calculate_domain_separator(domain: &EIP712Domain) -> Result<B256> {
-    let domain_type_hash = keccak256(
-        "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)".as_bytes()
-    );
-    
-    let name_hash = keccak256(domain.name.as_bytes());
-    let version_hash = keccak256(domain.version.as_bytes());
-    
-    let mut encoded = Vec::new();
-    encoded.extend_from_slice(domain_type_hash.as_slice());
-    encoded.extend_from_slice(name_hash.as_slice());
-    encoded.extend_from_slice(version_hash.as_slice());
-    
-    // Chain ID as uint256
-    let mut chain_id_bytes = [0u8; 32];
-    let chain_id_u64_bytes = domain.chain_id.to_be_bytes();
-    chain_id_bytes[24..32].copy_from_slice(&chain_id_u64_bytes);
-    encoded.extend_from_slice(&chain_id_bytes);
-    
-    // Verifying contract as address (20 bytes, padded to 32)
-    let mut padded_addr = [0u8; 32];
-    padded_addr[12..32].copy_from_slice(domain.verifying_contract.as_slice());
-    encoded.extend_from_slice(&padded_addr);
-    
-    Ok(keccak256(&encoded))
-}
+/// Responses that call tools, as committed in a response hash
+mod response {
+    alloy::sol! {
+        struct ToolCall {
+            string id;
+            string name;
+            string arguments;
+        }
 
-/// Calculate type hash for LlmRequestCommitment
-pub fn calculate_request_type_hash() -> B256 {
-    keccak256(
-        "LlmRequestCommitment(string requestId,address clientAddress,address executorAddress,string modelName,uint32 maxTokens,string temperature,bytes32 promptHash,uint256 maxPricePerToken,uint256 maxTotalCost,uint64 timestamp,uint64 nonce)".as_bytes()
-    )
+        struct ResponseWithToolCalls {
+            string content;
+            ToolCall[] toolCalls;
+        }
+    }
 }
 
-/// Calculate type hash for LlmResponseCommitment
-pub fn calculate_response_type_hash() -> B256 {
-    keccak256(
-        "LlmResponseCommitment(string requestId,address executorAddress,bytes32 responseHash,uint32 inputTokens,uint32 outputTokens,uint32 totalTokens,uint256 pricePerToken,uint256 totalCost,uint64 timestamp)".as_bytes()
-    )
-}
+/// A struct signed as EIP-712 typed data.
+///
+/// Implemented for every [`SolStruct`], so structs declared with [`sol!`] get
+/// hashing, signing, verification and wallet export for free.
+pub trait Eip712Struct: SolStruct + Serialize {
+    /// The hash signed for this struct in `domain`
+    fn signing_hash(&self, domain: &EIP712Domain) -> B256 {
+        self.eip712_signing_hash(&domain.to_sol())
+    }
 
-/// Calculate struct hash for LlmRequestCommitment
-pub fn calculate_request_struct_hash(commitment: &LlmRequestCommitment) -> Result<B256> {
-    let type_hash = calculate_request_type_hash();
-    
-    let mut encoded = Vec::new();
-    encoded.extend_from_slice(type_hash.as_slice());
-    
-    // String fields are hashed
-    encoded.extend_from_slice(keccak256(commitment.request_id.as_bytes()).as_slice());
-    
-    // Address fields
-    let client_addr: Address = commitment.client_address.parse()
-        .map_err(|e| Error::Other(format!("Invalid client address: {}", e)))?;
-    let mut padded_client = [0u8; 32];
-    padded_client[12..32].copy_from_slice(client_addr.as_slice());
-    encoded.extend_from_slice(&padded_client);
-    
-    let executor_addr: Address = commitment.executor_address.parse()
-        .map_err(|e| Error::Other(format!("Invalid executor address: {}", e)))?;
-    let mut padded_executor = [0u8; 32];
-    padded_executor[12..32].copy_from_slice(executor_addr.as_slice());
-    encoded.extend_from_slice(&padded_executor);
-    
-    // String fields are hashed
-    encoded.extend_from_slice(keccak256(commitment.model_name.as_bytes()).as_slice());
-    
-    // uint32 maxTokens
-    let mut max_tokens_bytes = [0u8; 32];
-    let max_tokens_u32_bytes = commitment.max_tokens.to_be_bytes();
-    max_tokens_bytes[28..32].copy_from_slice(&max_tokens_u32_bytes);
-    encoded.extend_from_slice(&max_tokens_bytes);
-    
-    // String temperature is hashed
-    encoded.extend_from_slice(keccak256(commitment.temperature.as_bytes()).as_slice());
-    
-    // bytes32 promptHash
-    let prompt_hash = hex::decode(commitment.prompt_hash.trim_start_matches("0x"))
-        .map_err(|e| Error::Other(format!("Invalid prompt hash: {}", e)))?;
-    if prompt_hash.len() != 32 {
-        return Err(Error::Other("Prompt hash must be 32 bytes".to_string()));
+    /// Typed data for `eth_signTypedData_v4`, so a wallet can sign the struct
+    fn typed_data(&self, domain: &EIP712Domain) -> TypedData {
+        TypedData::from_struct(self, Some(domain.to_sol()))
     }
-    encoded.extend_from_slice(&prompt_hash);
-    
-    // uint256 fields
-    let max_price = parse_uint256(&commitment.max_price_per_token)?;
-    encoded.extend_from_slice(&max_price);
-    
-    let max_cost = parse_uint256(&commitment.max_total_cost)?;
-    encoded.extend_from_slice(&max_cost);
-    
-    // uint64 fields
-    let mut timestamp_bytes = [0u8; 32];
-    let timestamp_u64_bytes = commitment.timestamp.to_be_bytes();
-    timestamp_bytes[24..32].copy_from_slice(&timestamp_u64_bytes);
-    encoded.extend_from_slice(&timestamp_bytes);
-    
-    let mut nonce_bytes = [0u8; 32];
-    let nonce_u64_bytes = commitment.nonce.to_be_bytes();
-    nonce_bytes[24..32].copy_from_slice(&nonce_u64_bytes);
-    encoded.extend_from_slice(&nonce_bytes);
-    
-    Ok(keccak256(&encoded))
-}
 
-/// Calculate struct hash for LlmResponseCommitment
-pub fn calculate_response_struct_hash(commitment: &LlmResponseCommitment) -> Result<B256> {
-    let type_hash = calculate_response_type_hash();
-    
-    let mut encoded = Vec::new();
-    encoded.extend_from_slice(type_hash.as_slice());
-    
-    // String fields are hashed
-    encoded.extend_from_slice(keccak256(commitment.request_id.as_bytes()).as_slice());
-    
-    // Address field
-    let executor_addr: Address = commitment.executor_address.parse()
-        .map_err(|e| Error::Other(format!("Invalid executor address: {}", e)))?;
-    let mut padded_executor = [0u8; 32];
-    padded_executor[12..32].copy_from_slice(executor_addr.as_slice());
-    encoded.extend_from_slice(&padded_executor);
-    
-    // bytes32 responseHash
-    let response_hash = hex::decode(commitment.response_hash.trim_start_matches("0x"))
-        .map_err(|e| Error::Other(format!("Invalid response hash: {}", e)))?;
-    if response_hash.len() != 32 {
-        return Err(Error::Other("Response hash must be 32 bytes".to_string()));
+    /// Sign the struct in `domain`
+    fn sign<S: Signer + ?Sized>(&self, signer: &S, domain: &EIP712Domain) -> Result<Signature> {
+        signer.sign_typed_data(&self.typed_data(domain))
     }
-    encoded.extend_from_slice(&response_hash);
-    
-    // uint32 fields
-    let mut input_tokens_bytes = [0u8; 32];
-    let input_tokens_u32_bytes = commitment.input_tokens.to_be_bytes();
-    input_tokens_bytes[28..32].copy_from_slice(&input_tokens_u32_bytes);
-    encoded.extend_from_slice(&input_tokens_bytes);
-    
-    let mut output_tokens_bytes = [0u8; 32];
-    let output_tokens_u32_bytes = commitment.output_tokens.to_be_bytes();
-    output_tokens_bytes[28..32].copy_from_slice(&output_tokens_u32_bytes);
-    encoded.extend_from_slice(&output_tokens_bytes);
-    
-    let mut total_tokens_bytes = [0u8; 32];
-    let total_tokens_u32_bytes = commitment.total_tokens.to_be_bytes();
-    total_tokens_bytes[28..32].copy_from_slice(&total_tokens_u32_bytes);
-    encoded.extend_from_slice(&total_tokens_bytes);
-    
-    // uint256 fields
-    let price = parse_uint256(&commitment.price_per_token)?;
-    encoded.extend_from_slice(&price);
-    
-    let total_cost = parse_uint256(&commitment.total_cost)?;
-    encoded.extend_from_slice(&total_cost);
-    
-    // uint64 timestamp
-    let mut timestamp_bytes = [0u8; 32];
-    let timestamp_u64_bytes = commitment.timestamp.to_be_bytes();
-    timestamp_bytes[24..32].copy_from_slice(&timestamp_u64_bytes);
-    encoded.extend_from_slice(&timestamp_bytes);
-    
-    Ok(keccak256(&encoded))
-}
 
-/// Parse a UINT256 string to 32-byte array
-fn parse_uint256(value: &str) -> Result<[u8; 32]> {
-    let value = value.trim_start_matches("0x");
-    if value.len() > 64 {
-        return Err(Error::Other("UINT256 value too large".to_string()));
+    /// Recover the address that signed the struct in `domain`
+    fn recover_signer(&self, domain: &EIP712Domain, signature: &Signature) -> Result<Address> {
+        signature.recover_address_from_prehash(&self.signing_hash(domain))
+            .map_err(|e| Error::Verification(format!("Failed to recover address: {}", e)))
     }
-    
-    // Pad with leading zeros if necessary
-    let padded = format!("{:0>64}", value);
-    let bytes = hex::decode(padded)
-        .map_err(|e| Error::Other(format!("Invalid UINT256 format: {}", e)))?;
-    
-    let mut result = [0u8; 32];
-    result.copy_from_slice(&bytes);
-    Ok(result)
-}
 
-/// Calculate EIP-712 message hash
-pub fn calculate_eip712_hash(domain: &EIP712Domain, struct_hash: &B256) -> Result<B256> {
-    let domain_separator = calculate_domain_separator(domain)?;
-    
-    let mut message = Vec::new();
-    message.push(0x19);
-    message.push(0x01);
-    message.extend_from_slice(domain_separator.as_slice());
-    message.extend_from_slice(struct_hash.as_slice());
-    
-    Ok(keccak256(&message))
+    /// Whether `signature` over the struct in `domain` is from `address`
+    fn verify_signature(&self, address: &Address, domain: &EIP712Domain, signature: &Signature) -> Result<bool> {
+        Ok(self.recover_signer(domain, signature)? == *address)
+    }
 }
 
-/// Sign a request commitment using EIP-712
-pub fn sign_request_commitment(
-    private_key: &PrivateKeySigner,
-    domain: &EIP712Domain,
-    commitment: &LlmRequestCommitment,
-) -> Result<Signature> {
-    let struct_hash = calculate_request_struct_hash(commitment)?;
-    let message_hash = calculate_eip712_hash(domain, &struct_hash)?;
-    
-    // Use the same synchronous approach as in signing.rs
-    let private_key_bytes = private_key.credential().to_bytes();
-    let signing_key = k256::ecdsa::SigningKey::from_bytes(&private_key_bytes)
-        .map_err(|e| Error::Signature(format!("Failed to create signing key: {}", e)))?;
-
-    let (signature, recovery_id) = signing_key
-        .sign_prehash_recoverable(message_hash.as_slice())
-        .map_err(|e| Error::Signature(format!("Failed to sign message hash: {}", e)))?;
-
-    // Convert to the 65-byte format expected by Ethereum (r + s + v)
-    let mut signature_bytes = [0u8; 65];
-    signature_bytes[..64].copy_from_slice(&signature.to_bytes());
-    signature_bytes[64] = recovery_id.to_byte();
-
-    let alloy_signature = Signature::try_from(&signature_bytes[..])
-        .map_err(|e| Error::Signature(format!("Failed to create alloy signature: {}", e)))?;
-    
-    Ok(alloy_signature)
+impl<T: SolStruct + Serialize> Eip712Struct for T {}
+
+/// Hash a message list as the EIP-712 encoding of `ChatMessage[]`.
+///
+/// Messages with images are encoded as `ChatMessageWithImages`, committing to
+/// the hash of every image's bytes; other messages hash as before images existed.
+pub fn hash_chat_messages(messages: &[ChatMessage]) -> B256 {
+    let mut encoded = Vec::with_capacity(messages.len() * 32);
+    for message in messages {
+        let role = message.role.clone();
+        let content = message.content.clone();
+        let struct_hash = match message.images.as_deref().filter(|images| !images.is_empty()) {
+            Some(images) => chat::ChatMessageWithImages {
+                role,
+                content,
+                // A malformed hash commits to zero, which no image can match
+                images: images.iter().map(|image| image.hash.parse().unwrap_or_default()).collect(),
+            }
+            .eip712_hash_struct(),
+            None => chat::ChatMessage { role, content }.eip712_hash_struct(),
+        };
+        encoded.extend_from_slice(struct_hash.as_slice());
+    }
+    keccak256(&encoded)
 }
 
-/// Sign a response commitment using EIP-712
-pub fn sign_response_commitment(
-    private_key: &PrivateKeySigner,
-    domain: &EIP712Domain,
-    commitment: &LlmResponseCommitment,
-) -> Result<Signature> {
-    let struct_hash = calculate_response_struct_hash(commitment)?;
-    let message_hash = calculate_eip712_hash(domain, &struct_hash)?;
-    
-    // Use the same synchronous approach as in signing.rs
-    let private_key_bytes = private_key.credential().to_bytes();
-    let signing_key = k256::ecdsa::SigningKey::from_bytes(&private_key_bytes)
-        .map_err(|e| Error::Signature(format!("Failed to create signing key: {}", e)))?;
-
-    let (signature, recovery_id) = signing_key
-        .sign_prehash_recoverable(message_hash.as_slice())
-        .map_err(|e| Error::Signature(format!("Failed to sign message hash: {}", e)))?;
-
-    // Convert to the 65-byte format expected by Ethereum (r + s + v)
-    let mut signature_bytes = [0u8; 65];
-    signature_bytes[..64].copy_from_slice(&signature.to_bytes());
-    signature_bytes[64] = recovery_id.to_byte();
-
-    let alloy_signature = Signature::try_from(&signature_bytes[..])
-        .map_err(|e| Error::Signature(format!("Failed to create alloy signature: {}", e)))?;
-    
-    Ok(alloy_signature)
+/// Response hash committed for a response.
+///
+/// Responses calling tools hash as `ResponseWithToolCalls`, committing to every
+/// call's id, function name and arguments; other responses hash their content.
+pub fn calculate_response_hash(response: &LlmResponse) -> B256 {
+    match response.tool_calls.as_deref().filter(|calls| !calls.is_empty()) {
+        Some(calls) => response::ResponseWithToolCalls {
+            content: response.content.clone(),
+            toolCalls: calls
+                .iter()
+                .map(|call| response::ToolCall {
+                    id: call.id.clone(),
+                    name: call.function.name.clone(),
+                    arguments: call.function.arguments.clone(),
+                })
+                .collect(),
+        }
+        .eip712_hash_struct(),
+        None => keccak256(response.content.as_bytes()),
+    }
 }
 
-/// Verify a request commitment signature
-pub fn verify_request_signature(
-    public_address: &Address,
-    domain: &EIP712Domain,
-    commitment: &LlmRequestCommitment,
-    signature: &Signature,
-) -> Result<bool> {
-    let struct_hash = calculate_request_struct_hash(commitment)?;
-    let message_hash = calculate_eip712_hash(domain, &struct_hash)?;
-    
-    let recovered_address = signature.recover_address_from_prehash(&message_hash)
-        .map_err(|e| Error::Verification(format!("Failed to recover address: {}", e)))?;
-    
-    Ok(recovered_address == *public_address)
+/// Prompt hash committed for a request: the whole message list when present,
+/// otherwise the plain prompt
+pub fn calculate_prompt_hash(request: &LlmRequest) -> B256 {
+    match &request.messages {
+        Some(messages) => hash_chat_messages(messages),
+        None => keccak256(request.prompt.as_bytes()),
+    }
 }
 
-/// Verify a response commitment signature
-pub fn verify_response_signature(
-    public_address: &Address,
-    domain: &EIP712Domain,
-    commitment: &LlmResponseCommitment,
-    signature: &Signature,
-) -> Result<bool> {
-    let struct_hash = calculate_response_struct_hash(commitment)?;
-    let message_hash = calculate_eip712_hash(domain, &struct_hash)?;
-    
-    let recovered_address = signature.recover_address_from_prehash(&message_hash)
-        .map_err(|e| Error::Verification(format!("Failed to recover address: {}", e)))?;
-    
-    Ok(recovered_address == *public_address)
+/// Sampling hash committed for a request: keccak256 of the parameters' JSON as
+/// sent on the wire, so `n`, `stop` and the rest can't be dropped unnoticed.
+/// Zero when no parameter is set.
+pub fn calculate_sampling_hash(sampling: &SamplingParams) -> B256 {
+    if sampling.is_empty() {
+        return B256::ZERO;
+    }
+    keccak256(serde_json::to_vec(sampling).unwrap_or_default())
 }
 
 /// Convert LlmRequest to LlmRequestCommitment
-pub fn request_to_commitment(
-    request: &LlmRequest,
-    request_id: String,
-    client_address: String,
-    max_price_per_token: String,
-    max_total_cost: String,
-) -> Result<LlmRequestCommitment> {
-    // Calculate prompt hash
-    let prompt_hash = hex::encode(keccak256(request.prompt.as_bytes()));
-    
+///
+/// The commitment's `requestId` is [`LlmRequest::id`], so it matches the id the
+/// client and executor log and record usage under. `executor` is the executor's
+/// EVM address: [`LlmRequest::executor_address`] holds its peer id, which the
+/// contract can't check against a signer. Fails if a price is not a wei amount.
+pub fn request_to_commitment(request: &LlmRequest, executor: Address) -> Result<LlmRequestCommitment> {
     Ok(LlmRequestCommitment {
-        request_id,
-        client_address,
-        executor_address: request.executor_address.clone(),
-        model_name: request.model.clone(),
-        max_tokens: request.max_tokens.unwrap_or(1000),
-        temperature: request.temperature.unwrap_or(1.0).to_string(),
-        prompt_hash,
-        max_price_per_token,
-        max_total_cost,
-        timestamp: request.deadline,
+        requestId: request.id(),
+        executor,
+        model: request.model.clone(),
+        promptHash: calculate_prompt_hash(request),
+        systemPromptHash: request.system_prompt.as_ref()
+            .map(|prompt| keccak256(prompt.as_bytes()))
+            .unwrap_or_default(),
+        maxTokens: request.max_tokens.unwrap_or(1000),
+        temperature: temperature_units(request.temperature.unwrap_or(1.0)),
+        samplingHash: calculate_sampling_hash(&request.sampling),
+        inboundPrice: parse_price(&request.inbound_price)?,
+        outboundPrice: parse_price(&request.outbound_price)?,
         nonce: request.nonce,
+        deadline: request.deadline,
     })
 }
 
 /// Convert LlmResponse to LlmResponseCommitment
+///
+/// The commitment answers `request`, signed by `client` in `domain`, and bills
+/// at its prices. Fails if the response echoes a different request id.
 pub fn response_to_commitment(
     response: &LlmResponse,
-    request_id: String,
-    executor_address: String,
-    price_per_token: String,
+    request: &LlmRequestCommitment,
+    domain: &EIP712Domain,
+    client: Address,
 ) -> Result<LlmResponseCommitment> {
-    // Calculate response hash
-    let response_hash = hex::encode(keccak256(response.content.as_bytes()));
-    
+    if let Some(echoed) = &response.request_id {
+        if *echoed != request.requestId {
+            return Err(Error::Verification(format!(
+                "Response is for request {}, expected {}",
+                echoed, request.requestId
+            )));
+        }
+    }
+
     Ok(LlmResponseCommitment {
-        request_id,
-        executor_address,
-        response_hash,
-        input_tokens: response.inbound_tokens as u32,
-        output_tokens: response.outbound_tokens as u32,
-        total_tokens: (response.inbound_tokens + response.outbound_tokens) as u32,
-        price_per_token,
-        total_cost: response.total_cost.clone(),
+        requestId: request.requestId.clone(),
+        requestHash: request.signing_hash(domain),
+        client,
+        model: response.model_used.clone(),
+        contentHash: calculate_response_hash(response),
+        inboundTokens: response.inbound_tokens as u32,
+        outboundTokens: response.outbound_tokens as u32,
+        inboundPrice: request.inboundPrice,
+        outboundPrice: request.outboundPrice,
         timestamp: std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs(),
+        success: response.error.is_none(),
     })
 }
 
+/// Temperature in the contract's fixed point, e.g. 0.7 → 7000
+fn temperature_units(temperature: f32) -> u32 {
+    (temperature * 10000.0).round() as u32
+}
+
+fn parse_price(price: &str) -> Result<U256> {
+    price.parse()
+        .map_err(|e| Error::Other(format!("Invalid price {}: {}", price, e)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use alloy::signers::local::PrivateKeySigner;
 
+    fn test_domain() -> EIP712Domain {
+        EIP712Domain::new(1, "0x1234567890123456789012345678901234567890".parse().unwrap())
+    }
+
+    fn test_request_commitment() -> LlmRequestCommitment {
+        LlmRequestCommitment {
+            requestId: "req_123".to_string(),
+            executor: "0x1234567890123456789012345678901234567890".parse().unwrap(),
+            model: "gpt-3.5-turbo".to_string(),
+            promptHash: B256::ZERO,
+            systemPromptHash: B256::ZERO,
+            maxTokens: 100,
+            temperature: 7000,
+            samplingHash: B256::ZERO,
+            inboundPrice: U256::from(1_000_000_000_000_000_000u128),
+            outboundPrice: U256::from(100_000_000_000_000_000_000u128),
+            nonce: 1,
+            deadline: 1640995200,
+        }
+    }
+
     #[test]
     fn test_domain_separator() {
-        let domain = EIP712Domain::new(1, "0x1234567890123456789012345678901234567890".parse().unwrap());
-        let separator = calculate_domain_separator(&domain).unwrap();
-        assert_eq!(separator.len(), 32);
+        let domain = test_domain();
+        let expected = keccak256(
+            [
+                keccak256("EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)").as_slice(),
+                keccak256("Lloom Network").as_slice(),
+                keccak256("1.0.0").as_slice(),
+                &U256::from(1).to_be_bytes::<32>(),
+                B256::left_padding_from(domain.verifying_contract.as_slice()).as_slice(),
+            ]
+            .concat(),
+        );
+        assert_eq!(domain.separator(), expected);
     }
 
     #[test]
     fn test_type_hashes() {
-        let request_hash = calculate_request_type_hash();
-        let response_hash = calculate_response_type_hash();
-        
-        assert_eq!(request_hash.len(), 32);
-        assert_eq!(response_hash.len(), 32);
-        assert_ne!(request_hash, response_hash);
+        // The commitments hash as the accounting contract's type strings
+        let contract = include_str!("../../../solidity/src/Accounting.sol");
+        let request_type = "LlmRequestCommitment(string requestId,address executor,string model,bytes32 promptHash,bytes32 systemPromptHash,uint32 maxTokens,uint32 temperature,bytes32 samplingHash,uint256 inboundPrice,uint256 outboundPrice,uint64 nonce,uint64 deadline)";
+        let response_type = "LlmResponseCommitment(string requestId,bytes32 requestHash,address client,string model,bytes32 contentHash,uint32 inboundTokens,uint32 outboundTokens,uint256 inboundPrice,uint256 outboundPrice,uint64 timestamp,bool success)";
+        assert!(contract.contains(&format!("\"{}\"", request_type)));
+        assert!(contract.contains(&format!("\"{}\"", response_type)));
+        assert_eq!(LlmRequestCommitment::eip712_encode_type(), request_type);
+        assert_eq!(test_request_commitment().eip712_type_hash(), keccak256(request_type));
+        assert_eq!(LlmResponseCommitment::eip712_encode_type(), response_type);
+        assert_eq!(LlmResponseCommitment::eip712_type_hash(&Default::default()), keccak256(response_type));
+        assert_eq!(chat::ChatMessage::eip712_encode_type(), "ChatMessage(string role,string content)");
+        assert_eq!(
+            chat::ChatMessageWithImages::eip712_encode_type(),
+            "ChatMessageWithImages(string role,string content,bytes32[] images)"
+        );
+        assert_eq!(
+            response::ResponseWithToolCalls::eip712_encode_type(),
+            "ResponseWithToolCalls(string content,ToolCall[] toolCalls)ToolCall(string id,string name,string arguments)"
+        );
     }
 
     #[test]
-    fn test_uint256_parsing() {
-        // Test basic functionality - just verify the function works
-        let result = parse_uint256("1000").unwrap();
-        assert_eq!(result.len(), 32);
-        
-        let result = parse_uint256("0x1000").unwrap();
-        assert_eq!(result.len(), 32);
-        
-        // Test that non-zero values are placed correctly
-        let result = parse_uint256("ff").unwrap();
-        assert_eq!(result[31], 255);
+    fn test_uint256_fields_encode_as_numbers() {
+        let commitment = test_request_commitment();
+        let encoded = commitment.eip712_encode_data();
+        // Fields 9 and 10 (after the type hash) are the prices, big-endian
+        assert_eq!(U256::from_be_slice(&encoded[8 * 32..9 * 32]), U256::from(1_000_000_000_000_000_000u128));
+        assert_eq!(U256::from_be_slice(&encoded[9 * 32..10 * 32]), U256::from(100_000_000_000_000_000_000u128));
     }
 
     #[test]
@@ -553,25 +380,225 @@ mod tests {
             .parse()
             .expect("Valid private key");
         
-        let domain = EIP712Domain::new(1, "0x1234567890123456789012345678901234567890".parse().unwrap());
+        let domain = test_domain();
+        let commitment = test_request_commitment();
+        
+        let signature = commitment.sign(&signer, &domain).unwrap();
+        let is_valid = commitment.verify_signature(&signer.address(), &domain, &signature).unwrap();
+        
+        assert!(is_valid);
+        
+        // Neither another domain nor another commitment verifies
+        let other_domain = EIP712Domain::new(2, domain.verifying_contract);
+        assert!(!commitment.verify_signature(&signer.address(), &other_domain, &signature).unwrap());
+        let other = LlmRequestCommitment { nonce: 2, ..commitment };
+        assert!(!other.verify_signature(&signer.address(), &domain, &signature).unwrap());
+    }
+
+    #[test]
+    fn test_typed_data_for_wallets() {
+        let domain = test_domain();
+        let commitment = test_request_commitment();
+        let typed_data = commitment.typed_data(&domain);
         
-        let commitment = LlmRequestCommitment {
-            request_id: "req_123".to_string(),
-            client_address: signer.address().to_string(),
-            executor_address: "0x1234567890123456789012345678901234567890".to_string(),
-            model_name: "gpt-3.5-turbo".to_string(),
-            max_tokens: 100,
-            temperature: "0.7".to_string(),
-            prompt_hash: hex::encode([0u8; 32]),
-            max_price_per_token: "1000000000000000000".to_string(),
-            max_total_cost: "100000000000000000000".to_string(),
-            timestamp: 1640995200,
+        // A wallet signing the exported JSON signs the same hash
+        let json = serde_json::to_value(&typed_data).unwrap();
+        assert_eq!(json["primaryType"], "LlmRequestCommitment");
+        assert_eq!(json["domain"]["name"], "Lloom Network");
+        assert_eq!(json["message"]["model"], "gpt-3.5-turbo");
+        assert!(json["types"]["LlmRequestCommitment"].is_array());
+        let parsed: TypedData = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.eip712_signing_hash().unwrap(), commitment.signing_hash(&domain));
+    }
+
+    #[test]
+    fn test_prompt_hash_commits_to_messages() {
+        let mut request = LlmRequest {
+            model: "gpt-4".to_string(),
+            prompt: "Hello".to_string(),
+            system_prompt: None,
+            temperature: None,
+            max_tokens: None,
+            executor_address: "0x742d35Cc6634C0532925a3b8D404cB8b3d3A5d3a".to_string(),
+            inbound_price: "500000000000000".to_string(),
+            outbound_price: "1000000000000000".to_string(),
             nonce: 1,
+            deadline: 1234567890,
+            ..Default::default()
         };
         
-        let signature = sign_request_commitment(&signer, &domain, &commitment).unwrap();
-        let is_valid = verify_request_signature(&signer.address(), &domain, &commitment, &signature).unwrap();
+        // Legacy requests hash the prompt only
+        assert_eq!(calculate_prompt_hash(&request), keccak256("Hello".as_bytes()));
         
-        assert!(is_valid);
+        let messages = vec![
+            ChatMessage::system("Be brief"),
+            ChatMessage::user("Hello"),
+            ChatMessage::assistant("Hi"),
+        ];
+        request.messages = Some(messages.clone());
+        request.version = crate::protocol::constants::LLM_REQUEST_VERSION;
+        let hash = calculate_prompt_hash(&request);
+        assert_eq!(hash, hash_chat_messages(&messages));
+        assert_ne!(hash, keccak256("Hello".as_bytes()));
+        
+        // Every message, its role and its order are committed
+        let mut changed = messages.clone();
+        changed[2].content = "Hey".to_string();
+        assert_ne!(hash, hash_chat_messages(&changed));
+        let mut changed = messages.clone();
+        changed[1].role = "assistant".to_string();
+        assert_ne!(hash, hash_chat_messages(&changed));
+        let mut changed = messages.clone();
+        changed.swap(1, 2);
+        assert_ne!(hash, hash_chat_messages(&changed));
+        assert_ne!(hash, hash_chat_messages(&messages[..2]));
+        
+        let commitment = request_to_commitment(&request, Address::repeat_byte(1)).unwrap();
+        assert_eq!(commitment.promptHash, hash);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_prompt_hash_commits_to_image_bytes() {
+        use crate::protocol::ImageInput;
+
+        let text_only = vec![ChatMessage::user("What is this?")];
+        let image = ImageInput::inline("image/png", b"png bytes");
+        let with_image = vec![ChatMessage::user_with_images("What is this?", vec![image.clone()])];
+        let hash = hash_chat_messages(&with_image);
+        assert_ne!(hash, hash_chat_messages(&text_only));
+
+        // A reference commits to the same bytes as the inline image
+        let referenced = vec![ChatMessage::user_with_images("What is this?", vec![image.reference()])];
+        assert_eq!(hash, hash_chat_messages(&referenced));
+
+        let other = vec![ChatMessage::user_with_images("What is this?", vec![ImageInput::inline("image/png", b"other bytes")])];
+        assert_ne!(hash, hash_chat_messages(&other));
+
+        // An empty image list hashes like a text-only message
+        let empty = vec![ChatMessage::user_with_images("What is this?", Vec::new())];
+        assert_eq!(hash_chat_messages(&empty), hash_chat_messages(&text_only));
+    }
+
+    #[test]
+    fn test_response_hash_commits_to_tool_calls() {
+        use crate::protocol::{FunctionCall, ToolCall};
+
+        let mut response = LlmResponse {
+            content: String::new(),
+            inbound_tokens: 1,
+            outbound_tokens: 1,
+            total_cost: "2".to_string(),
+            model_used: "gpt-4".to_string(),
+            error: None,
+            request_id: None,
+            error_code: None,
+            tool_calls: None,
+            choices: None,
+        };
+        // Responses without tool calls hash their content
+        let content_hash = calculate_response_hash(&response);
+        assert_eq!(content_hash, keccak256(b""));
+        response.tool_calls = Some(Vec::new());
+        assert_eq!(calculate_response_hash(&response), content_hash);
+
+        let call = |arguments: &str| ToolCall {
+            id: "call_1".to_string(),
+            kind: "function".to_string(),
+            function: FunctionCall { name: "get_weather".to_string(), arguments: arguments.to_string() },
+        };
+        response.tool_calls = Some(vec![call(r#"{"city":"Paris"}"#)]);
+        let hash = calculate_response_hash(&response);
+        assert_ne!(hash, content_hash);
+        response.tool_calls = Some(vec![call(r#"{"city":"Rome"}"#)]);
+        assert_ne!(calculate_response_hash(&response), hash);
+    }
+
+    #[test]
+    fn test_commitment_covers_sampling_params() {
+        let mut request = LlmRequest {
+            model: "gpt-4".to_string(),
+            prompt: "Hello".to_string(),
+            inbound_price: "1".to_string(),
+            outbound_price: "2".to_string(),
+            ..Default::default()
+        };
+        let executor = Address::repeat_byte(2);
+        // Requests without sampling parameters commit to zero
+        let plain = request_to_commitment(&request, executor).unwrap();
+        assert_eq!(plain.samplingHash, B256::ZERO);
+
+        // Asking for more choices multiplies billed output, so it is committed
+        request.sampling.n = Some(3);
+        let three = request_to_commitment(&request, executor).unwrap();
+        assert_ne!(three.samplingHash, B256::ZERO);
+        assert_ne!(three.signing_hash(&test_domain()), plain.signing_hash(&test_domain()));
+        request.sampling.n = Some(2);
+        assert_ne!(request_to_commitment(&request, executor).unwrap().samplingHash, three.samplingHash);
+
+        // So is every other parameter
+        request.sampling.n = Some(3);
+        request.sampling.stop = Some(vec!["\n".to_string()]);
+        assert_ne!(request_to_commitment(&request, executor).unwrap().samplingHash, three.samplingHash);
+    }
+
+    #[test]
+    fn test_commitments_carry_request_id() {
+        let mut request = LlmRequest {
+            model: "gpt-4".to_string(),
+            prompt: "Hello".to_string(),
+            system_prompt: Some("Be brief".to_string()),
+            temperature: Some(0.7),
+            max_tokens: None,
+            // Clients address executors by peer id
+            executor_address: libp2p::PeerId::random().to_string(),
+            inbound_price: "500000000000000".to_string(),
+            outbound_price: "1000000000000000".to_string(),
+            nonce: 1,
+            deadline: 1234567890,
+            request_id: Some("req_1".to_string()),
+            ..Default::default()
+        };
+        let mut response = LlmResponse {
+            content: "Hi".to_string(),
+            inbound_tokens: 1,
+            outbound_tokens: 1,
+            total_cost: "1500000000000000".to_string(),
+            model_used: "gpt-4".to_string(),
+            error: None,
+            request_id: Some("req_1".to_string()),
+            error_code: None,
+            tool_calls: None,
+            choices: None,
+        };
+        let domain = test_domain();
+        let client: Address = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266".parse().unwrap();
+        let executor = Address::repeat_byte(2);
+        
+        let commitment = request_to_commitment(&request, executor).unwrap();
+        assert_eq!(commitment.requestId, "req_1");
+        assert_eq!(commitment.executor, executor);
+        assert_eq!(commitment.systemPromptHash, keccak256("Be brief"));
+        assert_eq!(commitment.temperature, 7000);
+        assert_eq!(commitment.inboundPrice, U256::from(500_000_000_000_000u64));
+        let answer = response_to_commitment(&response, &commitment, &domain, client).unwrap();
+        assert_eq!(answer.requestId, "req_1");
+        assert_eq!(answer.requestHash, commitment.signing_hash(&domain));
+        assert_eq!(answer.client, client);
+        assert_eq!(answer.outboundPrice, commitment.outboundPrice);
+        assert!(answer.success);
+        
+        // A response echoing another request is refused
+        response.request_id = Some("req_2".to_string());
+        assert!(response_to_commitment(&response, &commitment, &domain, client).is_err());
+        
+        // Legacy requests fall back to their hash
+        request.request_id = None;
+        let commitment = request_to_commitment(&request, executor).unwrap();
+        assert_eq!(commitment.requestId, request.id());
+        assert!(commitment.requestId.starts_with("0x"));
+
+        // Prices must be wei amounts
+        request.inbound_price = "cheap".to_string();
+        assert!(request_to_commitment(&request, executor).is_err());
+    }
+}
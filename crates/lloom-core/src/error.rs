@@ -29,6 +29,10 @@ pub enum Error {
     #[error("Verification error: {0}")]
     Verification(String),
     
+    /// A signed message that was already accepted
+    #[error("Replayed message: {0}")]
+    Replay(String),
+    
     /// Invalid signer error
     #[error("Invalid signer: expected {expected}, but recovered {recovered}")]
     InvalidSigner {
@@ -86,6 +90,9 @@ mod tests {
         let alloy_error = Error::Alloy("RPC error".to_string());
         assert_eq!(format!("{}", alloy_error), "Alloy error: RPC error");
 
+        let replay_error = Error::Replay("nonce 7 already used".to_string());
+        assert_eq!(format!("{}", replay_error), "Replayed message: nonce 7 already used");
+
         let other_error = Error::Other("Unknown error".to_string());
         assert_eq!(format!("{}", other_error), "Unknown error");
     }
@@ -2,26 +2,50 @@
 //!
 //! This module provides a unified cryptographic identity that works with both
 //! libp2p networking and Ethereum blockchain operations.
+//!
+//! Keys can be kept in Web3 Secret Storage (v3) keystore files, the encrypted
+//! JSON format used by Ethereum wallets, or derived from a BIP-39 mnemonic.
 
-use alloy::primitives::Address;
-use alloy::signers::local::PrivateKeySigner;
+use alloy::primitives::{Address, B256};
+use alloy::signers::local::{MnemonicBuilder, PrivateKeySigner, coins_bip39::English};
+use alloy::primitives::Signature;
+use std::path::Path;
+use std::sync::Arc;
 use libp2p::identity::{Keypair, PeerId, secp256k1};
+use crate::eip712::TypedData;
 use crate::error::{Error, Result};
+use crate::signer::Signer;
+use crate::signing::{LEGACY_SIGNATURE_VERSION, SIGNATURE_VERSION};
+
+/// Environment variable holding the password of a keystore file.
+pub const KEYSTORE_PASSWORD_ENV: &str = "LLOOM_KEYSTORE_PASSWORD";
+
+/// BIP-32 path of the first Ethereum account, used by most wallets.
+pub const DEFAULT_DERIVATION_PATH: &str = "m/44'/60'/0'/0/0";
 
 /// A unified identity for nodes in the Lloom network.
 ///
 /// This struct combines a secp256k1 private key that can be used for both
 /// P2P networking (via libp2p) and blockchain operations (via alloy).
+///
+/// Messages are signed by [`Identity::signer`], which is the wallet itself
+/// unless [`Identity::with_signer`] moved signing elsewhere, such as to a
+/// [`RemoteSigner`](crate::signer::RemoteSigner). The identity is itself a
+/// [`Signer`] for that key.
 #[derive(Clone)]
 pub struct Identity {
-    /// The wallet containing the secp256k1 private key.
+    /// The wallet containing the secp256k1 private key of the node.
     pub wallet: PrivateKeySigner,
     /// The libp2p keypair, derived from the wallet's private key.
     pub p2p_keypair: Keypair,
     /// The libp2p PeerId, derived from the p2p_keypair's public key.
     pub peer_id: PeerId,
-    /// The EVM-compatible address, derived from the wallet's public key.
+    /// The EVM-compatible address messages are signed for.
     pub evm_address: Address,
+    /// Signs protocol messages and EIP-712 commitments for `evm_address`.
+    pub signer: Arc<dyn Signer>,
+    /// Envelope version of the protocol messages the identity signs.
+    pub signature_version: u8,
 }
 
 impl Identity {
@@ -43,13 +67,37 @@ impl Identity {
         let evm_address = wallet.address();
 
         Ok(Self {
+            signer: Arc::new(wallet.clone()),
             wallet,
             p2p_keypair,
             peer_id,
             evm_address,
+            signature_version: SIGNATURE_VERSION,
         })
     }
 
+    /// Signs protocol messages as envelope `version`: 2, the default, or 1 so
+    /// that peers which predate version 2 accept them while the network
+    /// upgrades. Version 1 needs a signer that signs bare hashes, and messages
+    /// only upgraded peers understand are still signed as version 2.
+    pub fn with_signature_version(mut self, version: u8) -> Result<Self> {
+        if version != SIGNATURE_VERSION && version != LEGACY_SIGNATURE_VERSION {
+            return Err(Error::Identity(format!(
+                "Unsupported signature version {}; use {} or {}",
+                version, LEGACY_SIGNATURE_VERSION, SIGNATURE_VERSION
+            )));
+        }
+        self.signature_version = version;
+        Ok(self)
+    }
+
+    /// Signs with `signer` instead of the wallet, which remains the network key.
+    pub fn with_signer(mut self, signer: Arc<dyn Signer>) -> Self {
+        self.evm_address = signer.address();
+        self.signer = signer;
+        self
+    }
+
     /// Generates a completely new, random identity.
     pub fn generate() -> Self {
         let wallet = PrivateKeySigner::random();
@@ -57,11 +105,86 @@ impl Identity {
     }
 
     /// Loads an identity from a hex-encoded private key string.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(private_key: &str) -> Result<Self> {
         let wallet: PrivateKeySigner = private_key.parse()
             .map_err(|e| Error::Identity(format!("Failed to parse private key: {}", e)))?;
         Self::new(wallet)
     }
+
+    /// Loads an identity from a Web3 Secret Storage (v3) keystore file.
+    pub fn from_keystore(path: impl AsRef<Path>, password: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let wallet = PrivateKeySigner::decrypt_keystore(path, password)
+            .map_err(|e| Error::Identity(format!("Failed to decrypt keystore {}: {}", path.display(), e)))?;
+        Self::new(wallet)
+    }
+
+    /// Loads an identity from a keystore file, with the password from
+    /// [`KEYSTORE_PASSWORD_ENV`] or else asked for on the terminal.
+    pub fn from_keystore_prompt(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let password = keystore_password(&format!("Password for keystore {}: ", path.display()))?;
+        Self::from_keystore(path, &password)
+    }
+
+    /// Saves the private key as a Web3 Secret Storage (v3) keystore file at `path`.
+    pub fn save_keystore(&self, path: impl AsRef<Path>, password: &str) -> Result<()> {
+        let path = path.as_ref();
+        let name = path.file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| Error::Identity(format!("Invalid keystore path {}", path.display())))?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        PrivateKeySigner::encrypt_keystore(dir, &mut rand::thread_rng(), self.wallet.to_bytes(), password, Some(name))
+            .map_err(|e| Error::Identity(format!("Failed to write keystore {}: {}", path.display(), e)))?;
+        Ok(())
+    }
+
+    /// Derives an identity from a BIP-39 mnemonic phrase along a BIP-32 path,
+    /// such as [`DEFAULT_DERIVATION_PATH`].
+    pub fn from_mnemonic(phrase: &str, derivation_path: &str) -> Result<Self> {
+        let wallet = MnemonicBuilder::<English>::default()
+            .phrase(phrase.trim())
+            .derivation_path(derivation_path)
+            .and_then(|builder| builder.build())
+            .map_err(|e| Error::Identity(format!("Failed to derive key from mnemonic: {}", e)))?;
+        Self::new(wallet)
+    }
+}
+
+/// Password for a keystore, from [`KEYSTORE_PASSWORD_ENV`] or else read from
+/// the terminal without echo after showing `prompt`.
+pub fn keystore_password(prompt: &str) -> Result<String> {
+    if let Ok(password) = std::env::var(KEYSTORE_PASSWORD_ENV) {
+        return Ok(password);
+    }
+    rpassword::prompt_password(prompt)
+        .map_err(|e| Error::Identity(format!("Failed to read keystore password: {}", e)))
+}
+
+impl Signer for Identity {
+    fn address(&self) -> Address {
+        self.evm_address
+    }
+
+    fn sign_message(&self, message: &[u8]) -> Result<Signature> {
+        self.signer.sign_message(message)
+    }
+
+    fn sign_typed_data(&self, typed_data: &TypedData) -> Result<Signature> {
+        self.signer.sign_typed_data(typed_data)
+    }
+
+    fn signature_version(&self) -> u8 {
+        self.signature_version
+    }
+
+    fn sign_hash(&self, hash: &B256) -> Result<Signature> {
+        self.signer.sign_hash(hash)
+    }
 }
 
 impl std::fmt::Debug for Identity {
@@ -137,6 +260,39 @@ mod tests {
         assert_eq!(identity1.evm_address, identity2.evm_address);
     }
 
+    #[test]
+    fn test_keystore_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.json");
+        let identity = Identity::generate();
+        identity.save_keystore(&path, "secret").unwrap();
+        
+        // The file is v3 JSON without the plaintext key
+        let keystore: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(keystore["version"], 3);
+        assert!(!keystore.to_string().contains(&hex::encode(identity.wallet.to_bytes())));
+        
+        let loaded = Identity::from_keystore(&path, "secret").unwrap();
+        assert_eq!(loaded.peer_id, identity.peer_id);
+        assert_eq!(loaded.evm_address, identity.evm_address);
+        assert!(Identity::from_keystore(&path, "wrong").is_err());
+    }
+
+    #[test]
+    fn test_identity_from_mnemonic() {
+        // The standard test mnemonic derives the well-known development accounts
+        let phrase = "test test test test test test test test test test test junk";
+        let identity = Identity::from_mnemonic(phrase, DEFAULT_DERIVATION_PATH).unwrap();
+        let expected = Identity::from_str("ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80").unwrap();
+        assert_eq!(identity.evm_address, expected.evm_address);
+        
+        let second = Identity::from_mnemonic(phrase, "m/44'/60'/0'/0/1").unwrap();
+        assert_ne!(second.evm_address, identity.evm_address);
+        
+        assert!(Identity::from_mnemonic("not a mnemonic", DEFAULT_DERIVATION_PATH).is_err());
+        assert!(Identity::from_mnemonic(phrase, "not a path").is_err());
+    }
+
     #[test]
     fn test_identity_uniqueness() {
         let identity1 = Identity::generate();
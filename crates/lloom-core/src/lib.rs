@@ -27,6 +27,7 @@
 //! - Kademlia DHT for service discovery
 //! - Gossipsub for network announcements
 //! - Request-Response protocol for LLM interactions
+//! - Stream protocol for token-by-token LLM responses ([`streaming`])
 //! - Bootstrap and peer management
 //!
 //! ### Message Protocol ([`protocol`])
@@ -37,6 +38,11 @@
 //! - Service role definitions and discovery keys
 //! - Message routing and validation
 //!
+//! ### Output Validation ([`schema`])
+//!
+//! Checks structured model output against the JSON Schema a client requested
+//! through [`ResponseFormat`].
+//!
 //! ### Cryptographic Signing ([`signing`])
 //!
 //! Provides EIP-712 compliant message signing and verification:
@@ -96,26 +102,44 @@
 //!     outbound_price: "1000000000000000".to_string(),
 //!     nonce: 1,
 //!     deadline: 1234567890,
+//!     messages: None,
+//!     version: 1,
+//!     request_id: None,
+//!     tools: None,
+//!     tool_choice: None,
+//!     response_format: None,
+//!     sampling: Default::default(),
+//!     quote_id: None,
+//!     session: None,
+//!     credential: None,
 //! };
 //!
-//! let signed_request = request.sign_blocking(&identity.wallet)?;
+//! let signed_request = request.sign_blocking(&identity)?;
 //! # Ok::<(), lloom_core::Error>(())
 //! ```
 
 pub mod eip712;
 pub mod identity;
 pub mod network;
+pub mod org;
 pub mod protocol;
+pub mod schema;
+pub mod session;
+pub mod signer;
 pub mod signing;
+pub mod streaming;
 pub mod error;
 
 pub use eip712::*;
 pub use identity::Identity;
+pub use session::{SessionCertificate, SessionGrant, SessionKey};
+pub use org::{Membership, OrgCredential};
+pub use signer::Signer;
 pub use network::{LloomBehaviour, LloomEvent};
-pub use protocol::{LlmRequest, LlmResponse, UsageRecord, RequestMessage, ResponseMessage, SignedLlmRequest, SignedLlmResponse};
-pub use signing::{SignedMessage, SignableMessage, VerificationConfig, sign_message_blocking, verify_signed_message, verify_signed_message_basic, verify_signed_message_permissive};
+pub use protocol::{BilledRequest, ChatMessage, EmbeddingRequest, EmbeddingResponse, LlmErrorCode, LlmRequest, LlmResponse, Quote, QuoteRequest, ResponseFormat, SamplingParams, Tool, ToolCall, UsageRecord, RequestMessage, ResponseMessage, SignedLlmRequest, SignedLlmResponse};
+pub use signing::{ReplayGuard, SignedMessage, SignableMessage, VerificationConfig, canonical_json, sign_message_blocking, signing_bytes, verify_signed_message, verify_signed_message_basic, verify_signed_message_permissive};
 pub use error::{Error, Result};
 
 // Re-export commonly used types
 pub use libp2p::{PeerId, Multiaddr};
-pub use alloy::primitives::Address;
\ No newline at end of file
+pub use alloy::primitives::{Address, U256};
\ No newline at end of file
@@ -3,24 +3,33 @@
 //! This module defines the composite libp2p NetworkBehaviour that combines
 //! various protocols for discovery, communication, and messaging.
 
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncWrite};
 use libp2p::{
     gossipsub::{self, MessageAuthenticity, ValidationMode},
     kad::{self, store::MemoryStore},
+    multiaddr::Protocol,
     request_response::{self, ProtocolSupport},
     swarm::NetworkBehaviour,
-    StreamProtocol,
+    Multiaddr, StreamProtocol, Swarm, SwarmBuilder,
 };
-use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use std::{io, net::Ipv4Addr, str::FromStr, time::Duration};
 
-use crate::protocol::{RequestMessage, ResponseMessage, constants::LLM_PROTOCOL};
+use crate::protocol::{RequestMessage, ResponseMessage, constants::{self, LLM_PROTOCOLS}};
 use crate::error::Result;
 
 /// The custom event type that the behaviour will emit to the Swarm owner.
+// Events are handled one at a time as they come off the swarm, so the size of
+// the request-response variant does not matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum LloomEvent {
     RequestResponse(request_response::Event<RequestMessage, ResponseMessage>),
     Kademlia(kad::Event),
     Gossipsub(gossipsub::Event),
+    /// Never emitted: streams are handed out through [`libp2p_stream::Control`].
+    Stream,
 }
 
 // Implement From<T> for LloomEvent for each inner event type
@@ -42,6 +51,12 @@ impl From<gossipsub::Event> for LloomEvent {
     }
 }
 
+impl From<()> for LloomEvent {
+    fn from(_: ()) -> Self {
+        LloomEvent::Stream
+    }
+}
+
 /// The main network behaviour struct combining all protocols.
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "LloomEvent")]
@@ -53,7 +68,10 @@ pub struct LloomBehaviour {
     pub gossipsub: gossipsub::Behaviour,
     
     /// A custom request-response protocol for direct LLM queries.
-    pub request_response: request_response::cbor::Behaviour<RequestMessage, ResponseMessage>,
+    pub request_response: request_response::Behaviour<LloomCodec>,
+    
+    /// Raw streams for incremental LLM responses (see [`crate::streaming`]).
+    pub stream: libp2p_stream::Behaviour,
 }
 
 impl LloomBehaviour {
@@ -84,13 +102,13 @@ impl LloomBehaviour {
         )
         .map_err(|e| crate::error::Error::Network(format!("Failed to create gossipsub behaviour: {}", e)))?;
         
-        // Configure request-response
-        let protocols = std::iter::once((
-            StreamProtocol::new(LLM_PROTOCOL),
-            ProtocolSupport::Full,
-        ));
+        // Configure request-response, with every protocol version spoken
+        let protocols = LLM_PROTOCOLS
+            .iter()
+            .map(|(_, protocol)| (StreamProtocol::new(protocol), ProtocolSupport::Full));
         
-        let request_response = request_response::cbor::Behaviour::new(
+        let request_response = request_response::Behaviour::with_codec(
+            LloomCodec::default(),
             protocols,
             request_response::Config::default()
                 .with_request_timeout(Duration::from_secs(300)),
@@ -100,10 +118,172 @@ impl LloomBehaviour {
             kademlia,
             gossipsub,
             request_response,
+            stream: libp2p_stream::Behaviour::new(),
         })
     }
 }
 
+/// CBOR codec for [`RequestMessage`]s and [`ResponseMessage`]s that does not
+/// send a message over a protocol version older than the message.
+///
+/// Messages a peer does not know are read as `Unknown` rather than failing the
+/// stream, so newer peers can add messages without breaking older ones.
+#[derive(Clone)]
+pub struct LloomCodec {
+    inner: request_response::cbor::codec::Codec<RequestMessage, ResponseMessage>,
+}
+
+impl Default for LloomCodec {
+    fn default() -> Self {
+        // Requests may carry inline images, well past the codec's 1 MiB default
+        Self {
+            inner: request_response::cbor::codec::Codec::default()
+                .set_request_size_maximum(constants::MAX_REQUEST_BYTES as u64),
+        }
+    }
+}
+
+/// Protocol version of a negotiated request-response protocol
+pub fn protocol_version(protocol: &StreamProtocol) -> Option<u8> {
+    LLM_PROTOCOLS
+        .iter()
+        .find(|(_, name)| *name == protocol.as_ref())
+        .map(|(version, _)| *version)
+}
+
+/// Check that peers speaking `protocol` understand a message of `version`
+fn check_version(protocol: &StreamProtocol, version: u8) -> io::Result<()> {
+    match protocol_version(protocol) {
+        Some(negotiated) if negotiated >= version => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Message of protocol version {} cannot be sent over {}", version, protocol),
+        )),
+    }
+}
+
+#[async_trait]
+impl request_response::Codec for LloomCodec {
+    type Protocol = StreamProtocol;
+    type Request = RequestMessage;
+    type Response = ResponseMessage;
+
+    async fn read_request<T>(&mut self, protocol: &StreamProtocol, io: &mut T) -> io::Result<RequestMessage>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        self.inner.read_request(protocol, io).await
+    }
+
+    async fn read_response<T>(&mut self, protocol: &StreamProtocol, io: &mut T) -> io::Result<ResponseMessage>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        self.inner.read_response(protocol, io).await
+    }
+
+    async fn write_request<T>(&mut self, protocol: &StreamProtocol, io: &mut T, request: RequestMessage) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        check_version(protocol, request.protocol_version())?;
+        self.inner.write_request(protocol, io, request).await
+    }
+
+    async fn write_response<T>(&mut self, protocol: &StreamProtocol, io: &mut T, response: ResponseMessage) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        check_version(protocol, response.protocol_version())?;
+        self.inner.write_response(protocol, io, response).await
+    }
+}
+
+/// Transports a node listens on and dials over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// TCP with noise and yamux
+    #[default]
+    Tcp,
+    /// QUIC (`/udp/<port>/quic-v1`)
+    Quic,
+    /// TCP and QUIC side by side, on the same port number
+    Dual,
+}
+
+impl Transport {
+    /// Whether TCP is among the transports
+    pub fn tcp(self) -> bool {
+        matches!(self, Transport::Tcp | Transport::Dual)
+    }
+
+    /// Whether QUIC is among the transports
+    pub fn quic(self) -> bool {
+        matches!(self, Transport::Quic | Transport::Dual)
+    }
+
+    /// Addresses to listen on, on all IPv4 interfaces at `port`
+    pub fn listen_addrs(self, port: u16) -> Vec<Multiaddr> {
+        let ip = Multiaddr::empty().with(Protocol::Ip4(Ipv4Addr::UNSPECIFIED));
+        let mut addrs = Vec::new();
+        if self.tcp() {
+            addrs.push(ip.clone().with(Protocol::Tcp(port)));
+        }
+        if self.quic() {
+            addrs.push(ip.with(Protocol::Udp(port)).with(Protocol::QuicV1));
+        }
+        addrs
+    }
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(Transport::Tcp),
+            "quic" => Ok(Transport::Quic),
+            "dual" => Ok(Transport::Dual),
+            _ => Err(format!("Unknown transport {} (expected tcp, quic or dual)", s)),
+        }
+    }
+}
+
+/// Whether `addr` is a QUIC address
+pub fn is_quic(addr: &Multiaddr) -> bool {
+    addr.iter().any(|protocol| matches!(protocol, Protocol::QuicV1))
+}
+
+/// Build a swarm running [`LloomBehaviour`] for `identity` over `transport`.
+pub fn build_swarm(identity: &crate::identity::Identity, transport: Transport) -> Result<Swarm<LloomBehaviour>> {
+    let behaviour = LloomBehaviour::new(identity)?;
+    let builder = SwarmBuilder::with_existing_identity(identity.p2p_keypair.clone()).with_tokio();
+    let network_error = |e: &dyn std::fmt::Display| crate::error::Error::Network(format!("Failed to build swarm: {}", e));
+
+    let swarm = match transport {
+        Transport::Tcp => builder
+            .with_tcp(libp2p::tcp::Config::default(), libp2p::noise::Config::new, libp2p::yamux::Config::default)
+            .map_err(|e| network_error(&e))?
+            .with_behaviour(|_| behaviour)
+            .map_err(|e| network_error(&e))?
+            .build(),
+        Transport::Quic => builder
+            .with_quic()
+            .with_behaviour(|_| behaviour)
+            .map_err(|e| network_error(&e))?
+            .build(),
+        Transport::Dual => builder
+            .with_tcp(libp2p::tcp::Config::default(), libp2p::noise::Config::new, libp2p::yamux::Config::default)
+            .map_err(|e| network_error(&e))?
+            .with_quic()
+            .with_behaviour(|_| behaviour)
+            .map_err(|e| network_error(&e))?
+            .build(),
+    };
+    Ok(swarm)
+}
+
 /// Helper functions for network operations.
 pub mod helpers {
     use super::*;
@@ -149,6 +329,100 @@ mod tests {
         assert!(behaviour.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_codec_versions() {
+        use request_response::Codec;
+
+        let mut codec = LloomCodec::default();
+        let legacy = StreamProtocol::new(constants::LEGACY_LLM_PROTOCOL);
+        assert_eq!(protocol_version(&legacy), Some(constants::MIN_PROTOCOL_VERSION));
+        assert_eq!(protocol_version(&StreamProtocol::new(constants::LLM_PROTOCOL)), Some(constants::PROTOCOL_VERSION));
+
+        // Messages of the oldest version go over every protocol
+        let response = ResponseMessage::LlmResponse(crate::protocol::LlmResponse {
+            content: "Hello".to_string(),
+            inbound_tokens: 1,
+            outbound_tokens: 1,
+            total_cost: "0".to_string(),
+            model_used: "gpt-4".to_string(),
+            error: None,
+            request_id: None,
+            error_code: None,
+            tool_calls: None,
+            choices: None,
+        });
+        let mut bytes = Vec::new();
+        codec.write_response(&legacy, &mut bytes, response).await.unwrap();
+        let read = codec.read_response(&legacy, &mut futures::io::Cursor::new(bytes)).await.unwrap();
+        assert!(matches!(read, ResponseMessage::LlmResponse(response) if response.content == "Hello"));
+
+        // Unknown messages are never sent
+        let mut bytes = Vec::new();
+        assert!(codec.write_request(&legacy, &mut bytes, RequestMessage::Unknown).await.is_err());
+        assert!(bytes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_codec_keeps_new_messages_off_legacy_protocol() {
+        use crate::protocol::{CancelRequest, QuoteRequest};
+        use crate::signing::SignableMessage;
+        use request_response::Codec;
+
+        let identity = Identity::generate();
+        let mut codec = LloomCodec::default();
+        let legacy = StreamProtocol::new(constants::LEGACY_LLM_PROTOCOL);
+        let current = StreamProtocol::new(constants::LLM_PROTOCOL);
+
+        let cancel = CancelRequest {
+            request_id: "request-1".to_string(),
+            executor_address: identity.peer_id.to_string(),
+            reason: None,
+        };
+        let quote = QuoteRequest {
+            model: "gpt-4".to_string(),
+            messages: Vec::new(),
+            max_tokens: Some(100),
+            executor_address: identity.peer_id.to_string(),
+        };
+        let requests = [
+            RequestMessage::CancelRequest(cancel.sign_blocking(&identity).unwrap()),
+            RequestMessage::QuoteRequest(quote.sign_blocking(&identity).unwrap()),
+        ];
+
+        for request in requests {
+            assert_eq!(request.protocol_version(), constants::PROTOCOL_VERSION);
+
+            // Legacy peers cannot decode messages added since
+            let mut bytes = Vec::new();
+            let error = codec.write_request(&legacy, &mut bytes, request.clone()).await.unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+            assert!(bytes.is_empty());
+
+            let mut bytes = Vec::new();
+            codec.write_request(&current, &mut bytes, request).await.unwrap();
+            assert!(!bytes.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_messages_are_read() {
+        use request_response::Codec;
+
+        /// A message enum of a newer peer
+        #[derive(serde::Serialize, serde::Deserialize)]
+        enum NewerRequestMessage {
+            Renegotiate { window: u32 },
+        }
+
+        let protocol = StreamProtocol::new(constants::LLM_PROTOCOL);
+        let mut newer = request_response::cbor::codec::Codec::<NewerRequestMessage, ResponseMessage>::default();
+        let mut bytes = Vec::new();
+        newer.write_request(&protocol, &mut bytes, NewerRequestMessage::Renegotiate { window: 4 }).await.unwrap();
+
+        let read = LloomCodec::default().read_request(&protocol, &mut futures::io::Cursor::new(bytes)).await.unwrap();
+        assert!(matches!(read, RequestMessage::Unknown));
+    }
+
     #[test]
     fn test_service_role_kad_keys() {
         let executor_key = ServiceRole::Executor.to_kad_key();
@@ -161,12 +435,105 @@ mod tests {
 
     #[tokio::test]
     async fn test_behaviour_components() {
+        use libp2p::{PeerId, core::upgrade::UpgradeInfo, swarm::{ConnectionHandler, ConnectionId}};
+
         let identity = Identity::generate();
-        let _behaviour = LloomBehaviour::new(&identity).unwrap();
+        let mut behaviour = LloomBehaviour::new(&identity).unwrap();
         
-        // Ensure all components are properly initialized
-        // This is a basic structural test - just verify the behaviour was created
-        assert!(true); // Basic smoke test
+        // Inbound LLM requests are accepted over every protocol version
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/9000".parse().unwrap();
+        let handler = behaviour.request_response
+            .handle_established_inbound_connection(ConnectionId::new_unchecked(0), PeerId::random(), &addr, &addr)
+            .unwrap();
+        let protocols: Vec<String> = handler.listen_protocol().upgrade().protocol_info()
+            .map(|protocol| protocol.to_string())
+            .collect();
+        assert!(protocols.contains(&constants::LLM_PROTOCOL.to_string()));
+        assert!(protocols.contains(&constants::LEGACY_LLM_PROTOCOL.to_string()));
+    }
+
+    /// Listen over `transport` on an ephemeral local port, returning the
+    /// address of each transport
+    async fn listen_locally(swarm: &mut Swarm<LloomBehaviour>, transport: Transport) -> Vec<Multiaddr> {
+        use futures::StreamExt;
+        use libp2p::swarm::SwarmEvent;
+
+        let local = Multiaddr::empty().with(Protocol::Ip4(Ipv4Addr::LOCALHOST));
+        let mut wanted = Vec::new();
+        if transport.tcp() {
+            wanted.push(local.clone().with(Protocol::Tcp(0)));
+        }
+        if transport.quic() {
+            wanted.push(local.with(Protocol::Udp(0)).with(Protocol::QuicV1));
+        }
+        for addr in &wanted {
+            swarm.listen_on(addr.clone()).unwrap();
+        }
+        let mut addrs = Vec::new();
+        while addrs.len() < wanted.len() {
+            if let SwarmEvent::NewListenAddr { address, .. } = swarm.select_next_some().await {
+                addrs.push(address);
+            }
+        }
+        addrs
+    }
+
+    /// Have `dialer` dial `listener` at `addr`, returning whether they connected
+    async fn connects(dialer: &mut Swarm<LloomBehaviour>, listener: &mut Swarm<LloomBehaviour>, addr: &Multiaddr) -> bool {
+        use futures::StreamExt;
+        use libp2p::swarm::SwarmEvent;
+
+        if dialer.dial(addr.clone()).is_err() {
+            return false;
+        }
+        let outcome = async {
+            loop {
+                tokio::select! {
+                    event = dialer.select_next_some() => match event {
+                        SwarmEvent::ConnectionEstablished { .. } => return true,
+                        SwarmEvent::OutgoingConnectionError { .. } => return false,
+                        _ => {}
+                    },
+                    _ = listener.select_next_some() => {}
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), outcome).await.unwrap_or(false)
+    }
+
+    #[test]
+    fn test_transport_listen_addrs() {
+        assert_eq!(Transport::Tcp.listen_addrs(9000), vec!["/ip4/0.0.0.0/tcp/9000".parse::<Multiaddr>().unwrap()]);
+        let dual = Transport::Dual.listen_addrs(9000);
+        assert_eq!(dual.len(), 2);
+        assert!(!is_quic(&dual[0]));
+        assert_eq!(dual[1], "/ip4/0.0.0.0/udp/9000/quic-v1".parse::<Multiaddr>().unwrap());
+        assert_eq!("quic".parse::<Transport>().unwrap(), Transport::Quic);
+        assert!("udp".parse::<Transport>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_quic_only_and_dual_stack_nodes_connect() {
+        let dual_identity = Identity::generate();
+        let mut dual = build_swarm(&dual_identity, Transport::Dual).unwrap();
+        let dual_addrs = listen_locally(&mut dual, Transport::Dual).await;
+        let dual_quic = dual_addrs.iter().find(|addr| is_quic(addr)).unwrap().clone();
+        let dual_tcp = dual_addrs.iter().find(|addr| !is_quic(addr)).unwrap().clone();
+
+        // QUIC-only and TCP-only nodes each reach the dual-stack node
+        let mut quic = build_swarm(&Identity::generate(), Transport::Quic).unwrap();
+        assert!(connects(&mut quic, &mut dual, &dual_quic).await);
+        let mut tcp = build_swarm(&Identity::generate(), Transport::Tcp).unwrap();
+        assert!(connects(&mut tcp, &mut dual, &dual_tcp).await);
+
+        // The dual-stack node reaches a QUIC-only node
+        let mut quic_listener = build_swarm(&Identity::generate(), Transport::Quic).unwrap();
+        let quic_addrs = listen_locally(&mut quic_listener, Transport::Quic).await;
+        assert!(connects(&mut dual, &mut quic_listener, &quic_addrs[0]).await);
+
+        // A QUIC-only node cannot dial TCP
+        let mut quic = build_swarm(&Identity::generate(), Transport::Quic).unwrap();
+        assert!(!connects(&mut quic, &mut dual, &dual_tcp).await);
     }
 
     mod helpers_tests {
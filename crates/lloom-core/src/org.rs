@@ -0,0 +1,80 @@
+//! Organization membership credentials.
+//!
+//! An organization can run executors for its own staff. Its org key signs a
+//! [`Membership`] for each member address, and requests carry the resulting
+//! [`OrgCredential`]. Executors configured with the org check credentials with
+//! [`verify_membership`] to serve only members, or to bill them at a discount.
+//! Models announced as private to an org are not listed publicly by validators.
+
+use crate::error::{Error, Result};
+use crate::signing::{SIGNATURE_VERSION, SignableMessage, SignedMessage, VerificationConfig, verify_signed_message};
+use alloy::primitives::Address;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Membership of an address in the organization whose key signs it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Membership {
+    /// Member address, the address billed for the member's requests
+    pub member: Address,
+    /// Unix timestamp after which the membership lapses
+    pub expires_at: u64,
+}
+
+impl SignableMessage for Membership {
+    const MESSAGE_TYPE: &'static str = "Membership";
+    const MIN_SIGNATURE_VERSION: u8 = SIGNATURE_VERSION;
+}
+
+/// A membership signed by the org key
+pub type OrgCredential = SignedMessage<Membership>;
+
+/// Check that `credential` makes `member` a member of `org` at `now`.
+///
+/// Credentials must use the current envelope version.
+pub fn verify_membership(credential: &OrgCredential, org: Address, member: Address, now: u64) -> Result<()> {
+    let config = VerificationConfig::permissive().require_version(SIGNATURE_VERSION);
+    verify_signed_message(credential, &config)?;
+
+    if credential.signer != org {
+        return Err(Error::Verification(format!("Credential is issued by {}, not {}", credential.signer, org)));
+    }
+    if credential.payload.member != member {
+        return Err(Error::Verification(format!("Credential is for {}, not {}", credential.payload.member, member)));
+    }
+    if now >= credential.payload.expires_at {
+        return Err(Error::Verification(format!("Credential expired at {}", credential.payload.expires_at)));
+    }
+    Ok(())
+}
+
+/// Read a credential file
+pub fn load_credential(path: impl AsRef<Path>) -> Result<OrgCredential> {
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::Identity;
+
+    #[test]
+    fn test_membership_credentials() {
+        let org = Identity::generate();
+        let member = Identity::generate().evm_address;
+        let credential = Membership { member, expires_at: 2000 }.sign_blocking(&org).unwrap();
+        assert!(verify_membership(&credential, org.evm_address, member, 1000).is_ok());
+
+        // Only for the member, from the org, until it lapses
+        assert!(verify_membership(&credential, org.evm_address, org.evm_address, 1000).is_err());
+        assert!(verify_membership(&credential, member, member, 1000).is_err());
+        assert!(verify_membership(&credential, org.evm_address, member, 2000).is_err());
+
+        // Members cannot extend their own membership
+        let extended = OrgCredential {
+            payload: Membership { member, expires_at: 3000 },
+            ..credential
+        };
+        assert!(verify_membership(&extended, org.evm_address, member, 2500).is_err());
+    }
+}
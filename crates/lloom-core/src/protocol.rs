@@ -4,7 +4,9 @@
 //! communication between nodes in the network.
 
 use serde::{Deserialize, Serialize};
-use alloy::primitives::Address;
+use alloy::primitives::{Address, U256, keccak256};
+use crate::org::OrgCredential;
+use crate::session::{SessionCertificate, verify_session};
 use crate::signing::{SignedMessage, SignableMessage};
 use std::collections::HashMap;
 
@@ -31,6 +33,444 @@ pub struct LlmRequest {
     pub nonce: u64,
     /// Unix timestamp deadline for request validity
     pub deadline: u64,
+    /// Full conversation (request version 2+). When present it supersedes `prompt`
+    /// and `system_prompt`, which are kept as a fallback for older executors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub messages: Option<Vec<ChatMessage>>,
+    /// Request format version. Omitted on the wire for version 1 so that legacy
+    /// requests serialize, and therefore sign, exactly as before.
+    #[serde(default = "legacy_request_version", skip_serializing_if = "is_legacy_request_version")]
+    pub version: u8,
+    /// Client-generated id used to correlate logs, responses, usage records and
+    /// commitments for this request. Omitted on the wire when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Tools the model may call, forwarded to the backend unchanged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    /// Which tool the model should call, in the OpenAI format: "none", "auto",
+    /// "required" or `{"type": "function", "function": {"name": ...}}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+    /// Format the output must follow; the executor checks it before answering.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    /// Id of the [`Quote`] this request accepts. The executor then bills at the
+    /// quoted prices, which `inbound_price` and `outbound_price` must repeat.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote_id: Option<String>,
+    /// Certificate authorizing the session key that signed the request. The
+    /// main address that issued it is billed instead of the session key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<SessionCertificate>,
+    /// Credential making the billed address a member of an organization, for
+    /// executors that serve or discount its members.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<OrgCredential>,
+    /// Sampling parameters beyond `temperature` and `max_tokens`. They sit at the
+    /// top level of the request, so setting one commits it in the signature.
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
+}
+
+/// Optional sampling parameters, forwarded to the backend under their OpenAI names.
+///
+/// Unset parameters are omitted on the wire and leave the backend's defaults.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SamplingParams {
+    /// Nucleus sampling: only tokens within this cumulative probability (0.0 to 1.0).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// Only sample from the k most likely tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    /// Sequences that end generation when produced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    /// Seed for reproducible sampling, where the backend supports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Penalty for tokens that already appeared (-2.0 to 2.0).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    /// Penalty proportional to how often tokens appeared (-2.0 to 2.0).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    /// Number of choices to generate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+}
+
+impl SamplingParams {
+    /// Whether no parameter is set.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+fn legacy_request_version() -> u8 {
+    constants::LEGACY_LLM_REQUEST_VERSION
+}
+
+fn is_legacy_request_version(version: &u8) -> bool {
+    *version == constants::LEGACY_LLM_REQUEST_VERSION
+}
+
+/// An empty legacy request, the same as deserializing one without optional
+/// fields; fill in the rest with struct update syntax.
+impl Default for LlmRequest {
+    fn default() -> Self {
+        Self {
+            model: String::new(),
+            prompt: String::new(),
+            system_prompt: None,
+            temperature: None,
+            max_tokens: None,
+            executor_address: String::new(),
+            inbound_price: String::new(),
+            outbound_price: String::new(),
+            nonce: 0,
+            deadline: 0,
+            messages: None,
+            version: legacy_request_version(),
+            request_id: None,
+            tools: None,
+            tool_choice: None,
+            response_format: None,
+            quote_id: None,
+            session: None,
+            credential: None,
+            sampling: SamplingParams::default(),
+        }
+    }
+}
+
+impl LlmRequest {
+    /// The conversation to send to the backend: `messages` if present, otherwise
+    /// the system prompt (if any) followed by the prompt as a user message.
+    pub fn chat_messages(&self) -> Vec<ChatMessage> {
+        if let Some(messages) = &self.messages {
+            return messages.clone();
+        }
+        let mut messages = Vec::with_capacity(2);
+        if let Some(system_prompt) = &self.system_prompt {
+            messages.push(ChatMessage::system(system_prompt.clone()));
+        }
+        messages.push(ChatMessage::user(self.prompt.clone()));
+        messages
+    }
+
+    /// Whether this node understands the request's format version.
+    pub fn is_supported_version(&self) -> bool {
+        self.version <= constants::LLM_REQUEST_VERSION
+    }
+
+    /// Identifier of this request: the client-generated `request_id`, or for
+    /// requests without one the hex keccak256 hash of the serialized request, so
+    /// both sides still derive the same id.
+    pub fn id(&self) -> String {
+        if let Some(request_id) = &self.request_id {
+            return request_id.clone();
+        }
+        let bytes = serde_json::to_vec(self).unwrap_or_default();
+        format!("{}", keccak256(bytes))
+    }
+}
+
+/// A request billed to the client that signed it.
+///
+/// Requests signed by a session key carry its certificate, and are billed to
+/// the main address that issued it. Members of an organization may attach
+/// their membership credential.
+pub trait BilledRequest {
+    /// The model the request uses
+    fn model(&self) -> &str;
+
+    /// Certificate of the session key that signed the request, if any
+    fn session(&self) -> Option<&SessionCertificate>;
+
+    /// Membership credential of the billed address, if any
+    fn credential(&self) -> Option<&OrgCredential>;
+
+    /// Address billed for this request when `signer` signed it: the main
+    /// address of its session certificate, if it carries one, otherwise
+    /// `signer` itself.
+    fn account(&self, signer: Address, now: u64) -> crate::error::Result<Address> {
+        match self.session() {
+            Some(certificate) => verify_session(certificate, signer, self.model(), now),
+            None => Ok(signer),
+        }
+    }
+}
+
+impl BilledRequest for LlmRequest {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn session(&self) -> Option<&SessionCertificate> {
+        self.session.as_ref()
+    }
+
+    fn credential(&self) -> Option<&OrgCredential> {
+        self.credential.as_ref()
+    }
+}
+
+/// A single message of a chat conversation, forwarded to backends unchanged.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChatMessage {
+    /// Author of the message ("system", "user", "assistant" or "tool").
+    pub role: String,
+    /// The message text. Backends send `null` for assistant messages that only
+    /// call tools, which is read as an empty string.
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub content: String,
+    /// Tools called by an assistant message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// Id of the tool call a "tool" message answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// Images attached to a user message, for vision models.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub images: Option<Vec<ImageInput>>,
+}
+
+/// An image attached to a chat message.
+///
+/// Images travel inline as base64. Once an executor has received an image it
+/// can be sent again as a reference, with `data` omitted, and the executor
+/// looks it up by `hash`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImageInput {
+    /// MIME type of the image, e.g. "image/png"
+    pub media_type: String,
+    /// Keccak-256 hash of the image bytes (0x-prefixed hex)
+    pub hash: String,
+    /// Base64-encoded image bytes; omitted for references
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+}
+
+impl ImageInput {
+    /// Creates an inline image from its bytes.
+    pub fn inline(media_type: impl Into<String>, bytes: &[u8]) -> Self {
+        use base64::Engine;
+        Self {
+            media_type: media_type.into(),
+            hash: keccak256(bytes).to_string(),
+            data: Some(base64::engine::general_purpose::STANDARD.encode(bytes)),
+        }
+    }
+
+    /// A reference to this image, for an executor that has already received it.
+    pub fn reference(&self) -> Self {
+        Self { data: None, ..self.clone() }
+    }
+
+    /// Decodes the inline bytes, checking their size and hash.
+    pub fn decode(&self) -> std::result::Result<Vec<u8>, String> {
+        use base64::Engine;
+        let data = self.data.as_ref().ok_or_else(|| format!("Image {} is a reference without data", self.hash))?;
+        // Base64 takes 4 characters for every 3 bytes
+        if data.len() / 4 * 3 > constants::MAX_IMAGE_BYTES {
+            return Err(format!("Image {} exceeds {} bytes", self.hash, constants::MAX_IMAGE_BYTES));
+        }
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .map_err(|e| format!("Image {} is not valid base64: {}", self.hash, e))?;
+        if bytes.len() > constants::MAX_IMAGE_BYTES {
+            return Err(format!("Image {} exceeds {} bytes", self.hash, constants::MAX_IMAGE_BYTES));
+        }
+        if keccak256(&bytes).to_string() != self.hash.to_lowercase() {
+            return Err(format!("Image data does not match hash {}", self.hash));
+        }
+        Ok(bytes)
+    }
+
+    /// The image as a `data:` URL, if it is inline.
+    pub fn data_url(&self) -> Option<String> {
+        self.data.as_ref().map(|data| format!("data:{};base64,{}", self.media_type, data))
+    }
+}
+
+fn null_as_empty<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+impl ChatMessage {
+    /// Creates a message with an arbitrary role.
+    pub fn new(role: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            content: content.into(),
+            tool_calls: None,
+            tool_call_id: None,
+            images: None,
+        }
+    }
+
+    /// Creates a system message.
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new("system", content)
+    }
+
+    /// Creates a user message.
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new("user", content)
+    }
+
+    /// Creates an assistant message, e.g. to prefill the start of the reply.
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new("assistant", content)
+    }
+
+    /// Creates a user message showing `images` along with the text.
+    pub fn user_with_images(content: impl Into<String>, images: Vec<ImageInput>) -> Self {
+        Self {
+            images: Some(images),
+            ..Self::user(content)
+        }
+    }
+
+    /// Creates a message carrying the result of the tool call `tool_call_id`.
+    pub fn tool(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id.into()),
+            ..Self::new("tool", content)
+        }
+    }
+}
+
+/// A tool the model may call, in the OpenAI `tools` format.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Tool {
+    /// Kind of tool; "function" is the only kind defined.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The function the model may call.
+    pub function: FunctionDefinition,
+}
+
+impl Tool {
+    /// Creates a function tool whose arguments follow the JSON Schema `parameters`.
+    pub fn function(
+        name: impl Into<String>,
+        description: Option<String>,
+        parameters: serde_json::Value,
+    ) -> Self {
+        Self {
+            kind: "function".to_string(),
+            function: FunctionDefinition {
+                name: name.into(),
+                description,
+                parameters: Some(parameters),
+            },
+        }
+    }
+}
+
+/// Name, description and parameter schema of a callable function.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FunctionDefinition {
+    /// Function name the model calls it by.
+    pub name: String,
+    /// What the function does, to help the model decide when to call it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON Schema of the function's arguments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+}
+
+/// A tool call made by the model.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ToolCall {
+    /// Id to answer the call with, see [`ChatMessage::tool`].
+    pub id: String,
+    /// Kind of tool called; always "function".
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The function called and its arguments.
+    pub function: FunctionCall,
+}
+
+/// Output format requested from the model, in the OpenAI `response_format` format.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    /// Free-form text, the default
+    Text,
+    /// Any JSON object
+    JsonObject,
+    /// JSON matching a schema
+    JsonSchema {
+        /// The schema and its name
+        json_schema: JsonSchemaFormat,
+    },
+}
+
+/// A named JSON Schema for [`ResponseFormat::JsonSchema`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JsonSchemaFormat {
+    /// Name of the schema, required by OpenAI-compatible backends
+    pub name: String,
+    /// What the output describes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The JSON Schema itself
+    pub schema: serde_json::Value,
+    /// Ask the backend to follow the schema exactly, where supported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+impl ResponseFormat {
+    /// Requests JSON output matching `schema`.
+    pub fn json_schema(name: impl Into<String>, schema: serde_json::Value) -> Self {
+        ResponseFormat::JsonSchema {
+            json_schema: JsonSchemaFormat {
+                name: name.into(),
+                description: None,
+                schema,
+                strict: None,
+            },
+        }
+    }
+
+    /// Check that the format can be enforced, describing the first problem found.
+    ///
+    /// JSON schemas may only use the keywords [`crate::schema::validate`] checks.
+    pub fn check(&self) -> std::result::Result<(), String> {
+        match self {
+            ResponseFormat::JsonSchema { json_schema } => crate::schema::check_schema(&json_schema.schema)
+                .map_err(|e| format!("schema {} is not supported: {}", json_schema.name, e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check model output against the format, describing the first problem found.
+    pub fn validate(&self, content: &str) -> std::result::Result<(), String> {
+        let parse = || serde_json::from_str::<serde_json::Value>(content).map_err(|e| format!("output is not valid JSON: {}", e));
+        match self {
+            ResponseFormat::Text => Ok(()),
+            ResponseFormat::JsonObject => match parse()? {
+                serde_json::Value::Object(_) => Ok(()),
+                _ => Err("output is not a JSON object".to_string()),
+            },
+            ResponseFormat::JsonSchema { json_schema } => crate::schema::validate(&json_schema.schema, &parse()?),
+        }
+    }
+}
+
+/// Function name and arguments of a [`ToolCall`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FunctionCall {
+    /// Name of the function called.
+    pub name: String,
+    /// Arguments as a JSON-encoded string, exactly as the model produced them.
+    pub arguments: String,
 }
 
 /// A response sent from an Executor to a Client.
@@ -48,6 +488,110 @@ pub struct LlmResponse {
     pub model_used: String,
     /// Optional error message if the request failed.
     pub error: Option<String>,
+    /// `request_id` of the request this answers, echoed back when it was set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Machine-readable reason for `error`. Absent from older executors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<LlmErrorCode>,
+    /// Tools the model called instead of, or in addition to, answering in `content`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// Every generated choice, in order, when the request asked for more than
+    /// one; `content` holds the first.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub choices: Option<Vec<String>>,
+}
+
+impl LlmResponse {
+    /// Whether the failed request may succeed on another executor.
+    ///
+    /// `None` if the response succeeded or its error code is missing or unknown,
+    /// in which case only the message is left to go by.
+    pub fn retryable(&self) -> Option<bool> {
+        self.error.as_ref()?;
+        self.error_code?.is_retryable()
+    }
+}
+
+/// Why an `LlmRequest` failed, carried in [`LlmResponse::error_code`] next to
+/// the human-readable `error` message.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LlmErrorCode {
+    /// The executor does not serve the requested model
+    UnsupportedModel,
+    /// The backend serving the model is down or unreachable
+    BackendUnavailable,
+    /// The backend failed while generating
+    BackendError,
+    /// The executor or its backend is rate limiting the client
+    RateLimited,
+    /// The executor has no capacity for more requests right now
+    Busy,
+    /// The request's deadline passed before it completed
+    DeadlineExpired,
+    /// The request signature did not verify
+    InvalidSignature,
+    /// The offered price is below what the executor accepts
+    PriceTooLow,
+    /// The backend refused to process the content
+    ContentRejected,
+    /// The executor does not understand the request version
+    UnsupportedVersion,
+    /// The request was malformed or conflicts with another request
+    InvalidRequest,
+    /// The client cancelled the request
+    Cancelled,
+    /// The model's output did not match the requested response format
+    InvalidOutput,
+    /// The referenced quote is unknown, expired or does not match the request
+    InvalidQuote,
+    /// The request's session certificate is invalid, expired, does not allow
+    /// the model, or its spending cap is used up, or the executor only serves
+    /// members of an organization and the request has no valid credential
+    Unauthorized,
+    /// A code this peer does not know, sent by a newer peer
+    #[serde(other)]
+    Unknown,
+}
+
+impl LlmErrorCode {
+    /// Whether a request failing with this code may succeed on another executor.
+    ///
+    /// `None` for unknown codes, which say nothing about the failure; callers
+    /// fall back to the error message.
+    pub fn is_retryable(self) -> Option<bool> {
+        let retryable = match self {
+            LlmErrorCode::UnsupportedModel
+            | LlmErrorCode::BackendUnavailable
+            | LlmErrorCode::BackendError
+            | LlmErrorCode::RateLimited
+            | LlmErrorCode::Busy
+            | LlmErrorCode::PriceTooLow
+            | LlmErrorCode::UnsupportedVersion
+            | LlmErrorCode::InvalidOutput
+            | LlmErrorCode::InvalidQuote => true,
+            LlmErrorCode::DeadlineExpired
+            | LlmErrorCode::InvalidSignature
+            | LlmErrorCode::ContentRejected
+            | LlmErrorCode::InvalidRequest
+            | LlmErrorCode::Cancelled
+            | LlmErrorCode::Unauthorized => false,
+            LlmErrorCode::Unknown => return None,
+        };
+        Some(retryable)
+    }
+}
+
+impl std::fmt::Display for LlmErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = serde_json::to_value(self)
+            .ok()
+            .and_then(|value| value.as_str().map(str::to_string))
+            .unwrap_or_default();
+        write!(f, "{}", name)
+    }
 }
 
 /// A usage record that tracks work done by an Executor.
@@ -61,6 +605,178 @@ pub struct UsageRecord {
     pub token_count: u32,
     /// Timestamp of when the work was completed.
     pub timestamp: u64,
+    /// Whether the request was cancelled (or ran past its deadline) and only the
+    /// tokens produced before stopping were counted.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
+    /// Id of the request the work was done for, see [`LlmRequest::id`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// Tracing span for work on a single LLM request.
+///
+/// Clients and executors run everything they do for a request inside this span,
+/// so every log line for it carries the same `request_id` field.
+pub fn request_span(request_id: &str) -> tracing::Span {
+    tracing::info_span!("llm_request", request_id = %request_id)
+}
+
+/// Asks an executor to stop working on an in-flight request.
+///
+/// Must be signed by the same key as the request it cancels (or, for unsigned
+/// requests, sent from the same peer). The executor answers with the final
+/// response of the cancelled request, which includes whatever it billed, or with
+/// an unsuccessful acknowledgment if there is nothing to cancel.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CancelRequest {
+    /// Id of the request to cancel, see [`LlmRequest::id`].
+    pub request_id: String,
+    /// Executor the request was sent to, so the cancellation can't be replayed
+    /// against another executor.
+    pub executor_address: String,
+    /// Why the client gave up on the request.
+    pub reason: Option<String>,
+}
+
+/// A request for embedding vectors, sent from a Client to an Executor.
+///
+/// Embeddings produce no output tokens, so the request is billed per input
+/// token at `price_per_token`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EmbeddingRequest {
+    /// The embedding model to use.
+    pub model: String,
+    /// Texts to embed, answered in the same order.
+    pub input: Vec<String>,
+    /// The executor's peer id this request is intended for.
+    pub executor_address: String,
+    /// Price per input token in wei (UINT256 as string).
+    pub price_per_token: String,
+    /// Nonce for replay protection.
+    pub nonce: u64,
+    /// Unix timestamp after which the request expires (0 for none).
+    pub deadline: u64,
+    /// Client-generated identifier, see [`LlmRequest::request_id`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Certificate authorizing the session key that signed the request, see
+    /// [`LlmRequest::session`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<SessionCertificate>,
+    /// Membership credential of the billed address, see [`LlmRequest::credential`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<OrgCredential>,
+}
+
+impl EmbeddingRequest {
+    /// Identifier of this request, derived like [`LlmRequest::id`].
+    pub fn id(&self) -> String {
+        if let Some(request_id) = &self.request_id {
+            return request_id.clone();
+        }
+        let bytes = serde_json::to_vec(self).unwrap_or_default();
+        format!("{}", keccak256(bytes))
+    }
+}
+
+impl BilledRequest for EmbeddingRequest {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn session(&self) -> Option<&SessionCertificate> {
+        self.session.as_ref()
+    }
+
+    fn credential(&self) -> Option<&OrgCredential> {
+        self.credential.as_ref()
+    }
+}
+
+/// A response to an [`EmbeddingRequest`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EmbeddingResponse {
+    /// One vector per input, in request order.
+    pub embeddings: Vec<Vec<f32>>,
+    /// Number of input tokens billed.
+    pub input_tokens: u64,
+    /// Total cost in wei (UINT256 as string)
+    pub total_cost: String,
+    /// The model that was actually used.
+    pub model_used: String,
+    /// Optional error message if the request failed.
+    pub error: Option<String>,
+    /// Machine-readable reason for `error`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<LlmErrorCode>,
+    /// `request_id` of the request this answers, echoed back when it was set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// Asks an executor to price an LLM request before it is sent.
+///
+/// The executor counts the prompt tokens with the model's tokenizer and answers
+/// with a signed [`Quote`], which the client accepts by setting
+/// [`LlmRequest::quote_id`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QuoteRequest {
+    /// The model the request will use.
+    pub model: String,
+    /// The conversation that will be sent, see [`LlmRequest::chat_messages`].
+    pub messages: Vec<ChatMessage>,
+    /// Maximum tokens the request will ask for.
+    pub max_tokens: Option<u32>,
+    /// The executor's peer id this quote request is intended for.
+    pub executor_address: String,
+}
+
+/// Prices an executor commits to for a single LLM request.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Quote {
+    /// Identifier the request refers to in [`LlmRequest::quote_id`].
+    pub quote_id: String,
+    /// The quoted model.
+    pub model: String,
+    /// The executor's peer id.
+    pub executor_address: String,
+    /// Prompt tokens, as counted by the model's tokenizer.
+    pub prompt_tokens: u64,
+    /// Most output tokens covered by `max_cost`.
+    pub max_tokens: u32,
+    /// Price per inbound token in wei (UINT256 as string)
+    pub inbound_price: String,
+    /// Price per outbound token in wei (UINT256 as string)
+    pub outbound_price: String,
+    /// Most the request can cost in wei (UINT256 as string)
+    pub max_cost: String,
+    /// Unix timestamp after which the quote can no longer be accepted.
+    pub expires_at: u64,
+    /// Optional error message if no quote could be given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Machine-readable reason for `error`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<LlmErrorCode>,
+}
+
+impl Quote {
+    /// Cost in wei of a request under this quote producing `outbound_tokens`.
+    ///
+    /// The prompt is billed at the quoted token count, and the total never
+    /// exceeds `max_cost`.
+    pub fn cost(&self, outbound_tokens: u64) -> std::result::Result<U256, String> {
+        let parse = |value: &str| value.parse::<U256>().map_err(|e| format!("Invalid quoted amount {}: {}", value, e));
+        let cost = U256::from(self.prompt_tokens) * parse(&self.inbound_price)?
+            + U256::from(outbound_tokens) * parse(&self.outbound_price)?;
+        Ok(cost.min(parse(&self.max_cost)?))
+    }
+
+    /// Whether the quote can no longer be accepted at unix time `now`.
+    pub fn is_expired(&self, now: u64) -> bool {
+        now > self.expires_at
+    }
 }
 
 /// Information about an Executor's capabilities.
@@ -96,7 +812,34 @@ impl ServiceRole {
 /// Protocol constants.
 pub mod constants {
     /// The protocol ID for LLM request/response.
-    pub const LLM_PROTOCOL: &str = "/lloom/llm/1.0.0";
+    pub const LLM_PROTOCOL: &str = "/lloom/llm/2.0.0";
+    
+    /// The protocol ID spoken by nodes that fail on messages they do not know.
+    pub const LEGACY_LLM_PROTOCOL: &str = "/lloom/llm/1.0.0";
+    
+    /// Protocol version of this node, the version of [`LLM_PROTOCOL`]. Announced
+    /// to validators in [`ModelAnnouncement`](super::ModelAnnouncement)s.
+    pub const PROTOCOL_VERSION: u8 = 2;
+    
+    /// Oldest protocol version still spoken.
+    pub const MIN_PROTOCOL_VERSION: u8 = 1;
+    
+    /// Request/response protocols spoken side by side, highest version first.
+    /// Dialers offer them in this order, so peers settle on the highest
+    /// version both speak.
+    pub const LLM_PROTOCOLS: [(u8, &str); 2] = [
+        (PROTOCOL_VERSION, LLM_PROTOCOL),
+        (MIN_PROTOCOL_VERSION, LEGACY_LLM_PROTOCOL),
+    ];
+    
+    /// The protocol ID for streamed LLM responses.
+    pub const LLM_STREAM_PROTOCOL: &str = "/lloom/llm-stream/1.0.0";
+    
+    /// `LlmRequest` format version of requests without a `version` field.
+    pub const LEGACY_LLM_REQUEST_VERSION: u8 = 1;
+    
+    /// Latest `LlmRequest` format version (2 added `messages`).
+    pub const LLM_REQUEST_VERSION: u8 = 2;
     
     /// Default timeout for LLM requests (in seconds).
     pub const DEFAULT_REQUEST_TIMEOUT: u64 = 300; // 5 minutes
@@ -104,6 +847,15 @@ pub mod constants {
     /// Maximum age for signed messages (in seconds) - 5 minutes for replay protection.
     pub const MAX_MESSAGE_AGE_SECS: u64 = 300;
     
+    /// Largest image accepted in a request (2 MiB).
+    pub const MAX_IMAGE_BYTES: usize = 2 * 1024 * 1024;
+    
+    /// Most images accepted in a single request.
+    pub const MAX_IMAGES_PER_REQUEST: usize = 4;
+    
+    /// Largest encoded request accepted, enough for the maximum inline images (16 MiB).
+    pub const MAX_REQUEST_BYTES: usize = 16 * 1024 * 1024;
+    
     /// Maximum batch size for blockchain submissions.
     pub const MAX_BATCH_SIZE: usize = 100;
     
@@ -112,21 +864,62 @@ pub mod constants {
 }
 
 // Implement SignableMessage for protocol messages
-impl SignableMessage for LlmRequest {}
-impl SignableMessage for LlmResponse {}
-impl SignableMessage for UsageRecord {}
+impl SignableMessage for LlmRequest {
+    const MESSAGE_TYPE: &'static str = "LlmRequest";
+}
+impl SignableMessage for LlmResponse {
+    const MESSAGE_TYPE: &'static str = "LlmResponse";
+}
+impl SignableMessage for UsageRecord {
+    const MESSAGE_TYPE: &'static str = "UsageRecord";
+}
+impl SignableMessage for CancelRequest {
+    const MESSAGE_TYPE: &'static str = "CancelRequest";
+    const MIN_SIGNATURE_VERSION: u8 = crate::signing::SIGNATURE_VERSION;
+}
+impl SignableMessage for EmbeddingRequest {
+    const MESSAGE_TYPE: &'static str = "EmbeddingRequest";
+    const MIN_SIGNATURE_VERSION: u8 = crate::signing::SIGNATURE_VERSION;
+}
+impl SignableMessage for EmbeddingResponse {
+    const MESSAGE_TYPE: &'static str = "EmbeddingResponse";
+    const MIN_SIGNATURE_VERSION: u8 = crate::signing::SIGNATURE_VERSION;
+}
+impl SignableMessage for QuoteRequest {
+    const MESSAGE_TYPE: &'static str = "QuoteRequest";
+    const MIN_SIGNATURE_VERSION: u8 = crate::signing::SIGNATURE_VERSION;
+}
+impl SignableMessage for Quote {
+    const MESSAGE_TYPE: &'static str = "Quote";
+    const MIN_SIGNATURE_VERSION: u8 = crate::signing::SIGNATURE_VERSION;
+}
 
 // Implement SignableMessage for model announcement protocol messages
-impl SignableMessage for ModelAnnouncement {}
-impl SignableMessage for ModelQuery {}
-impl SignableMessage for ModelQueryResponse {}
-impl SignableMessage for ModelUpdate {}
-impl SignableMessage for AcknowledgmentResponse {}
+impl SignableMessage for ModelAnnouncement {
+    const MESSAGE_TYPE: &'static str = "ModelAnnouncement";
+}
+impl SignableMessage for ModelQuery {
+    const MESSAGE_TYPE: &'static str = "ModelQuery";
+}
+impl SignableMessage for ModelQueryResponse {
+    const MESSAGE_TYPE: &'static str = "ModelQueryResponse";
+}
+impl SignableMessage for ModelUpdate {
+    const MESSAGE_TYPE: &'static str = "ModelUpdate";
+}
+impl SignableMessage for AcknowledgmentResponse {
+    const MESSAGE_TYPE: &'static str = "AcknowledgmentResponse";
+}
 
 /// Type aliases for commonly used signed messages
 pub type SignedLlmRequest = SignedMessage<LlmRequest>;
 pub type SignedLlmResponse = SignedMessage<LlmResponse>;
 pub type SignedUsageRecord = SignedMessage<UsageRecord>;
+pub type SignedCancelRequest = SignedMessage<CancelRequest>;
+pub type SignedEmbeddingRequest = SignedMessage<EmbeddingRequest>;
+pub type SignedEmbeddingResponse = SignedMessage<EmbeddingResponse>;
+pub type SignedQuoteRequest = SignedMessage<QuoteRequest>;
+pub type SignedQuote = SignedMessage<Quote>;
 
 /// Type aliases for model announcement protocol signed messages
 pub type SignedModelAnnouncement = SignedMessage<ModelAnnouncement>;
@@ -142,6 +935,12 @@ pub enum RequestMessage {
     LlmRequest(LlmRequest),
     /// Signed LLM request (with cryptographic signature)
     SignedLlmRequest(SignedLlmRequest),
+    /// Signed cancellation of an in-flight LLM request
+    CancelRequest(SignedCancelRequest),
+    /// Signed embedding request
+    EmbeddingRequest(SignedEmbeddingRequest),
+    /// Signed request for a price quote
+    QuoteRequest(SignedQuoteRequest),
     
     // Model announcement protocol messages
     /// Model announcement from executor to validator
@@ -150,6 +949,9 @@ pub enum RequestMessage {
     ModelQuery(SignedMessage<ModelQuery>),
     /// Model update from executor to validator
     ModelUpdate(SignedMessage<ModelUpdate>),
+    /// A message this peer does not know, sent by a newer peer. Never sent.
+    #[serde(other)]
+    Unknown,
 }
 
 /// Wrapper enum for response messages to support both signed and unsigned variants
@@ -159,12 +961,59 @@ pub enum ResponseMessage {
     LlmResponse(LlmResponse),
     /// Signed LLM response (with cryptographic signature)
     SignedLlmResponse(SignedLlmResponse),
+    /// Signed response to an embedding request
+    EmbeddingResponse(SignedEmbeddingResponse),
+    /// Signed price quote
+    Quote(SignedQuote),
     
     // Model announcement protocol responses
     /// Response to model query
     ModelQueryResponse(SignedMessage<ModelQueryResponse>),
     /// Acknowledgment response for announcements and updates
     AcknowledgmentResponse(SignedMessage<AcknowledgmentResponse>),
+    /// A message this peer does not know, sent by a newer peer. Never sent.
+    #[serde(other)]
+    Unknown,
+}
+
+impl RequestMessage {
+    /// Id of the request this message belongs to, for messages that carry one
+    pub fn request_id(&self) -> Option<String> {
+        match self {
+            RequestMessage::LlmRequest(request) => Some(request.id()),
+            RequestMessage::SignedLlmRequest(signed) => Some(signed.payload.id()),
+            RequestMessage::CancelRequest(signed) => Some(signed.payload.request_id.clone()),
+            RequestMessage::EmbeddingRequest(signed) => Some(signed.payload.id()),
+            _ => None,
+        }
+    }
+
+    /// Lowest protocol version whose peers understand this message.
+    ///
+    /// Messages are not sent to peers that negotiated an older version.
+    /// Messages added since the legacy protocol return the version introducing them.
+    pub fn protocol_version(&self) -> u8 {
+        match self {
+            RequestMessage::CancelRequest(_)
+            | RequestMessage::EmbeddingRequest(_)
+            | RequestMessage::QuoteRequest(_) => constants::PROTOCOL_VERSION,
+            RequestMessage::Unknown => u8::MAX,
+            _ => constants::MIN_PROTOCOL_VERSION,
+        }
+    }
+}
+
+impl ResponseMessage {
+    /// Lowest protocol version whose peers understand this message.
+    ///
+    /// See [`RequestMessage::protocol_version`].
+    pub fn protocol_version(&self) -> u8 {
+        match self {
+            ResponseMessage::EmbeddingResponse(_) | ResponseMessage::Quote(_) => constants::PROTOCOL_VERSION,
+            ResponseMessage::Unknown => u8::MAX,
+            _ => constants::MIN_PROTOCOL_VERSION,
+        }
+    }
 }
 
 // ============================================================================
@@ -192,7 +1041,8 @@ pub struct ModelAnnouncement {
     /// Nonce for replay protection
     pub nonce: u64,
     
-    /// Protocol version for compatibility
+    /// Highest protocol version the executor speaks
+    /// (see [`constants::PROTOCOL_VERSION`])
     pub protocol_version: u8,
 }
 
@@ -213,6 +1063,11 @@ pub struct ModelDescriptor {
     
     /// Pricing information (optional)
     pub pricing: Option<ModelPricing>,
+    
+    /// Organization whose members alone may use the model. Validators do not
+    /// list org-private models publicly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org: Option<Address>,
 }
 
 /// Model capabilities and metadata
@@ -277,6 +1132,9 @@ pub enum AnnouncementType {
     Removal,
     /// Heartbeat to maintain presence
     Heartbeat,
+    /// A type this peer does not know, sent by a newer peer
+    #[serde(other)]
+    Unknown,
 }
 
 /// Query from client to validator for model information
@@ -562,6 +1420,7 @@ mod tests {
             outbound_price: "1000000000000000".to_string(),
             nonce: 1,
             deadline: 1234567890,
+            ..Default::default()
         };
 
         assert_eq!(request.model, "gpt-3.5-turbo");
@@ -571,6 +1430,26 @@ mod tests {
         assert_eq!(request.max_tokens, Some(150));
     }
 
+    #[test]
+    fn test_request_defaults_match_deserialized() {
+        let request = LlmRequest {
+            model: "gpt-4".to_string(),
+            prompt: "Hi".to_string(),
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&request).unwrap();
+        let parsed: LlmRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.version, constants::LEGACY_LLM_REQUEST_VERSION);
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+
+        let message = RequestMessage::LlmRequest(LlmRequest {
+            request_id: Some("req-1".to_string()),
+            ..request
+        });
+        assert_eq!(message.request_id(), Some("req-1".to_string()));
+        assert_eq!(RequestMessage::Unknown.request_id(), None);
+    }
+
     #[test]
     fn test_llm_request_minimal() {
         let request = LlmRequest {
@@ -584,6 +1463,7 @@ mod tests {
             outbound_price: "1000000000000000".to_string(), // 0.001 ETH per token
             nonce: 2,
             deadline: 1234567891,
+            ..Default::default()
         };
 
         assert_eq!(request.model, "gpt-4");
@@ -607,6 +1487,10 @@ mod tests {
             total_cost: "62000000000000000".to_string(), // 20 * 0.001 + 22 * 0.002 = 0.064 ETH
             model_used: "gpt-3.5-turbo".to_string(),
             error: None,
+            request_id: None,
+            error_code: None,
+            tool_calls: None,
+            choices: None,
         };
 
         assert_eq!(response.content, "Generated content");
@@ -626,6 +1510,10 @@ mod tests {
             total_cost: "0".to_string(),
             model_used: "gpt-4".to_string(),
             error: Some("API rate limit exceeded".to_string()),
+            request_id: None,
+            error_code: None,
+            tool_calls: None,
+            choices: None,
         };
 
         assert!(response.content.is_empty());
@@ -633,6 +1521,51 @@ mod tests {
         assert_eq!(response.outbound_tokens, 0);
         assert_eq!(response.total_cost, "0");
         assert_eq!(response.error, Some("API rate limit exceeded".to_string()));
+        assert_eq!(response.retryable(), None);
+    }
+
+    #[test]
+    fn test_llm_error_codes() {
+        let mut response = LlmResponse {
+            content: String::new(),
+            inbound_tokens: 0,
+            outbound_tokens: 0,
+            total_cost: "0".to_string(),
+            model_used: "gpt-4".to_string(),
+            error: Some("Model gpt-4 not supported".to_string()),
+            request_id: None,
+            error_code: Some(LlmErrorCode::UnsupportedModel),
+            tool_calls: None,
+            choices: None,
+        };
+        assert_eq!(response.retryable(), Some(true));
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.contains("\"error_code\":\"unsupported_model\""));
+        assert_eq!(LlmErrorCode::UnsupportedModel.to_string(), "unsupported_model");
+
+        response.error_code = Some(LlmErrorCode::InvalidSignature);
+        assert_eq!(response.retryable(), Some(false));
+
+        // Responses from older executors carry no code and keep their wire format
+        response.error_code = None;
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(!json.contains("error_code"));
+        let parsed: LlmResponse = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.error_code, None);
+
+        // Codes added by newer executors are still readable
+        let json = json.replace("\"error\":", "\"error_code\":\"quota_exhausted\",\"error\":");
+        let parsed: LlmResponse = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.error_code, Some(LlmErrorCode::Unknown));
+        assert_eq!(parsed.retryable(), None);
+        assert_eq!(LlmErrorCode::Unknown.is_retryable(), None);
+    }
+
+    #[test]
+    fn test_unknown_announcement_type() {
+        let parsed: AnnouncementType = serde_json::from_str("\"Migration\"").unwrap();
+        assert_eq!(parsed, AnnouncementType::Unknown);
+        assert_eq!(RequestMessage::Unknown.protocol_version(), u8::MAX);
     }
 
     #[test]
@@ -645,6 +1578,8 @@ mod tests {
             model: "gpt-4".to_string(),
             token_count: 100,
             timestamp,
+            cancelled: false,
+            request_id: None,
         };
 
         assert_eq!(usage_record.client_address, client_address);
@@ -699,6 +1634,7 @@ mod tests {
             outbound_price: "1000000000000000".to_string(),
             nonce: 1,
             deadline: 1234567890,
+            ..Default::default()
         };
 
         let serialized = serde_json::to_string(&request).unwrap();
@@ -716,6 +1652,383 @@ mod tests {
         assert_eq!(request.deadline, deserialized.deadline);
     }
 
+    #[test]
+    fn test_legacy_llm_request_wire_format() {
+        // A version 1 peer neither sends nor expects `messages` or `version`
+        let legacy_json = r#"{"model":"gpt-4","prompt":"Hi","system_prompt":null,"temperature":null,"max_tokens":null,"executor_address":"0x742d35Cc6634C0532925a3b8D404cB8b3d3A5d3a","inbound_price":"1","outbound_price":"2","nonce":1,"deadline":1234567890}"#;
+        let request: LlmRequest = serde_json::from_str(legacy_json).unwrap();
+        assert_eq!(request.version, constants::LEGACY_LLM_REQUEST_VERSION);
+        assert!(request.messages.is_none());
+        assert!(request.is_supported_version());
+
+        // Re-serializing yields identical bytes, so legacy signatures still verify
+        assert_eq!(serde_json::to_string(&request).unwrap(), legacy_json);
+        assert_eq!(
+            request.chat_messages(),
+            vec![ChatMessage::user("Hi")]
+        );
+    }
+
+    #[test]
+    fn test_llm_request_messages() {
+        let messages = vec![
+            ChatMessage::system("Be brief"),
+            ChatMessage::user("Hi"),
+            ChatMessage::assistant("Hello"),
+            ChatMessage::user("How are you?"),
+        ];
+        let request = LlmRequest {
+            model: "gpt-4".to_string(),
+            prompt: "How are you?".to_string(),
+            system_prompt: Some("Ignored".to_string()),
+            temperature: None,
+            max_tokens: None,
+            executor_address: "0x742d35Cc6634C0532925a3b8D404cB8b3d3A5d3a".to_string(),
+            inbound_price: "1".to_string(),
+            outbound_price: "2".to_string(),
+            nonce: 1,
+            deadline: 1234567890,
+            messages: Some(messages.clone()),
+            version: constants::LLM_REQUEST_VERSION,
+            ..Default::default()
+        };
+
+        // Messages supersede prompt and system prompt
+        assert_eq!(request.chat_messages(), messages);
+
+        let serialized = serde_json::to_string(&request).unwrap();
+        assert!(serialized.contains(r#""version":2"#));
+        let deserialized: LlmRequest = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.messages, Some(messages));
+        assert_eq!(deserialized.version, constants::LLM_REQUEST_VERSION);
+
+        let mut future = deserialized;
+        future.version = constants::LLM_REQUEST_VERSION + 1;
+        assert!(!future.is_supported_version());
+    }
+
+    #[test]
+    fn test_tool_calling_wire_format() {
+        // Plain messages serialize exactly as before tools existed
+        assert_eq!(
+            serde_json::to_string(&ChatMessage::user("Hi")).unwrap(),
+            r#"{"role":"user","content":"Hi"}"#
+        );
+
+        // Assistant messages that only call tools carry null content
+        let assistant: ChatMessage = serde_json::from_str(
+            r#"{"role":"assistant","content":null,"tool_calls":[{"id":"call_1","type":"function","function":{"name":"get_weather","arguments":"{\"city\":\"Paris\"}"}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(assistant.content, "");
+        let tool_calls = assistant.tool_calls.clone().unwrap();
+        assert_eq!(tool_calls[0].kind, "function");
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+
+        let result = ChatMessage::tool("call_1", "Sunny");
+        assert_eq!(
+            serde_json::to_string(&result).unwrap(),
+            r#"{"role":"tool","content":"Sunny","tool_call_id":"call_1"}"#
+        );
+
+        let tool = Tool::function("get_weather", None, serde_json::json!({"type": "object"}));
+        assert_eq!(
+            serde_json::to_value(&tool).unwrap(),
+            serde_json::json!({"type": "function", "function": {"name": "get_weather", "parameters": {"type": "object"}}})
+        );
+
+        let request = LlmRequest {
+            model: "gpt-4".to_string(),
+            prompt: String::new(),
+            system_prompt: None,
+            temperature: None,
+            max_tokens: None,
+            executor_address: String::new(),
+            inbound_price: "1".to_string(),
+            outbound_price: "2".to_string(),
+            nonce: 1,
+            deadline: 0,
+            messages: Some(vec![ChatMessage::user("Weather in Paris?"), assistant, result]),
+            version: constants::LLM_REQUEST_VERSION,
+            tools: Some(vec![tool]),
+            tool_choice: Some(serde_json::json!("auto")),
+            ..Default::default()
+        };
+        let deserialized: LlmRequest = serde_json::from_str(&serde_json::to_string(&request).unwrap()).unwrap();
+        assert_eq!(deserialized.tools, request.tools);
+        assert_eq!(deserialized.tool_choice, request.tool_choice);
+        assert_eq!(deserialized.messages, request.messages);
+
+        let response: LlmResponse = serde_json::from_str(
+            r#"{"content":"","inbound_tokens":1,"outbound_tokens":1,"total_cost":"2","model_used":"gpt-4","error":null,"tool_calls":[{"id":"call_2","type":"function","function":{"name":"get_weather","arguments":"{}"}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(response.tool_calls.unwrap()[0].id, "call_2");
+    }
+
+    #[test]
+    fn test_sampling_params_wire_format() {
+        let legacy_json = r#"{"model":"gpt-4","prompt":"Hi","system_prompt":null,"temperature":null,"max_tokens":null,"executor_address":"0x742d35Cc6634C0532925a3b8D404cB8b3d3A5d3a","inbound_price":"1","outbound_price":"2","nonce":1,"deadline":1234567890}"#;
+        let mut request: LlmRequest = serde_json::from_str(legacy_json).unwrap();
+        assert!(request.sampling.is_empty());
+
+        request.sampling = SamplingParams {
+            top_p: Some(0.5),
+            stop: Some(vec!["END".to_string()]),
+            seed: Some(42),
+            n: Some(2),
+            ..Default::default()
+        };
+        // Parameters sit at the top level, next to temperature, so they are signed
+        let value = serde_json::to_value(&request).unwrap();
+        assert_eq!(value["top_p"], 0.5);
+        assert_eq!(value["stop"], serde_json::json!(["END"]));
+        assert_eq!(value["seed"], 42);
+        assert_eq!(value["n"], 2);
+        assert!(value.get("top_k").is_none());
+        assert!(value.get("sampling").is_none());
+
+        let parsed: LlmRequest = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed.sampling, request.sampling);
+        assert_ne!(parsed.id(), serde_json::from_str::<LlmRequest>(legacy_json).unwrap().id());
+    }
+
+    #[test]
+    fn test_image_inputs() {
+        let image = ImageInput::inline("image/png", b"\x89PNG fake image");
+        assert_eq!(image.hash, keccak256(b"\x89PNG fake image").to_string());
+        assert_eq!(image.decode().unwrap(), b"\x89PNG fake image");
+        assert!(image.data_url().unwrap().starts_with("data:image/png;base64,"));
+
+        let reference = image.reference();
+        assert!(reference.data.is_none());
+        assert!(reference.data_url().is_none());
+        assert!(reference.decode().is_err());
+        let json = serde_json::to_string(&reference).unwrap();
+        assert!(!json.contains("data"));
+
+        // Data that does not match its hash is rejected
+        let mut tampered = ImageInput::inline("image/png", b"other image");
+        tampered.hash = image.hash.clone();
+        assert!(tampered.decode().unwrap_err().contains("does not match"));
+
+        let oversized = ImageInput::inline("image/png", &vec![0u8; constants::MAX_IMAGE_BYTES + 1]);
+        assert!(oversized.decode().unwrap_err().contains("exceeds"));
+
+        // Text-only messages keep their wire format
+        let message = ChatMessage::user_with_images("What is this?", vec![reference]);
+        let parsed: ChatMessage = serde_json::from_str(&serde_json::to_string(&message).unwrap()).unwrap();
+        assert_eq!(parsed, message);
+        assert!(!serde_json::to_string(&ChatMessage::user("Hi")).unwrap().contains("images"));
+    }
+
+    #[test]
+    fn test_response_format() {
+        let schema = serde_json::json!({"type": "object", "required": ["city"]});
+        let format = ResponseFormat::json_schema("weather", schema.clone());
+        assert_eq!(
+            serde_json::to_value(&format).unwrap(),
+            serde_json::json!({"type": "json_schema", "json_schema": {"name": "weather", "schema": schema}})
+        );
+        assert_eq!(serde_json::to_string(&ResponseFormat::JsonObject).unwrap(), r#"{"type":"json_object"}"#);
+
+        assert!(format.validate(r#"{"city": "Paris"}"#).is_ok());
+        assert!(format.validate(r#"{"town": "Paris"}"#).unwrap_err().contains("city"));
+        assert!(format.validate("Paris").unwrap_err().starts_with("output is not valid JSON"));
+        assert!(ResponseFormat::JsonObject.validate("{}").is_ok());
+        assert!(ResponseFormat::JsonObject.validate("[1]").is_err());
+        assert!(ResponseFormat::Text.validate("anything").is_ok());
+    }
+
+    #[test]
+    fn test_chat_messages_from_prompt() {
+        let request = LlmRequest {
+            model: "gpt-4".to_string(),
+            prompt: "Hi".to_string(),
+            system_prompt: Some("Be brief".to_string()),
+            temperature: None,
+            max_tokens: None,
+            executor_address: String::new(),
+            inbound_price: "1".to_string(),
+            outbound_price: "2".to_string(),
+            nonce: 1,
+            deadline: 0,
+            ..Default::default()
+        };
+        assert_eq!(
+            request.chat_messages(),
+            vec![ChatMessage::system("Be brief"), ChatMessage::user("Hi")]
+        );
+    }
+
+    #[test]
+    fn test_request_id() {
+        let request = LlmRequest {
+            model: "gpt-4".to_string(),
+            prompt: "Hi".to_string(),
+            system_prompt: None,
+            temperature: None,
+            max_tokens: None,
+            executor_address: String::new(),
+            inbound_price: "1".to_string(),
+            outbound_price: "2".to_string(),
+            nonce: 1,
+            deadline: 0,
+            ..Default::default()
+        };
+        let id = request.id();
+        assert!(id.starts_with("0x"));
+        assert_eq!(id.len(), 66);
+        assert_eq!(id, request.clone().id());
+
+        // Retries under a new nonce are distinct requests
+        let mut retry = request.clone();
+        retry.nonce = 2;
+        assert_ne!(retry.id(), id);
+
+        // A client-chosen id takes precedence
+        let mut tagged = request.clone();
+        tagged.request_id = Some("req-1".to_string());
+        assert_eq!(tagged.id(), "req-1");
+        let json = serde_json::to_string(&tagged).unwrap();
+        assert!(json.contains("\"request_id\":\"req-1\""));
+
+        // Legacy requests keep their wire format
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(!json.contains("request_id"));
+        let parsed: LlmRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.request_id, None);
+        assert_eq!(parsed.id(), id);
+    }
+
+    #[tokio::test]
+    async fn test_signed_cancel_request() {
+        use crate::signing::SignableMessage;
+        use alloy::signers::local::PrivateKeySigner;
+
+        let signer: PrivateKeySigner = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+            .parse()
+            .expect("Valid private key");
+        let cancel = CancelRequest {
+            request_id: "0x1234".to_string(),
+            executor_address: "12D3KooWBmwkafWE2fqfzS96VoTZgpGp6aFdD7zdBUyJ1BDdyWz4".to_string(),
+            reason: Some("Deadline passed".to_string()),
+        };
+
+        let signed_cancel: SignedCancelRequest = cancel.sign_blocking(&signer).unwrap();
+        assert_eq!(signed_cancel.verify_basic().unwrap(), signer.address());
+
+        let message = RequestMessage::CancelRequest(signed_cancel);
+        let serialized = serde_json::to_string(&message).unwrap();
+        match serde_json::from_str::<RequestMessage>(&serialized).unwrap() {
+            RequestMessage::CancelRequest(signed) => assert_eq!(signed.payload, cancel),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signed_embedding_messages() {
+        use crate::signing::SignableMessage;
+        use alloy::signers::local::PrivateKeySigner;
+
+        let signer: PrivateKeySigner = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+            .parse()
+            .expect("Valid private key");
+        let request = EmbeddingRequest {
+            model: "text-embedding-3-small".to_string(),
+            input: vec!["first".to_string(), "second".to_string()],
+            executor_address: "12D3KooWBmwkafWE2fqfzS96VoTZgpGp6aFdD7zdBUyJ1BDdyWz4".to_string(),
+            price_per_token: "1000".to_string(),
+            nonce: 1,
+            deadline: 0,
+            request_id: Some("req-1".to_string()),
+            session: None,
+            credential: None,
+        };
+        assert_eq!(request.id(), "req-1");
+
+        let message = RequestMessage::EmbeddingRequest(request.sign_blocking(&signer).unwrap());
+        let serialized = serde_json::to_string(&message).unwrap();
+        match serde_json::from_str::<RequestMessage>(&serialized).unwrap() {
+            RequestMessage::EmbeddingRequest(signed) => {
+                assert_eq!(signed.verify_basic().unwrap(), signer.address());
+                assert_eq!(signed.payload, request);
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+
+        let response = EmbeddingResponse {
+            embeddings: vec![vec![0.5, -0.25], vec![1.0, 0.0]],
+            input_tokens: 2,
+            total_cost: "2000".to_string(),
+            model_used: "text-embedding-3-small".to_string(),
+            error: None,
+            error_code: None,
+            request_id: request.request_id.clone(),
+        };
+        let message = ResponseMessage::EmbeddingResponse(response.sign_blocking(&signer).unwrap());
+        let serialized = serde_json::to_string(&message).unwrap();
+        match serde_json::from_str::<ResponseMessage>(&serialized).unwrap() {
+            ResponseMessage::EmbeddingResponse(signed) => assert_eq!(signed.payload, response),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signed_quote_messages() {
+        use crate::signing::SignableMessage;
+        use alloy::signers::local::PrivateKeySigner;
+
+        let signer: PrivateKeySigner = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+            .parse()
+            .expect("Valid private key");
+        let request = QuoteRequest {
+            model: "gpt-4".to_string(),
+            messages: vec![ChatMessage::user("Hello")],
+            max_tokens: Some(100),
+            executor_address: "12D3KooWBmwkafWE2fqfzS96VoTZgpGp6aFdD7zdBUyJ1BDdyWz4".to_string(),
+        };
+        let message = RequestMessage::QuoteRequest(request.sign_blocking(&signer).unwrap());
+        match serde_json::from_str::<RequestMessage>(&serde_json::to_string(&message).unwrap()).unwrap() {
+            RequestMessage::QuoteRequest(signed) => {
+                assert_eq!(signed.verify_basic().unwrap(), signer.address());
+                assert_eq!(signed.payload, request);
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+
+        let quote = Quote {
+            quote_id: "quote-1".to_string(),
+            model: request.model.clone(),
+            executor_address: request.executor_address.clone(),
+            prompt_tokens: 10,
+            max_tokens: 100,
+            inbound_price: "5".to_string(),
+            outbound_price: "10".to_string(),
+            max_cost: "1050".to_string(),
+            expires_at: 1000,
+            error: None,
+            error_code: None,
+        };
+        let message = ResponseMessage::Quote(quote.sign_blocking(&signer).unwrap());
+        let json = serde_json::to_string(&message).unwrap();
+        assert!(!json.contains("error"));
+        match serde_json::from_str::<ResponseMessage>(&json).unwrap() {
+            ResponseMessage::Quote(signed) => assert_eq!(signed.payload, quote),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+
+        // The prompt is billed at the quoted count and the total is capped
+        assert_eq!(quote.cost(0).unwrap(), U256::from(50));
+        assert_eq!(quote.cost(20).unwrap(), U256::from(250));
+        assert_eq!(quote.cost(500).unwrap(), U256::from(1050));
+        assert!(!quote.is_expired(1000));
+        assert!(quote.is_expired(1001));
+
+        let invalid = Quote { outbound_price: "ten".to_string(), ..quote };
+        assert!(invalid.cost(1).is_err());
+    }
+
     #[test]
     fn test_serialization_llm_response() {
         let response = LlmResponse {
@@ -725,6 +2038,10 @@ mod tests {
             total_cost: "25000000000000000".to_string(),
             model_used: "gpt-4".to_string(),
             error: None,
+            request_id: None,
+            error_code: None,
+            tool_calls: None,
+            choices: None,
         };
 
         let serialized = serde_json::to_string(&response).unwrap();
@@ -746,6 +2063,8 @@ mod tests {
             model: "gpt-3.5-turbo".to_string(),
             token_count: 50,
             timestamp: 1234567890,
+            cancelled: false,
+            request_id: None,
         };
 
         let serialized = serde_json::to_string(&usage_record).unwrap();
@@ -755,6 +2074,12 @@ mod tests {
         assert_eq!(usage_record.model, deserialized.model);
         assert_eq!(usage_record.token_count, deserialized.token_count);
         assert_eq!(usage_record.timestamp, deserialized.timestamp);
+
+        // The cancellation flag is only sent when set
+        assert!(!serialized.contains("cancelled"));
+        let cancelled = UsageRecord { cancelled: true, ..usage_record };
+        let serialized = serde_json::to_string(&cancelled).unwrap();
+        assert!(serde_json::from_str::<UsageRecord>(&serialized).unwrap().cancelled);
     }
 
     #[test]
@@ -778,7 +2103,9 @@ mod tests {
 
     #[test]
     fn test_protocol_constants() {
-        assert_eq!(constants::LLM_PROTOCOL, "/lloom/llm/1.0.0");
+        assert_eq!(constants::LLM_PROTOCOL, "/lloom/llm/2.0.0");
+        assert_eq!(constants::LEGACY_LLM_PROTOCOL, "/lloom/llm/1.0.0");
+        assert_eq!(constants::LLM_PROTOCOLS[0], (constants::PROTOCOL_VERSION, constants::LLM_PROTOCOL));
         assert_eq!(constants::DEFAULT_REQUEST_TIMEOUT, 300);
         assert_eq!(constants::MAX_BATCH_SIZE, 100);
         assert_eq!(constants::BATCH_SUBMISSION_INTERVAL, 300);
@@ -797,6 +2124,7 @@ mod tests {
             outbound_price: "1000000000000000".to_string(),
             nonce: 1,
             deadline: 1234567890,
+            ..Default::default()
         };
 
         let cloned = original.clone();
@@ -816,6 +2144,10 @@ mod tests {
             total_cost: "15000000000000000".to_string(),
             model_used: "gpt-3.5-turbo".to_string(),
             error: Some("Test error".to_string()),
+            request_id: None,
+            error_code: None,
+            tool_calls: None,
+            choices: None,
         };
 
         let cloned = original.clone();
@@ -847,6 +2179,7 @@ mod tests {
             outbound_price: "1000000000000000".to_string(),
             nonce: 1,
             deadline: 1234567890,
+            ..Default::default()
         };
 
         let signed_request = request.sign_blocking(&signer).unwrap();
@@ -873,6 +2206,10 @@ mod tests {
             total_cost: "10000000000000000".to_string(),
             model_used: "gpt-4".to_string(),
             error: None,
+            request_id: None,
+            error_code: None,
+            tool_calls: None,
+            choices: None,
         };
 
         let signed_response = response.sign_blocking(&signer).unwrap();
@@ -899,6 +2236,8 @@ mod tests {
             model: "gpt-3.5-turbo".to_string(),
             token_count: 50,
             timestamp: 1234567890,
+            cancelled: false,
+            request_id: None,
         };
 
         let signed_usage_record = usage_record.sign_blocking(&signer).unwrap();
@@ -930,6 +2269,7 @@ mod tests {
             outbound_price: "1000000000000000".to_string(),
             nonce: 1,
             deadline: 1234567890,
+            ..Default::default()
         };
 
         // Test that the type alias works
@@ -943,6 +2283,10 @@ mod tests {
             total_cost: "15000000000000000".to_string(),
             model_used: "gpt-4".to_string(),
             error: None,
+            request_id: None,
+            error_code: None,
+            tool_calls: None,
+            choices: None,
         };
 
         let signed_response: SignedLlmResponse = response.sign_blocking(&signer).unwrap();
@@ -954,6 +2298,8 @@ mod tests {
             model: "gpt-4".to_string(),
             token_count: 20,
             timestamp: 1234567890,
+            cancelled: false,
+            request_id: None,
         };
 
         let signed_usage_record: SignedUsageRecord = usage_record.sign_blocking(&signer).unwrap();
@@ -980,6 +2326,7 @@ mod tests {
             outbound_price: "1000000000000000".to_string(),
             nonce: 1,
             deadline: 1234567890,
+            ..Default::default()
         };
 
         let signed_request = request.sign_blocking(&signer).unwrap();
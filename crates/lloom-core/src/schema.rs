@@ -0,0 +1,310 @@
+//! Validation of JSON values against JSON Schemas.
+//!
+//! Executors check structured output against the schema a client asked for
+//! before answering. Only the keywords commonly used to describe model output
+//! are understood: `type`, `enum`, `const`, `properties`, `required`,
+//! `additionalProperties`, `items`, `minItems`/`maxItems`,
+//! `minLength`/`maxLength`, `minimum`/`maximum`, `anyOf`, `oneOf` and `allOf`.
+//! Schemas using any other keyword are refused by [`check_schema`] up front,
+//! so output is never accepted against constraints that were not checked.
+
+use serde_json::Value;
+
+/// Keywords [`validate`] checks
+const VALIDATION_KEYWORDS: &[&str] = &[
+    "type", "enum", "const", "properties", "required", "additionalProperties", "items",
+    "minItems", "maxItems", "minLength", "maxLength", "minimum", "maximum", "anyOf", "oneOf", "allOf",
+];
+
+/// Keywords that only annotate a schema and never constrain values
+const ANNOTATION_KEYWORDS: &[&str] = &["$schema", "$id", "$comment", "title", "description", "default", "examples"];
+
+/// Check that `schema` only uses keywords [`validate`] understands.
+///
+/// On failure, returns the first unsupported keyword, prefixed with the JSON
+/// pointer of the subschema using it.
+pub fn check_schema(schema: &Value) -> Result<(), String> {
+    check_schema_at(schema, "")
+}
+
+fn check_schema_at(schema: &Value, path: &str) -> Result<(), String> {
+    let fail = |message: String| Err(format!("{}: {}", if path.is_empty() { "/" } else { path }, message));
+    let schema = match schema {
+        Value::Bool(_) => return Ok(()),
+        Value::Object(schema) => schema,
+        _ => return fail("schema must be an object or a boolean".to_string()),
+    };
+    for (keyword, value) in schema {
+        let child = format!("{}/{}", path, keyword);
+        match keyword.as_str() {
+            "properties" => match value {
+                Value::Object(properties) => {
+                    for (name, property) in properties {
+                        check_schema_at(property, &format!("{}/{}", child, name))?;
+                    }
+                }
+                _ => return fail("properties must be an object".to_string()),
+            },
+            "additionalProperties" | "items" => check_schema_at(value, &child)?,
+            "anyOf" | "oneOf" | "allOf" => match value {
+                Value::Array(sub_schemas) => {
+                    for (index, sub_schema) in sub_schemas.iter().enumerate() {
+                        check_schema_at(sub_schema, &format!("{}/{}", child, index))?;
+                    }
+                }
+                _ => return fail(format!("{} must be an array", keyword)),
+            },
+            "type" => {
+                let names = match value {
+                    Value::Array(names) => names.iter().collect(),
+                    name => vec![name],
+                };
+                if let Some(name) = names.iter().find(|name| !name.as_str().is_some_and(is_type_name)) {
+                    return fail(format!("unknown type {}", name));
+                }
+            }
+            keyword if VALIDATION_KEYWORDS.contains(&keyword) || ANNOTATION_KEYWORDS.contains(&keyword) => {}
+            keyword => return fail(format!("unsupported keyword \"{}\"", keyword)),
+        }
+    }
+    Ok(())
+}
+
+fn is_type_name(name: &str) -> bool {
+    matches!(name, "null" | "boolean" | "object" | "array" | "string" | "number" | "integer")
+}
+
+/// Check `instance` against `schema`.
+///
+/// On failure, returns a description of the first violation found, prefixed
+/// with the JSON pointer of the offending value.
+pub fn validate(schema: &Value, instance: &Value) -> Result<(), String> {
+    validate_at(schema, instance, "")
+}
+
+fn validate_at(schema: &Value, instance: &Value, path: &str) -> Result<(), String> {
+    let fail = |message: String| Err(format!("{}: {}", if path.is_empty() { "/" } else { path }, message));
+    let schema = match schema {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => return fail("no value is allowed here".to_string()),
+        Value::Object(schema) => schema,
+        _ => return fail("schema must be an object or a boolean".to_string()),
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let allowed: Vec<&str> = match expected {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !allowed.iter().any(|name| has_type(instance, name)) {
+            return fail(format!("expected {}, found {}", allowed.join(" or "), type_name(instance)));
+        }
+    }
+    if let Some(Value::Array(options)) = schema.get("enum") {
+        if !options.contains(instance) {
+            return fail(format!("{} is not one of the allowed values", instance));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != instance {
+            return fail(format!("expected {}", expected));
+        }
+    }
+
+    match instance {
+        Value::Object(object) => {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(name) {
+                        return fail(format!("missing required property \"{}\"", name));
+                    }
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (name, value) in object {
+                let child = format!("{}/{}", path, name);
+                match properties.and_then(|properties| properties.get(name)) {
+                    Some(property) => validate_at(property, value, &child)?,
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            return fail(format!("unexpected property \"{}\"", name));
+                        }
+                        Some(additional) => validate_at(additional, value, &child)?,
+                        None => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+                if (items.len() as u64) < min {
+                    return fail(format!("expected at least {} items, found {}", min, items.len()));
+                }
+            }
+            if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+                if items.len() as u64 > max {
+                    return fail(format!("expected at most {} items, found {}", max, items.len()));
+                }
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    validate_at(item_schema, item, &format!("{}/{}", path, index))?;
+                }
+            }
+        }
+        Value::String(text) => {
+            let length = text.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+                if length < min {
+                    return fail(format!("expected at least {} characters, found {}", min, length));
+                }
+            }
+            if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+                if length > max {
+                    return fail(format!("expected at most {} characters, found {}", max, length));
+                }
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or(f64::NAN);
+            if let Some(min) = schema.get("minimum").and_then(Value::as_f64) {
+                if number < min {
+                    return fail(format!("{} is below the minimum {}", number, min));
+                }
+            }
+            if let Some(max) = schema.get("maximum").and_then(Value::as_f64) {
+                if number > max {
+                    return fail(format!("{} is above the maximum {}", number, max));
+                }
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(Value::Array(all)) = schema.get("allOf") {
+        for sub_schema in all {
+            validate_at(sub_schema, instance, path)?;
+        }
+    }
+    if let Some(Value::Array(any)) = schema.get("anyOf") {
+        if !any.iter().any(|sub_schema| validate_at(sub_schema, instance, path).is_ok()) {
+            return fail("value matches none of the anyOf schemas".to_string());
+        }
+    }
+    if let Some(Value::Array(one)) = schema.get("oneOf") {
+        let matches = one.iter().filter(|sub_schema| validate_at(sub_schema, instance, path).is_ok()).count();
+        if matches != 1 {
+            return fail(format!("value matches {} of the oneOf schemas instead of exactly one", matches));
+        }
+    }
+    Ok(())
+}
+
+fn has_type(instance: &Value, name: &str) -> bool {
+    match name {
+        "null" => instance.is_null(),
+        "boolean" => instance.is_boolean(),
+        "object" => instance.is_object(),
+        "array" => instance.is_array(),
+        "string" => instance.is_string(),
+        "number" => instance.is_number(),
+        "integer" => instance.is_i64() || instance.is_u64() || instance.as_f64().is_some_and(|n| n.fract() == 0.0),
+        _ => false,
+    }
+}
+
+fn type_name(instance: &Value) -> &'static str {
+    match instance {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn person_schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string", "minLength": 1 },
+                "age": { "type": "integer", "minimum": 0 },
+                "tags": { "type": "array", "items": { "type": "string" }, "maxItems": 2 },
+                "role": { "enum": ["admin", "user"] }
+            },
+            "required": ["name", "age"],
+            "additionalProperties": false
+        })
+    }
+
+    #[test]
+    fn test_valid_instances() {
+        let schema = person_schema();
+        assert!(validate(&schema, &json!({"name": "Ada", "age": 36})).is_ok());
+        assert!(validate(&schema, &json!({"name": "Ada", "age": 36, "tags": ["x"], "role": "admin"})).is_ok());
+        assert!(validate(&json!(true), &json!([1, 2])).is_ok());
+        assert!(validate(&json!({}), &json!("anything")).is_ok());
+        assert!(validate(&json!({"type": ["string", "null"]}), &Value::Null).is_ok());
+        assert!(validate(&json!({"type": "integer"}), &json!(2.0)).is_ok());
+    }
+
+    #[test]
+    fn test_violations_name_the_offending_value() {
+        let schema = person_schema();
+        let error = validate(&schema, &json!({"name": "Ada"})).unwrap_err();
+        assert_eq!(error, "/: missing required property \"age\"");
+
+        let error = validate(&schema, &json!({"name": "Ada", "age": "old"})).unwrap_err();
+        assert_eq!(error, "/age: expected integer, found string");
+
+        let error = validate(&schema, &json!({"name": "Ada", "age": 1, "tags": ["a", 2]})).unwrap_err();
+        assert_eq!(error, "/tags/1: expected string, found number");
+
+        assert!(validate(&schema, &json!({"name": "", "age": 1})).is_err());
+        assert!(validate(&schema, &json!({"name": "Ada", "age": -1})).is_err());
+        assert!(validate(&schema, &json!({"name": "Ada", "age": 1, "tags": ["a", "b", "c"]})).is_err());
+        assert!(validate(&schema, &json!({"name": "Ada", "age": 1, "role": "root"})).is_err());
+        assert!(validate(&schema, &json!({"name": "Ada", "age": 1, "email": "a@b.c"})).is_err());
+        assert!(validate(&json!(false), &json!(1)).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_keywords_are_refused() {
+        assert!(check_schema(&person_schema()).is_ok());
+        assert!(check_schema(&json!({"title": "Any", "description": "Anything", "anyOf": [true, {"type": ["string", "null"]}]})).is_ok());
+
+        let error = check_schema(&json!({"type": "string", "pattern": "^a"})).unwrap_err();
+        assert_eq!(error, "/: unsupported keyword \"pattern\"");
+        let error = check_schema(&json!({"properties": {"id": {"type": "string", "format": "uuid"}}})).unwrap_err();
+        assert_eq!(error, "/properties/id: unsupported keyword \"format\"");
+        let error = check_schema(&json!({"items": {"$ref": "#/$defs/item"}})).unwrap_err();
+        assert_eq!(error, "/items: unsupported keyword \"$ref\"");
+        for keyword in ["exclusiveMinimum", "exclusiveMaximum", "uniqueItems", "multipleOf", "dependentRequired"] {
+            assert!(check_schema(&json!({"anyOf": [{ keyword: 1 }]})).is_err(), "{}", keyword);
+        }
+        assert!(check_schema(&json!({"type": "date"})).is_err());
+        assert!(check_schema(&json!({"properties": []})).is_err());
+    }
+
+    #[test]
+    fn test_combinators() {
+        let schema = json!({"anyOf": [{"type": "string"}, {"type": "integer"}]});
+        assert!(validate(&schema, &json!(1)).is_ok());
+        assert!(validate(&schema, &json!(true)).is_err());
+
+        let schema = json!({"oneOf": [{"type": "number"}, {"type": "integer"}]});
+        assert!(validate(&schema, &json!(1.5)).is_ok());
+        assert!(validate(&schema, &json!(1)).is_err());
+
+        let schema = json!({"allOf": [{"type": "string"}, {"maxLength": 2}]});
+        assert!(validate(&schema, &json!("ab")).is_ok());
+        assert!(validate(&schema, &json!("abc")).is_err());
+    }
+}
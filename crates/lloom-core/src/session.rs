@@ -0,0 +1,172 @@
+//! Delegated session keys.
+//!
+//! A client's main key does not have to sit on every machine that sends
+//! requests. It signs a [`SessionGrant`] authorizing an ephemeral session key
+//! until an expiry, up to a spending cap and for a set of models, and requests
+//! signed by the session key carry the resulting [`SessionCertificate`].
+//! Executors check the chain of signatures with [`verify_session`] and bill the
+//! main address.
+//!
+//! The spending cap is enforced by each executor for what it bills itself;
+//! executors do not share what a session key has spent elsewhere.
+
+use crate::error::{Error, Result};
+use crate::identity::Identity;
+use crate::signer::Signer;
+use crate::signing::{SIGNATURE_VERSION, SignableMessage, SignedMessage, VerificationConfig, verify_signed_message};
+use alloy::primitives::{Address, U256};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Authorization, signed by a main key, for a session key to sign requests.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionGrant {
+    /// Address of the session key
+    pub session_key: Address,
+    /// Unix timestamp after which the session key is no longer authorized
+    pub expires_at: u64,
+    /// Most the session key may spend, in wei (UINT256 as string)
+    pub spending_cap: String,
+    /// Models the session key may request; any model if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_models: Vec<String>,
+}
+
+impl SignableMessage for SessionGrant {
+    const MESSAGE_TYPE: &'static str = "SessionGrant";
+    const MIN_SIGNATURE_VERSION: u8 = SIGNATURE_VERSION;
+}
+
+/// A session grant signed by the main key it delegates for
+pub type SessionCertificate = SignedMessage<SessionGrant>;
+
+impl SessionGrant {
+    /// The spending cap as a number
+    pub fn spending_cap(&self) -> Result<U256> {
+        U256::from_str(&self.spending_cap)
+            .map_err(|e| Error::Verification(format!("Invalid spending cap {}: {}", self.spending_cap, e)))
+    }
+
+    /// Whether the session key may request `model`
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.allowed_models.is_empty() || self.allowed_models.iter().any(|allowed| allowed == model)
+    }
+}
+
+/// Check that `certificate` authorizes `signer` to request `model` at `now`.
+///
+/// Returns the main address that signed the certificate, which is billed for
+/// the request. Certificates must use the current envelope version.
+pub fn verify_session(
+    certificate: &SessionCertificate,
+    signer: Address,
+    model: &str,
+    now: u64,
+) -> Result<Address> {
+    let config = VerificationConfig::permissive().require_version(SIGNATURE_VERSION);
+    verify_signed_message(certificate, &config)?;
+
+    let grant = &certificate.payload;
+    if grant.session_key != signer {
+        return Err(Error::Verification(format!("Session certificate is for {}, not {}", grant.session_key, signer)));
+    }
+    if now >= grant.expires_at {
+        return Err(Error::Verification(format!("Session certificate expired at {}", grant.expires_at)));
+    }
+    if !grant.allows_model(model) {
+        return Err(Error::Verification(format!("Session certificate does not allow model {}", model)));
+    }
+    grant.spending_cap()?;
+    Ok(certificate.signer)
+}
+
+/// A session key together with the certificate authorizing it, as kept on a
+/// client machine in place of the main key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionKey {
+    /// Private key of the session key (hex encoded)
+    pub private_key: String,
+    /// Certificate signed by the main key
+    pub certificate: SessionCertificate,
+}
+
+impl SessionKey {
+    /// Generate a session key and have `main` authorize it.
+    pub fn generate<S: Signer + ?Sized>(
+        main: &S,
+        expires_at: u64,
+        spending_cap: U256,
+        allowed_models: Vec<String>,
+    ) -> Result<Self> {
+        let session = Identity::generate();
+        let grant = SessionGrant {
+            session_key: session.evm_address,
+            expires_at,
+            spending_cap: spending_cap.to_string(),
+            allowed_models,
+        };
+        Ok(Self {
+            private_key: hex::encode(session.wallet.to_bytes()),
+            certificate: grant.sign_blocking(main)?,
+        })
+    }
+
+    /// The identity signing with the session key
+    pub fn identity(&self) -> Result<Identity> {
+        Identity::from_str(&self.private_key)
+    }
+
+    /// Read a session key file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Write a session key file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::now_secs;
+
+    #[test]
+    fn test_session_chain_of_signatures() {
+        let main = Identity::generate();
+        let now = now_secs().unwrap();
+        let session = SessionKey::generate(&main, now + 3600, U256::from(1000), vec!["gpt-4".to_string()]).unwrap();
+        let session_identity = session.identity().unwrap();
+        assert_eq!(session.certificate.payload.session_key, session_identity.evm_address);
+
+        let certificate = &session.certificate;
+        assert_eq!(verify_session(certificate, session_identity.evm_address, "gpt-4", now).unwrap(), main.evm_address);
+
+        // Only the session key, for the allowed models, until the expiry
+        assert!(verify_session(certificate, main.evm_address, "gpt-4", now).is_err());
+        assert!(verify_session(certificate, session_identity.evm_address, "gpt-3.5-turbo", now).is_err());
+        assert!(verify_session(certificate, session_identity.evm_address, "gpt-4", now + 3600).is_err());
+
+        // The grant cannot be altered without the main key
+        let mut widened = certificate.clone();
+        widened.payload.spending_cap = "1000000".to_string();
+        assert!(verify_session(&widened, session_identity.evm_address, "gpt-4", now).is_err());
+    }
+
+    #[test]
+    fn test_session_key_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.json");
+        let main = Identity::generate();
+        let session = SessionKey::generate(&main, 2_000_000_000, U256::from(1), Vec::new()).unwrap();
+        session.save(&path).unwrap();
+
+        let loaded = SessionKey::load(&path).unwrap();
+        assert_eq!(loaded.identity().unwrap().evm_address, session.certificate.payload.session_key);
+        assert_eq!(loaded.certificate.signer, main.evm_address);
+        assert!(loaded.certificate.payload.allows_model("any-model"));
+    }
+}
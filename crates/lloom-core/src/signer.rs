@@ -0,0 +1,297 @@
+//! Signers for protocol messages and EIP-712 commitments.
+//!
+//! A [`Signer`] holds the key behind a node's EVM address. The default is the
+//! in-process [`PrivateKeySigner`]; a [`RemoteSigner`] keeps the key in a
+//! separate signer daemon and asks it for signatures over a Unix socket.
+
+use crate::eip712::TypedData;
+use crate::error::{Error, Result};
+use crate::signing::SIGNATURE_VERSION;
+use alloy::primitives::{Address, B256, Signature};
+use alloy::signers::SignerSync;
+use alloy::signers::local::PrivateKeySigner;
+
+/// Something that signs for an EVM address.
+///
+/// The two operations are those of the `eth_sign` and `eth_signTypedData_v4`
+/// JSON-RPC methods, so wallets and signer daemons can implement them.
+pub trait Signer: Send + Sync {
+    /// The address signatures recover to
+    fn address(&self) -> Address;
+
+    /// Sign `message` as an EIP-191 personal message
+    fn sign_message(&self, message: &[u8]) -> Result<Signature>;
+
+    /// Sign EIP-712 typed data
+    fn sign_typed_data(&self, typed_data: &TypedData) -> Result<Signature>;
+
+    /// Envelope version protocol messages are signed with, see [`crate::signing`]
+    fn signature_version(&self) -> u8 {
+        SIGNATURE_VERSION
+    }
+
+    /// Sign a 32-byte hash directly, as version 1 envelopes are.
+    ///
+    /// Signers speaking only `eth_sign` and `eth_signTypedData_v4` cannot.
+    fn sign_hash(&self, _hash: &B256) -> Result<Signature> {
+        Err(Error::Signature("Signer cannot sign bare hashes".to_string()))
+    }
+}
+
+impl Signer for PrivateKeySigner {
+    fn address(&self) -> Address {
+        PrivateKeySigner::address(self)
+    }
+
+    fn sign_message(&self, message: &[u8]) -> Result<Signature> {
+        self.sign_message_sync(message)
+            .map_err(|e| Error::Signature(format!("Failed to sign message: {}", e)))
+    }
+
+    fn sign_typed_data(&self, typed_data: &TypedData) -> Result<Signature> {
+        let hash = typed_data.eip712_signing_hash()
+            .map_err(|e| Error::Signature(format!("Invalid typed data: {}", e)))?;
+        self.sign_hash_sync(&hash)
+            .map_err(|e| Error::Signature(format!("Failed to sign typed data: {}", e)))
+    }
+
+    fn sign_hash(&self, hash: &B256) -> Result<Signature> {
+        self.sign_hash_sync(hash)
+            .map_err(|e| Error::Signature(format!("Failed to sign hash: {}", e)))
+    }
+}
+
+#[cfg(unix)]
+pub use remote::RemoteSigner;
+
+#[cfg(unix)]
+mod remote {
+    use super::*;
+    use serde_json::{Value, json};
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+
+    /// How long to wait for the daemon, which may ask its operator to confirm
+    const SIGNER_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Signs through a signer daemon listening on a Unix socket.
+    ///
+    /// Each call opens a connection and sends one JSON-RPC 2.0 request as a
+    /// line of JSON, and the daemon answers with one line. The methods used are
+    /// `eth_accounts`, `eth_sign` (address, hex data) and `eth_signTypedData_v4`
+    /// (address, typed data as a JSON string); signatures are 65 hex bytes.
+    pub struct RemoteSigner {
+        path: PathBuf,
+        address: Address,
+        next_id: AtomicU64,
+    }
+
+    impl RemoteSigner {
+        /// Use the daemon at `path` to sign for `address`, or for the first
+        /// account it reports if none is given.
+        pub fn connect(path: impl Into<PathBuf>, address: Option<Address>) -> Result<Self> {
+            let mut signer = Self {
+                path: path.into(),
+                address: address.unwrap_or_default(),
+                next_id: AtomicU64::new(1),
+            };
+            let accounts: Vec<Address> = serde_json::from_value(signer.call("eth_accounts", json!([]))?)
+                .map_err(|e| Error::Signature(format!("Invalid eth_accounts result: {}", e)))?;
+            match address {
+                Some(address) if !accounts.contains(&address) => {
+                    return Err(Error::Signature(format!("Signer daemon has no key for {}", address)));
+                }
+                Some(_) => {}
+                None => {
+                    signer.address = *accounts.first()
+                        .ok_or_else(|| Error::Signature("Signer daemon has no accounts".to_string()))?;
+                }
+            }
+            Ok(signer)
+        }
+
+        fn call(&self, method: &str, params: Value) -> Result<Value> {
+            let failed = |e: std::io::Error| {
+                Error::Signature(format!("Signer daemon at {} failed: {}", self.path.display(), e))
+            };
+            let stream = UnixStream::connect(&self.path).map_err(failed)?;
+            stream.set_read_timeout(Some(SIGNER_TIMEOUT)).map_err(failed)?;
+            stream.set_write_timeout(Some(SIGNER_TIMEOUT)).map_err(failed)?;
+
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let mut request = serde_json::to_vec(&json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": method,
+                "params": params,
+            }))?;
+            request.push(b'\n');
+            (&stream).write_all(&request).map_err(failed)?;
+
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).map_err(failed)?;
+            let response: Value = serde_json::from_str(&line)
+                .map_err(|e| Error::Signature(format!("Invalid {} response: {}", method, e)))?;
+            if response["id"] != json!(id) {
+                return Err(Error::Signature(format!("Mismatched {} response id", method)));
+            }
+            if let Some(error) = response.get("error") {
+                return Err(Error::Signature(format!("{} refused: {}", method, error["message"].as_str().unwrap_or("unknown error"))));
+            }
+            response.get("result").cloned()
+                .ok_or_else(|| Error::Signature(format!("{} response has no result", method)))
+        }
+
+        fn signature(&self, method: &str, params: Value) -> Result<Signature> {
+            let result = self.call(method, params)?;
+            let bytes = result.as_str()
+                .and_then(|hex| hex::decode(hex.trim_start_matches("0x")).ok())
+                .ok_or_else(|| Error::Signature(format!("{} returned no signature", method)))?;
+            Signature::from_raw(&bytes)
+                .map_err(|e| Error::Signature(format!("{} returned an invalid signature: {}", method, e)))
+        }
+    }
+
+    impl Signer for RemoteSigner {
+        fn address(&self) -> Address {
+            self.address
+        }
+
+        fn sign_message(&self, message: &[u8]) -> Result<Signature> {
+            self.signature("eth_sign", json!([self.address, format!("0x{}", hex::encode(message))]))
+        }
+
+        fn sign_typed_data(&self, typed_data: &TypedData) -> Result<Signature> {
+            self.signature("eth_signTypedData_v4", json!([self.address, serde_json::to_string(typed_data)?]))
+        }
+    }
+
+    impl std::fmt::Debug for RemoteSigner {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("RemoteSigner")
+                .field("path", &self.path)
+                .field("address", &self.address)
+                .finish()
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::eip712::{EIP712Domain, Eip712Struct, LlmRequestCommitment};
+    use crate::identity::Identity;
+    use crate::signing::{SignableMessage, verify_signed_message_basic};
+    use alloy::primitives::{B256, U256};
+    use serde_json::{Value, json};
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    /// Serve the signer daemon protocol for `key` on a socket in a new directory.
+    fn fake_daemon(key: PrivateKeySigner) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("signer.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { break };
+                let mut line = String::new();
+                if BufReader::new(&stream).read_line(&mut line).is_err() {
+                    continue;
+                }
+                let request: Value = serde_json::from_str(&line).unwrap();
+                let reply = match answer(&key, &request) {
+                    Ok(result) => json!({"jsonrpc": "2.0", "id": request["id"], "result": result}),
+                    Err(message) => json!({"jsonrpc": "2.0", "id": request["id"], "error": {"code": -32000, "message": message}}),
+                };
+                let _ = writeln!(&stream, "{}", reply);
+            }
+        });
+        (dir, path)
+    }
+
+    fn answer(key: &PrivateKeySigner, request: &Value) -> std::result::Result<Value, String> {
+        let params = &request["params"];
+        if request["method"] != "eth_accounts" && params[0] != json!(key.address()) {
+            return Err("unknown account".to_string());
+        }
+        let signature = match request["method"].as_str() {
+            Some("eth_accounts") => return Ok(json!([key.address()])),
+            Some("eth_sign") => {
+                let data = hex::decode(params[1].as_str().unwrap().trim_start_matches("0x")).unwrap();
+                key.sign_message(&data)
+            }
+            Some("eth_signTypedData_v4") => {
+                let typed_data: TypedData = serde_json::from_str(params[1].as_str().unwrap()).unwrap();
+                key.sign_typed_data(&typed_data)
+            }
+            _ => return Err("method not found".to_string()),
+        };
+        Ok(json!(format!("0x{}", hex::encode(signature.map_err(|e| e.to_string())?.as_bytes()))))
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+    struct Ping {
+        value: u32,
+    }
+
+    impl SignableMessage for Ping {
+        const MESSAGE_TYPE: &'static str = "Ping";
+    }
+
+    #[test]
+    fn test_remote_signer_signs_messages() {
+        let key = PrivateKeySigner::random();
+        let (_dir, path) = fake_daemon(key.clone());
+        let remote = RemoteSigner::connect(&path, None).unwrap();
+        assert_eq!(Signer::address(&remote), key.address());
+
+        // Signatures from the daemon verify like local ones
+        let signed = Ping { value: 1 }.sign_blocking(&remote).unwrap();
+        assert_eq!(signed.signer, key.address());
+        assert!(verify_signed_message_basic(&signed).is_ok());
+
+        let domain = EIP712Domain::new(1, Address::repeat_byte(1));
+        let commitment = LlmRequestCommitment {
+            requestId: "req_1".to_string(),
+            executor: Address::repeat_byte(2),
+            model: "gpt-4".to_string(),
+            promptHash: B256::ZERO,
+            systemPromptHash: B256::ZERO,
+            maxTokens: 10,
+            temperature: 10000,
+            samplingHash: B256::ZERO,
+            inboundPrice: U256::from(1),
+            outboundPrice: U256::from(2),
+            nonce: 1,
+            deadline: 0,
+        };
+        let signature = commitment.sign(&remote, &domain).unwrap();
+        assert_eq!(signature, commitment.sign(&key, &domain).unwrap());
+        assert!(commitment.verify_signature(&key.address(), &domain, &signature).unwrap());
+
+        // A daemon without the requested key is refused up front
+        assert!(RemoteSigner::connect(&path, Some(Address::repeat_byte(3))).is_err());
+        assert!(RemoteSigner::connect(path.with_file_name("missing.sock"), None).is_err());
+    }
+
+    #[test]
+    fn test_identity_with_remote_signer() {
+        let key = PrivateKeySigner::random();
+        let (_dir, path) = fake_daemon(key.clone());
+        let node = Identity::generate();
+        let peer_id = node.peer_id;
+        let identity = node.with_signer(Arc::new(RemoteSigner::connect(&path, None).unwrap()));
+
+        // The network identity stays local while messages are signed remotely
+        assert_eq!(identity.peer_id, peer_id);
+        assert_eq!(identity.evm_address, key.address());
+        let signed = Ping { value: 2 }.sign_blocking(&identity).unwrap();
+        assert_eq!(verify_signed_message_basic(&signed).map(|_| signed.signer).unwrap(), key.address());
+    }
+}
@@ -2,32 +2,85 @@
 //!
 //! This module provides cryptographic signing and verification capabilities for protocol messages,
 //! ensuring non-repudiation and creating audit trails for all LLM requests and responses.
+//!
+//! The signed bytes are a domain tag naming the message type and envelope
+//! version, `lloom:<type>:v2` followed by a newline, and then canonical JSON
+//! (see [`canonical_json`]) of the whole envelope: message type, payload,
+//! signer, timestamp and nonce. Any implementation can reproduce them, a
+//! signature for one message type never verifies as another, and the metadata
+//! cannot be rewritten without invalidating the signature. They are signed as
+//! an EIP-191 personal message, so any [`Signer`], including a wallet or signer
+//! daemon answering `eth_sign`, can produce the signature.
+//!
+//! Peers that predate versioned envelopes sign the keccak hash of the payload's
+//! plain JSON (version 1), leaving the timestamp and nonce unauthenticated.
+//! These envelopes are still accepted while peers upgrade, unless
+//! [`VerificationConfig::min_version`] rules them out; once every peer signs
+//! version 2, require it with [`VerificationConfig::require_version`]. Until
+//! every peer verifies version 2, a node can keep signing version 1 with
+//! [`Identity::with_signature_version`](crate::identity::Identity::with_signature_version).
+//!
+//! Verification alone accepts a message any number of times within its age
+//! window; a [`ReplayGuard`] accepts each one once.
 
 use alloy::primitives::{Address, Bytes};
-use alloy::signers::local::PrivateKeySigner;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
 use crate::error::{Error, Result};
+use crate::signer::Signer;
+
+/// Envelope version of signatures over the payload's plain JSON.
+pub const LEGACY_SIGNATURE_VERSION: u8 = 1;
+
+/// Latest envelope version (2 signs the envelope as an EIP-191 personal message).
+pub const SIGNATURE_VERSION: u8 = 2;
+
+/// How far in the future a message timestamp may be, to allow for clock skew.
+const CLOCK_SKEW_TOLERANCE: u64 = 300; // 5 minutes
+
+/// Number of signed messages a [`ReplayGuard`] remembers by default.
+pub const DEFAULT_REPLAY_CAPACITY: usize = 100_000;
+
+fn legacy_signature_version() -> u8 {
+    LEGACY_SIGNATURE_VERSION
+}
 
 /// A wrapper struct for cryptographically signed messages.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct SignedMessage<T: Serialize> {
     /// The actual message payload
     pub payload: T,
     /// The signer's Ethereum address
     pub signer: Address,
-    /// Signature of the serialized payload
+    /// Signature of the payload's signing bytes, see [`signing_bytes`]
     pub signature: Bytes,
     /// Timestamp when the message was signed (Unix timestamp in seconds)
     pub timestamp: u64,
     /// Optional nonce to prevent replay attacks
     pub nonce: Option<u64>,
+    /// Envelope version, which decides what bytes were signed. Envelopes from
+    /// older peers carry none and are version 1.
+    #[serde(default = "legacy_signature_version")]
+    pub version: u8,
 }
 
 /// Trait for messages that can be cryptographically signed.
 pub trait SignableMessage: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync {
+    /// Name of the message type in the signature's domain tag. It must be
+    /// unique among signed messages and never change.
+    const MESSAGE_TYPE: &'static str;
+
+    /// Oldest envelope version the message is signed with, whatever the
+    /// signer's [`Signer::signature_version`]. Messages only upgraded peers
+    /// understand never need legacy envelopes.
+    const MIN_SIGNATURE_VERSION: u8 = LEGACY_SIGNATURE_VERSION;
+
     /// Sign this message using the provided signer (blocking version).
-    fn sign_blocking(&self, signer: &PrivateKeySigner) -> Result<SignedMessage<Self>> {
+    fn sign_blocking<S: Signer + ?Sized>(&self, signer: &S) -> Result<SignedMessage<Self>> {
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .map_err(|e| Error::Signature(format!("Failed to get timestamp: {}", e)))?
@@ -37,9 +90,9 @@ pub trait SignableMessage: Serialize + for<'de> Deserialize<'de> + Clone + Send
     }
 
     /// Sign this message with a specific timestamp and optional nonce (blocking version).
-    fn sign_with_params_blocking(
+    fn sign_with_params_blocking<S: Signer + ?Sized>(
         &self,
-        signer: &PrivateKeySigner,
+        signer: &S,
         timestamp: u64,
         nonce: Option<u64>,
     ) -> Result<SignedMessage<Self>> {
@@ -54,6 +107,8 @@ pub struct VerificationConfig {
     pub max_age_seconds: Option<u64>,
     /// Whether to enforce strict timestamp validation
     pub strict_timestamp: bool,
+    /// Oldest envelope version accepted
+    pub min_version: u8,
 }
 
 impl Default for VerificationConfig {
@@ -61,6 +116,7 @@ impl Default for VerificationConfig {
         Self {
             max_age_seconds: Some(3600), // 1 hour default
             strict_timestamp: true,
+            min_version: LEGACY_SIGNATURE_VERSION,
         }
     }
 }
@@ -71,6 +127,7 @@ impl VerificationConfig {
         Self {
             max_age_seconds: None,
             strict_timestamp: false,
+            min_version: LEGACY_SIGNATURE_VERSION,
         }
     }
 
@@ -79,69 +136,146 @@ impl VerificationConfig {
         Self {
             max_age_seconds: Some(max_age_seconds),
             strict_timestamp: true,
+            min_version: LEGACY_SIGNATURE_VERSION,
+        }
+    }
+
+    /// Reject envelopes older than `min_version`.
+    pub fn require_version(mut self, min_version: u8) -> Self {
+        self.min_version = min_version;
+        self
+    }
+}
+
+/// Encode a value as canonical JSON: object keys sorted by their UTF-8 bytes,
+/// no whitespace, and strings and numbers as `serde_json` prints them.
+///
+/// Map fields such as `HashMap`s encode the same whatever their iteration order.
+pub fn canonical_json<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let value = serde_json::to_value(value)
+        .map_err(|e| Error::Signature(format!("Failed to serialize message: {}", e)))?;
+    let mut out = Vec::new();
+    write_canonical(&value, &mut out);
+    Ok(out)
+}
+
+fn write_canonical(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.as_bytes().cmp(b.as_bytes()));
+            out.push(b'{');
+            for (index, (key, value)) in entries.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(b',');
+                }
+                write_canonical(&Value::String(key.clone()), out);
+                out.push(b':');
+                write_canonical(value, out);
+            }
+            out.push(b'}');
+        }
+        Value::Array(items) => {
+            out.push(b'[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(b',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(b']');
         }
+        // Scalars have a single serde_json encoding
+        scalar => out.extend_from_slice(scalar.to_string().as_bytes()),
     }
 }
 
+/// The bytes a signature covers under envelope `version`.
+///
+/// Version 2 commits to every field of the envelope but the signature itself;
+/// the signer is encoded as lowercase `0x`-prefixed hex. Version 1 ignores
+/// `signer`, `timestamp` and `nonce`.
+pub fn signing_bytes<T: SignableMessage>(
+    payload: &T,
+    signer: Address,
+    timestamp: u64,
+    nonce: Option<u64>,
+    version: u8,
+) -> Result<Vec<u8>> {
+    let mut bytes = format!("lloom:{}:v{}\n", T::MESSAGE_TYPE, version).into_bytes();
+    match version {
+        LEGACY_SIGNATURE_VERSION => {
+            return serde_json::to_vec(payload)
+                .map_err(|e| Error::Signature(format!("Failed to serialize message: {}", e)));
+        }
+        SIGNATURE_VERSION => {
+            let payload = serde_json::to_value(payload)
+                .map_err(|e| Error::Signature(format!("Failed to serialize message: {}", e)))?;
+            bytes.extend(canonical_json(&serde_json::json!({
+                "message_type": T::MESSAGE_TYPE,
+                "nonce": nonce,
+                "payload": payload,
+                "signer": format!("{:#x}", signer),
+                "timestamp": timestamp,
+            }))?);
+        }
+        _ => return Err(Error::Signature(format!("Unsupported signature version {}", version))),
+    }
+    Ok(bytes)
+}
+
 /// Sign a message using the provided signer (blocking version).
 ///
+/// The envelope version is the signer's [`Signer::signature_version`], raised
+/// to the message's [`SignableMessage::MIN_SIGNATURE_VERSION`]. Version 1
+/// envelopes sign the keccak hash of their bytes with [`Signer::sign_hash`].
+///
 /// # Arguments
 /// * `message` - The message to sign
-/// * `signer` - The private key signer
+/// * `signer` - The signer holding the key
 /// * `timestamp` - Unix timestamp in seconds
 /// * `nonce` - Optional nonce for replay protection
 ///
 /// # Returns
 /// A `SignedMessage` containing the original message and signature metadata.
-pub fn sign_message_blocking<T: Serialize + for<'de> Deserialize<'de>>(
+pub fn sign_message_blocking<T: SignableMessage, S: Signer + ?Sized>(
     message: &T,
-    signer: &PrivateKeySigner,
+    signer: &S,
     timestamp: u64,
     nonce: Option<u64>,
 ) -> Result<SignedMessage<T>> {
-    // Serialize the message to JSON bytes
-    let message_bytes = serde_json::to_vec(message)
-        .map_err(|e| Error::Signature(format!("Failed to serialize message: {}", e)))?;
-
-    // Create the hash of the message
-    let message_hash = alloy::primitives::keccak256(&message_bytes);
-
-    // Get the private key bytes from the signer
-    let private_key_bytes = signer.credential().to_bytes();
-    
-    // Create a k256 signing key directly for synchronous signing
-    let signing_key = k256::ecdsa::SigningKey::from_bytes(&private_key_bytes)
-        .map_err(|e| Error::Signature(format!("Failed to create signing key: {}", e)))?;
-
-    // Sign the hash synchronously using k256
-    let (signature, recovery_id) = signing_key
-        .sign_prehash_recoverable(message_hash.as_slice())
-        .map_err(|e| Error::Signature(format!("Failed to sign message hash: {}", e)))?;
-
-    // Convert to the 65-byte format expected by Ethereum (r + s + v)
-    let mut signature_bytes = [0u8; 65];
-    signature_bytes[..64].copy_from_slice(&signature.to_bytes());
-    signature_bytes[64] = recovery_id.to_byte();
+    let address = signer.address();
+    let version = signer.signature_version().max(T::MIN_SIGNATURE_VERSION);
+    let message_bytes = signing_bytes(message, address, timestamp, nonce, version)?;
+    let signature = if version >= SIGNATURE_VERSION {
+        signer.sign_message(&message_bytes)?
+    } else {
+        signer.sign_hash(&alloy::primitives::keccak256(&message_bytes))?
+    };
 
     Ok(SignedMessage {
-        payload: serde_json::from_slice(&message_bytes)
-            .map_err(|e| Error::Signature(format!("Failed to deserialize message: {}", e)))?,
-        signer: signer.address(),
-        signature: signature_bytes.into(),
+        payload: message.clone(),
+        signer: address,
+        signature: signature.as_bytes().into(),
         timestamp,
         nonce,
+        version,
     })
 }
 
 /// Verify a signed message.
 ///
+/// Legacy envelopes (version 1) pass when `config.min_version` allows
+/// them, but their timestamp and nonce are unauthenticated, so the age check
+/// only bounds what an honest sender claimed.
+///
 /// # Arguments
 /// * `signed_message` - The signed message to verify
 /// * `config` - Verification configuration
 ///
 /// # Returns
 /// `Ok(())` if the signature is valid, otherwise an error.
-pub fn verify_signed_message<T: Serialize>(
+pub fn verify_signed_message<T: SignableMessage>(
     signed_message: &SignedMessage<T>,
     config: &VerificationConfig,
 ) -> Result<()> {
@@ -150,12 +284,27 @@ pub fn verify_signed_message<T: Serialize>(
         validate_timestamp(signed_message.timestamp, config.max_age_seconds)?;
     }
 
-    // Serialize the payload to get the original message bytes
-    let message_bytes = serde_json::to_vec(&signed_message.payload)
-        .map_err(|e| Error::Verification(format!("Failed to serialize payload: {}", e)))?;
-
-    // Create the hash of the message
-    let message_hash = alloy::primitives::keccak256(&message_bytes);
+    if signed_message.version < config.min_version {
+        return Err(Error::Verification(format!(
+            "Signature version {} is older than the minimum {}",
+            signed_message.version, config.min_version
+        )));
+    }
+    if signed_message.version < SIGNATURE_VERSION {
+        tracing::debug!(
+            signer = %signed_message.signer,
+            version = signed_message.version,
+            "Accepting legacy signature without signed metadata"
+        );
+    }
+    let message_bytes = signing_bytes(
+        &signed_message.payload,
+        signed_message.signer,
+        signed_message.timestamp,
+        signed_message.nonce,
+        signed_message.version,
+    )
+    .map_err(|e| Error::Verification(e.to_string()))?;
 
     // Convert signature bytes back to signature
     let signature_bytes: [u8; 65] = signed_message.signature.as_ref().try_into()
@@ -165,8 +314,14 @@ pub fn verify_signed_message<T: Serialize>(
     let signature = alloy::primitives::Signature::try_from(&signature_bytes[..])
         .map_err(|e| Error::Verification(format!("Failed to parse signature: {}", e)))?;
 
-    // Recover the signer's address from the signature
-    let recovered_address = signature.recover_address_from_prehash(&message_hash)
+    // Recover the signer's address from the signature; version 2 signs an
+    // EIP-191 personal message, version 1 the bare hash
+    let recovered = if signed_message.version >= SIGNATURE_VERSION {
+        signature.recover_address_from_msg(&message_bytes)
+    } else {
+        signature.recover_address_from_prehash(&alloy::primitives::keccak256(&message_bytes))
+    };
+    let recovered_address = recovered
         .map_err(|e| Error::Verification(format!("Failed to recover address: {}", e)))?;
 
     // Verify that the recovered address matches the claimed signer
@@ -188,7 +343,6 @@ fn validate_timestamp(timestamp: u64, max_age_seconds: Option<u64>) -> Result<()
         .as_secs();
 
     // Check if the timestamp is from the future (with a small tolerance for clock skew)
-    const CLOCK_SKEW_TOLERANCE: u64 = 300; // 5 minutes
     if timestamp > current_time + CLOCK_SKEW_TOLERANCE {
         return Err(Error::Verification(format!(
             "Message timestamp is too far in the future: {} > {}",
@@ -211,20 +365,20 @@ fn validate_timestamp(timestamp: u64, max_age_seconds: Option<u64>) -> Result<()
 }
 
 /// Verify a signed message with basic validation (uses default config).
-pub fn verify_signed_message_basic<T: Serialize>(
+pub fn verify_signed_message_basic<T: SignableMessage>(
     signed_message: &SignedMessage<T>,
 ) -> Result<()> {
     verify_signed_message(signed_message, &VerificationConfig::default())
 }
 
 /// Verify a signed message with permissive validation (no timestamp checks).
-pub fn verify_signed_message_permissive<T: Serialize>(
+pub fn verify_signed_message_permissive<T: SignableMessage>(
     signed_message: &SignedMessage<T>,
 ) -> Result<()> {
     verify_signed_message(signed_message, &VerificationConfig::permissive())
 }
 
-impl<T: Serialize> SignedMessage<T> {
+impl<T: SignableMessage> SignedMessage<T> {
     /// Verify this signed message with a time window for replay protection.
     ///
     /// # Arguments
@@ -251,6 +405,169 @@ impl<T: Serialize> SignedMessage<T> {
     }
 }
 
+/// Remembers verified messages so that each is accepted only once.
+///
+/// A message is identified by its signer, type and nonce when the nonce is
+/// signed (envelope version 2), and otherwise by its signer and the hash of its
+/// signed bytes. It is remembered until it is too old to pass verification. If
+/// more than `capacity` messages are live, those closest to expiry are forgotten
+/// first: memory stays bounded, at the cost of a shorter window under flood.
+///
+/// Legacy envelopes do not sign their timestamp, so one that is forgotten can
+/// be replayed with a fresh timestamp. Once every peer signs version 2, refuse
+/// them with [`ReplayGuard::require_version`].
+#[derive(Clone)]
+pub struct ReplayGuard {
+    inner: Arc<Mutex<ReplayState>>,
+    config: VerificationConfig,
+    capacity: usize,
+    path: Option<PathBuf>,
+}
+
+#[derive(Default)]
+struct ReplayState {
+    expiries: HashMap<String, u64>,
+    by_expiry: BTreeSet<(u64, String)>,
+}
+
+impl ReplayState {
+    fn insert(&mut self, key: String, expires_at: u64) {
+        self.by_expiry.insert((expires_at, key.clone()));
+        self.expiries.insert(key, expires_at);
+    }
+
+    fn forget_first(&mut self) {
+        if let Some((_, key)) = self.by_expiry.pop_first() {
+            self.expiries.remove(&key);
+        }
+    }
+
+    fn prune(&mut self, now: u64) {
+        while self.by_expiry.first().is_some_and(|(expires_at, _)| *expires_at < now) {
+            self.forget_first();
+        }
+    }
+}
+
+impl ReplayGuard {
+    /// Create a guard for messages at most `max_age_seconds` old, remembering
+    /// up to `capacity` of them.
+    pub fn new(max_age_seconds: u64, capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(ReplayState::default())),
+            config: VerificationConfig::with_max_age(max_age_seconds),
+            capacity,
+            path: None,
+        }
+    }
+
+    /// Refuse envelopes older than `min_version`, which must be
+    /// [`LEGACY_SIGNATURE_VERSION`] or [`SIGNATURE_VERSION`].
+    pub fn require_version(mut self, min_version: u8) -> Result<Self> {
+        if min_version != LEGACY_SIGNATURE_VERSION && min_version != SIGNATURE_VERSION {
+            return Err(Error::Verification(format!(
+                "Unsupported minimum signature version {}; use {} or {}",
+                min_version, LEGACY_SIGNATURE_VERSION, SIGNATURE_VERSION
+            )));
+        }
+        self.config = self.config.require_version(min_version);
+        Ok(self)
+    }
+
+    /// Keep the seen messages in `path` across restarts, loading any already there.
+    pub fn with_persistence(mut self, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        if path.exists() {
+            let saved: HashMap<String, u64> = serde_json::from_slice(&std::fs::read(&path)?)?;
+            let mut state = self.inner.lock().unwrap();
+            for (key, expires_at) in saved {
+                state.insert(key, expires_at);
+            }
+            state.prune(now_secs()?);
+            while state.expiries.len() > self.capacity {
+                state.forget_first();
+            }
+        }
+        self.path = Some(path);
+        Ok(self)
+    }
+
+    /// Verify a signed message and accept it if it has not been seen before.
+    ///
+    /// Returns the signer's address.
+    pub fn verify<T: SignableMessage>(&self, signed_message: &SignedMessage<T>) -> Result<Address> {
+        verify_signed_message(signed_message, &self.config)?;
+        self.check(signed_message, now_secs()?)?;
+        Ok(signed_message.signer)
+    }
+
+    /// Record a message as seen at `now`, failing if it already was.
+    ///
+    /// The signature is not checked; use [`ReplayGuard::verify`] for that.
+    pub fn check<T: SignableMessage>(&self, signed_message: &SignedMessage<T>, now: u64) -> Result<()> {
+        let key = match signed_message.nonce {
+            Some(nonce) if signed_message.version >= SIGNATURE_VERSION => {
+                format!("{}:{:#x}:nonce:{}", T::MESSAGE_TYPE, signed_message.signer, nonce)
+            }
+            _ => {
+                let bytes = signing_bytes(
+                    &signed_message.payload,
+                    signed_message.signer,
+                    signed_message.timestamp,
+                    signed_message.nonce,
+                    signed_message.version,
+                )?;
+                format!("{:#x}:{}", signed_message.signer, alloy::primitives::keccak256(bytes))
+            }
+        };
+        let max_age = self.config.max_age_seconds.unwrap_or(0);
+        let expires_at = signed_message.timestamp.min(now + CLOCK_SKEW_TOLERANCE).saturating_add(max_age);
+
+        let mut state = self.inner.lock().unwrap();
+        state.prune(now);
+        if state.expiries.contains_key(&key) {
+            return Err(Error::Replay(format!("{} from {} was already accepted", T::MESSAGE_TYPE, signed_message.signer)));
+        }
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        while state.expiries.len() >= self.capacity {
+            state.forget_first();
+        }
+        state.insert(key, expires_at);
+        Ok(())
+    }
+
+    /// Write the live entries to the persistence file, if there is one.
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let bytes = {
+            let mut state = self.inner.lock().unwrap();
+            state.prune(now_secs()?);
+            serde_json::to_vec(&state.expiries)?
+        };
+        // Write to a temporary file first so a crash never leaves a truncated file
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, bytes)?;
+        std::fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.inner.lock().unwrap().expiries.len()
+    }
+}
+
+pub(crate) fn now_secs() -> Result<u64> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| Error::Verification(format!("Failed to get current time: {}", e)))?
+        .as_secs())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -263,7 +580,9 @@ mod tests {
         value: u32,
     }
 
-    impl SignableMessage for TestMessage {}
+    impl SignableMessage for TestMessage {
+        const MESSAGE_TYPE: &'static str = "TestMessage";
+    }
 
     fn create_test_signer() -> PrivateKeySigner {
         "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
@@ -271,6 +590,28 @@ mod tests {
             .expect("Valid private key")
     }
 
+    /// Sign as older peers did, over the keccak hash of a legacy envelope's bytes.
+    fn sign_versioned<T: SignableMessage>(
+        message: &T,
+        signer: &PrivateKeySigner,
+        timestamp: u64,
+        nonce: Option<u64>,
+        version: u8,
+    ) -> Result<SignedMessage<T>> {
+        use alloy::signers::SignerSync;
+
+        let bytes = signing_bytes(message, signer.address(), timestamp, nonce, version)?;
+        let signature = signer.sign_hash_sync(&alloy::primitives::keccak256(&bytes)).unwrap();
+        Ok(SignedMessage {
+            payload: message.clone(),
+            signer: signer.address(),
+            signature: signature.as_bytes().into(),
+            timestamp,
+            nonce,
+            version,
+        })
+    }
+
     #[tokio::test]
     async fn test_sign_and_verify_message() {
         let signer = create_test_signer();
@@ -327,7 +668,10 @@ mod tests {
         match result.unwrap_err() {
             Error::InvalidSigner { expected, recovered } => {
                 assert_eq!(expected, signer2.address());
-                assert_eq!(recovered, signer1.address());
+                // The signer is part of the signed bytes, so the recovered
+                // address is neither the claimed nor the original signer
+                assert_ne!(recovered, signer2.address());
+                assert_ne!(recovered, signer1.address());
             }
             _ => panic!("Expected InvalidSigner error"),
         }
@@ -395,6 +739,7 @@ mod tests {
         let default_config = VerificationConfig::default();
         assert_eq!(default_config.max_age_seconds, Some(3600));
         assert!(default_config.strict_timestamp);
+        assert_eq!(default_config.min_version, LEGACY_SIGNATURE_VERSION);
 
         let permissive_config = VerificationConfig::permissive();
         assert_eq!(permissive_config.max_age_seconds, None);
@@ -419,6 +764,7 @@ mod tests {
             outbound_price: "1000000000000000".to_string(),
             nonce: 1,
             deadline: 1234567890,
+            ..Default::default()
         };
 
         let signed_request = request.sign_blocking(&signer).unwrap();
@@ -438,6 +784,10 @@ mod tests {
             total_cost: "25000000000000000".to_string(),
             model_used: "gpt-4".to_string(),
             error: None,
+            request_id: None,
+            error_code: None,
+            tool_calls: None,
+            choices: None,
         };
 
         let signed_response = response.sign_blocking(&signer).unwrap();
@@ -484,6 +834,203 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("Invalid signature length"));
     }
 
+    #[test]
+    fn test_canonical_json() {
+        use std::collections::HashMap;
+
+        let mut first = HashMap::new();
+        let mut second = HashMap::new();
+        for key in ["zeta", "alpha", "mid", "Beta"] {
+            first.insert(key.to_string(), serde_json::json!({"b": 1, "a": [true, null, "x\"y"]}));
+        }
+        for key in ["Beta", "mid", "alpha", "zeta"] {
+            second.insert(key.to_string(), serde_json::json!({"a": [true, null, "x\"y"], "b": 1}));
+        }
+        let encoded = canonical_json(&first).unwrap();
+        assert_eq!(encoded, canonical_json(&second).unwrap());
+        assert!(String::from_utf8(encoded).unwrap().starts_with(r#"{"Beta":{"a":[true,null,"x\"y"],"b":1},"alpha":"#));
+    }
+
+    #[test]
+    fn test_signatures_are_bound_to_the_message_type() {
+        #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+        struct OtherMessage {
+            content: String,
+            value: u32,
+        }
+        impl SignableMessage for OtherMessage {
+            const MESSAGE_TYPE: &'static str = "OtherMessage";
+        }
+
+        let signer = create_test_signer();
+        let message = TestMessage { content: "Same fields".to_string(), value: 7 };
+        let signed = message.sign_blocking(&signer).unwrap();
+        assert_eq!(signed.version, SIGNATURE_VERSION);
+
+        // The same JSON under another type does not verify
+        let replayed = SignedMessage {
+            payload: OtherMessage { content: message.content.clone(), value: message.value },
+            signer: signed.signer,
+            signature: signed.signature.clone(),
+            timestamp: signed.timestamp,
+            nonce: signed.nonce,
+            version: signed.version,
+        };
+        assert!(verify_signed_message_basic(&replayed).is_err());
+
+        // Nor does relabelling the envelope as legacy
+        let relabelled = SignedMessage { version: LEGACY_SIGNATURE_VERSION, ..signed.clone() };
+        assert!(verify_signed_message_basic(&relabelled).is_err());
+        for version in [0, 3, 9] {
+            let unknown = SignedMessage { version, ..signed.clone() };
+            assert!(verify_signed_message_basic(&unknown).is_err());
+            assert!(sign_versioned(&message, &signer, signed.timestamp, None, version).is_err());
+        }
+    }
+
+    #[test]
+    fn test_envelope_metadata_is_signed() {
+        let signer = create_test_signer();
+        let message = TestMessage { content: "Metadata".to_string(), value: 5 };
+        let signed = message.sign_with_params_blocking(&signer, 1234567890, Some(1)).unwrap();
+        assert!(verify_signed_message_permissive(&signed).is_ok());
+
+        // A captured message cannot be made fresh or given another nonce
+        let refreshed = SignedMessage { timestamp: now_secs().unwrap(), ..signed.clone() };
+        assert!(verify_signed_message_basic(&refreshed).is_err());
+        let renonced = SignedMessage { nonce: Some(2), ..signed.clone() };
+        assert!(verify_signed_message_permissive(&renonced).is_err());
+        let unnonced = SignedMessage { nonce: None, ..signed };
+        assert!(verify_signed_message_permissive(&unnonced).is_err());
+
+        // Legacy envelopes still verify until version 2 is required
+        let legacy = sign_versioned(&message, &signer, 1234567890, Some(1), LEGACY_SIGNATURE_VERSION).unwrap();
+        let refreshed = SignedMessage { timestamp: now_secs().unwrap(), ..legacy };
+        assert!(verify_signed_message_basic(&refreshed).is_ok());
+        let strict = VerificationConfig::default().require_version(SIGNATURE_VERSION);
+        assert!(verify_signed_message(&refreshed, &strict).is_err());
+    }
+
+    #[test]
+    fn test_legacy_envelopes() {
+        let signer = create_test_signer();
+        let message = TestMessage { content: "Legacy".to_string(), value: 1 };
+        let signed = sign_versioned(&message, &signer, 1234567890, None, LEGACY_SIGNATURE_VERSION).unwrap();
+
+        // Envelopes from older peers have no version field
+        let mut json = serde_json::to_value(&signed).unwrap();
+        json.as_object_mut().unwrap().remove("version");
+        let parsed: SignedMessage<TestMessage> = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.version, LEGACY_SIGNATURE_VERSION);
+        assert!(verify_signed_message_permissive(&parsed).is_ok());
+
+        let strict = VerificationConfig::permissive().require_version(SIGNATURE_VERSION);
+        assert!(verify_signed_message(&parsed, &strict).is_err());
+        let current = message.sign_with_params_blocking(&signer, 1234567890, None).unwrap();
+        assert!(verify_signed_message(&current, &strict).is_ok());
+    }
+
+    #[test]
+    fn test_legacy_signing_for_rollout() {
+        use crate::identity::Identity;
+        use crate::protocol::CancelRequest;
+        use alloy::signers::SignerSync;
+
+        let identity = Identity::generate().with_signature_version(LEGACY_SIGNATURE_VERSION).unwrap();
+        let message = TestMessage { content: "Rollout".to_string(), value: 1 };
+        let signed = message.sign_with_params_blocking(&identity, 1234567890, None).unwrap();
+        assert_eq!(signed.version, LEGACY_SIGNATURE_VERSION);
+        // Baseline peers sign, and verify, the hash of the plain JSON payload
+        let expected = identity.wallet.sign_hash_sync(&alloy::primitives::keccak256(serde_json::to_vec(&message).unwrap())).unwrap();
+        assert_eq!(signed.signature, Bytes::from(expected.as_bytes()));
+        assert!(verify_signed_message_permissive(&signed).is_ok());
+
+        // Messages only upgraded peers understand are still signed as version 2
+        let cancel = CancelRequest { request_id: "req-1".to_string(), executor_address: String::new(), reason: None };
+        assert_eq!(cancel.sign_blocking(&identity).unwrap().version, SIGNATURE_VERSION);
+
+        assert!(Identity::generate().with_signature_version(3).is_err());
+    }
+
+    #[test]
+    fn test_replay_guard_accepts_each_message_once() {
+        let signer = create_test_signer();
+        let guard = ReplayGuard::new(3600, DEFAULT_REPLAY_CAPACITY);
+        let message = TestMessage { content: "Once".to_string(), value: 1 };
+        let now = now_secs().unwrap();
+
+        let signed = message.sign_with_params_blocking(&signer, now, None).unwrap();
+        assert_eq!(guard.verify(&signed).unwrap(), signer.address());
+        assert!(matches!(guard.verify(&signed), Err(Error::Replay(_))));
+
+        // Signed nonces identify a message whatever its payload
+        let first = message.sign_with_params_blocking(&signer, now, Some(7)).unwrap();
+        let reused = TestMessage { value: 2, ..message.clone() }.sign_with_params_blocking(&signer, now, Some(7)).unwrap();
+        assert!(guard.verify(&first).is_ok());
+        assert!(matches!(guard.verify(&reused), Err(Error::Replay(_))));
+
+        // The same payload signed by someone else is a different message
+        let other = message.sign_with_params_blocking(&PrivateKeySigner::random(), now, None).unwrap();
+        assert!(guard.verify(&other).is_ok());
+
+        // Legacy envelopes cannot be replayed by rewriting their timestamp
+        let legacy = sign_versioned(&message, &signer, now, None, LEGACY_SIGNATURE_VERSION).unwrap();
+        assert!(guard.verify(&legacy).is_ok());
+        let rewritten = SignedMessage { timestamp: now - 10, ..legacy };
+        assert!(matches!(guard.verify(&rewritten), Err(Error::Replay(_))));
+    }
+
+    #[test]
+    fn test_replay_guard_requires_version() {
+        let signer = create_test_signer();
+        let message = TestMessage { content: "Migrated".to_string(), value: 1 };
+        let now = now_secs().unwrap();
+        let guard = ReplayGuard::new(3600, DEFAULT_REPLAY_CAPACITY).require_version(SIGNATURE_VERSION).unwrap();
+
+        // Once the migration is over, legacy envelopes are refused outright
+        let legacy = sign_versioned(&message, &signer, now, None, LEGACY_SIGNATURE_VERSION).unwrap();
+        assert!(guard.verify(&legacy).is_err());
+        assert!(guard.verify(&message.sign_with_params_blocking(&signer, now, None).unwrap()).is_ok());
+
+        assert!(ReplayGuard::new(3600, DEFAULT_REPLAY_CAPACITY).require_version(3).is_err());
+    }
+
+    #[test]
+    fn test_replay_guard_memory_is_bounded() {
+        let signer = create_test_signer();
+        let guard = ReplayGuard::new(60, 2);
+        let message = TestMessage { content: "Bounded".to_string(), value: 1 };
+        let signed = |timestamp, nonce| message.sign_with_params_blocking(&signer, timestamp, Some(nonce)).unwrap();
+
+        // Entries are forgotten once too old to verify
+        guard.check(&signed(1000, 1), 1000).unwrap();
+        assert!(guard.check(&signed(1000, 1), 1060).is_err());
+        guard.check(&signed(1061, 2), 1061).unwrap();
+        assert_eq!(guard.len(), 1);
+
+        // Past capacity the entry closest to expiry goes first
+        guard.check(&signed(1070, 3), 1070).unwrap();
+        guard.check(&signed(1065, 4), 1070).unwrap();
+        assert_eq!(guard.len(), 2);
+        assert!(guard.check(&signed(1070, 3), 1070).is_err());
+        assert!(guard.check(&signed(1065, 4), 1070).is_err());
+    }
+
+    #[test]
+    fn test_replay_guard_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("replay.json");
+        let signer = create_test_signer();
+        let signed = TestMessage { content: "Persisted".to_string(), value: 1 }.sign_blocking(&signer).unwrap();
+
+        let guard = ReplayGuard::new(3600, DEFAULT_REPLAY_CAPACITY).with_persistence(&path).unwrap();
+        guard.verify(&signed).unwrap();
+        guard.save().unwrap();
+
+        let restarted = ReplayGuard::new(3600, DEFAULT_REPLAY_CAPACITY).with_persistence(&path).unwrap();
+        assert!(matches!(restarted.verify(&signed), Err(Error::Replay(_))));
+    }
+
     #[test]
     fn test_deterministic_signing() {
         let signer = create_test_signer();
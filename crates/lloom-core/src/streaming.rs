@@ -0,0 +1,197 @@
+//! Framing for streamed LLM responses.
+//!
+//! Streaming uses its own libp2p protocol, [`LLM_STREAM_PROTOCOL`], next to the
+//! request-response protocol. The client opens a stream and writes a single
+//! [`RequestMessage`] frame carrying the (optionally signed) `LlmRequest`. The
+//! executor answers with any number of [`LlmStreamFrame::Chunk`] frames as the
+//! backend produces content, followed by exactly one [`LlmStreamFrame::Final`]
+//! frame holding the complete response and its usage, signed like a regular
+//! response.
+//!
+//! A client cancels by closing its side of the stream. The executor then stops
+//! the completion and still sends the final frame, which carries what it billed
+//! for the work done so far.
+//!
+//! Every frame is a big-endian `u32` length followed by that many bytes of JSON.
+
+use crate::error::{Error, Result};
+use crate::protocol::{RequestMessage, ResponseMessage, constants::LLM_STREAM_PROTOCOL};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Future};
+use libp2p::{PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+/// Largest frame accepted in either direction, enough for a request with inline images
+pub const MAX_FRAME_SIZE: usize = crate::protocol::constants::MAX_REQUEST_BYTES;
+
+/// Frames sent by the executor on an LLM stream
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum LlmStreamFrame {
+    /// Incremental content, in the order produced by the backend
+    Chunk(String),
+    /// Complete response with usage; always the last frame
+    Final(Box<ResponseMessage>),
+}
+
+/// The stream protocol as a libp2p [`StreamProtocol`]
+pub fn stream_protocol() -> StreamProtocol {
+    StreamProtocol::new(LLM_STREAM_PROTOCOL)
+}
+
+/// Write a single length-prefixed JSON frame and flush it
+pub async fn write_frame<W, T>(writer: &mut W, frame: &T) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let bytes = serde_json::to_vec(frame)?;
+    if bytes.len() > MAX_FRAME_SIZE {
+        return Err(Error::Protocol(format!(
+            "Frame of {} bytes exceeds the {} byte limit",
+            bytes.len(),
+            MAX_FRAME_SIZE
+        )));
+    }
+    writer.write_all(&(bytes.len() as u32).to_be_bytes()).await?;
+    writer.write_all(&bytes).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Read a single length-prefixed JSON frame.
+///
+/// Returns `None` when the stream ends cleanly between frames.
+pub async fn read_frame<R, T>(reader: &mut R) -> Result<Option<T>>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let mut header = [0u8; 4];
+    let mut filled = 0;
+    while filled < header.len() {
+        let read = reader.read(&mut header[filled..]).await?;
+        if read == 0 {
+            if filled == 0 {
+                return Ok(None);
+            }
+            return Err(Error::Protocol("Stream ended inside a frame header".to_string()));
+        }
+        filled += read;
+    }
+
+    let len = u32::from_be_bytes(header) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(Error::Protocol(format!(
+            "Frame of {} bytes exceeds the {} byte limit",
+            len, MAX_FRAME_SIZE
+        )));
+    }
+    let mut bytes = vec![0u8; len];
+    reader.read_exact(&mut bytes).await?;
+    Ok(Some(serde_json::from_slice(&bytes)?))
+}
+
+/// Read executor frames until the final response, passing each chunk to `on_chunk`
+pub async fn read_llm_stream<R>(reader: &mut R, mut on_chunk: impl FnMut(&str)) -> Result<ResponseMessage>
+where
+    R: AsyncRead + Unpin,
+{
+    loop {
+        match read_frame::<_, LlmStreamFrame>(reader).await? {
+            Some(LlmStreamFrame::Chunk(content)) => on_chunk(&content),
+            Some(LlmStreamFrame::Final(response)) => return Ok(*response),
+            None => return Err(Error::Protocol("Stream closed before the final response".to_string())),
+        }
+    }
+}
+
+/// Client side of the stream protocol: send `request` to `peer` and stream the reply.
+///
+/// Once `cancel` resolves the client's side of the stream is closed and the
+/// executor's final response for the stopped completion is still read.
+pub async fn request_llm_stream(
+    control: &mut libp2p_stream::Control,
+    peer: PeerId,
+    request: &RequestMessage,
+    on_chunk: impl FnMut(&str),
+    cancel: impl Future<Output = ()>,
+) -> Result<ResponseMessage> {
+    let mut stream = control
+        .open_stream(peer, stream_protocol())
+        .await
+        .map_err(|e| Error::Network(e.to_string()))?;
+    write_frame(&mut stream, request).await?;
+    let (mut reader, mut writer) = stream.split();
+    let response = read_llm_stream(&mut reader, on_chunk);
+    futures::pin_mut!(response, cancel);
+    let response = match futures::future::select(response, cancel).await {
+        futures::future::Either::Left((response, _)) => response?,
+        futures::future::Either::Right(((), response)) => {
+            let _ = writer.close().await;
+            response.await?
+        }
+    };
+    let _ = writer.close().await;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::LlmResponse;
+    use futures::io::Cursor;
+
+    fn final_response() -> ResponseMessage {
+        ResponseMessage::LlmResponse(LlmResponse {
+            content: "Hello world".to_string(),
+            inbound_tokens: 3,
+            outbound_tokens: 2,
+            total_cost: "5".to_string(),
+            model_used: "gpt-4".to_string(),
+            error: None,
+            request_id: None,
+            error_code: None,
+            tool_calls: None,
+            choices: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_frames_round_trip() {
+        let mut buffer = Cursor::new(Vec::new());
+        write_frame(&mut buffer, &LlmStreamFrame::Chunk("Hello".to_string())).await.unwrap();
+        write_frame(&mut buffer, &LlmStreamFrame::Chunk(" world".to_string())).await.unwrap();
+        write_frame(&mut buffer, &LlmStreamFrame::Final(Box::new(final_response()))).await.unwrap();
+
+        let mut reader = Cursor::new(buffer.into_inner());
+        let mut chunks = Vec::new();
+        let response = read_llm_stream(&mut reader, |chunk| chunks.push(chunk.to_string())).await.unwrap();
+
+        assert_eq!(chunks, vec!["Hello", " world"]);
+        match response {
+            ResponseMessage::LlmResponse(response) => assert_eq!(response.content, "Hello world"),
+            other => panic!("Unexpected final frame: {:?}", other),
+        }
+        assert!(read_frame::<_, LlmStreamFrame>(&mut reader).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stream_without_final_frame_fails() {
+        let mut buffer = Cursor::new(Vec::new());
+        write_frame(&mut buffer, &LlmStreamFrame::Chunk("partial".to_string())).await.unwrap();
+
+        let mut reader = Cursor::new(buffer.into_inner());
+        let result = read_llm_stream(&mut reader, |_| {}).await;
+        assert!(matches!(result, Err(Error::Protocol(_))));
+
+        // Cut off inside the length prefix
+        let mut truncated = Cursor::new(vec![0u8, 0]);
+        assert!(read_frame::<_, LlmStreamFrame>(&mut truncated).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frame_rejected() {
+        let mut reader = Cursor::new(((MAX_FRAME_SIZE + 1) as u32).to_be_bytes().to_vec());
+        let result = read_frame::<_, LlmStreamFrame>(&mut reader).await;
+        assert!(matches!(result, Err(Error::Protocol(_))));
+    }
+}
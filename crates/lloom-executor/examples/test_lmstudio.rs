@@ -8,7 +8,6 @@
 
 // Import from the executor library
 use lloom_executor::{LlmBackendConfig, LlmClient};
-use tokio;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -24,6 +23,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         api_key: None, // No API key needed for local LMStudio
         supported_models: vec![], // Will be auto-discovered
         rate_limit: Some(100),
+        vision_models: vec![],
+        tool_models: vec![],
+        structured_output_models: vec![],
+        streaming: true,
     };
     
     // Create client
@@ -2,7 +2,6 @@ use lloom_core::protocol::{ModelAnnouncement, ModelDescriptor, ModelCapabilities
 use std::collections::HashMap;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::time::sleep;
-use serde_json;
 
 /// Integration test example demonstrating the model announcement system
 ///
@@ -122,6 +121,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             output_token_price: "1500000000000000".to_string(),
             minimum_fee: None,
         }),
+        org: None,
     };
     
     let _new_announcement = ModelAnnouncement {
@@ -174,6 +174,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         },
         is_available: false,
         pricing: None,
+        org: None,
     };
     
     println!("✅ Would validate and reject invalid model:");
@@ -251,6 +252,7 @@ fn create_sample_models() -> HashMap<String, ModelDescriptor> {
                 output_token_price: "2000000000000000".to_string(),
                 minimum_fee: None,
             }),
+            org: None,
         },
     );
     
@@ -285,6 +287,7 @@ fn create_sample_models() -> HashMap<String, ModelDescriptor> {
                 output_token_price: "120000000000000000".to_string(), // Higher output price
                 minimum_fee: Some("1000000000000000".to_string()), // 0.001 ETH minimum
             }),
+            org: None,
         },
     );
     
@@ -323,6 +326,7 @@ fn create_sample_models() -> HashMap<String, ModelDescriptor> {
                 output_token_price: "1000000000000000".to_string(),
                 minimum_fee: None,
             }),
+            org: None,
         },
     );
     
@@ -4,7 +4,7 @@
 //! and manage models from all configured LLM backends.
 
 use lloom_executor::{
-    ExecutorConfig, LlmBackendConfig, BlockchainConfig, NetworkConfig,
+    ExecutorConfig, LlmBackendConfig, BlockchainConfig, NetworkConfig, BillingConfig, OrgConfig, ReplayConfig, StructuredOutputConfig,
     LlmClient, ModelInfo,
 };
 use std::{collections::HashMap, sync::Arc};
@@ -46,7 +46,7 @@ impl TestExecutorState {
         let mut all_models = Vec::new();
         
         // Just return configured models from each backend
-        for (backend_name, _llm_client) in &self.llm_clients {
+        for backend_name in self.llm_clients.keys() {
             // Create demo models for each backend
             match backend_name.as_str() {
                 "mock-backend" => {
@@ -115,6 +115,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     "mock-gpt-4".to_string(),
                 ],
                 rate_limit: Some(100),
+                vision_models: vec![],
+                tool_models: vec![],
+                structured_output_models: vec![],
+                streaming: true,
             },
             // OpenAI-compatible backend
             LlmBackendConfig {
@@ -127,6 +131,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     "gpt-4-turbo".to_string(),
                 ],
                 rate_limit: Some(60),
+                vision_models: vec![],
+                tool_models: vec![],
+                structured_output_models: vec![],
+                streaming: true,
             },
             // LMStudio backend (will attempt discovery)
             LlmBackendConfig {
@@ -138,6 +146,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     "mistral-7b-instruct".to_string(),
                 ],
                 rate_limit: None,
+                vision_models: vec![],
+                tool_models: vec![],
+                structured_output_models: vec![],
+                streaming: true,
             },
         ],
         blockchain: BlockchainConfig {
@@ -149,10 +161,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         },
         network: NetworkConfig {
             port: 9001,
+            transport: Default::default(),
             external_address: None,
             bootstrap_nodes: vec![],
             announce_interval_secs: 300,
         },
+        billing: BillingConfig::default(),
+        structured_output: StructuredOutputConfig::default(),
+        replay: ReplayConfig::default(),
+        org: OrgConfig::default(),
     };
 
     // Initialize test executor state
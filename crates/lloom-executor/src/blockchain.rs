@@ -356,6 +356,8 @@ mod tests {
                 model: "gpt-3.5-turbo".to_string(),
                 token_count: 100,
                 timestamp: 1234567890,
+                cancelled: false,
+                request_id: None,
             }
         ];
         
@@ -372,6 +374,8 @@ mod tests {
             model: "gpt-4".to_string(),
             token_count: 250,
             timestamp: 1234567890,
+            cancelled: false,
+            request_id: None,
         };
 
         assert_eq!(usage_record.client_address, client_address);
@@ -1,7 +1,10 @@
 //! Configuration management for the Executor node.
 
+use alloy::primitives::{Address, U256};
+use lloom_core::{network::Transport, signing::{DEFAULT_REPLAY_CAPACITY, LEGACY_SIGNATURE_VERSION}};
 use serde::{Deserialize, Serialize};
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
 
 /// Configuration for the Executor node
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,6 +17,22 @@ pub struct ExecutorConfig {
     
     /// P2P network configuration
     pub network: NetworkConfig,
+    
+    /// Billing policy
+    #[serde(default)]
+    pub billing: BillingConfig,
+    
+    /// Handling of requests asking for structured output
+    #[serde(default)]
+    pub structured_output: StructuredOutputConfig,
+    
+    /// Replay protection for signed requests
+    #[serde(default)]
+    pub replay: ReplayConfig,
+    
+    /// Organization whose members this executor serves or discounts
+    #[serde(default)]
+    pub org: OrgConfig,
 }
 
 /// Configuration for an LLM backend
@@ -33,6 +52,27 @@ pub struct LlmBackendConfig {
     
     /// Rate limit (requests per minute)
     pub rate_limit: Option<u32>,
+    
+    /// Supported models that accept image inputs
+    #[serde(default)]
+    pub vision_models: Vec<String>,
+    
+    /// Supported models that can call tools
+    #[serde(default)]
+    pub tool_models: Vec<String>,
+    
+    /// Supported models that honour a requested response format
+    #[serde(default)]
+    pub structured_output_models: Vec<String>,
+    
+    /// Whether the backend serves chat completions as server-sent events. When
+    /// disabled, streamed requests get the whole completion as a single chunk.
+    #[serde(default = "default_streaming")]
+    pub streaming: bool,
+}
+
+fn default_streaming() -> bool {
+    true
 }
 
 /// Blockchain configuration
@@ -60,6 +100,10 @@ pub struct NetworkConfig {
     /// Port to listen on
     pub port: u16,
     
+    /// Transports to listen on; with both, TCP and UDP share the port number
+    #[serde(default)]
+    pub transport: Transport,
+    
     /// External address (if behind NAT)
     pub external_address: Option<String>,
     
@@ -70,6 +114,188 @@ pub struct NetworkConfig {
     pub announce_interval_secs: u64,
 }
 
+/// Billing policy configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BillingConfig {
+    /// Bill the tokens produced before a request was cancelled or ran past its
+    /// deadline. When disabled, cancelled requests are free.
+    #[serde(default = "default_bill_cancelled")]
+    pub bill_cancelled: bool,
+    
+    /// How long a price quote can be accepted, in seconds
+    #[serde(default = "default_quote_ttl_secs")]
+    pub quote_ttl_secs: u64,
+    
+    /// Quotes kept at once; the soonest to expire are dropped beyond this
+    #[serde(default = "default_max_quotes")]
+    pub max_quotes: usize,
+    
+    /// Prices of models without an entry in `model_prices`
+    #[serde(default, flatten)]
+    pub prices: TokenPrices,
+    
+    /// Prices per model
+    #[serde(default)]
+    pub model_prices: HashMap<String, TokenPrices>,
+}
+
+/// Advertised per-token prices, in wei
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenPrices {
+    /// Price per input token
+    #[serde(default = "default_input_token_price")]
+    pub input_token_price: String,
+    
+    /// Price per output token
+    #[serde(default = "default_output_token_price")]
+    pub output_token_price: String,
+}
+
+fn default_bill_cancelled() -> bool {
+    true
+}
+
+fn default_quote_ttl_secs() -> u64 {
+    60
+}
+
+fn default_max_quotes() -> usize {
+    10_000
+}
+
+/// 0.0005 ETH
+fn default_input_token_price() -> String {
+    "500000000000000".to_string()
+}
+
+/// 0.001 ETH
+fn default_output_token_price() -> String {
+    "1000000000000000".to_string()
+}
+
+impl Default for BillingConfig {
+    fn default() -> Self {
+        Self {
+            bill_cancelled: default_bill_cancelled(),
+            quote_ttl_secs: default_quote_ttl_secs(),
+            max_quotes: default_max_quotes(),
+            prices: TokenPrices::default(),
+            model_prices: HashMap::new(),
+        }
+    }
+}
+
+impl Default for TokenPrices {
+    fn default() -> Self {
+        Self {
+            input_token_price: default_input_token_price(),
+            output_token_price: default_output_token_price(),
+        }
+    }
+}
+
+impl BillingConfig {
+    /// Advertised prices of `model`
+    pub fn prices(&self, model: &str) -> &TokenPrices {
+        self.model_prices.get(model).unwrap_or(&self.prices)
+    }
+    
+    /// Check that every price is a wei amount
+    fn validate(&self) -> Result<()> {
+        let named = self.model_prices.iter().map(|(model, prices)| (model.as_str(), prices));
+        for (model, prices) in std::iter::once(("default", &self.prices)).chain(named) {
+            for price in [&prices.input_token_price, &prices.output_token_price] {
+                price.parse::<U256>()
+                    .map_err(|_| anyhow!("Invalid {} token price {}: expected an amount in wei", model, price))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replay protection configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayConfig {
+    /// File keeping the signed requests already accepted across restarts.
+    /// Without one, a request can be replayed once after a restart.
+    #[serde(default)]
+    pub cache_file: Option<String>,
+    
+    /// Accepted requests remembered at once
+    #[serde(default = "default_replay_capacity")]
+    pub capacity: usize,
+    
+    /// Oldest signature envelope version accepted. Version 1 envelopes do not
+    /// sign their timestamp, so they can be replayed once forgotten; set this to
+    /// 2 once every client signs version 2 to end the migration.
+    #[serde(default = "default_min_signature_version")]
+    pub min_signature_version: u8,
+}
+
+fn default_replay_capacity() -> usize {
+    DEFAULT_REPLAY_CAPACITY
+}
+
+fn default_min_signature_version() -> u8 {
+    LEGACY_SIGNATURE_VERSION
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            cache_file: None,
+            capacity: default_replay_capacity(),
+            min_signature_version: default_min_signature_version(),
+        }
+    }
+}
+
+/// Organization configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OrgConfig {
+    /// Address of the org key issuing membership credentials. Without one,
+    /// credentials are ignored.
+    #[serde(default)]
+    pub address: Option<Address>,
+    
+    /// Serve only members of the org, and announce models as private to it
+    #[serde(default)]
+    pub members_only: bool,
+    
+    /// Percentage taken off what members are billed
+    #[serde(default)]
+    pub member_discount_percent: u8,
+}
+
+impl OrgConfig {
+    /// Org the executor's models are private to, if it only serves members
+    pub fn private_org(&self) -> Option<Address> {
+        self.address.filter(|_| self.members_only)
+    }
+}
+
+/// Structured output policy configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StructuredOutputConfig {
+    /// Backend calls made per request-response request before output that does
+    /// not match the requested format is reported as an error. Only the accepted
+    /// attempt is billed. Streamed output is validated but never retried.
+    #[serde(default = "default_output_attempts")]
+    pub max_attempts: u32,
+}
+
+fn default_output_attempts() -> u32 {
+    2
+}
+
+impl Default for StructuredOutputConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_output_attempts(),
+        }
+    }
+}
+
 impl Default for ExecutorConfig {
     fn default() -> Self {
         Self {
@@ -83,6 +309,10 @@ impl Default for ExecutorConfig {
                     "gpt-4-turbo".to_string(),
                 ],
                 rate_limit: Some(60),
+                vision_models: vec![],
+                tool_models: vec!["gpt-4".to_string(), "gpt-4-turbo".to_string()],
+                structured_output_models: vec!["gpt-4-turbo".to_string()],
+                streaming: true,
             }],
             blockchain: BlockchainConfig {
                 rpc_url: "https://rpc.sepolia.org".to_string(),
@@ -93,14 +323,60 @@ impl Default for ExecutorConfig {
             },
             network: NetworkConfig {
                 port: 9001,
+                transport: Transport::default(),
                 external_address: None,
                 bootstrap_nodes: vec![],
                 announce_interval_secs: 300, // 5 minutes
             },
+            billing: BillingConfig::default(),
+            structured_output: StructuredOutputConfig::default(),
+            replay: ReplayConfig::default(),
+            org: OrgConfig::default(),
         }
     }
 }
 
+impl ExecutorConfig {
+    /// Load configuration from a TOML file
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let config: Self = toml::from_str(&content)?;
+        config.billing.validate()?;
+        Ok(config)
+    }
+    
+    /// Find a backend that supports the given model
+    pub fn find_backend_for_model(&self, model: &str) -> Option<&LlmBackendConfig> {
+        self.llm_backends.iter()
+            .find(|backend| backend.supported_models.contains(&model.to_string()))
+    }
+    
+    /// Whether the given model accepts image inputs
+    pub fn supports_vision(&self, model: &str) -> bool {
+        self.llm_backends.iter()
+            .any(|backend| backend.vision_models.iter().any(|m| m == model))
+    }
+    
+    /// Whether the given model can call tools
+    pub fn supports_tools(&self, model: &str) -> bool {
+        self.llm_backends.iter()
+            .any(|backend| backend.tool_models.iter().any(|m| m == model))
+    }
+    
+    /// Whether the given model honours a requested response format
+    pub fn supports_structured_output(&self, model: &str) -> bool {
+        self.llm_backends.iter()
+            .any(|backend| backend.structured_output_models.iter().any(|m| m == model))
+    }
+    
+    /// Get all supported models across all backends
+    pub fn get_all_supported_models(&self) -> Vec<String> {
+        self.llm_backends.iter()
+            .flat_map(|backend| backend.supported_models.clone())
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -134,6 +410,10 @@ mod tests {
             api_key: Some("test-key".to_string()),
             supported_models: vec!["model1".to_string(), "model2".to_string()],
             rate_limit: Some(100),
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: true,
         };
 
         assert_eq!(backend.name, "test-backend");
@@ -164,6 +444,7 @@ mod tests {
     fn test_network_config() {
         let network_config = NetworkConfig {
             port: 8080,
+            transport: Transport::Dual,
             external_address: Some("/ip4/1.2.3.4/tcp/8080".to_string()),
             bootstrap_nodes: vec!["/ip4/5.6.7.8/tcp/9000".to_string()],
             announce_interval_secs: 120,
@@ -173,6 +454,7 @@ mod tests {
         assert_eq!(network_config.external_address, Some("/ip4/1.2.3.4/tcp/8080".to_string()));
         assert_eq!(network_config.bootstrap_nodes.len(), 1);
         assert_eq!(network_config.announce_interval_secs, 120);
+        assert_eq!(network_config.transport.listen_addrs(8080).len(), 2);
     }
 
     #[test]
@@ -214,6 +496,10 @@ mod tests {
                     api_key: None,
                     supported_models: vec!["gpt-3.5-turbo".to_string()],
                     rate_limit: Some(60),
+                    vision_models: vec![],
+                    tool_models: vec![],
+                    structured_output_models: vec![],
+                    streaming: true,
                 },
                 LlmBackendConfig {
                     name: "anthropic".to_string(),
@@ -221,6 +507,10 @@ mod tests {
                     api_key: None,
                     supported_models: vec!["claude-3".to_string()],
                     rate_limit: Some(50),
+                    vision_models: vec![],
+                    tool_models: vec![],
+                    structured_output_models: vec![],
+                    streaming: true,
                 },
             ],
             blockchain: BlockchainConfig {
@@ -232,10 +522,15 @@ mod tests {
             },
             network: NetworkConfig {
                 port: 9001,
+                transport: Transport::default(),
                 external_address: None,
                 bootstrap_nodes: vec![],
                 announce_interval_secs: 300,
             },
+            billing: BillingConfig::default(),
+            structured_output: StructuredOutputConfig::default(),
+            replay: ReplayConfig::default(),
+            org: OrgConfig::default(),
         };
 
         // Should find OpenAI backend for GPT models
@@ -277,12 +572,22 @@ endpoint = "https://api.openai.com/v1"
 api_key = "test-key"
 supported_models = ["gpt-3.5-turbo", "gpt-4"]
 rate_limit = 60
+vision_models = ["gpt-4"]
+tool_models = ["gpt-4"]
+structured_output_models = ["gpt-3.5-turbo", "gpt-4"]
 
 [[llm_backends]]
 name = "anthropic"
 endpoint = "https://api.anthropic.com/v1"
 supported_models = ["claude-3"]
 rate_limit = 50
+
+[billing]
+output_token_price = "2000000000000000"
+
+[billing.model_prices.gpt-4]
+input_token_price = "3000000000000000"
+output_token_price = "6000000000000000"
 "#;
 
         let mut temp_file = NamedTempFile::new()?;
@@ -293,6 +598,8 @@ rate_limit = 50
         assert_eq!(config.network.port, 8080);
         assert_eq!(config.network.external_address, Some("/ip4/1.2.3.4/tcp/8080".to_string()));
         assert_eq!(config.network.bootstrap_nodes.len(), 1);
+        // Configs without a transport keep listening on TCP alone
+        assert_eq!(config.network.transport, Transport::Tcp);
         
         assert_eq!(config.blockchain.rpc_url, "https://mainnet.infura.io/v3/key");
         assert_eq!(config.blockchain.contract_address, Some("0x123456".to_string()));
@@ -303,7 +610,40 @@ rate_limit = 50
         assert_eq!(config.llm_backends[0].api_key, Some("test-key".to_string()));
         assert_eq!(config.llm_backends[1].name, "anthropic");
         assert!(config.llm_backends[1].api_key.is_none());
+        assert!(config.supports_vision("gpt-4"));
+        assert!(!config.supports_vision("gpt-3.5-turbo"));
+        assert!(!config.supports_vision("claude-3"));
+        assert!(config.supports_tools("gpt-4"));
+        assert!(!config.supports_tools("gpt-3.5-turbo"));
+        assert!(config.supports_structured_output("gpt-3.5-turbo"));
+        assert!(!config.supports_structured_output("claude-3"));
+        
+        // Models without their own prices get the configured default ones
+        assert_eq!(config.billing.prices("gpt-4").output_token_price, "6000000000000000");
+        assert_eq!(config.billing.prices("gpt-3.5-turbo").input_token_price, "500000000000000");
+        assert_eq!(config.billing.prices("gpt-3.5-turbo").output_token_price, "2000000000000000");
+        
+        // Omitted billing, structured output and replay sections and fields fall back to the defaults
+        assert!(config.billing.bill_cancelled);
+        assert_eq!(config.billing.quote_ttl_secs, 60);
+        assert_eq!(config.billing.max_quotes, 10_000);
+        assert_eq!(config.structured_output.max_attempts, 2);
+        assert!(config.replay.cache_file.is_none());
+        assert_eq!(config.replay.capacity, DEFAULT_REPLAY_CAPACITY);
+        assert_eq!(config.replay.min_signature_version, LEGACY_SIGNATURE_VERSION);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_prices_are_refused() -> Result<()> {
+        let mut temp_file = NamedTempFile::new()?;
+        let mut toml_content = toml::to_string(&ExecutorConfig::default())?;
+        toml_content.push_str("\n[billing.model_prices.gpt-4]\ninput_token_price = \"0.5 ETH\"\n");
+        writeln!(temp_file, "{}", toml_content)?;
         
+        let error = ExecutorConfig::from_file(temp_file.path().to_str().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("gpt-4"));
         Ok(())
     }
 
@@ -340,26 +680,4 @@ rate_limit = 50
         assert!(debug_str.contains("blockchain"));
         assert!(debug_str.contains("network"));
     }
-}
-
-impl ExecutorConfig {
-    /// Load configuration from a TOML file
-    pub fn from_file(path: &str) -> Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        let config: Self = toml::from_str(&content)?;
-        Ok(config)
-    }
-    
-    /// Find a backend that supports the given model
-    pub fn find_backend_for_model(&self, model: &str) -> Option<&LlmBackendConfig> {
-        self.llm_backends.iter()
-            .find(|backend| backend.supported_models.contains(&model.to_string()))
-    }
-    
-    /// Get all supported models across all backends
-    pub fn get_all_supported_models(&self) -> Vec<String> {
-        self.llm_backends.iter()
-            .flat_map(|backend| backend.supported_models.clone())
-            .collect()
-    }
 }
\ No newline at end of file
@@ -0,0 +1,185 @@
+//! Serving of `EmbeddingRequest`s.
+//!
+//! Like LLM completions, backend calls run on their own task so the swarm keeps
+//! being polled; the outcome is sent back to the main loop, which answers the
+//! client. Embeddings produce no output, so they are billed per input token at
+//! the price the client signed.
+
+use crate::{inflight, llm_client::{self, LlmClient}};
+use alloy::primitives::{Address, U256};
+use anyhow::anyhow;
+use lloom_core::protocol::{EmbeddingRequest, EmbeddingResponse, LlmErrorCode};
+use tokio::sync::mpsc;
+use tracing::{Instrument, Span};
+
+/// How an embedding backend call ended
+#[derive(Debug)]
+pub enum EmbeddingOutcome {
+    /// The backend call finished with the vectors and input token count, or an error
+    Finished(anyhow::Result<(Vec<Vec<f32>>, u32)>),
+    /// The request's deadline passed before the backend finished
+    DeadlineExceeded,
+}
+
+/// Finished backend call of an embedding request
+pub struct EmbeddingCompletion<R> {
+    /// Where to send the response
+    pub reply: R,
+    /// The request that was served
+    pub request: EmbeddingRequest,
+    /// Verified signer of the request, if signing is enabled
+    pub signer: Option<Address>,
+    /// Address billed for the request, see [`crate::sessions::SessionBudgets::admit`]
+    pub account: Option<Address>,
+    /// How the backend call ended
+    pub outcome: EmbeddingOutcome,
+}
+
+/// Run an embedding request's backend call on its own task.
+///
+/// The call is abandoned once the request's deadline passes (0 means none). The
+/// task runs in the caller's tracing span.
+pub fn spawn_embedding<R: Send + 'static>(
+    llm_client: LlmClient,
+    request: EmbeddingRequest,
+    signer: Option<Address>,
+    account: Option<Address>,
+    reply: R,
+    completions: mpsc::UnboundedSender<EmbeddingCompletion<R>>,
+) {
+    tokio::spawn(async move {
+        let call = llm_client.embeddings(&request.model, request.input.clone());
+        let outcome = if request.deadline > 0 {
+            tokio::time::timeout(inflight::time_until(request.deadline), call)
+                .await
+                .map_or(EmbeddingOutcome::DeadlineExceeded, EmbeddingOutcome::Finished)
+        } else {
+            EmbeddingOutcome::Finished(call.await)
+        };
+        let _ = completions.send(EmbeddingCompletion { reply, request, signer, account, outcome });
+    }.instrument(Span::current()));
+}
+
+/// Cost in wei of `input_tokens` at `price_per_token`
+pub fn embedding_cost(input_tokens: u64, price_per_token: &str) -> anyhow::Result<U256> {
+    let price: U256 = price_per_token
+        .parse()
+        .map_err(|e| anyhow!("Invalid price per token {}: {}", price_per_token, e))?;
+    Ok(U256::from(input_tokens) * price)
+}
+
+/// Response to a finished embedding backend call
+pub fn embedding_response(request: &EmbeddingRequest, outcome: EmbeddingOutcome) -> EmbeddingResponse {
+    let (embeddings, input_tokens) = match outcome {
+        EmbeddingOutcome::Finished(Ok(result)) => result,
+        EmbeddingOutcome::Finished(Err(e)) => {
+            return embedding_error(request, llm_client::error_code(&e), e.to_string());
+        }
+        EmbeddingOutcome::DeadlineExceeded => {
+            return embedding_error(request, LlmErrorCode::DeadlineExpired, "Request deadline passed".to_string());
+        }
+    };
+    let total_cost = match embedding_cost(input_tokens as u64, &request.price_per_token) {
+        Ok(cost) => cost,
+        Err(e) => return embedding_error(request, LlmErrorCode::InvalidRequest, e.to_string()),
+    };
+    EmbeddingResponse {
+        embeddings,
+        input_tokens: input_tokens as u64,
+        total_cost: total_cost.to_string(),
+        model_used: request.model.clone(),
+        error: None,
+        error_code: None,
+        request_id: request.request_id.clone(),
+    }
+}
+
+/// Unbilled response for an embedding request that failed
+pub fn embedding_error(request: &EmbeddingRequest, code: LlmErrorCode, error: String) -> EmbeddingResponse {
+    EmbeddingResponse {
+        embeddings: Vec::new(),
+        input_tokens: 0,
+        total_cost: "0".to_string(),
+        model_used: request.model.clone(),
+        error: Some(error),
+        error_code: Some(code),
+        request_id: request.request_id.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LlmBackendConfig;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn test_request() -> EmbeddingRequest {
+        EmbeddingRequest {
+            model: "text-embedding-3-small".to_string(),
+            input: vec!["Hello".to_string()],
+            executor_address: String::new(),
+            price_per_token: "1000".to_string(),
+            nonce: 1,
+            deadline: 0,
+            request_id: Some("req-1".to_string()),
+            session: None,
+            credential: None,
+        }
+    }
+
+    #[test]
+    fn test_embedding_billed_per_input_token() {
+        let request = test_request();
+        let response = embedding_response(&request, EmbeddingOutcome::Finished(Ok((vec![vec![0.5, 0.5]], 3))));
+        assert_eq!(response.input_tokens, 3);
+        assert_eq!(response.total_cost, "3000");
+        assert_eq!(response.request_id.as_deref(), Some("req-1"));
+        assert!(response.error.is_none());
+
+        let response = embedding_response(&request, EmbeddingOutcome::Finished(Err(anyhow!("LLM API error (500): boom"))));
+        assert_eq!(response.total_cost, "0");
+        assert_eq!(response.error_code, Some(LlmErrorCode::BackendError));
+        let response = embedding_response(&request, EmbeddingOutcome::DeadlineExceeded);
+        assert_eq!(response.error_code, Some(LlmErrorCode::DeadlineExpired));
+
+        let mut request = test_request();
+        request.price_per_token = "cheap".to_string();
+        assert!(embedding_cost(1, &request.price_per_token).is_err());
+        let response = embedding_response(&request, EmbeddingOutcome::Finished(Ok((vec![vec![0.5, 0.5]], 3))));
+        assert_eq!(response.error_code, Some(LlmErrorCode::InvalidRequest));
+    }
+
+    #[tokio::test]
+    async fn test_spawn_embedding_reports_completion() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/embeddings"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": [{ "embedding": [0.25, 0.75], "index": 0 }],
+                "usage": { "prompt_tokens": 1, "total_tokens": 1 }
+            })))
+            .mount(&mock_server)
+            .await;
+        let llm_client = LlmClient::new(LlmBackendConfig {
+            name: "test-backend".to_string(),
+            endpoint: mock_server.uri(),
+            api_key: Some("test-key".to_string()),
+            supported_models: vec!["text-embedding-3-small".to_string()],
+            rate_limit: None,
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: true,
+        }).unwrap();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        spawn_embedding(llm_client, test_request(), None, None, 7u8, tx);
+        let completion = rx.recv().await.unwrap();
+        assert_eq!(completion.reply, 7);
+        match completion.outcome {
+            EmbeddingOutcome::Finished(Ok(result)) => assert_eq!(result, (vec![vec![0.25, 0.75]], 1)),
+            other => panic!("Unexpected outcome: {:?}", other),
+        }
+    }
+}
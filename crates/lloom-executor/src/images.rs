@@ -0,0 +1,203 @@
+//! Images attached to chat messages.
+//!
+//! Inline images are decoded and checked against their hash before a request
+//! reaches a backend, and kept in a small cache so clients can send them again
+//! by reference. References are filled in from the cache; a reference the
+//! executor has not seen (or has evicted) is rejected and must be resent inline.
+
+use crate::config::ExecutorConfig;
+use lloom_core::protocol::{ChatMessage, LlmErrorCode, LlmRequest, constants::MAX_IMAGES_PER_REQUEST};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+/// Total size of the base64 image data kept for references (64 MiB)
+pub const IMAGE_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Recently received images, keyed by hash and evicted oldest first
+#[derive(Clone)]
+pub struct ImageCache {
+    inner: Arc<Mutex<CacheInner>>,
+    capacity: usize,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    images: HashMap<String, String>,
+    order: VecDeque<String>,
+    size: usize,
+}
+
+impl ImageCache {
+    /// Create a cache holding up to `capacity` bytes of base64 image data
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(CacheInner::default())),
+            capacity,
+        }
+    }
+
+    /// Validate the images of `messages` and fill in references from the cache.
+    ///
+    /// Returns the number of images in the messages.
+    pub fn resolve(&self, messages: &mut [ChatMessage]) -> Result<usize, String> {
+        let count: usize = messages.iter().filter_map(|m| m.images.as_ref()).map(Vec::len).sum();
+        if count > MAX_IMAGES_PER_REQUEST {
+            return Err(format!("{} images exceed the limit of {} per request", count, MAX_IMAGES_PER_REQUEST));
+        }
+        for image in messages.iter_mut().filter_map(|m| m.images.as_mut()).flatten() {
+            let key = image.hash.to_lowercase();
+            match &image.data {
+                Some(data) => {
+                    image.decode()?;
+                    self.insert(&key, data);
+                }
+                None => {
+                    let data = self.get(&key)
+                        .ok_or_else(|| format!("Unknown image {}, send it inline", image.hash))?;
+                    image.data = Some(data);
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.inner.lock().unwrap().images.len()
+    }
+
+    fn get(&self, hash: &str) -> Option<String> {
+        self.inner.lock().unwrap().images.get(hash).cloned()
+    }
+
+    fn insert(&self, hash: &str, data: &str) {
+        if data.len() > self.capacity {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if inner.images.contains_key(hash) {
+            return;
+        }
+        while inner.size + data.len() > self.capacity {
+            let Some(oldest) = inner.order.pop_front() else { break };
+            if let Some(evicted) = inner.images.remove(&oldest) {
+                inner.size -= evicted.len();
+            }
+        }
+        inner.size += data.len();
+        inner.order.push_back(hash.to_string());
+        inner.images.insert(hash.to_string(), data.to_string());
+    }
+}
+
+/// Prepare the images of an LLM request for its backend.
+///
+/// Images are only accepted for models configured as vision models.
+pub fn resolve_request_images(
+    config: &ExecutorConfig,
+    cache: &ImageCache,
+    request: &mut LlmRequest,
+) -> Result<(), (LlmErrorCode, String)> {
+    let Some(messages) = request.messages.as_mut() else {
+        return Ok(());
+    };
+    if !messages.iter().any(|m| m.images.as_ref().is_some_and(|images| !images.is_empty())) {
+        return Ok(());
+    }
+    if !config.supports_vision(&request.model) {
+        return Err((LlmErrorCode::UnsupportedModel, format!("Model {} does not accept images", request.model)));
+    }
+    cache.resolve(messages).map(|_| ()).map_err(|e| (LlmErrorCode::InvalidRequest, e))
+}
+
+impl Default for ImageCache {
+    fn default() -> Self {
+        Self::new(IMAGE_CACHE_BYTES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lloom_core::protocol::ImageInput;
+
+    fn message(images: Vec<ImageInput>) -> Vec<ChatMessage> {
+        vec![ChatMessage::user_with_images("What is this?", images)]
+    }
+
+    #[test]
+    fn test_references_resolve_after_inline_upload() {
+        let cache = ImageCache::default();
+        let image = ImageInput::inline("image/png", b"image bytes");
+
+        let mut unknown = message(vec![image.reference()]);
+        assert!(cache.resolve(&mut unknown).unwrap_err().contains("Unknown image"));
+
+        assert_eq!(cache.resolve(&mut message(vec![image.clone()])).unwrap(), 1);
+        let mut referenced = message(vec![image.reference()]);
+        cache.resolve(&mut referenced).unwrap();
+        assert_eq!(referenced[0].images.as_ref().unwrap()[0], image);
+    }
+
+    #[test]
+    fn test_invalid_images_are_rejected() {
+        let cache = ImageCache::default();
+        let mut tampered = ImageInput::inline("image/png", b"image bytes");
+        tampered.hash = ImageInput::inline("image/png", b"other bytes").hash;
+        assert!(cache.resolve(&mut message(vec![tampered])).is_err());
+        assert_eq!(cache.len(), 0);
+
+        let images = (0..=MAX_IMAGES_PER_REQUEST as u8).map(|i| ImageInput::inline("image/png", &[i])).collect();
+        assert!(cache.resolve(&mut message(images)).unwrap_err().contains("exceed the limit"));
+    }
+
+    #[test]
+    fn test_oldest_images_are_evicted() {
+        let first = ImageInput::inline("image/png", b"first");
+        let second = ImageInput::inline("image/png", b"second");
+        let cache = ImageCache::new(first.data.as_ref().unwrap().len() + 4);
+
+        cache.resolve(&mut message(vec![first.clone()])).unwrap();
+        cache.resolve(&mut message(vec![second.clone()])).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(cache.resolve(&mut message(vec![first.reference()])).is_err());
+        assert!(cache.resolve(&mut message(vec![second.reference()])).is_ok());
+    }
+
+    #[test]
+    fn test_images_require_a_vision_model() {
+        let mut config = ExecutorConfig::default();
+        config.llm_backends[0].vision_models = vec!["gpt-4".to_string()];
+        let cache = ImageCache::default();
+        let mut request = LlmRequest {
+            model: "gpt-3.5-turbo".to_string(),
+            prompt: String::new(),
+            system_prompt: None,
+            temperature: None,
+            max_tokens: None,
+            executor_address: String::new(),
+            inbound_price: "1".to_string(),
+            outbound_price: "2".to_string(),
+            nonce: 1,
+            deadline: 0,
+            messages: Some(message(vec![ImageInput::inline("image/png", b"image bytes")])),
+            version: lloom_core::protocol::constants::LLM_REQUEST_VERSION,
+            ..Default::default()
+        };
+
+        let (code, _) = resolve_request_images(&config, &cache, &mut request).unwrap_err();
+        assert_eq!(code, LlmErrorCode::UnsupportedModel);
+        assert_eq!(cache.len(), 0);
+
+        request.model = "gpt-4".to_string();
+        assert!(resolve_request_images(&config, &cache, &mut request).is_ok());
+        assert_eq!(cache.len(), 1);
+
+        // Text-only requests are accepted for any model
+        request.model = "gpt-3.5-turbo".to_string();
+        request.messages = Some(vec![ChatMessage::user("Hello")]);
+        assert!(resolve_request_images(&config, &cache, &mut request).is_ok());
+    }
+}
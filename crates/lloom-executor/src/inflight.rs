@@ -0,0 +1,586 @@
+//! Tracking of in-flight request-response LLM calls.
+//!
+//! Backend calls for request-response `LlmRequest`s run on their own tasks so the
+//! executor keeps handling swarm events, including `CancelRequest`s, while a model
+//! is generating. Each call is registered under its request id until it finishes,
+//! is cancelled by its client or runs past its deadline. The content produced so
+//! far is kept so that a stopped request can be billed for the work actually done.
+
+use crate::{config::ExecutorConfig, llm_client::{Completion, LlmClient, count_tokens}, quote::SignedPrices};
+use alloy::primitives::Address;
+use libp2p::PeerId;
+use lloom_core::protocol::{LlmErrorCode, LlmRequest, Quote};
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::mpsc, task::AbortHandle};
+use tracing::{Instrument, Span, warn};
+
+/// How a tracked backend call ended
+#[derive(Debug)]
+pub enum CompletionOutcome {
+    /// The backend call finished, successfully or not
+    Finished(anyhow::Result<Completion>),
+    /// Every attempt produced output not matching the requested response format
+    InvalidOutput(String),
+    /// The request's deadline passed before the backend finished
+    DeadlineExceeded,
+}
+
+/// Why a cancellation was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelError {
+    /// No in-flight request has this id (it may already have completed)
+    UnknownRequest,
+    /// The cancellation did not come from the client that sent the request
+    NotAuthorized,
+}
+
+impl fmt::Display for CancelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CancelError::UnknownRequest => write!(f, "No in-flight request with this id"),
+            CancelError::NotAuthorized => write!(f, "Not authorized to cancel this request"),
+        }
+    }
+}
+
+/// A request whose backend call is still running.
+///
+/// `R` is the handle used to answer the client, normally a libp2p
+/// `ResponseChannel`.
+pub struct InFlightRequest<R> {
+    /// Where to send the final response
+    pub reply: R,
+    /// Peer the request came from
+    pub client_peer: PeerId,
+    /// Verified signer of the request, if it was signed
+    pub signer: Option<Address>,
+    /// Address billed for the request: the signer, or the main address that
+    /// authorized it as a session key
+    pub account: Option<Address>,
+    /// Requested model
+    pub model: String,
+    /// Client-chosen request id, echoed in the response
+    pub request_id: Option<String>,
+    /// Quote the request accepted, which sets its billing
+    pub quote: Option<Quote>,
+    /// Prices the client signed, billed when no quote was accepted
+    pub prices: SignedPrices,
+    prompt: String,
+    choices: u32,
+    produced: Arc<Mutex<String>>,
+    task: AbortHandle,
+}
+
+impl<R> InFlightRequest<R> {
+    /// Track a request whose backend call runs on `task`, appending its output to `produced`
+    pub fn new(
+        reply: R,
+        client_peer: PeerId,
+        signer: Option<Address>,
+        request: &LlmRequest,
+        quote: Option<Quote>,
+        produced: Arc<Mutex<String>>,
+        task: AbortHandle,
+    ) -> Self {
+        Self {
+            reply,
+            client_peer,
+            signer,
+            account: signer,
+            model: request.model.clone(),
+            request_id: request.request_id.clone(),
+            quote,
+            prices: SignedPrices::of(request),
+            prompt: prompt_text(request),
+            choices: request.sampling.n.unwrap_or(1),
+            produced,
+            task,
+        }
+    }
+
+    /// Bill the request to `account` instead of its signer
+    pub fn billed_to(mut self, account: Option<Address>) -> Self {
+        self.account = account;
+        self
+    }
+
+    /// Whether a cancellation from `peer`, signed by `signer`, may stop this request.
+    ///
+    /// Signed requests can only be cancelled by the same key; unsigned ones by
+    /// the peer that sent them.
+    pub fn may_cancel(&self, peer: &PeerId, signer: Address) -> bool {
+        match self.signer {
+            Some(expected) => expected == signer,
+            None => *peer == self.client_peer,
+        }
+    }
+
+    /// Content the backend produced so far
+    pub fn produced(&self) -> String {
+        self.produced.lock().map(|produced| produced.clone()).unwrap_or_default()
+    }
+
+    /// Estimated (inbound, outbound) tokens consumed so far
+    pub fn tokens_so_far(&self) -> (u64, u64) {
+        partial_tokens(&self.prompt, &self.produced(), &self.model, self.choices)
+    }
+
+    /// Stop the backend call
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+}
+
+/// Registry of in-flight requests, keyed by request id
+pub struct InFlightRequests<R> {
+    requests: HashMap<String, InFlightRequest<R>>,
+}
+
+impl<R> Default for InFlightRequests<R> {
+    fn default() -> Self {
+        Self { requests: HashMap::new() }
+    }
+}
+
+impl<R> InFlightRequests<R> {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking a request.
+    ///
+    /// Ids are chosen by clients, so a request whose id is already in flight is
+    /// refused and handed back; the running request is left alone.
+    pub fn insert(&mut self, request_id: String, request: InFlightRequest<R>) -> Result<(), Box<InFlightRequest<R>>> {
+        match self.requests.entry(request_id) {
+            Entry::Occupied(_) => Err(Box::new(request)),
+            Entry::Vacant(entry) => {
+                entry.insert(request);
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether a request with this id is in flight
+    pub fn contains(&self, request_id: &str) -> bool {
+        self.requests.contains_key(request_id)
+    }
+
+    /// Stop tracking a request whose backend call has ended
+    pub fn complete(&mut self, request_id: &str) -> Option<InFlightRequest<R>> {
+        self.requests.remove(request_id)
+    }
+
+    /// Cancel a request on behalf of `peer`/`signer`, aborting its backend call
+    pub fn cancel(
+        &mut self,
+        request_id: &str,
+        peer: &PeerId,
+        signer: Address,
+    ) -> Result<InFlightRequest<R>, CancelError> {
+        let request = self.requests.get(request_id).ok_or(CancelError::UnknownRequest)?;
+        if !request.may_cancel(peer, signer) {
+            return Err(CancelError::NotAuthorized);
+        }
+        let request = self.requests.remove(request_id).ok_or(CancelError::UnknownRequest)?;
+        request.abort();
+        Ok(request)
+    }
+
+    /// Number of requests in flight
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// Whether no requests are in flight
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+}
+
+/// Run a request's backend call on its own task.
+///
+/// Content is appended to the returned buffer as it is generated, or all at once
+/// for backends that are not streamed from. When the call
+/// finishes, or the request's deadline passes first, the outcome is sent on
+/// `completions` together with `request_id`. A deadline of 0 means none. The
+/// task runs in the caller's tracing span.
+///
+/// Output not matching the request's response format is thrown away and
+/// generated again, up to `max_attempts` backend calls in total.
+pub fn spawn_completion(
+    llm_client: LlmClient,
+    request: LlmRequest,
+    request_id: String,
+    max_attempts: u32,
+    completions: mpsc::UnboundedSender<(String, CompletionOutcome)>,
+) -> (Arc<Mutex<String>>, AbortHandle) {
+    let produced = Arc::new(Mutex::new(String::new()));
+    let buffer = Arc::clone(&produced);
+    let task = tokio::spawn(async move {
+        let deadline = tokio::time::sleep(time_until(request.deadline));
+        tokio::pin!(deadline);
+
+        let mut attempt = 1;
+        let outcome = loop {
+            if let Ok(mut produced) = buffer.lock() {
+                produced.clear();
+            }
+            let (chunk_tx, mut chunk_rx) = mpsc::unbounded_channel::<String>();
+            // LMStudio only reports its performance stats on whole responses
+            let completion = llm_client.complete(&request, !llm_client.is_lmstudio_backend(), chunk_tx);
+            tokio::pin!(completion);
+
+            let result = loop {
+                tokio::select! {
+                    Some(chunk) = chunk_rx.recv() => {
+                        if let Ok(mut produced) = buffer.lock() {
+                            produced.push_str(&chunk);
+                        }
+                    }
+                    result = &mut completion => break Some(result),
+                    _ = &mut deadline, if request.deadline > 0 => break None,
+                }
+            };
+            while let Ok(chunk) = chunk_rx.try_recv() {
+                if let Ok(mut produced) = buffer.lock() {
+                    produced.push_str(&chunk);
+                }
+            }
+
+            let completion = match result {
+                None => break CompletionOutcome::DeadlineExceeded,
+                Some(Err(e)) => break CompletionOutcome::Finished(Err(e)),
+                Some(Ok(completion)) => completion,
+            };
+            match check_output(&request, &completion) {
+                Ok(()) => break CompletionOutcome::Finished(Ok(completion)),
+                Err(reason) if attempt < max_attempts => {
+                    warn!("Attempt {} produced invalid output, retrying: {}", attempt, reason);
+                    attempt += 1;
+                }
+                Err(reason) => break CompletionOutcome::InvalidOutput(reason),
+            }
+        };
+        let _ = completions.send((request_id, outcome));
+    }.instrument(Span::current()));
+    (produced, task.abort_handle())
+}
+
+/// Refuse a request using tools or a response format its model was not
+/// configured for, or a response format that cannot be enforced.
+pub fn check_features(config: &ExecutorConfig, request: &LlmRequest) -> Result<(), (LlmErrorCode, String)> {
+    if let Some(format) = &request.response_format {
+        format.check().map_err(|e| (LlmErrorCode::InvalidRequest, e))?;
+    }
+    if request.tools.as_ref().is_some_and(|tools| !tools.is_empty()) && !config.supports_tools(&request.model) {
+        return Err((LlmErrorCode::UnsupportedModel, format!("Model {} does not call tools", request.model)));
+    }
+    if request.response_format.is_some() && !config.supports_structured_output(&request.model) {
+        return Err((LlmErrorCode::UnsupportedModel, format!("Model {} does not support response formats", request.model)));
+    }
+    Ok(())
+}
+
+/// Check every choice of a completion against the request's response format.
+///
+/// Completions that call tools are not expected to carry formatted content.
+pub fn check_output(request: &LlmRequest, completion: &Completion) -> Result<(), String> {
+    let Some(format) = &request.response_format else {
+        return Ok(());
+    };
+    if completion.tool_calls.is_some() {
+        return Ok(());
+    }
+    let choices = completion.choices.as_deref().unwrap_or(std::slice::from_ref(&completion.content));
+    choices.iter().try_for_each(|choice| format.validate(choice))
+}
+
+/// Time left until a unix timestamp (zero if it has passed)
+pub fn time_until(deadline: u64) -> Duration {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    Duration::from_secs(deadline.saturating_sub(now))
+}
+
+/// All message text of a request, for estimating its inbound tokens
+pub fn prompt_text(request: &LlmRequest) -> String {
+    request
+        .chat_messages()
+        .into_iter()
+        .map(|message| message.content)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Estimated (inbound, outbound) tokens of partially completed work.
+///
+/// Only the first of a request's `choices` is streamed and kept while it runs.
+/// The backend generates the others alongside it, so each is billed as long as
+/// the first.
+pub fn partial_tokens(prompt: &str, produced: &str, model: &str, choices: u32) -> (u64, u64) {
+    let outbound = if produced.is_empty() { 0 } else { estimate_tokens(produced, model) * u64::from(choices.max(1)) };
+    (estimate_tokens(prompt, model), outbound)
+}
+
+fn estimate_tokens(text: &str, model: &str) -> u64 {
+    count_tokens(text, model).map(|tokens| tokens as u64).unwrap_or((text.len() / 4) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lloom_core::protocol::constants::LEGACY_LLM_REQUEST_VERSION;
+
+    fn test_request() -> LlmRequest {
+        LlmRequest {
+            model: "gpt-3.5-turbo".to_string(),
+            prompt: "Tell me a story".to_string(),
+            system_prompt: None,
+            temperature: None,
+            max_tokens: None,
+            executor_address: String::new(),
+            inbound_price: "1".to_string(),
+            outbound_price: "2".to_string(),
+            nonce: 1,
+            deadline: 0,
+            version: LEGACY_LLM_REQUEST_VERSION,
+            ..Default::default()
+        }
+    }
+
+    fn address(byte: u8) -> Address {
+        Address::repeat_byte(byte)
+    }
+
+    fn tracked(
+        client_peer: PeerId,
+        signer: Option<Address>,
+    ) -> (InFlightRequest<()>, Arc<Mutex<String>>, tokio::task::JoinHandle<()>) {
+        let produced = Arc::new(Mutex::new(String::new()));
+        let task = tokio::spawn(std::future::pending::<()>());
+        let request = InFlightRequest::new((), client_peer, signer, &test_request(), None, Arc::clone(&produced), task.abort_handle());
+        (request, produced, task)
+    }
+
+    #[tokio::test]
+    async fn test_cancel_requires_matching_signer() {
+        let mut requests = InFlightRequests::new();
+        let peer = PeerId::random();
+        let (request, _, task) = tracked(peer, Some(address(1)));
+        assert!(requests.insert("0xabc".to_string(), request).is_ok());
+
+        // Same peer but a different key is refused
+        assert_eq!(requests.cancel("0xabc", &peer, address(2)).err(), Some(CancelError::NotAuthorized));
+        assert_eq!(requests.len(), 1);
+        assert!(!task.is_finished());
+
+        // The signing key may cancel from any peer
+        let cancelled = requests.cancel("0xabc", &PeerId::random(), address(1)).unwrap();
+        assert_eq!(cancelled.model, "gpt-3.5-turbo");
+        assert!(requests.is_empty());
+        assert!(task.await.unwrap_err().is_cancelled());
+
+        assert_eq!(requests.cancel("0xabc", &peer, address(1)).err(), Some(CancelError::UnknownRequest));
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_requests_cancelled_by_peer() {
+        let mut requests = InFlightRequests::new();
+        let peer = PeerId::random();
+        let (request, _, _task) = tracked(peer, None);
+        assert!(requests.insert("0xabc".to_string(), request).is_ok());
+
+        assert_eq!(
+            requests.cancel("0xabc", &PeerId::random(), address(1)).err(),
+            Some(CancelError::NotAuthorized)
+        );
+        assert!(requests.cancel("0xabc", &peer, address(1)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_ids_are_refused() {
+        let mut requests = InFlightRequests::new();
+        let peer = PeerId::random();
+        let (request, _, task) = tracked(peer, Some(address(1)));
+        assert!(requests.insert("0xabc".to_string(), request).is_ok());
+
+        // Another client reusing the id cannot displace the running request
+        let (duplicate, _, _task) = tracked(PeerId::random(), Some(address(2)));
+        let refused = requests.insert("0xabc".to_string(), duplicate).err().unwrap();
+        assert_eq!(refused.signer, Some(address(2)));
+        assert_eq!(requests.len(), 1);
+        assert!(!task.is_finished());
+        assert!(requests.cancel("0xabc", &peer, address(1)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_completed_requests_cannot_be_cancelled() {
+        let mut requests = InFlightRequests::new();
+        let peer = PeerId::random();
+        let (request, _, _task) = tracked(peer, None);
+        assert!(requests.insert("0xabc".to_string(), request).is_ok());
+        assert!(requests.contains("0xabc"));
+
+        assert!(requests.complete("0xabc").is_some());
+        assert!(!requests.contains("0xabc"));
+        assert!(requests.complete("0xabc").is_none());
+        assert_eq!(requests.cancel("0xabc", &peer, address(1)).err(), Some(CancelError::UnknownRequest));
+    }
+
+    #[tokio::test]
+    async fn test_tokens_so_far() {
+        let (request, produced, _task) = tracked(PeerId::random(), None);
+        let (inbound, outbound) = request.tokens_so_far();
+        assert!(inbound > 0);
+        assert_eq!(outbound, 0);
+
+        produced.lock().unwrap().push_str("Once upon a time");
+        let (_, outbound) = request.tokens_so_far();
+        assert!(outbound > 0);
+        assert_eq!(request.produced(), "Once upon a time");
+
+        // Every choice of a request asking for several is billed
+        let (inbound, single) = partial_tokens("Tell me a story", "Once upon a time", "gpt-3.5-turbo", 1);
+        assert_eq!(partial_tokens("Tell me a story", "Once upon a time", "gpt-3.5-turbo", 3), (inbound, 3 * single));
+        assert_eq!(partial_tokens("Tell me a story", "", "gpt-3.5-turbo", 3).1, 0);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_completion_reports_outcome() {
+        use crate::config::LlmBackendConfig;
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers::{method, path}};
+
+        let mock_server = MockServer::start().await;
+        let body = concat!(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Once\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\" upon\"}}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+        let client = LlmClient::new(LlmBackendConfig {
+            name: "test-backend".to_string(),
+            endpoint: mock_server.uri(),
+            api_key: Some("test-key".to_string()),
+            supported_models: vec!["gpt-3.5-turbo".to_string()],
+            rate_limit: None,
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: true,
+        })
+        .unwrap();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (produced, _task) = spawn_completion(client, test_request(), "0xabc".to_string(), 1, tx);
+        let (request_id, outcome) = rx.recv().await.unwrap();
+
+        assert_eq!(request_id, "0xabc");
+        match outcome {
+            CompletionOutcome::Finished(Ok(completion)) => assert_eq!(completion.content, "Once upon"),
+            other => panic!("Unexpected outcome: {:?}", other),
+        }
+        assert_eq!(*produced.lock().unwrap(), "Once upon");
+    }
+
+    #[tokio::test]
+    async fn test_spawn_completion_retries_invalid_output() {
+        use crate::config::LlmBackendConfig;
+        use lloom_core::protocol::ResponseFormat;
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers::{method, path}};
+
+        let mock_server = MockServer::start().await;
+        let invalid = "data: {\"choices\":[{\"delta\":{\"content\":\"Sure! Here it is\"}}]}\n\ndata: [DONE]\n\n";
+        let valid = "data: {\"choices\":[{\"delta\":{\"content\":\"{\\\"title\\\":\\\"Dune\\\"}\"}}]}\n\ndata: [DONE]\n\n";
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(invalid, "text/event-stream"))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(valid, "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+        let client = LlmClient::new(LlmBackendConfig {
+            name: "test-backend".to_string(),
+            endpoint: mock_server.uri(),
+            api_key: Some("test-key".to_string()),
+            supported_models: vec!["gpt-3.5-turbo".to_string()],
+            rate_limit: None,
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: true,
+        })
+        .unwrap();
+        let mut request = test_request();
+        request.response_format = Some(ResponseFormat::json_schema(
+            "book",
+            serde_json::json!({"type": "object", "required": ["title"]}),
+        ));
+
+        // A single attempt reports the invalid output
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        spawn_completion(client.clone(), request.clone(), "0xabc".to_string(), 1, tx);
+        match rx.recv().await.unwrap().1 {
+            CompletionOutcome::InvalidOutput(reason) => assert!(reason.contains("not valid JSON")),
+            other => panic!("Unexpected outcome: {:?}", other),
+        }
+
+        // A second attempt replaces the rejected output
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (produced, _task) = spawn_completion(client, request, "0xabc".to_string(), 2, tx);
+        match rx.recv().await.unwrap().1 {
+            CompletionOutcome::Finished(Ok(completion)) => assert_eq!(completion.content, r#"{"title":"Dune"}"#),
+            other => panic!("Unexpected outcome: {:?}", other),
+        }
+        assert_eq!(*produced.lock().unwrap(), r#"{"title":"Dune"}"#);
+    }
+
+    #[test]
+    fn test_check_features() {
+        use lloom_core::protocol::{ResponseFormat, Tool};
+
+        let config = ExecutorConfig::default();
+        let mut request = test_request();
+        assert!(check_features(&config, &request).is_ok());
+
+        // The default backend calls tools with gpt-4 but not gpt-3.5-turbo
+        request.tools = Some(vec![]);
+        assert!(check_features(&config, &request).is_ok());
+        request.tools = Some(vec![Tool::function("get_weather", None, serde_json::json!({"type": "object"}))]);
+        let (code, _) = check_features(&config, &request).unwrap_err();
+        assert_eq!(code, LlmErrorCode::UnsupportedModel);
+        request.model = "gpt-4".to_string();
+        assert!(check_features(&config, &request).is_ok());
+
+        request.response_format = Some(ResponseFormat::json_schema("book", serde_json::json!({"type": "object"})));
+        assert!(check_features(&config, &request).is_err());
+        request.model = "gpt-4-turbo".to_string();
+        assert!(check_features(&config, &request).is_ok());
+
+        // Schemas with keywords the executor cannot check are refused
+        request.response_format = Some(ResponseFormat::json_schema("id", serde_json::json!({"type": "string", "pattern": "^[a-z]+$"})));
+        let (code, _) = check_features(&config, &request).unwrap_err();
+        assert_eq!(code, LlmErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn test_time_until() {
+        assert_eq!(time_until(0), Duration::ZERO);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        assert!(time_until(now + 60) > Duration::from_secs(55));
+    }
+}
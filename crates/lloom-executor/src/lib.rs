@@ -35,12 +35,19 @@
 pub mod config;
 pub mod llm_client;
 pub mod blockchain;
+pub mod embedding;
+pub mod images;
+pub mod inflight;
+pub mod org;
+pub mod quote;
+pub mod sessions;
+pub mod streaming;
 
 /// Request processing and response utilities
 pub mod processing {
     use std::collections::HashMap;
-    use crate::{config::ExecutorConfig, llm_client::LlmClient};
-    use lloom_core::protocol::{LlmRequest, LlmResponse};
+    use crate::{config::ExecutorConfig, inflight::prompt_text, llm_client::{LlmClient, count_tokens}, quote::SignedPrices};
+    use lloom_core::protocol::{LlmErrorCode, LlmRequest, LlmResponse};
     use alloy::primitives::Address;
 
     /// High-level request processor for handling LLM requests
@@ -65,6 +72,21 @@ pub mod processing {
             request: LlmRequest,
             _verified_signer: Option<Address>,
         ) -> Result<LlmResponse, anyhow::Error> {
+            if !request.is_supported_version() {
+                return Ok(LlmResponse {
+                    content: String::new(),
+                    inbound_tokens: 0,
+                    outbound_tokens: 0,
+                    total_cost: "0".to_string(),
+                    model_used: request.model.clone(),
+                    error: Some(format!("Unsupported request version {}", request.version)),
+                    request_id: None,
+                    error_code: Some(LlmErrorCode::UnsupportedVersion),
+                    tool_calls: None,
+                    choices: None,
+                });
+            }
+
             // Find the appropriate backend for this model
             let backend_name = match self.config.find_backend_for_model(&request.model) {
                 Some(backend) => backend.name.clone(),
@@ -76,6 +98,10 @@ pub mod processing {
                         total_cost: "0".to_string(),
                         model_used: request.model.clone(),
                         error: Some(format!("Model {} not supported", request.model)),
+                        request_id: None,
+                        error_code: Some(LlmErrorCode::UnsupportedModel),
+                        tool_calls: None,
+                        choices: None,
                     });
                 }
             };
@@ -91,26 +117,41 @@ pub mod processing {
                         total_cost: "0".to_string(),
                         model_used: request.model.clone(),
                         error: Some(format!("Backend {} not available", backend_name)),
+                        request_id: None,
+                        error_code: Some(LlmErrorCode::BackendUnavailable),
+                        tool_calls: None,
+                        choices: None,
                     });
                 }
             };
 
             // Execute the LLM request
-            match llm_client.lmstudio_chat_completion(
+            match llm_client.lmstudio_chat_completion_messages(
                 &request.model,
-                &request.prompt,
-                request.system_prompt.as_deref(),
+                request.chat_messages(),
                 request.temperature,
                 request.max_tokens,
+                request.sampling.clone(),
             ).await {
                 Ok((content, token_count, _stats, _model_info)) => {
+                    // Only the total is reported, so the prompt's share is counted here
+                    let token_count = u64::from(token_count);
+                    let prompt_tokens = count_tokens(&prompt_text(&request), &request.model)
+                        .map_or(0, |tokens| tokens as u64)
+                        .min(token_count);
+                    let (inbound_tokens, outbound_tokens, total_cost) =
+                        SignedPrices::of(&request).billed(prompt_tokens, token_count - prompt_tokens);
                     Ok(LlmResponse {
                         content,
-                        inbound_tokens: (token_count / 2) as u64,  // Rough estimate
-                        outbound_tokens: (token_count / 2) as u64,
-                        total_cost: format!("{}", (token_count as u64) * 1000000000000000u64),
+                        inbound_tokens,
+                        outbound_tokens,
+                        total_cost,
                         model_used: request.model.clone(),
                         error: None,
+                        request_id: None,
+                        error_code: None,
+                        tool_calls: None,
+                        choices: None,
                     })
                 }
                 Err(e) => {
@@ -121,6 +162,10 @@ pub mod processing {
                         total_cost: "0".to_string(),
                         model_used: request.model,
                         error: Some(e.to_string()),
+                        request_id: None,
+                        error_code: Some(crate::llm_client::error_code(&e)),
+                        tool_calls: None,
+                        choices: None,
                     })
                 }
             }
@@ -179,6 +224,6 @@ pub mod utils {
 }
 
 // Re-export commonly used types for convenience
-pub use config::{ExecutorConfig, LlmBackendConfig, BlockchainConfig, NetworkConfig};
+pub use config::{ExecutorConfig, LlmBackendConfig, BlockchainConfig, NetworkConfig, BillingConfig, OrgConfig, ReplayConfig, StructuredOutputConfig};
 pub use llm_client::{LlmClient, ModelInfo};
 pub use processing::RequestProcessor;
\ No newline at end of file
@@ -5,26 +5,45 @@ use serde::{Deserialize, Serialize};
 use reqwest::{Client, header};
 use std::{time::Duration, collections::HashMap};
 use crate::config::LlmBackendConfig;
-use tracing::trace;
+use lloom_core::protocol::{FunctionCall, LlmErrorCode, LlmRequest, ResponseFormat, SamplingParams, Tool, ToolCall};
+use tokio::sync::mpsc;
+use tracing::{debug, trace};
 
 /// OpenAI-compatible chat completion request
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChatCompletionRequest {
     pub model: String,
+    #[serde(serialize_with = "serialize_messages")]
     pub messages: Vec<ChatMessage>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
 }
 
-/// Chat message for OpenAI-compatible APIs
+/// Options for streamed chat completions
 #[derive(Debug, Serialize, Deserialize)]
-pub struct ChatMessage {
-    pub role: String,
-    pub content: String,
+pub struct StreamOptions {
+    /// Ask the backend to send token usage in the last chunk
+    pub include_usage: bool,
 }
 
+/// Chat message for OpenAI-compatible APIs, shared with the wire protocol so
+/// client-supplied conversations are forwarded unchanged
+pub use lloom_core::protocol::ChatMessage;
+
 /// OpenAI-compatible chat completion response
 #[derive(Debug, Deserialize)]
 pub struct ChatCompletionResponse {
@@ -36,18 +55,134 @@ pub struct ChatCompletionResponse {
 pub struct Choice {
     pub message: ChatMessage,
     #[allow(dead_code)]
-    pub finish_reason: String,
+    pub finish_reason: Option<String>,
+}
+
+/// One server-sent event of a streamed chat completion
+#[derive(Debug, Deserialize)]
+pub struct ChatCompletionChunk {
+    #[serde(default)]
+    pub choices: Vec<ChunkChoice>,
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChunkChoice {
+    #[serde(default)]
+    pub index: usize,
+    pub delta: ChunkDelta,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChunkDelta {
+    pub content: Option<String>,
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+/// Fragment of a streamed tool call; fragments with the same index belong to one call
+#[derive(Debug, Deserialize)]
+pub struct ToolCallDelta {
+    pub index: usize,
+    pub id: Option<String>,
+    pub function: Option<FunctionCallDelta>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FunctionCallDelta {
+    pub name: Option<String>,
+    pub arguments: Option<String>,
+}
+
+/// An unsuccessful HTTP status returned by a backend's API
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: reqwest::StatusCode,
+    /// Response body, usually the backend's JSON error
+    pub message: String,
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "LLM API error ({}): {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Error code for a failed backend call.
+///
+/// Rate limiting, overload and content filtering are told apart by the HTTP
+/// status and error body of the backend; anything else is a backend error.
+pub fn error_code(error: &anyhow::Error) -> LlmErrorCode {
+    let Some(api_error) = error.downcast_ref::<ApiError>() else {
+        return LlmErrorCode::BackendError;
+    };
+    match api_error.status.as_u16() {
+        429 => LlmErrorCode::RateLimited,
+        // 529 is Anthropic's "overloaded"
+        503 | 529 => LlmErrorCode::Busy,
+        400 if ["content_filter", "content_policy"].iter().any(|marker| api_error.message.contains(marker)) => {
+            LlmErrorCode::ContentRejected
+        }
+        _ => LlmErrorCode::BackendError,
+    }
+}
+
+/// Result of a finished chat completion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    /// Generated text of the first choice
+    pub content: String,
+    /// Text of every choice, when more than one was generated
+    pub choices: Option<Vec<String>>,
+    /// Tools the model called, if any
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// Total tokens used, prompt included
+    pub token_count: u32,
+    /// Tokens of the prompt alone
+    pub prompt_tokens: u32,
+}
+
+impl Completion {
+    /// Tokens generated for every choice, tool calls included
+    pub fn completion_tokens(&self) -> u32 {
+        self.token_count.saturating_sub(self.prompt_tokens)
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Usage {
-    #[allow(dead_code)]
     pub prompt_tokens: u32,
     #[allow(dead_code)]
     pub completion_tokens: u32,
     pub total_tokens: u32,
 }
 
+/// OpenAI-compatible embeddings request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmbeddingsRequest {
+    pub model: String,
+    pub input: Vec<String>,
+}
+
+/// OpenAI-compatible embeddings response
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingsResponse {
+    pub data: Vec<EmbeddingData>,
+    pub usage: Option<EmbeddingsUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingData {
+    pub embedding: Vec<f32>,
+    pub index: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingsUsage {
+    pub prompt_tokens: u32,
+}
+
 /// LMStudio-specific model information from /api/v0/models
 #[derive(Debug, Deserialize)]
 pub struct LmStudioModel {
@@ -84,7 +219,9 @@ pub struct LmStudioUsage {
 /// LMStudio performance statistics
 #[derive(Debug, Deserialize)]
 pub struct LmStudioStats {
+    #[allow(dead_code)]
     pub tokens_per_second: Option<f64>,
+    #[allow(dead_code)]
     pub time_to_first_token: Option<f64>,
     #[allow(dead_code)]
     pub total_time: Option<f64>,
@@ -95,6 +232,7 @@ pub struct LmStudioStats {
 pub struct LmStudioModelInfo {
     #[allow(dead_code)]
     pub id: Option<String>,
+    #[allow(dead_code)]
     pub architecture: Option<String>,
     #[allow(dead_code)]
     pub size: Option<String>,
@@ -128,6 +266,7 @@ pub struct ModelInfo {
 
 
 /// Client for interacting with LLM backends
+#[derive(Clone)]
 pub struct LlmClient {
     http_client: Client,
     backend_config: LlmBackendConfig,
@@ -147,6 +286,7 @@ impl LlmClient {
     }
     
     /// Execute a chat completion request
+    #[allow(dead_code)]
     pub async fn chat_completion(
         &self,
         model: &str,
@@ -154,42 +294,41 @@ impl LlmClient {
         system_prompt: Option<&str>,
         temperature: Option<f32>,
         max_tokens: Option<u32>,
+    ) -> Result<(String, u32)> {
+        self.chat_completion_messages(model, build_messages(prompt, system_prompt), temperature, max_tokens, SamplingParams::default()).await
+    }
+    
+    /// Execute a chat completion request for a full conversation.
+    ///
+    /// Returns the first choice only.
+    pub async fn chat_completion_messages(
+        &self,
+        model: &str,
+        messages: Vec<ChatMessage>,
+        temperature: Option<f32>,
+        max_tokens: Option<u32>,
+        sampling: SamplingParams,
     ) -> Result<(String, u32)> {
         // Check if the model is supported
         if !self.backend_config.supported_models.contains(&model.to_string()) {
             return Err(anyhow!("Model {} not supported by backend {}", model, self.backend_config.name));
         }
         
-        // Build messages
-        let mut messages = vec![];
-        if let Some(system) = system_prompt {
-            messages.push(ChatMessage {
-                role: "system".to_string(),
-                content: system.to_string(),
-            });
-        }
-        messages.push(ChatMessage {
-            role: "user".to_string(),
-            content: prompt.to_string(),
-        });
-        
         // Build request
         let request = ChatCompletionRequest {
             model: model.to_string(),
             messages,
             temperature,
             max_tokens,
+            stream: None,
+            stream_options: None,
+            tools: None,
+            tool_choice: None,
+            response_format: None,
+            sampling,
         };
         
-        // Get API key from environment variable or config
-        let api_key = match &self.backend_config.api_key {
-            Some(key) => key.clone(),
-            None => {
-                let env_var = format!("{}_API_KEY", self.backend_config.name.to_uppercase());
-                std::env::var(&env_var)
-                    .map_err(|_| anyhow!("API key not found in config or {} environment variable", env_var))?
-            }
-        };
+        let api_key = self.api_key()?;
         
         // Make the request
         let url = format!("{}/chat/completions", self.backend_config.endpoint);
@@ -241,7 +380,7 @@ impl LlmClient {
             trace!("❌ Error Response Body:");
             trace!("{}", error_text);
             trace!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
-            return Err(anyhow!("LLM API error ({}): {}", status, error_text));
+            return Err(ApiError { status, message: error_text }.into());
         }
         
         // Get response text and log it
@@ -272,6 +411,273 @@ impl LlmClient {
         Ok((content, token_count))
     }
     
+    /// Execute a request's chat completion as a server-sent event stream.
+    ///
+    /// Each content delta is sent on `chunks` as soon as it arrives; tool call
+    /// fragments are assembled and returned with the completion. The token count
+    /// is estimated locally when the backend does not report usage.
+    pub async fn chat_completion_stream(
+        &self,
+        request: &LlmRequest,
+        chunks: mpsc::UnboundedSender<String>,
+    ) -> Result<Completion> {
+        let model = request.model.as_str();
+        if !self.backend_config.supported_models.contains(&request.model) {
+            return Err(anyhow!("Model {} not supported by backend {}", model, self.backend_config.name));
+        }
+        
+        // LMStudio runs locally without authentication
+        let api_key = if self.is_lmstudio_backend() {
+            self.backend_config.api_key.clone()
+        } else {
+            Some(self.api_key()?)
+        };
+        
+        let request = self.backend_request(request, true);
+        let prompt_text: String = request.messages.iter().map(|m| m.content.as_str()).collect::<Vec<_>>().join("\n");
+        
+        let url = format!("{}/chat/completions", self.backend_config.endpoint);
+        trace!("🌐 Streaming chat completion from {}", url);
+        
+        let mut http_request = self.http_client
+            .post(&url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT, "text/event-stream")
+            .json(&request);
+        if let Some(api_key) = api_key {
+            http_request = http_request.header(header::AUTHORIZATION, format!("Bearer {}", api_key));
+        }
+        let mut response = http_request.send().await?;
+        
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await?;
+            return Err(ApiError { status, message: error_text }.into());
+        }
+        
+        let mut parser = SseParser::default();
+        let mut content = String::new();
+        let mut other_choices: Vec<String> = Vec::new();
+        let mut tool_calls: Vec<ToolCall> = Vec::new();
+        let mut usage = None;
+        'events: while let Some(bytes) = response.chunk().await? {
+            for data in parser.push(&bytes) {
+                if data == "[DONE]" {
+                    break 'events;
+                }
+                let chunk: ChatCompletionChunk = serde_json::from_str(&data)
+                    .map_err(|e| anyhow!("Invalid stream chunk from backend: {}", e))?;
+                if let Some(chunk_usage) = chunk.usage {
+                    usage = Some(chunk_usage);
+                }
+                let mut delta = String::new();
+                for choice in chunk.choices {
+                    // Only the first choice is streamed; the others are returned at the end
+                    if choice.index > 0 {
+                        if other_choices.len() < choice.index {
+                            other_choices.resize(choice.index, String::new());
+                        }
+                        other_choices[choice.index - 1].extend(choice.delta.content);
+                        continue;
+                    }
+                    delta.extend(choice.delta.content);
+                    for fragment in choice.delta.tool_calls.into_iter().flatten() {
+                        merge_tool_call(&mut tool_calls, fragment);
+                    }
+                }
+                if !delta.is_empty() {
+                    content.push_str(&delta);
+                    // The receiver going away only means nobody is listening anymore
+                    let _ = chunks.send(delta);
+                }
+            }
+        }
+        
+        let (prompt_tokens, token_count) = match usage {
+            Some(usage) => (usage.prompt_tokens, usage.total_tokens),
+            None => {
+                let arguments: String = tool_calls.iter().map(|call| call.function.arguments.as_str()).collect();
+                let prompt_tokens = count_tokens(&prompt_text, model)?;
+                let estimate = prompt_tokens
+                    + count_tokens(&content, model)?
+                    + count_tokens(&other_choices.concat(), model)?
+                    + count_tokens(&arguments, model)?;
+                trace!("Backend did not report usage, estimated {} tokens", estimate);
+                (prompt_tokens as u32, estimate as u32)
+            }
+        };
+        let choices = (!other_choices.is_empty()).then(|| {
+            let mut choices = vec![content.clone()];
+            choices.append(&mut other_choices);
+            choices
+        });
+        Ok(Completion {
+            content,
+            choices,
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            token_count,
+            prompt_tokens,
+        })
+    }
+    
+    /// Execute a request's chat completion as a single, non-streamed response.
+    ///
+    /// Returns every choice and any tool calls, like a streamed completion.
+    /// LMStudio's performance stats are logged when the backend reports them.
+    pub async fn chat_completion_request(&self, request: &LlmRequest) -> Result<Completion> {
+        let model = request.model.as_str();
+        if !self.backend_config.supported_models.contains(&request.model) {
+            return Err(anyhow!("Model {} not supported by backend {}", model, self.backend_config.name));
+        }
+        
+        // LMStudio runs locally without authentication
+        let api_key = if self.is_lmstudio_backend() {
+            self.backend_config.api_key.clone()
+        } else {
+            Some(self.api_key()?)
+        };
+        
+        let url = format!("{}/chat/completions", self.backend_config.endpoint);
+        trace!("🌐 Requesting chat completion from {}", url);
+        
+        let mut http_request = self.http_client
+            .post(&url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .json(&self.backend_request(request, false));
+        if let Some(api_key) = api_key {
+            http_request = http_request.header(header::AUTHORIZATION, format!("Bearer {}", api_key));
+        }
+        let response = http_request.send().await?;
+        
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await?;
+            return Err(ApiError { status, message: error_text }.into());
+        }
+        
+        let response_text = response.text().await?;
+        trace!("📄 Response Body: {}", response_text);
+        if self.is_lmstudio_backend() {
+            if let Ok(LmStudioChatResponse { stats: Some(stats), .. }) = serde_json::from_str(&response_text) {
+                debug!("LMStudio stats: {:?} tokens/sec, {:?}s to first token",
+                       stats.tokens_per_second, stats.time_to_first_token);
+            }
+        }
+        
+        let completion: ChatCompletionResponse = serde_json::from_str(&response_text)?;
+        let mut messages = completion.choices.into_iter().map(|choice| choice.message);
+        let first = messages.next().ok_or_else(|| anyhow!("No completion choices returned"))?;
+        let others: Vec<String> = messages.map(|message| message.content).collect();
+        let choices = (!others.is_empty()).then(|| {
+            let mut choices = vec![first.content.clone()];
+            choices.extend(others);
+            choices
+        });
+        Ok(Completion {
+            content: first.content,
+            choices,
+            tool_calls: first.tool_calls.filter(|calls| !calls.is_empty()),
+            token_count: completion.usage.total_tokens,
+            prompt_tokens: completion.usage.prompt_tokens,
+        })
+    }
+    
+    /// Run a request's completion, sending content deltas on `chunks`.
+    ///
+    /// The backend is streamed from when `stream` is set and it supports
+    /// streaming; otherwise the whole content is sent as one chunk once done.
+    pub async fn complete(
+        &self,
+        request: &LlmRequest,
+        stream: bool,
+        chunks: mpsc::UnboundedSender<String>,
+    ) -> Result<Completion> {
+        if stream && self.backend_config.streaming {
+            return self.chat_completion_stream(request, chunks).await;
+        }
+        let completion = self.chat_completion_request(request).await?;
+        if !completion.content.is_empty() {
+            let _ = chunks.send(completion.content.clone());
+        }
+        Ok(completion)
+    }
+    
+    /// Embed `input` through the backend's `/embeddings` endpoint.
+    ///
+    /// Returns one vector per input, in input order, and the number of input
+    /// tokens, estimated locally when the backend does not report usage.
+    pub async fn embeddings(&self, model: &str, input: Vec<String>) -> Result<(Vec<Vec<f32>>, u32)> {
+        if !self.backend_config.supported_models.contains(&model.to_string()) {
+            return Err(anyhow!("Model {} not supported by backend {}", model, self.backend_config.name));
+        }
+        
+        // LMStudio runs locally without authentication
+        let api_key = if self.is_lmstudio_backend() {
+            self.backend_config.api_key.clone()
+        } else {
+            Some(self.api_key()?)
+        };
+        
+        let input_count = input.len();
+        let request = EmbeddingsRequest {
+            model: model.to_string(),
+            input,
+        };
+        
+        let url = format!("{}/embeddings", self.backend_config.endpoint);
+        trace!("🌐 Requesting {} embeddings from {}", input_count, url);
+        
+        let mut http_request = self.http_client
+            .post(&url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .json(&request);
+        if let Some(api_key) = api_key {
+            http_request = http_request.header(header::AUTHORIZATION, format!("Bearer {}", api_key));
+        }
+        let response = http_request.send().await?;
+        
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await?;
+            return Err(ApiError { status, message: error_text }.into());
+        }
+        
+        let mut embeddings: EmbeddingsResponse = response.json().await?;
+        if embeddings.data.len() != input_count {
+            return Err(anyhow!(
+                "Backend returned {} embeddings for {} inputs",
+                embeddings.data.len(),
+                input_count
+            ));
+        }
+        embeddings.data.sort_by_key(|data| data.index);
+        
+        let token_count = match embeddings.usage {
+            Some(usage) => usage.prompt_tokens,
+            None => {
+                let mut estimate = 0;
+                for text in &request.input {
+                    estimate += count_tokens(text, model)?;
+                }
+                trace!("Backend did not report usage, estimated {} tokens", estimate);
+                estimate as u32
+            }
+        };
+        Ok((embeddings.data.into_iter().map(|data| data.embedding).collect(), token_count))
+    }
+    
+    /// API key from the backend config or the `<NAME>_API_KEY` environment variable
+    fn api_key(&self) -> Result<String> {
+        match &self.backend_config.api_key {
+            Some(key) => Ok(key.clone()),
+            None => {
+                let env_var = format!("{}_API_KEY", self.backend_config.name.to_uppercase());
+                std::env::var(&env_var)
+                    .map_err(|_| anyhow!("API key not found in config or {} environment variable", env_var))
+            }
+        }
+    }
+    
     /// Discover available models for LMStudio backends
     pub async fn discover_lmstudio_models(&self) -> Result<Vec<String>> {
         if !self.is_lmstudio_backend() {
@@ -297,6 +703,34 @@ impl LlmClient {
         Ok(models)
     }
     
+    /// Check if this backend is OpenAI's own API
+    pub fn is_openai_backend(&self) -> bool {
+        self.backend_config.endpoint.contains("api.openai.com") ||
+        self.backend_config.name.eq_ignore_ascii_case("openai")
+    }
+    
+    /// Backend request body for a protocol request, leaving out sampling
+    /// parameters the backend would reject
+    fn backend_request(&self, request: &LlmRequest, stream: bool) -> ChatCompletionRequest {
+        let mut sampling = request.sampling.clone();
+        // OpenAI refuses unknown arguments such as top_k
+        if self.is_openai_backend() && sampling.top_k.take().is_some() {
+            debug!("Dropping top_k, which backend {} does not support", self.backend_config.name);
+        }
+        ChatCompletionRequest {
+            model: request.model.clone(),
+            messages: request.chat_messages(),
+            temperature: request.temperature,
+            max_tokens: request.max_tokens,
+            stream: stream.then_some(true),
+            stream_options: stream.then_some(StreamOptions { include_usage: true }),
+            tools: request.tools.clone(),
+            tool_choice: request.tool_choice.clone(),
+            response_format: request.response_format.clone(),
+            sampling,
+        }
+    }
+    
     /// Check if this backend is an LMStudio backend
     pub fn is_lmstudio_backend(&self) -> bool {
         self.backend_config.endpoint.contains("localhost:1234") ||
@@ -312,10 +746,22 @@ impl LlmClient {
         system_prompt: Option<&str>,
         temperature: Option<f32>,
         max_tokens: Option<u32>,
+    ) -> Result<(String, u32, Option<LmStudioStats>, Option<LmStudioModelInfo>)> {
+        self.lmstudio_chat_completion_messages(model, build_messages(prompt, system_prompt), temperature, max_tokens, SamplingParams::default()).await
+    }
+    
+    /// Execute a chat completion for a full conversation with LMStudio-specific enhancements
+    pub async fn lmstudio_chat_completion_messages(
+        &self,
+        model: &str,
+        messages: Vec<ChatMessage>,
+        temperature: Option<f32>,
+        max_tokens: Option<u32>,
+        sampling: SamplingParams,
     ) -> Result<(String, u32, Option<LmStudioStats>, Option<LmStudioModelInfo>)> {
         if !self.is_lmstudio_backend() {
             // Fall back to regular chat completion for non-LMStudio backends
-            let (content, tokens) = self.chat_completion(model, prompt, system_prompt, temperature, max_tokens).await?;
+            let (content, tokens) = self.chat_completion_messages(model, messages, temperature, max_tokens, sampling).await?;
             return Ok((content, tokens, None, None));
         }
         
@@ -324,25 +770,18 @@ impl LlmClient {
             return Err(anyhow!("Model {} not supported by backend {}", model, self.backend_config.name));
         }
         
-        // Build messages
-        let mut messages = vec![];
-        if let Some(system) = system_prompt {
-            messages.push(ChatMessage {
-                role: "system".to_string(),
-                content: system.to_string(),
-            });
-        }
-        messages.push(ChatMessage {
-            role: "user".to_string(),
-            content: prompt.to_string(),
-        });
-        
         // Build request
         let request = ChatCompletionRequest {
             model: model.to_string(),
             messages,
             temperature,
             max_tokens,
+            stream: None,
+            stream_options: None,
+            tools: None,
+            tool_choice: None,
+            response_format: None,
+            sampling,
         };
         
         // Make the request to LMStudio's enhanced endpoint
@@ -488,7 +927,7 @@ impl LlmClient {
             for model_id in &self.backend_config.supported_models {
                 let mut metadata = HashMap::new();
                 metadata.insert("configured".to_string(), serde_json::Value::Bool(true));
-                if let Some(_) = self.backend_config.endpoint.strip_prefix("https://api.openai.com") {
+                if self.backend_config.endpoint.strip_prefix("https://api.openai.com").is_some() {
                     metadata.insert("official_openai".to_string(), serde_json::Value::Bool(true));
                 } else {
                     metadata.insert("endpoint".to_string(), serde_json::Value::String(self.backend_config.endpoint.clone()));
@@ -506,6 +945,93 @@ impl LlmClient {
     }
 }
 
+/// Incremental parser for `text/event-stream` bodies
+#[derive(Debug, Default)]
+pub struct SseParser {
+    /// Raw bytes of the incomplete last line; decoded only once the line is
+    /// complete, so a character split across chunks is not mangled
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    /// Feed raw bytes and return the data of every event completed by them
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let raw: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&raw);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                // A blank line dispatches the event
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(data) = line.strip_prefix("data:") {
+                self.data.push(data.strip_prefix(' ').unwrap_or(data).to_string());
+            }
+            // Comments, `event:`, `id:` and `retry:` lines carry nothing we use
+        }
+        events
+    }
+}
+
+/// Add a streamed tool call fragment to the calls assembled so far
+fn merge_tool_call(tool_calls: &mut Vec<ToolCall>, fragment: ToolCallDelta) {
+    while tool_calls.len() <= fragment.index {
+        tool_calls.push(ToolCall {
+            id: String::new(),
+            kind: "function".to_string(),
+            function: FunctionCall { name: String::new(), arguments: String::new() },
+        });
+    }
+    let call = &mut tool_calls[fragment.index];
+    if let Some(id) = fragment.id {
+        call.id = id;
+    }
+    if let Some(function) = fragment.function {
+        call.function.name.extend(function.name);
+        call.function.arguments.extend(function.arguments);
+    }
+}
+
+/// Serialize messages for the backend, sending attached images as OpenAI
+/// content parts next to the text
+fn serialize_messages<S: serde::Serializer>(messages: &[ChatMessage], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    use serde::ser::{Error, SerializeSeq};
+    let mut seq = serializer.serialize_seq(Some(messages.len()))?;
+    for message in messages {
+        let Some(images) = message.images.as_ref().filter(|images| !images.is_empty()) else {
+            seq.serialize_element(message)?;
+            continue;
+        };
+        let mut parts = vec![serde_json::json!({ "type": "text", "text": message.content })];
+        for image in images {
+            let url = image.data_url().ok_or_else(|| S::Error::custom(format!("Image {} has no data", image.hash)))?;
+            parts.push(serde_json::json!({ "type": "image_url", "image_url": { "url": url } }));
+        }
+        let mut value = serde_json::to_value(message).map_err(S::Error::custom)?;
+        if let Some(object) = value.as_object_mut() {
+            object.remove("images");
+            object.insert("content".to_string(), serde_json::Value::Array(parts));
+        }
+        seq.serialize_element(&value)?;
+    }
+    seq.end()
+}
+
+/// Build the conversation for a single prompt with an optional system prompt
+fn build_messages(prompt: &str, system_prompt: Option<&str>) -> Vec<ChatMessage> {
+    let mut messages = vec![];
+    if let Some(system) = system_prompt {
+        messages.push(ChatMessage::system(system));
+    }
+    messages.push(ChatMessage::user(prompt));
+    messages
+}
+
 /// Count tokens in text using tiktoken
 #[allow(dead_code)]
 pub fn count_tokens(text: &str, model: &str) -> Result<usize> {
@@ -565,6 +1091,10 @@ mod tests {
             api_key: Some("test-key".to_string()),
             supported_models: vec!["test-model".to_string()],
             rate_limit: Some(100),
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: true,
         };
 
         let client = LlmClient::new(backend_config);
@@ -576,6 +1106,9 @@ mod tests {
         let message = ChatMessage {
             role: "user".to_string(),
             content: "Test message".to_string(),
+            tool_calls: None,
+            tool_call_id: None,
+            images: None,
         };
 
         assert_eq!(message.role, "user");
@@ -588,10 +1121,16 @@ mod tests {
             ChatMessage {
                 role: "system".to_string(),
                 content: "You are a helpful assistant".to_string(),
+                tool_calls: None,
+                tool_call_id: None,
+                images: None,
             },
             ChatMessage {
                 role: "user".to_string(),
                 content: "Hello".to_string(),
+                tool_calls: None,
+                tool_call_id: None,
+                images: None,
             },
         ];
 
@@ -600,6 +1139,12 @@ mod tests {
             messages,
             temperature: Some(0.7),
             max_tokens: Some(150),
+            stream: None,
+            stream_options: None,
+            tools: None,
+            tool_choice: None,
+            response_format: None,
+            sampling: SamplingParams::default(),
         };
 
         assert_eq!(request.model, "gpt-3.5-turbo");
@@ -614,6 +1159,9 @@ mod tests {
             ChatMessage {
                 role: "user".to_string(),
                 content: "Hello".to_string(),
+                tool_calls: None,
+                tool_call_id: None,
+                images: None,
             },
         ];
 
@@ -622,6 +1170,12 @@ mod tests {
             messages,
             temperature: None,
             max_tokens: None,
+            stream: None,
+            stream_options: None,
+            tools: None,
+            tool_choice: None,
+            response_format: None,
+            sampling: SamplingParams::default(),
         };
 
         assert_eq!(request.model, "gpt-4");
@@ -638,6 +1192,10 @@ mod tests {
             api_key: Some("test-key".to_string()),
             supported_models: vec!["gpt-3.5-turbo".to_string()],
             rate_limit: Some(100),
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: true,
         };
 
         let client = LlmClient::new(backend_config).unwrap();
@@ -687,6 +1245,10 @@ mod tests {
             api_key: Some("test-key".to_string()),
             supported_models: vec!["gpt-3.5-turbo".to_string()],
             rate_limit: Some(100),
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: true,
         };
 
         let client = LlmClient::new(backend_config).unwrap();
@@ -705,6 +1267,329 @@ mod tests {
         assert_eq!(token_count, 18);
     }
 
+    #[tokio::test]
+    async fn test_embeddings() {
+        let mock_server = MockServer::start().await;
+
+        // Vectors may come back out of order
+        let mock_response = serde_json::json!({
+            "data": [
+                { "embedding": [0.0, 1.0], "index": 1 },
+                { "embedding": [1.0, 0.0], "index": 0 }
+            ],
+            "usage": { "prompt_tokens": 4, "total_tokens": 4 }
+        });
+
+        Mock::given(method("POST"))
+            .and(path("/embeddings"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(mock_response))
+            .mount(&mock_server)
+            .await;
+
+        let backend_config = LlmBackendConfig {
+            name: "test-backend".to_string(),
+            endpoint: mock_server.uri(),
+            api_key: Some("test-key".to_string()),
+            supported_models: vec!["text-embedding-3-small".to_string()],
+            rate_limit: Some(100),
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: true,
+        };
+        let client = LlmClient::new(backend_config).unwrap();
+
+        let input = vec!["first".to_string(), "second".to_string()];
+        let (embeddings, tokens) = client.embeddings("text-embedding-3-small", input.clone()).await.unwrap();
+        assert_eq!(embeddings, vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+        assert_eq!(tokens, 4);
+
+        let result = client.embeddings("text-embedding-3-small", vec!["only one".to_string(), "missing".to_string(), "third".to_string()]).await;
+        assert!(result.unwrap_err().to_string().contains("2 embeddings for 3 inputs"));
+
+        let result = client.embeddings("other-model", input).await;
+        assert!(result.unwrap_err().to_string().contains("not supported"));
+    }
+
+    #[test]
+    fn test_sse_parser_handles_split_events() {
+        let mut parser = SseParser::default();
+        assert!(parser.push(b"data: {\"a\"").is_empty());
+        assert_eq!(parser.push(b":1}\n\n: keep-alive\n\ndata: [DONE]\r\n\r\n"), vec![
+            "{\"a\":1}".to_string(),
+            "[DONE]".to_string(),
+        ]);
+        assert!(parser.push(b"event: ping\n\n").is_empty());
+    }
+
+    #[test]
+    fn test_sse_parser_keeps_split_characters() {
+        let mut parser = SseParser::default();
+        let event = "data: {\"content\":\"caf\u{e9} \u{1f600}\"}\n\n".as_bytes();
+        // Split inside both the two-byte and the four-byte character
+        let e_acute = event.iter().position(|&byte| byte == 0xc3).unwrap();
+        let emoji = event.iter().position(|&byte| byte == 0xf0).unwrap();
+        assert!(parser.push(&event[..e_acute + 1]).is_empty());
+        assert!(parser.push(&event[e_acute + 1..emoji + 2]).is_empty());
+        assert_eq!(parser.push(&event[emoji + 2..]), vec!["{\"content\":\"caf\u{e9} \u{1f600}\"}".to_string()]);
+    }
+
+    fn stream_request(tools: Option<Vec<Tool>>) -> LlmRequest {
+        LlmRequest {
+            model: "gpt-3.5-turbo".to_string(),
+            prompt: String::new(),
+            system_prompt: None,
+            temperature: None,
+            max_tokens: None,
+            executor_address: String::new(),
+            inbound_price: "1".to_string(),
+            outbound_price: "1".to_string(),
+            nonce: 1,
+            deadline: 0,
+            messages: Some(vec![ChatMessage::user("Hi")]),
+            version: 2,
+            tools,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chat_completion_stream_sampling_and_choices() {
+        use wiremock::matchers::body_partial_json;
+
+        let mock_server = MockServer::start().await;
+        let body = concat!(
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Red\"}},{\"index\":1,\"delta\":{\"content\":\"Bl\"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":1,\"delta\":{\"content\":\"ue\"}}]}\n\n",
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":3,\"total_tokens\":8}}\n\n",
+            "data: [DONE]\n\n",
+        );
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(serde_json::json!({
+                "top_p": 0.5,
+                "top_k": 20,
+                "stop": ["\n"],
+                "seed": 42,
+                "presence_penalty": 1.0,
+                "frequency_penalty": -1.0,
+                "n": 2
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+
+        let client = LlmClient::new(LlmBackendConfig {
+            name: "test-backend".to_string(),
+            endpoint: mock_server.uri(),
+            api_key: Some("test-key".to_string()),
+            supported_models: vec!["gpt-3.5-turbo".to_string()],
+            rate_limit: None,
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: true,
+        }).unwrap();
+        let mut request = stream_request(None);
+        request.sampling = SamplingParams {
+            top_p: Some(0.5),
+            top_k: Some(20),
+            stop: Some(vec!["\n".to_string()]),
+            seed: Some(42),
+            presence_penalty: Some(1.0),
+            frequency_penalty: Some(-1.0),
+            n: Some(2),
+        };
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let completion = client.chat_completion_stream(&request, tx).await.unwrap();
+
+        assert_eq!(completion.content, "Red");
+        assert_eq!(completion.choices, Some(vec!["Red".to_string(), "Blue".to_string()]));
+        assert_eq!(completion.token_count, 8);
+        assert_eq!(completion.prompt_tokens, 5);
+        // Only the first choice is streamed
+        assert_eq!(rx.try_recv().unwrap(), "Red");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_openai_backend_drops_top_k() {
+        let mock_server = MockServer::start().await;
+        let body = concat!(
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"}}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+
+        let client = LlmClient::new(LlmBackendConfig {
+            name: "openai".to_string(),
+            endpoint: mock_server.uri(),
+            api_key: Some("test-key".to_string()),
+            supported_models: vec!["gpt-3.5-turbo".to_string()],
+            rate_limit: None,
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: true,
+        }).unwrap();
+        let mut request = stream_request(None);
+        request.sampling = SamplingParams { top_p: Some(0.5), top_k: Some(20), ..Default::default() };
+
+        let (tx, _rx) = mpsc::unbounded_channel();
+        client.chat_completion_stream(&request, tx).await.unwrap();
+
+        let sent: serde_json::Value = serde_json::from_slice(&mock_server.received_requests().await.unwrap()[0].body).unwrap();
+        assert_eq!(sent["top_p"], 0.5);
+        assert!(sent.get("top_k").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_chat_completion_stream_tool_calls() {
+        use wiremock::matchers::body_partial_json;
+
+        let mock_server = MockServer::start().await;
+        let body = concat!(
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":null,\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"type\":\"function\",\"function\":{\"name\":\"get_weather\",\"arguments\":\"\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"city\\\":\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"Paris\\\"}\"}}]}}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let tool = Tool::function(
+            "get_weather",
+            Some("Current weather of a city".to_string()),
+            serde_json::json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+        );
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(serde_json::json!({
+                "tools": [{"type": "function", "function": {"name": "get_weather"}}]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+
+        let client = LlmClient::new(LlmBackendConfig {
+            name: "test-backend".to_string(),
+            endpoint: mock_server.uri(),
+            api_key: Some("test-key".to_string()),
+            supported_models: vec!["gpt-3.5-turbo".to_string()],
+            rate_limit: None,
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: true,
+        }).unwrap();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let completion = client.chat_completion_stream(&stream_request(Some(vec![tool])), tx).await.unwrap();
+
+        assert_eq!(completion.content, "");
+        assert!(rx.try_recv().is_err());
+        let tool_calls = completion.tool_calls.unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].id, "call_1");
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(tool_calls[0].function.arguments, "{\"city\":\"Paris\"}");
+        // No usage reported, so the arguments count towards the estimate
+        assert!(completion.token_count > 0);
+    }
+
+    #[tokio::test]
+    async fn test_chat_completion_stream() {
+        let mock_server = MockServer::start().await;
+        let body = concat!(
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Hello\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\" there\"}}]}\n\n",
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":2,\"total_tokens\":7}}\n\n",
+            "data: [DONE]\n\n",
+        );
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+
+        let backend_config = LlmBackendConfig {
+            name: "test-backend".to_string(),
+            endpoint: mock_server.uri(),
+            api_key: Some("test-key".to_string()),
+            supported_models: vec!["gpt-3.5-turbo".to_string()],
+            rate_limit: Some(100),
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: true,
+        };
+        let client = LlmClient::new(backend_config).unwrap();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let completion = client.chat_completion_stream(&stream_request(None), tx).await.unwrap();
+
+        assert_eq!(completion.content, "Hello there");
+        assert_eq!(completion.token_count, 7);
+        assert_eq!(completion.prompt_tokens, 5);
+        assert!(completion.tool_calls.is_none());
+        let mut chunks = Vec::new();
+        while let Ok(chunk) = rx.try_recv() {
+            chunks.push(chunk);
+        }
+        assert_eq!(chunks, vec!["Hello", " there"]);
+    }
+
+    #[tokio::test]
+    async fn test_complete_without_streaming_backend() {
+        let mock_server = MockServer::start().await;
+        let body = serde_json::json!({
+            "choices": [
+                {"message": {"role": "assistant", "content": "Hello there"}, "finish_reason": "stop"},
+                {"message": {"role": "assistant", "content": "Hi"}, "finish_reason": null}
+            ],
+            "usage": {"prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8}
+        });
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(body))
+            .mount(&mock_server)
+            .await;
+
+        let backend_config = LlmBackendConfig {
+            name: "test-backend".to_string(),
+            endpoint: mock_server.uri(),
+            api_key: Some("test-key".to_string()),
+            supported_models: vec!["gpt-3.5-turbo".to_string()],
+            rate_limit: Some(100),
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: false,
+        };
+        let client = LlmClient::new(backend_config).unwrap();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let completion = client.complete(&stream_request(None), true, tx).await.unwrap();
+
+        assert_eq!(completion.content, "Hello there");
+        assert_eq!(completion.choices, Some(vec!["Hello there".to_string(), "Hi".to_string()]));
+        assert_eq!(completion.token_count, 8);
+        assert_eq!(completion.prompt_tokens, 5);
+        // The whole content arrives as one chunk
+        assert_eq!(rx.try_recv().unwrap(), "Hello there");
+        assert!(rx.try_recv().is_err());
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let sent: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert!(sent.get("stream").is_none());
+    }
+
     #[tokio::test]
     async fn test_chat_completion_api_error() {
         // Start a mock server that returns an error
@@ -722,6 +1607,10 @@ mod tests {
             api_key: Some("test-key".to_string()),
             supported_models: vec!["gpt-3.5-turbo".to_string()],
             rate_limit: Some(100),
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: true,
         };
 
         let client = LlmClient::new(backend_config).unwrap();
@@ -735,7 +1624,23 @@ mod tests {
         ).await;
 
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("LLM API error"));
+        let error = result.unwrap_err();
+        assert!(error.to_string().contains("LLM API error"));
+        assert_eq!(error_code(&error), LlmErrorCode::BackendError);
+    }
+
+    #[test]
+    fn test_error_codes_from_backend_status() {
+        let api_error = |status: u16, message: &str| anyhow::Error::from(ApiError {
+            status: reqwest::StatusCode::from_u16(status).unwrap(),
+            message: message.to_string(),
+        });
+        assert_eq!(error_code(&api_error(429, "Rate limit reached")), LlmErrorCode::RateLimited);
+        assert_eq!(error_code(&api_error(503, "The engine is currently overloaded")), LlmErrorCode::Busy);
+        let filtered = r#"{"error":{"code":"content_filter","message":"The response was filtered"}}"#;
+        assert_eq!(error_code(&api_error(400, filtered)), LlmErrorCode::ContentRejected);
+        assert_eq!(error_code(&api_error(400, "Bad Request")), LlmErrorCode::BackendError);
+        assert_eq!(error_code(&anyhow!("connection reset")), LlmErrorCode::BackendError);
     }
 
     #[tokio::test]
@@ -765,6 +1670,10 @@ mod tests {
             api_key: Some("test-key".to_string()),
             supported_models: vec!["gpt-3.5-turbo".to_string()],
             rate_limit: Some(100),
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: true,
         };
 
         let client = LlmClient::new(backend_config).unwrap();
@@ -789,10 +1698,19 @@ mod tests {
                 ChatMessage {
                     role: "user".to_string(),
                     content: "Hello".to_string(),
+                    tool_calls: None,
+                    tool_call_id: None,
+                    images: None,
                 },
             ],
             temperature: Some(0.5),
             max_tokens: Some(100),
+            stream: None,
+            stream_options: None,
+            tools: None,
+            tool_choice: None,
+            response_format: None,
+            sampling: SamplingParams::default(),
         };
 
         let serialized = serde_json::to_string(&request).unwrap();
@@ -804,6 +1722,39 @@ mod tests {
         assert_eq!(request.max_tokens, deserialized.max_tokens);
     }
 
+    #[test]
+    fn test_images_serialize_as_content_parts() {
+        let image = lloom_core::protocol::ImageInput::inline("image/png", b"image bytes");
+        let request = ChatCompletionRequest {
+            model: "llava".to_string(),
+            messages: vec![
+                ChatMessage::system("Describe images"),
+                ChatMessage::user_with_images("What is this?", vec![image.clone()]),
+            ],
+            temperature: None,
+            max_tokens: None,
+            stream: None,
+            stream_options: None,
+            tools: None,
+            tool_choice: None,
+            response_format: None,
+            sampling: SamplingParams::default(),
+        };
+
+        let value = serde_json::to_value(&request).unwrap();
+        assert_eq!(value["messages"][0]["content"], "Describe images");
+        let message = &value["messages"][1];
+        assert!(message.get("images").is_none());
+        assert_eq!(message["content"][0], serde_json::json!({ "type": "text", "text": "What is this?" }));
+        assert_eq!(message["content"][1]["type"], "image_url");
+        assert_eq!(message["content"][1]["image_url"]["url"], image.data_url().unwrap());
+
+        // Unresolved references cannot be sent to a backend
+        let mut request = request;
+        request.messages[1] = ChatMessage::user_with_images("What is this?", vec![image.reference()]);
+        assert!(serde_json::to_value(&request).is_err());
+    }
+
     #[test]
     fn test_response_deserialization() {
         let json_response = r#"{
@@ -837,6 +1788,10 @@ mod tests {
             api_key: Some("key".to_string()),
             supported_models: vec!["model1".to_string()],
             rate_limit: Some(60),
+            vision_models: vec![],
+            tool_models: vec![],
+            structured_output_models: vec![],
+            streaming: true,
         };
 
         let cloned = config.clone();
@@ -5,31 +5,50 @@
 mod config;
 mod llm_client;
 mod blockchain;
+mod embedding;
+mod images;
+mod inflight;
+mod org;
+mod quote;
+mod sessions;
+mod streaming;
 
 use anyhow::Result;
 use clap::Parser;
 use config::ExecutorConfig;
 use lloom_core::{
     identity::Identity,
-    network::{LloomBehaviour, LloomEvent, helpers},
+    network::{LloomBehaviour, LloomEvent, build_swarm, helpers},
     protocol::{
-        LlmRequest, LlmResponse, ServiceRole, UsageRecord, RequestMessage, ResponseMessage,
-        constants::MAX_MESSAGE_AGE_SECS, ModelAnnouncement, ModelDescriptor, ModelCapabilities,
-        AnnouncementType, ModelPricing
+        LlmErrorCode, LlmRequest, LlmResponse, ServiceRole, UsageRecord, RequestMessage, ResponseMessage,
+        constants::{MAX_MESSAGE_AGE_SECS, PROTOCOL_VERSION}, ModelAnnouncement, ModelDescriptor, ModelCapabilities,
+        AnnouncementType, ModelPricing, AcknowledgmentResponse, SignedCancelRequest, request_span,
+        EmbeddingRequest, EmbeddingResponse, SignedEmbeddingRequest, Quote, SignedQuoteRequest,
     },
-    signing::{SignableMessage},
+    signing::{ReplayGuard, SIGNATURE_VERSION, SignableMessage},
+    streaming::stream_protocol,
 };
+#[cfg(unix)]
+use lloom_core::signer::RemoteSigner;
 use futures::StreamExt;
 use libp2p::{
     kad::{self, Record},
     request_response::{self, ResponseChannel},
     swarm::{SwarmEvent, Swarm},
-    Multiaddr, SwarmBuilder,
+    Multiaddr,
 };
-use llm_client::LlmClient;
+use llm_client::{Completion, LlmClient};
 use blockchain::BlockchainClient;
+use streaming::StreamServer;
+use inflight::{CompletionOutcome, InFlightRequest, InFlightRequests};
+use embedding::EmbeddingCompletion;
+use images::ImageCache;
+use quote::QuoteBook;
+use sessions::SessionBudgets;
+use org::OrgMembers;
 use std::{
     collections::HashMap,
+    sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tokio::{
@@ -46,6 +65,23 @@ struct Args {
     #[arg(long, env = "LLOOM_PRIVATE_KEY")]
     private_key: Option<String>,
     
+    /// Encrypted (Web3 Secret Storage) keystore file for identity, used instead
+    /// of the private key. The password is read from LLOOM_KEYSTORE_PASSWORD or
+    /// prompted for.
+    #[arg(long, env = "LLOOM_KEYSTORE")]
+    keystore: Option<String>,
+    
+    /// Unix socket of a signer daemon that signs for the EVM address instead of
+    /// the local key. The network identity stays local.
+    #[arg(long, env = "LLOOM_SIGNER_SOCKET")]
+    signer_socket: Option<String>,
+    
+    /// Envelope version to sign messages with: 2, or 1 while peers that
+    /// predate version 2 still verify messages. Version 1 cannot be signed
+    /// through a signer daemon.
+    #[arg(long, env = "LLOOM_SIGNATURE_VERSION", default_value_t = SIGNATURE_VERSION)]
+    signature_version: u8,
+    
     /// Bootstrap nodes to connect to
     #[arg(long, value_delimiter = ',')]
     bootstrap_nodes: Vec<String>,
@@ -102,17 +138,25 @@ async fn create_model_descriptors(
         for model_id in &backend_config.supported_models {
             let mut capabilities = ModelCapabilities {
                 max_context_length: 4096, // Default context length
-                features: vec!["chat".to_string(), "completion".to_string()],
+                features: vec!["chat".to_string(), "completion".to_string(), "messages".to_string(), "streaming".to_string()],
                 architecture: None,
                 model_size: None,
                 performance: None,
                 metadata: std::collections::HashMap::new(),
             };
+            if backend_config.vision_models.contains(model_id) {
+                capabilities.features.push("vision".to_string());
+            }
+            if backend_config.tool_models.contains(model_id) {
+                capabilities.features.push("tools".to_string());
+            }
+            if backend_config.structured_output_models.contains(model_id) {
+                capabilities.features.push("structured_output".to_string());
+            }
             
             // Try to get model-specific information if available
             if client.is_lmstudio_backend() {
                 // For LMStudio, we could potentially get more detailed model info
-                capabilities.features.push("streaming".to_string());
                 capabilities.metadata.insert(
                     "backend_type".to_string(),
                     serde_json::Value::String("lmstudio".to_string())
@@ -125,10 +169,11 @@ async fn create_model_descriptors(
                 capabilities,
                 is_available: true,
                 pricing: Some(ModelPricing {
-                    input_token_price: "500000000000000".to_string(), // 0.0005 ETH per token
-                    output_token_price: "1000000000000000".to_string(), // 0.001 ETH per token
+                    input_token_price: config.billing.prices(model_id).input_token_price.clone(),
+                    output_token_price: config.billing.prices(model_id).output_token_price.clone(),
                     minimum_fee: None,
                 }),
+                org: config.org.private_org(),
             };
             
             descriptors.push(descriptor);
@@ -167,11 +212,11 @@ async fn announce_models_to_network(
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_nanos() as u64, // Use nanoseconds as nonce for uniqueness
-        protocol_version: 1,
+        protocol_version: PROTOCOL_VERSION,
     };
     
     // Sign the announcement
-    let signed_announcement = announcement.sign_blocking(&identity.wallet)?;
+    let signed_announcement = announcement.sign_blocking(identity)?;
     
     // Publish via gossipsub to model announcement topic
     let topic = libp2p::gossipsub::IdentTopic::new("lloom/model-announcements");
@@ -209,6 +254,33 @@ struct ExecutorState {
     pending_requests: HashMap<request_response::OutboundRequestId, ResponseChannel<ResponseMessage>>,
     blockchain_client: Option<BlockchainClient>,
     enable_signing: bool,
+    /// Request-response LLM calls whose backend is still generating
+    in_flight: InFlightRequests<ResponseChannel<ResponseMessage>>,
+    /// Images received inline, for requests that reference them
+    images: ImageCache,
+    /// Quotes given out and not yet accepted
+    quotes: QuoteBook,
+    /// Signed requests already accepted
+    replay_guard: ReplayGuard,
+    /// What session keys have been billed
+    sessions: SessionBudgets,
+    /// Members of the org this executor serves
+    org: OrgMembers,
+    /// Backend tasks report their outcome here
+    completion_tx: mpsc::UnboundedSender<(String, CompletionOutcome)>,
+    /// Embedding backend tasks report their outcome here
+    embedding_tx: mpsc::UnboundedSender<EmbeddingCompletion<ResponseChannel<ResponseMessage>>>,
+}
+
+/// Sign for `identity` through the signer daemon listening at `path`
+#[cfg(unix)]
+fn with_signer_daemon(identity: Identity, path: &str) -> Result<Identity> {
+    Ok(identity.with_signer(Arc::new(RemoteSigner::connect(path, None)?)))
+}
+
+#[cfg(not(unix))]
+fn with_signer_daemon(_identity: Identity, _path: &str) -> Result<Identity> {
+    anyhow::bail!("Signer daemons are only supported on Unix")
 }
 
 #[tokio::main]
@@ -240,16 +312,28 @@ async fn main() -> Result<()> {
     info!("RPC URL: {}", args.rpc_url);
     
     // Load or generate identity
-    let identity = match args.private_key {
-        Some(key) => {
+    let identity = match (&args.keystore, &args.private_key) {
+        (Some(path), _) => {
+            info!("Loading identity from keystore {}", path);
+            Identity::from_keystore_prompt(path)?
+        }
+        (None, Some(key)) => {
             info!("Loading identity from private key");
-            Identity::from_str(&key)?
+            Identity::from_str(key)?
         }
-        None => {
+        (None, None) => {
             info!("Generating ephemeral identity");
             Identity::generate()
         }
     };
+    let identity = match &args.signer_socket {
+        Some(path) => {
+            info!("Signing through signer daemon at {}", path);
+            with_signer_daemon(identity, path)?
+        }
+        None => identity,
+    };
+    let identity = identity.with_signature_version(args.signature_version)?;
     
     info!("Node identity: PeerId={}", identity.peer_id);
     info!("EVM address: {}", identity.evm_address);
@@ -335,7 +419,7 @@ async fn main() -> Result<()> {
     // If --test flag is provided, perform model health checks
     if args.test {
         info!("Running model health checks...");
-        match test_model_health(&llm_clients, &mut config, args.debug).await {
+        match test_model_health(&llm_clients, &config, args.debug).await {
             Ok(healthy_backends) => {
                 if healthy_backends.is_empty() {
                     return Err(anyhow::anyhow!(
@@ -362,23 +446,13 @@ async fn main() -> Result<()> {
         }
     }
     
-    // Create network behaviour
-    let behaviour = LloomBehaviour::new(&identity)?;
-    
     // Build swarm
-    let mut swarm = SwarmBuilder::with_existing_identity(identity.p2p_keypair.clone())
-        .with_tokio()
-        .with_tcp(
-            libp2p::tcp::Config::default(),
-            libp2p::noise::Config::new,
-            libp2p::yamux::Config::default,
-        )?
-        .with_behaviour(|_| behaviour)?
-        .build();
-    
-    // Listen on specified port
-    let listen_addr: Multiaddr = format!("/ip4/0.0.0.0/tcp/{}", config.network.port).parse()?;
-    swarm.listen_on(listen_addr)?;
+    let mut swarm = build_swarm(&identity, config.network.transport)?;
+    
+    // Listen on specified port, over each configured transport
+    for listen_addr in config.network.transport.listen_addrs(config.network.port) {
+        swarm.listen_on(listen_addr)?;
+    }
     
     // Add external address if configured
     if let Some(external_addr) = &config.network.external_address {
@@ -481,6 +555,36 @@ async fn main() -> Result<()> {
     info!("Executor node started successfully");
     info!("Supported models: {:?}", config.get_all_supported_models());
     
+    // Serve streamed requests on their own tasks; usage comes back over a channel
+    let image_cache = ImageCache::default();
+    let quote_book = QuoteBook::new(config.billing.max_quotes);
+    let session_budgets = SessionBudgets::new();
+    let org_members = OrgMembers::new(config.org.clone());
+    let replay_guard = ReplayGuard::new(MAX_MESSAGE_AGE_SECS, config.replay.capacity)
+        .require_version(config.replay.min_signature_version)?;
+    let replay_guard = match &config.replay.cache_file {
+        Some(path) => replay_guard.with_persistence(path)
+            .map_err(|e| anyhow::anyhow!("Failed to load replay cache {}: {}", path, e))?,
+        None => replay_guard,
+    };
+    let stream_server = Arc::new(StreamServer::new(
+        identity.clone(),
+        config.clone(),
+        llm_clients.clone(),
+        image_cache.clone(),
+        quote_book.clone(),
+        replay_guard.clone(),
+        session_budgets.clone(),
+        org_members.clone(),
+        args.enable_signing,
+    ));
+    let mut incoming_streams = swarm.behaviour().stream.new_control()
+        .accept(stream_protocol())
+        .map_err(|e| anyhow::anyhow!("Failed to accept LLM streams: {}", e))?;
+    let (stream_usage_tx, mut stream_usage_rx) = mpsc::unbounded_channel::<UsageRecord>();
+    let (completion_tx, mut completion_rx) = mpsc::unbounded_channel();
+    let (embedding_tx, mut embedding_rx) = mpsc::unbounded_channel();
+    
     // Initialize executor state
     let mut executor_state = ExecutorState {
         identity,
@@ -490,6 +594,14 @@ async fn main() -> Result<()> {
         pending_requests: HashMap::new(),
         blockchain_client,
         enable_signing: args.enable_signing,
+        in_flight: InFlightRequests::new(),
+        images: image_cache,
+        quotes: quote_book,
+        replay_guard,
+        sessions: session_budgets,
+        org: org_members,
+        completion_tx,
+        embedding_tx,
     };
     
     // Set up timers
@@ -516,6 +628,25 @@ async fn main() -> Result<()> {
             event = swarm.select_next_some() => {
                 handle_swarm_event(&mut swarm, event, &mut executor_state).await;
             }
+            Some((peer, stream)) = incoming_streams.next() => {
+                debug!("Accepted LLM stream from {}", peer);
+                let server = Arc::clone(&stream_server);
+                let usage_tx = stream_usage_tx.clone();
+                tokio::spawn(async move {
+                    if let Some(usage_record) = server.serve(peer, stream).await {
+                        let _ = usage_tx.send(usage_record);
+                    }
+                });
+            }
+            Some(usage_record) = stream_usage_rx.recv() => {
+                executor_state.usage_records.push(usage_record);
+            }
+            Some((request_id, outcome)) = completion_rx.recv() => {
+                finish_llm_request(&mut swarm, &mut executor_state, request_id, outcome);
+            }
+            Some(completion) = embedding_rx.recv() => {
+                finish_embedding_request(&mut swarm, &mut executor_state, completion);
+            }
             _ = announce_interval.tick() => {
                 announce_executor(&mut swarm, &executor_state).await;
             }
@@ -555,6 +686,9 @@ async fn main() -> Result<()> {
             }
             _ = batch_interval.tick() => {
                 submit_usage_batch(&mut executor_state).await;
+                if let Err(e) = executor_state.replay_guard.save() {
+                    warn!("Failed to save replay cache: {}", e);
+                }
             }
             _ = shutdown_rx.recv() => {
                 info!("Received shutdown signal");
@@ -578,12 +712,18 @@ async fn main() -> Result<()> {
     }
     
     info!("Shutting down executor node...");
+    if !executor_state.in_flight.is_empty() {
+        warn!("Abandoning {} in-flight LLM requests", executor_state.in_flight.len());
+    }
     
     // Submit any remaining usage records
     if !executor_state.usage_records.is_empty() {
         info!("Submitting remaining {} usage records", executor_state.usage_records.len());
         submit_usage_batch(&mut executor_state).await;
     }
+    if let Err(e) = executor_state.replay_guard.save() {
+        warn!("Failed to save replay cache: {}", e);
+    }
     
     Ok(())
 }
@@ -682,7 +822,7 @@ async fn handle_request_message(
             
             let signer_address = if state.enable_signing {
                 // Verify the signature with time window for replay protection
-                match signed_request.verify_with_time_window(MAX_MESSAGE_AGE_SECS) {
+                match state.replay_guard.verify(&signed_request) {
                     Ok(signer_address) => {
                         info!("✓ Request signature verified from signer: {}", signer_address);
                         Some(signer_address)
@@ -692,34 +832,15 @@ async fn handle_request_message(
                         warn!("Processing request anyway but logging security issue");
                         
                         // Send error response for invalid signature
-                        let error_response = LlmResponse {
-                            content: String::new(),
-                            inbound_tokens: 0,
-                            outbound_tokens: 0,
-                            total_cost: "0".to_string(),
-                            model_used: signed_request.payload.model.clone(),
-                            error: Some(format!("Signature verification failed: {}", e)),
-                        };
-                        
-                        let response_message = if state.enable_signing {
-                            // Sign the error response
-                            match error_response.sign_blocking(&state.identity.wallet) {
-                                Ok(signed_response) => {
-                                    info!("✓ Signed error response with timestamp: {}", signed_response.timestamp);
-                                    ResponseMessage::SignedLlmResponse(signed_response)
-                                }
-                                Err(sign_err) => {
-                                    error!("Failed to sign error response: {}, sending unsigned", sign_err);
-                                    ResponseMessage::LlmResponse(error_response)
-                                }
-                            }
-                        } else {
-                            ResponseMessage::LlmResponse(error_response)
-                        };
-                        
-                        if let Err(e) = swarm.behaviour_mut().request_response.send_response(channel, response_message) {
-                            error!("Failed to send error response: {:?}", e);
-                        }
+                        send_error_response(
+                            swarm,
+                            channel,
+                            state,
+                            &signed_request.payload.model,
+                            signed_request.payload.request_id.clone(),
+                            LlmErrorCode::InvalidSignature,
+                            format!("Signature verification failed: {}", e),
+                        );
                         return;
                     }
                 }
@@ -730,6 +851,15 @@ async fn handle_request_message(
             
             handle_llm_request(swarm, signed_request.payload, channel, client_peer, state, signer_address).await;
         }
+        RequestMessage::CancelRequest(signed_cancel) => {
+            handle_cancel_request(swarm, signed_cancel, channel, client_peer, state);
+        }
+        RequestMessage::EmbeddingRequest(signed_request) => {
+            handle_embedding_request(swarm, signed_request, channel, client_peer, state);
+        }
+        RequestMessage::QuoteRequest(signed_request) => {
+            handle_quote_request(swarm, signed_request, channel, client_peer, state);
+        }
         RequestMessage::ModelAnnouncement(signed_announcement) => {
             // Log model announcements received from other executors
             debug!("Received model announcement from {}: {} models",
@@ -747,22 +877,105 @@ async fn handle_request_message(
                    client_peer, signed_update.payload.update_type);
             // For now, just log - could be used for dynamic model discovery
         }
+        RequestMessage::Unknown => {
+            warn!("Received a request from {} this executor does not know", client_peer);
+            send_error_response(
+                swarm,
+                channel,
+                state,
+                "",
+                None,
+                LlmErrorCode::UnsupportedVersion,
+                "Request not supported by this executor".to_string(),
+            );
+        }
+    }
+}
+
+/// Send an (optionally signed) error response for an LLM request
+fn send_error_response(
+    swarm: &mut Swarm<LloomBehaviour>,
+    channel: ResponseChannel<ResponseMessage>,
+    state: &ExecutorState,
+    model: &str,
+    request_id: Option<String>,
+    code: LlmErrorCode,
+    error: String,
+) {
+    let error_response = LlmResponse {
+        content: String::new(),
+        inbound_tokens: 0,
+        outbound_tokens: 0,
+        total_cost: "0".to_string(),
+        model_used: model.to_string(),
+        error: Some(error),
+        request_id,
+        error_code: Some(code),
+        tool_calls: None,
+        choices: None,
+    };
+    
+    let response_message = llm_response_message(state, error_response);
+    if let Err(e) = swarm.behaviour_mut().request_response.send_response(channel, response_message) {
+        error!("Failed to send error response: {:?}", e);
+    }
+}
+
+/// Wrap an LLM response for sending, signing it if signing is enabled
+fn llm_response_message(state: &ExecutorState, response: LlmResponse) -> ResponseMessage {
+    if !state.enable_signing {
+        return ResponseMessage::LlmResponse(response);
+    }
+    match response.sign_blocking(&state.identity) {
+        Ok(signed_response) => {
+            info!("✓ Signed response with timestamp: {}", signed_response.timestamp);
+            ResponseMessage::SignedLlmResponse(signed_response)
+        }
+        Err(sign_err) => {
+            error!("Failed to sign response: {}, sending unsigned", sign_err);
+            ResponseMessage::LlmResponse(response)
+        }
     }
 }
 
 /// Handle an incoming LLM request
+///
+/// The backend call runs on its own task (see [`inflight`]) and is answered from
+/// [`finish_llm_request`] once it completes, so cancellations can be handled meanwhile.
 async fn handle_llm_request(
-    _swarm: &mut Swarm<LloomBehaviour>,
-    request: LlmRequest,
+    swarm: &mut Swarm<LloomBehaviour>,
+    mut request: LlmRequest,
     channel: ResponseChannel<ResponseMessage>,
-    _client_peer: libp2p::PeerId,
+    client_peer: libp2p::PeerId,
     state: &mut ExecutorState,
     verified_signer: Option<alloy::primitives::Address>,
 ) {
+    let request_id = request.id();
+    let _span = request_span(&request_id).entered();
     let model = request.model.clone();
     info!("DEBUG: 🎯 Received LLM request for model: '{}'", model);
     info!("DEBUG: Available models: {:?}", state.config.get_all_supported_models());
     
+    if !request.is_supported_version() {
+        warn!("Rejecting LLM request with unsupported version {}", request.version);
+        send_error_response(swarm, channel, state, &model, request.request_id.clone(), LlmErrorCode::UnsupportedVersion, format!("Unsupported request version {}", request.version));
+        return;
+    }
+    
+    if request.deadline > 0 && inflight::time_until(request.deadline).is_zero() {
+        warn!("Rejecting LLM request past its deadline {}", request.deadline);
+        send_error_response(swarm, channel, state, &model, request.request_id.clone(), LlmErrorCode::DeadlineExpired, "Request deadline has passed".to_string());
+        return;
+    }
+    
+    // Ids are chosen by clients, so a duplicate must not replace the running
+    // request; it is refused before it can use up a quote or session budget
+    if state.in_flight.contains(&request_id) {
+        warn!("Rejecting LLM request {} that is already in flight", request_id);
+        send_error_response(swarm, channel, state, &model, request.request_id.clone(), LlmErrorCode::InvalidRequest, format!("Request {} is already in flight", request_id));
+        return;
+    }
+    
     // Find the appropriate backend for this model
     let backend_name = match state.config.find_backend_for_model(&model) {
         Some(backend) => {
@@ -772,174 +985,475 @@ async fn handle_llm_request(
         None => {
             error!("DEBUG: ❌ No backend found for model '{}'. Available models: {:?}",
                    model, state.config.get_all_supported_models());
-            let error_response = LlmResponse {
-                content: String::new(),
-                inbound_tokens: 0,
-                outbound_tokens: 0,
-                total_cost: "0".to_string(),
-                model_used: model.clone(),
-                error: Some(format!("Model {} not supported", model)),
-            };
-            
-            let response_message = if state.enable_signing {
-                match error_response.sign_blocking(&state.identity.wallet) {
-                    Ok(signed_response) => {
-                        info!("✓ Signed error response with timestamp: {}", signed_response.timestamp);
-                        ResponseMessage::SignedLlmResponse(signed_response)
-                    }
-                    Err(sign_err) => {
-                        error!("Failed to sign error response: {}, sending unsigned", sign_err);
-                        ResponseMessage::LlmResponse(error_response)
-                    }
-                }
-            } else {
-                ResponseMessage::LlmResponse(error_response)
-            };
-            
-            if let Err(e) = _swarm.behaviour_mut().request_response.send_response(channel, response_message) {
-                error!("Failed to send error response: {:?}", e);
-            }
+            send_error_response(swarm, channel, state, &model, request.request_id.clone(), LlmErrorCode::UnsupportedModel, format!("Model {} not supported", model));
             return;
         }
     };
     
     // Get the LLM client
     let llm_client = match state.llm_clients.get(&backend_name) {
-        Some(client) => client,
+        Some(client) => client.clone(),
         None => {
-            let error_response = LlmResponse {
-                content: String::new(),
-                inbound_tokens: 0,
-                outbound_tokens: 0,
-                total_cost: "0".to_string(),
-                model_used: model.clone(),
-                error: Some(format!("Backend {} not available", backend_name)),
-            };
-            
-            let response_message = if state.enable_signing {
-                match error_response.sign_blocking(&state.identity.wallet) {
-                    Ok(signed_response) => {
-                        info!("✓ Signed error response with timestamp: {}", signed_response.timestamp);
-                        ResponseMessage::SignedLlmResponse(signed_response)
-                    }
-                    Err(sign_err) => {
-                        error!("Failed to sign error response: {}, sending unsigned", sign_err);
-                        ResponseMessage::LlmResponse(error_response)
-                    }
-                }
-            } else {
-                ResponseMessage::LlmResponse(error_response)
-            };
-            
-            if let Err(e) = _swarm.behaviour_mut().request_response.send_response(channel, response_message) {
-                error!("Failed to send error response: {:?}", e);
-            }
+            send_error_response(swarm, channel, state, &model, request.request_id.clone(), LlmErrorCode::BackendUnavailable, format!("Backend {} not available", backend_name));
             return;
         }
     };
     
-    // Execute the LLM request with LMStudio enhancements if available
-    match llm_client.lmstudio_chat_completion(
-        &request.model,
-        &request.prompt,
-        request.system_prompt.as_deref(),
-        request.temperature,
-        request.max_tokens,
-    ).await {
-        Ok((content, token_count, stats, model_info)) => {
-            let mut log_msg = format!("LLM request completed: {} tokens used", token_count);
-            
-            // Log LMStudio-specific performance metrics if available
-            if let Some(stats) = &stats {
-                if let Some(tps) = stats.tokens_per_second {
-                    log_msg.push_str(&format!(", {:.2} tokens/sec", tps));
-                }
-                if let Some(ttft) = stats.time_to_first_token {
-                    log_msg.push_str(&format!(", {:.3}s to first token", ttft));
-                }
-            }
-            
-            if let Some(model_info) = &model_info {
-                if let Some(arch) = &model_info.architecture {
-                    log_msg.push_str(&format!(", architecture: {}", arch));
-                }
-            }
-            
-            info!("{}", log_msg);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let account = match state.sessions.admit(&request, verified_signer, now) {
+        Ok(account) => account,
+        Err(e) => {
+            warn!("Rejecting LLM request with an invalid session: {}", e);
+            send_error_response(swarm, channel, state, &model, request.request_id.clone(), LlmErrorCode::Unauthorized, e);
+            return;
+        }
+    };
+    if let Err(e) = state.org.admit(&request, account, now) {
+        warn!("Rejecting LLM request from outside the org: {}", e);
+        send_error_response(swarm, channel, state, &model, request.request_id.clone(), LlmErrorCode::Unauthorized, e);
+        return;
+    }
+    if let Err((code, error)) = quote::check_price(&request, state.config.billing.prices(&request.model)) {
+        warn!("Rejecting LLM request: {}", error);
+        send_error_response(swarm, channel, state, &model, request.request_id.clone(), code, error);
+        return;
+    }
+    // The quote is checked against the prompt as quoted, before images are
+    // resolved, but only used up once every other check has passed
+    let quote = match state.quotes.check(&request, verified_signer, now) {
+        Ok(quote) => quote,
+        Err(e) => {
+            warn!("Rejecting LLM request with an invalid quote: {}", e);
+            send_error_response(swarm, channel, state, &model, request.request_id.clone(), LlmErrorCode::InvalidQuote, e.to_string());
+            return;
+        }
+    };
+    
+    if let Err((code, error)) = inflight::check_features(&state.config, &request) {
+        warn!("Rejecting LLM request: {}", error);
+        send_error_response(swarm, channel, state, &model, request.request_id.clone(), code, error);
+        return;
+    }
+    if let Err((code, error)) = images::resolve_request_images(&state.config, &state.images, &mut request) {
+        warn!("Rejecting LLM request with images: {}", error);
+        send_error_response(swarm, channel, state, &model, request.request_id.clone(), code, error);
+        return;
+    }
+    if let Some(Err(e)) = quote.as_ref().map(|quote| state.quotes.redeem(quote)) {
+        warn!("Rejecting LLM request with an invalid quote: {}", e);
+        send_error_response(swarm, channel, state, &model, request.request_id.clone(), LlmErrorCode::InvalidQuote, e.to_string());
+        return;
+    }
+    
+    debug!("Starting backend call for request {}", request_id);
+    let (produced, task) = inflight::spawn_completion(
+        llm_client,
+        request.clone(),
+        request_id.clone(),
+        state.config.structured_output.max_attempts,
+        state.completion_tx.clone(),
+    );
+    let entry = InFlightRequest::new(channel, client_peer, verified_signer, &request, quote, produced, task)
+        .billed_to(account);
+    if let Err(entry) = state.in_flight.insert(request_id.clone(), entry) {
+        entry.abort();
+        let InFlightRequest { reply, .. } = *entry;
+        send_error_response(swarm, reply, state, &model, request.request_id.clone(), LlmErrorCode::InvalidRequest, format!("Request {} is already in flight", request_id));
+        return;
+    }
+    debug!("{} LLM requests in flight", state.in_flight.len());
+}
+
+/// Answer an in-flight LLM request once its backend call has ended
+fn finish_llm_request(
+    swarm: &mut Swarm<LloomBehaviour>,
+    state: &mut ExecutorState,
+    request_id: String,
+    outcome: CompletionOutcome,
+) {
+    let _span = request_span(&request_id).entered();
+    // Cancelled requests have already been answered
+    let Some(entry) = state.in_flight.complete(&request_id) else {
+        debug!("Ignoring completion of request {} that is no longer in flight", request_id);
+        return;
+    };
+    
+    match outcome {
+        CompletionOutcome::Finished(Ok(completion)) => {
+            let completion_tokens = u64::from(completion.completion_tokens());
+            let Completion { content, choices, tool_calls, token_count, prompt_tokens } = completion;
+            info!("LLM request {} completed: {} tokens used", request_id, token_count);
             
+            let (inbound_tokens, outbound_tokens, total_cost) = match &entry.quote {
+                Some(quote) => quote::billed(quote, completion_tokens),
+                None => entry.prices.billed(u64::from(prompt_tokens), completion_tokens),
+            };
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+            let total_cost = state.org.bill(entry.account, total_cost, now);
             let response = LlmResponse {
                 content,
-                inbound_tokens: (token_count / 2) as u64,  // Rough estimate - could be improved
-                outbound_tokens: (token_count / 2) as u64,
-                total_cost: format!("{}", (token_count as u64) * 1000000000000000u64), // 0.001 ETH per token
-                model_used: model.clone(),
+                inbound_tokens,
+                outbound_tokens,
+                total_cost,
+                model_used: entry.model.clone(),
                 error: None,
+                request_id: entry.request_id.clone(),
+                error_code: None,
+                tool_calls,
+                choices,
             };
             
-            // Sign response if signing is enabled
-            let response_message = if state.enable_signing {
-                match response.sign_blocking(&state.identity.wallet) {
-                    Ok(signed_response) => {
-                        info!("✓ Signed response with timestamp: {}", signed_response.timestamp);
-                        ResponseMessage::SignedLlmResponse(signed_response)
-                    }
-                    Err(sign_err) => {
-                        error!("Failed to sign response: {}, sending unsigned", sign_err);
-                        ResponseMessage::LlmResponse(response)
-                    }
-                }
-            } else {
-                ResponseMessage::LlmResponse(response)
-            };
-            
-            // Send response
-            if let Err(e) = _swarm.behaviour_mut().request_response.send_response(channel, response_message) {
+            let response_cost = response.total_cost.clone();
+            let response_message = llm_response_message(state, response);
+            if let Err(e) = swarm.behaviour_mut().request_response.send_response(entry.reply, response_message) {
                 error!("Failed to send response: {:?}", e);
             } else {
                 // Record usage for blockchain submission
-                // Use verified signer address if available, otherwise use placeholder
-                let client_address = verified_signer.unwrap_or(state.identity.evm_address);
+                // Use the billed address if the request was signed, otherwise use placeholder
+                state.sessions.charge(entry.signer, entry.account, &response_cost);
+                let client_address = entry.account.unwrap_or(state.identity.evm_address);
                 let usage_record = UsageRecord {
                     client_address,
-                    model: model.clone(),
+                    model: entry.model,
                     token_count,
                     timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+                    cancelled: false,
+                    request_id: Some(request_id.clone()),
                 };
                 state.usage_records.push(usage_record);
             }
         }
-        Err(e) => {
-            error!("LLM request failed: {}", e);
-            
-            let error_response = LlmResponse {
-                content: String::new(),
-                inbound_tokens: 0,
-                outbound_tokens: 0,
-                total_cost: "0".to_string(),
-                model_used: model,
-                error: Some(e.to_string()),
+        CompletionOutcome::Finished(Err(e)) => {
+            error!("LLM request {} failed: {}", request_id, e);
+            send_error_response(swarm, entry.reply, state, &entry.model, entry.request_id.clone(), llm_client::error_code(&e), e.to_string());
+        }
+        CompletionOutcome::InvalidOutput(reason) => {
+            warn!("LLM request {} produced invalid output: {}", request_id, reason);
+            send_error_response(swarm, entry.reply, state, &entry.model, entry.request_id.clone(), LlmErrorCode::InvalidOutput, format!("Output does not match the response format: {}", reason));
+        }
+        CompletionOutcome::DeadlineExceeded => {
+            warn!("LLM request {} ran past its deadline, stopping it", request_id);
+            entry.abort();
+            let response = stop_llm_request(state, &request_id, &entry, LlmErrorCode::DeadlineExpired, "Request deadline passed");
+            let response_message = llm_response_message(state, response);
+            if let Err(e) = swarm.behaviour_mut().request_response.send_response(entry.reply, response_message) {
+                error!("Failed to send deadline response: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Build the final response of a request stopped before completion.
+///
+/// Tokens produced so far are billed, and recorded as cancelled usage, if the
+/// billing policy allows it.
+fn stop_llm_request(
+    state: &mut ExecutorState,
+    request_id: &str,
+    entry: &InFlightRequest<ResponseChannel<ResponseMessage>>,
+    code: LlmErrorCode,
+    reason: &str,
+) -> LlmResponse {
+    let (inbound_tokens, outbound_tokens, total_cost) = match (&entry.quote, state.config.billing.bill_cancelled) {
+        (_, false) => (0, 0, "0".to_string()),
+        (Some(quote), true) => quote::billed(quote, entry.tokens_so_far().1),
+        (None, true) => {
+            let (inbound_tokens, outbound_tokens) = entry.tokens_so_far();
+            entry.prices.billed(inbound_tokens, outbound_tokens)
+        }
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let total_cost = state.org.bill(entry.account, total_cost, now);
+    let token_count = inbound_tokens + outbound_tokens;
+    if token_count > 0 {
+        state.sessions.charge(entry.signer, entry.account, &total_cost);
+        let client_address = entry.account.unwrap_or(state.identity.evm_address);
+        state.usage_records.push(UsageRecord {
+            client_address,
+            model: entry.model.clone(),
+            token_count: token_count as u32,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            cancelled: true,
+            request_id: Some(request_id.to_string()),
+        });
+    }
+    info!("Stopped LLM request: {} (billed {} tokens)", reason, token_count);
+    
+    LlmResponse {
+        content: entry.produced(),
+        inbound_tokens,
+        outbound_tokens,
+        total_cost,
+        model_used: entry.model.clone(),
+        error: Some(reason.to_string()),
+        request_id: entry.request_id.clone(),
+        error_code: Some(code),
+        tool_calls: None,
+        choices: None,
+    }
+}
+
+/// Handle a signed cancellation of an in-flight LLM request.
+///
+/// The cancelled request is answered with its final (partial) response, and the
+/// canceller receives the same response so it learns what was billed. Refused
+/// cancellations get an unsuccessful acknowledgment.
+fn handle_cancel_request(
+    swarm: &mut Swarm<LloomBehaviour>,
+    signed_cancel: SignedCancelRequest,
+    channel: ResponseChannel<ResponseMessage>,
+    client_peer: libp2p::PeerId,
+    state: &mut ExecutorState,
+) {
+    let cancel = &signed_cancel.payload;
+    let _span = request_span(&cancel.request_id).entered();
+    info!("Received cancellation of request {} from {}", cancel.request_id, client_peer);
+    
+    let cancelled = match state.replay_guard.verify(&signed_cancel) {
+        Err(e) => Err(format!("Signature verification failed: {}", e)),
+        Ok(_) if cancel.executor_address != state.identity.peer_id.to_string() => {
+            Err("Cancellation is addressed to another executor".to_string())
+        }
+        Ok(signer) => state.in_flight.cancel(&cancel.request_id, &client_peer, signer)
+            .map_err(|e| e.to_string()),
+    };
+    
+    let response_message = match cancelled {
+        Ok(entry) => {
+            let reason = match &cancel.reason {
+                Some(reason) => format!("Request cancelled: {}", reason),
+                None => "Request cancelled".to_string(),
             };
-            
-            let response_message = if state.enable_signing {
-                match error_response.sign_blocking(&state.identity.wallet) {
-                    Ok(signed_response) => {
-                        info!("✓ Signed error response with timestamp: {}", signed_response.timestamp);
-                        ResponseMessage::SignedLlmResponse(signed_response)
-                    }
-                    Err(sign_err) => {
-                        error!("Failed to sign error response: {}, sending unsigned", sign_err);
-                        ResponseMessage::LlmResponse(error_response)
-                    }
-                }
-            } else {
-                ResponseMessage::LlmResponse(error_response)
+            let response = stop_llm_request(state, &cancel.request_id, &entry, LlmErrorCode::Cancelled, &reason);
+            let original = llm_response_message(state, response.clone());
+            if let Err(e) = swarm.behaviour_mut().request_response.send_response(entry.reply, original) {
+                debug!("Cancelled request {} could not be answered: {:?}", cancel.request_id, e);
+            }
+            llm_response_message(state, response)
+        }
+        Err(error) => {
+            warn!("Refusing cancellation of request {}: {}", cancel.request_id, error);
+            let acknowledgment = AcknowledgmentResponse {
+                request_id: cancel.request_id.clone(),
+                success: false,
+                message: Some(error),
+                timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
             };
-            
-            if let Err(e) = _swarm.behaviour_mut().request_response.send_response(channel, response_message) {
-                error!("Failed to send error response: {:?}", e);
+            match acknowledgment.sign_blocking(&state.identity) {
+                Ok(signed) => ResponseMessage::AcknowledgmentResponse(signed),
+                Err(e) => {
+                    error!("Failed to sign cancellation acknowledgment: {}", e);
+                    return;
+                }
+            }
+        }
+    };
+    
+    if let Err(e) = swarm.behaviour_mut().request_response.send_response(channel, response_message) {
+        error!("Failed to answer cancellation: {:?}", e);
+    }
+}
+
+/// Handle a signed embedding request.
+///
+/// The backend call runs on its own task and is answered from
+/// [`finish_embedding_request`].
+fn handle_embedding_request(
+    swarm: &mut Swarm<LloomBehaviour>,
+    signed_request: SignedEmbeddingRequest,
+    channel: ResponseChannel<ResponseMessage>,
+    client_peer: libp2p::PeerId,
+    state: &mut ExecutorState,
+) {
+    let request = &signed_request.payload;
+    let _span = request_span(&request.id()).entered();
+    info!("Received embedding request from {}: model={}, {} inputs", client_peer, request.model, request.input.len());
+    
+    let signer = if state.enable_signing {
+        match state.replay_guard.verify(&signed_request) {
+            Ok(signer_address) => Some(signer_address),
+            Err(e) => {
+                error!("✗ Embedding request signature verification failed: {}", e);
+                let error = format!("Signature verification failed: {}", e);
+                send_embedding_error(swarm, channel, state, request, LlmErrorCode::InvalidSignature, error);
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    
+    if request.executor_address != state.identity.peer_id.to_string() {
+        let error = "Request is addressed to another executor".to_string();
+        send_embedding_error(swarm, channel, state, request, LlmErrorCode::InvalidRequest, error);
+        return;
+    }
+    if request.input.is_empty() {
+        send_embedding_error(swarm, channel, state, request, LlmErrorCode::InvalidRequest, "No input to embed".to_string());
+        return;
+    }
+    if let Err((code, error)) = quote::check_embedding_price(request, state.config.billing.prices(&request.model)) {
+        send_embedding_error(swarm, channel, state, request, code, error);
+        return;
+    }
+    if request.deadline > 0 && inflight::time_until(request.deadline).is_zero() {
+        send_embedding_error(swarm, channel, state, request, LlmErrorCode::DeadlineExpired, "Request deadline has passed".to_string());
+        return;
+    }
+    let Some(backend) = state.config.find_backend_for_model(&request.model) else {
+        let error = format!("Model {} not supported", request.model);
+        send_embedding_error(swarm, channel, state, request, LlmErrorCode::UnsupportedModel, error);
+        return;
+    };
+    let Some(llm_client) = state.llm_clients.get(&backend.name).cloned() else {
+        let error = format!("Backend {} not available", backend.name);
+        send_embedding_error(swarm, channel, state, request, LlmErrorCode::BackendUnavailable, error);
+        return;
+    };
+    
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let account = match state.sessions.admit(request, signer, now) {
+        Ok(account) => account,
+        Err(e) => {
+            send_embedding_error(swarm, channel, state, request, LlmErrorCode::Unauthorized, e);
+            return;
+        }
+    };
+    if let Err(e) = state.org.admit(request, account, now) {
+        send_embedding_error(swarm, channel, state, request, LlmErrorCode::Unauthorized, e);
+        return;
+    }
+    
+    embedding::spawn_embedding(llm_client, signed_request.payload, signer, account, channel, state.embedding_tx.clone());
+}
+
+/// Handle a signed request for a price quote.
+///
+/// Quotes are free: the prompt is only counted, not sent to a backend.
+fn handle_quote_request(
+    swarm: &mut Swarm<LloomBehaviour>,
+    signed_request: SignedQuoteRequest,
+    channel: ResponseChannel<ResponseMessage>,
+    client_peer: libp2p::PeerId,
+    state: &mut ExecutorState,
+) {
+    let request = &signed_request.payload;
+    info!("Received quote request from {}: model={}", client_peer, request.model);
+    
+    let signer = if state.enable_signing {
+        match state.replay_guard.verify(&signed_request) {
+            Ok(signer_address) => Some(signer_address),
+            Err(e) => {
+                error!("✗ Quote request signature verification failed: {}", e);
+                let error = format!("Signature verification failed: {}", e);
+                send_quote(swarm, channel, state, quote::quote_error(request, LlmErrorCode::InvalidSignature, error));
+                return;
             }
         }
+    } else {
+        None
+    };
+    
+    if state.config.find_backend_for_model(&request.model).is_none() {
+        let error = format!("Model {} not supported", request.model);
+        send_quote(swarm, channel, state, quote::quote_error(request, LlmErrorCode::UnsupportedModel, error));
+        return;
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let quote = match state.quotes.issue(request, state.config.billing.prices(&request.model), signer, state.config.billing.quote_ttl_secs, now) {
+        Ok(quote) => {
+            info!("Quoted {} prompt tokens, at most {} wei, until {}", quote.prompt_tokens, quote.max_cost, quote.expires_at);
+            quote
+        }
+        Err(e) => quote::quote_error(request, LlmErrorCode::InvalidRequest, e.to_string()),
+    };
+    send_quote(swarm, channel, state, quote);
+}
+
+/// Sign and send a quote
+fn send_quote(
+    swarm: &mut Swarm<LloomBehaviour>,
+    channel: ResponseChannel<ResponseMessage>,
+    state: &ExecutorState,
+    quote: Quote,
+) {
+    // Quotes are always signed, the client holds the executor to them
+    let signed_quote = match quote.sign_blocking(&state.identity) {
+        Ok(signed_quote) => signed_quote,
+        Err(e) => {
+            error!("Failed to sign quote: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = swarm.behaviour_mut().request_response.send_response(channel, ResponseMessage::Quote(signed_quote)) {
+        error!("Failed to send quote: {:?}", e);
+    }
+}
+
+/// Answer an embedding request once its backend call has ended
+fn finish_embedding_request(
+    swarm: &mut Swarm<LloomBehaviour>,
+    state: &mut ExecutorState,
+    completion: EmbeddingCompletion<ResponseChannel<ResponseMessage>>,
+) {
+    let request = completion.request;
+    let _span = request_span(&request.id()).entered();
+    let mut response = embedding::embedding_response(&request, completion.outcome);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    response.total_cost = state.org.bill(completion.account, response.total_cost, now);
+    match &response.error {
+        Some(error) => error!("Embedding request failed: {}", error),
+        None => info!("Embedding request completed: {} input tokens", response.input_tokens),
+    }
+    
+    let input_tokens = response.input_tokens;
+    let total_cost = response.total_cost.clone();
+    if send_embedding_response(swarm, completion.reply, state, response) && input_tokens > 0 {
+        state.sessions.charge(completion.signer, completion.account, &total_cost);
+        state.usage_records.push(UsageRecord {
+            client_address: completion.account.unwrap_or(state.identity.evm_address),
+            model: request.model.clone(),
+            token_count: input_tokens as u32,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            cancelled: false,
+            request_id: Some(request.id()),
+        });
+    }
+}
+
+/// Send an unbilled error response for an embedding request
+fn send_embedding_error(
+    swarm: &mut Swarm<LloomBehaviour>,
+    channel: ResponseChannel<ResponseMessage>,
+    state: &ExecutorState,
+    request: &EmbeddingRequest,
+    code: LlmErrorCode,
+    error: String,
+) {
+    warn!("Rejecting embedding request: {}", error);
+    send_embedding_response(swarm, channel, state, embedding::embedding_error(request, code, error));
+}
+
+/// Sign and send an embedding response, returning whether it was delivered
+fn send_embedding_response(
+    swarm: &mut Swarm<LloomBehaviour>,
+    channel: ResponseChannel<ResponseMessage>,
+    state: &ExecutorState,
+    response: EmbeddingResponse,
+) -> bool {
+    // Embedding responses are always signed, they are a newer message than unsigned responses
+    let signed_response = match response.sign_blocking(&state.identity) {
+        Ok(signed_response) => signed_response,
+        Err(e) => {
+            error!("Failed to sign embedding response: {}", e);
+            return false;
+        }
+    };
+    if let Err(e) = swarm.behaviour_mut().request_response.send_response(channel, ResponseMessage::EmbeddingResponse(signed_response)) {
+        error!("Failed to send embedding response: {:?}", e);
+        return false;
     }
+    true
 }
 
 /// Announce executor availability
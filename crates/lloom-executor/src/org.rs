@@ -0,0 +1,171 @@
+//! Organization membership.
+//!
+//! An executor configured with an org key checks the membership credentials
+//! requests carry (see [`lloom_core::org`]). It serves only members when
+//! configured as members-only, and bills members at the configured discount. A
+//! membership is remembered until its credential expires, so members need not
+//! attach the credential to every request.
+
+use crate::config::OrgConfig;
+use alloy::primitives::{Address, U256};
+use lloom_core::{org::verify_membership, protocol::BilledRequest};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+/// Members of the executor's org, with the expiry of their credentials
+#[derive(Clone, Default)]
+pub struct OrgMembers {
+    config: OrgConfig,
+    members: Arc<Mutex<HashMap<Address, u64>>>,
+}
+
+impl OrgMembers {
+    /// Track the members of the org in `config`
+    pub fn new(config: OrgConfig) -> Self {
+        Self {
+            config,
+            members: Arc::default(),
+        }
+    }
+
+    /// Check the credential of `request`, billed to `account`.
+    ///
+    /// Members-only executors refuse requests from anyone but members.
+    pub fn admit<R: BilledRequest>(&self, request: &R, account: Option<Address>, now: u64) -> Result<(), String> {
+        let Some(org) = self.config.address else {
+            return Ok(());
+        };
+        if let (Some(credential), Some(account)) = (request.credential(), account) {
+            match verify_membership(credential, org, account, now) {
+                Ok(()) => {
+                    self.members.lock().unwrap().insert(account, credential.payload.expires_at);
+                }
+                Err(e) if self.config.members_only => return Err(e.to_string()),
+                Err(e) => tracing::debug!("Ignoring invalid credential from {}: {}", account, e),
+            }
+        }
+        if self.config.members_only && !self.is_member(account, now) {
+            return Err(format!("Only members of {} are served", org));
+        }
+        Ok(())
+    }
+
+    /// What `account` is billed for `cost` wei, after any member discount
+    pub fn bill(&self, account: Option<Address>, cost: String, now: u64) -> String {
+        let discount = self.config.member_discount_percent.min(100);
+        if discount == 0 || !self.is_member(account, now) {
+            return cost;
+        }
+        match U256::from_str(&cost) {
+            Ok(cost) => (cost * U256::from(100 - discount) / U256::from(100)).to_string(),
+            Err(_) => cost,
+        }
+    }
+
+    fn is_member(&self, account: Option<Address>, now: u64) -> bool {
+        let Some(account) = account else {
+            return false;
+        };
+        let mut members = self.members.lock().unwrap();
+        members.retain(|_, expires_at| *expires_at > now);
+        members.contains_key(&account)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lloom_core::{EmbeddingRequest, Identity, LlmRequest, Membership, OrgCredential, SignableMessage};
+
+    fn request(credential: Option<OrgCredential>) -> LlmRequest {
+        LlmRequest {
+            model: "gpt-4".to_string(),
+            prompt: "Hello".to_string(),
+            system_prompt: None,
+            temperature: None,
+            max_tokens: None,
+            executor_address: String::new(),
+            inbound_price: "1".to_string(),
+            outbound_price: "2".to_string(),
+            nonce: 1,
+            deadline: 0,
+            version: lloom_core::protocol::constants::LLM_REQUEST_VERSION,
+            credential,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_members_only_executor() {
+        let org = Identity::generate();
+        let member = Identity::generate().evm_address;
+        let outsider = Identity::generate().evm_address;
+        let members = OrgMembers::new(OrgConfig {
+            address: Some(org.evm_address),
+            members_only: true,
+            member_discount_percent: 0,
+        });
+        let credential = Membership { member, expires_at: 2000 }.sign_blocking(&org).unwrap();
+
+        assert!(members.admit(&request(None), Some(member), 1000).is_err());
+        assert!(members.admit(&request(Some(credential.clone())), Some(member), 1000).is_ok());
+        // The membership is remembered until it lapses
+        assert!(members.admit(&request(None), Some(member), 1500).is_ok());
+        assert!(members.admit(&request(None), Some(member), 2000).is_err());
+
+        // Someone else's credential does not help
+        assert!(members.admit(&request(Some(credential)), Some(outsider), 1000).is_err());
+        assert!(members.admit(&request(None), None, 1000).is_err());
+    }
+
+    #[test]
+    fn test_members_only_embeddings() {
+        let org = Identity::generate();
+        let member = Identity::generate().evm_address;
+        let members = OrgMembers::new(OrgConfig {
+            address: Some(org.evm_address),
+            members_only: true,
+            member_discount_percent: 50,
+        });
+        let credential = Membership { member, expires_at: 2000 }.sign_blocking(&org).unwrap();
+        let embedding = |credential: Option<OrgCredential>| EmbeddingRequest {
+            model: "text-embedding-3-small".to_string(),
+            input: vec!["Hello".to_string()],
+            executor_address: String::new(),
+            price_per_token: "1".to_string(),
+            nonce: 1,
+            deadline: 0,
+            request_id: None,
+            session: None,
+            credential,
+        };
+
+        // Embeddings are gated and discounted like LLM requests
+        assert!(members.admit(&embedding(None), Some(member), 1000).is_err());
+        assert!(members.admit(&embedding(Some(credential.clone())), Some(Address::ZERO), 1000).is_err());
+        members.admit(&embedding(Some(credential)), Some(member), 1000).unwrap();
+        assert_eq!(members.bill(Some(member), "1000".to_string(), 1000), "500");
+    }
+
+    #[test]
+    fn test_member_discount() {
+        let org = Identity::generate();
+        let member = Identity::generate().evm_address;
+        let members = OrgMembers::new(OrgConfig {
+            address: Some(org.evm_address),
+            members_only: false,
+            member_discount_percent: 25,
+        });
+        let credential = Membership { member, expires_at: 2000 }.sign_blocking(&org).unwrap();
+
+        // Everyone is served, members pay less
+        assert!(members.admit(&request(None), Some(Address::ZERO), 1000).is_ok());
+        assert_eq!(members.bill(Some(member), "1000".to_string(), 1000), "1000");
+        members.admit(&request(Some(credential)), Some(member), 1000).unwrap();
+        assert_eq!(members.bill(Some(member), "1000".to_string(), 1000), "750");
+        assert_eq!(members.bill(Some(Address::ZERO), "1000".to_string(), 1000), "1000");
+    }
+}
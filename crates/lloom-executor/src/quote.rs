@@ -0,0 +1,395 @@
+//! Price quotes for LLM requests.
+//!
+//! Before sending a request, a client can ask for a quote. The executor counts
+//! the prompt with the model's tokenizer, prices it at the model's configured
+//! per-token prices and keeps the quote until it expires. A request accepting a
+//! quote is checked against it and billed at the quoted prices, never above the
+//! quoted maximum. Each quote can be accepted once. Requests sent without a
+//! quote are billed at the prices their client signed.
+
+use crate::{config::TokenPrices, llm_client::count_tokens};
+use alloy::primitives::{Address, B256, U256, keccak256};
+use anyhow::anyhow;
+use lloom_core::protocol::{ChatMessage, EmbeddingRequest, LlmErrorCode, LlmRequest, Quote, QuoteRequest};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Output tokens quoted for requests that do not set `max_tokens`
+pub const DEFAULT_QUOTED_MAX_TOKENS: u32 = 4096;
+
+/// Quotes given out and not yet accepted
+#[derive(Clone)]
+pub struct QuoteBook {
+    quotes: Arc<Mutex<HashMap<String, IssuedQuote>>>,
+    capacity: usize,
+}
+
+struct IssuedQuote {
+    quote: Quote,
+    /// Signer of the quote request, the only client that may accept it
+    client: Option<Address>,
+    /// Hash of the quoted conversation
+    prompt_hash: B256,
+}
+
+impl QuoteBook {
+    /// Create an empty quote book keeping at most `capacity` quotes
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            quotes: Arc::new(Mutex::new(HashMap::new())),
+            capacity,
+        }
+    }
+
+    /// Price `request` at `prices` and remember the quote for `ttl_secs`.
+    ///
+    /// When the book is full, the quotes closest to expiring are dropped first.
+    pub fn issue(
+        &self,
+        request: &QuoteRequest,
+        prices: &TokenPrices,
+        client: Option<Address>,
+        ttl_secs: u64,
+        now: u64,
+    ) -> anyhow::Result<Quote> {
+        let prompt_tokens = count_tokens(&prompt_text(&request.messages), &request.model)? as u64;
+        let max_tokens = request.max_tokens.unwrap_or(DEFAULT_QUOTED_MAX_TOKENS);
+        let max_cost = U256::from(prompt_tokens) * prices.input_token_price.parse::<U256>()?
+            + U256::from(max_tokens) * prices.output_token_price.parse::<U256>()?;
+        let quote = Quote {
+            quote_id: uuid::Uuid::new_v4().to_string(),
+            model: request.model.clone(),
+            executor_address: request.executor_address.clone(),
+            prompt_tokens,
+            max_tokens,
+            inbound_price: prices.input_token_price.clone(),
+            outbound_price: prices.output_token_price.clone(),
+            max_cost: max_cost.to_string(),
+            expires_at: now + ttl_secs,
+            error: None,
+            error_code: None,
+        };
+
+        let mut quotes = self.quotes.lock().unwrap();
+        quotes.retain(|_, issued| !issued.quote.is_expired(now));
+        while !quotes.is_empty() && quotes.len() >= self.capacity {
+            let soonest = quotes.iter()
+                .min_by_key(|(_, issued)| issued.quote.expires_at)
+                .map(|(quote_id, _)| quote_id.clone());
+            if let Some(quote_id) = soonest {
+                quotes.remove(&quote_id);
+            }
+        }
+        quotes.insert(quote.quote_id.clone(), IssuedQuote {
+            quote: quote.clone(),
+            client,
+            prompt_hash: prompt_hash(&request.messages),
+        });
+        Ok(quote)
+    }
+
+    /// Check that the quote `request` accepts, if any, still holds, without
+    /// using it up.
+    ///
+    /// Only a request matching the quote can use it up, with
+    /// [`QuoteBook::redeem`], so a request from another client cannot spend it.
+    /// Requests refused before then keep their quote.
+    pub fn check(&self, request: &LlmRequest, client: Option<Address>, now: u64) -> anyhow::Result<Option<Quote>> {
+        let Some(quote_id) = &request.quote_id else {
+            return Ok(None);
+        };
+        let prompt_hash = prompt_hash(&request.chat_messages());
+        let quotes = self.quotes.lock().unwrap();
+        let issued = quotes.get(quote_id).ok_or_else(|| anyhow!("Unknown quote {}", quote_id))?;
+        let quote = &issued.quote;
+        if quote.is_expired(now) {
+            return Err(anyhow!("Quote {} expired at {}", quote_id, quote.expires_at));
+        }
+        if issued.client.is_some() && issued.client != client {
+            return Err(anyhow!("Quote {} was given to another client", quote_id));
+        }
+        if quote.model != request.model || quote.executor_address != request.executor_address {
+            return Err(anyhow!("Quote {} is for another model or executor", quote_id));
+        }
+        if quote.inbound_price != request.inbound_price || quote.outbound_price != request.outbound_price {
+            return Err(anyhow!("Request prices do not match quote {}", quote_id));
+        }
+        if request.max_tokens.unwrap_or(DEFAULT_QUOTED_MAX_TOKENS) > quote.max_tokens {
+            return Err(anyhow!("Request asks for more than the {} tokens quoted", quote.max_tokens));
+        }
+        if prompt_hash != issued.prompt_hash {
+            return Err(anyhow!("Request prompt differs from the one quoted"));
+        }
+        Ok(Some(quote.clone()))
+    }
+
+    /// Use up a quote that passed [`QuoteBook::check`], failing if another
+    /// request already took it.
+    pub fn redeem(&self, quote: &Quote) -> anyhow::Result<()> {
+        self.quotes.lock().unwrap()
+            .remove(&quote.quote_id)
+            .map(|_| ())
+            .ok_or_else(|| anyhow!("Quote {} was already used", quote.quote_id))
+    }
+}
+
+/// Refuse an unquoted request offering less than the model's `prices`.
+///
+/// Requests accepting a quote are checked against the quote instead.
+pub fn check_price(request: &LlmRequest, prices: &TokenPrices) -> Result<(), (LlmErrorCode, String)> {
+    if request.quote_id.is_some() {
+        return Ok(());
+    }
+    check_offer("input", &request.inbound_price, &prices.input_token_price)?;
+    check_offer("output", &request.outbound_price, &prices.output_token_price)
+}
+
+/// Refuse an embedding request offering less than the model's input price
+pub fn check_embedding_price(request: &EmbeddingRequest, prices: &TokenPrices) -> Result<(), (LlmErrorCode, String)> {
+    check_offer("input", &request.price_per_token, &prices.input_token_price)
+}
+
+fn check_offer(kind: &str, offered: &str, advertised: &str) -> Result<(), (LlmErrorCode, String)> {
+    let offered_wei: U256 = offered
+        .parse()
+        .map_err(|_| (LlmErrorCode::InvalidRequest, format!("Invalid {} token price {}", kind, offered)))?;
+    let advertised_wei: U256 = advertised.parse().unwrap_or(U256::MAX);
+    if offered_wei < advertised_wei {
+        let error = format!("Offered {} token price of {} wei is below {} wei", kind, offered, advertised);
+        return Err((LlmErrorCode::PriceTooLow, error));
+    }
+    Ok(())
+}
+
+/// A quote that could not be given
+pub fn quote_error(request: &QuoteRequest, code: LlmErrorCode, error: String) -> Quote {
+    Quote {
+        quote_id: String::new(),
+        model: request.model.clone(),
+        executor_address: request.executor_address.clone(),
+        prompt_tokens: 0,
+        max_tokens: 0,
+        inbound_price: "0".to_string(),
+        outbound_price: "0".to_string(),
+        max_cost: "0".to_string(),
+        expires_at: 0,
+        error: Some(error),
+        error_code: Some(code),
+    }
+}
+
+/// (inbound, outbound) tokens and cost in wei billed under `quote`
+pub fn billed(quote: &Quote, outbound_tokens: u64) -> (u64, u64, String) {
+    // The quote was issued by this executor, so its amounts always parse
+    let cost = quote.cost(outbound_tokens).map(|cost| cost.to_string()).unwrap_or_else(|_| quote.max_cost.clone());
+    (quote.prompt_tokens, outbound_tokens, cost)
+}
+
+/// Prices the client of an unquoted request signed for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedPrices {
+    /// Price per inbound token in wei
+    pub inbound_price: String,
+    /// Price per outbound token in wei
+    pub outbound_price: String,
+    /// Most outbound tokens signed for, over every choice
+    pub max_outbound_tokens: Option<u64>,
+}
+
+impl SignedPrices {
+    /// The prices `request` signed for
+    pub fn of(request: &LlmRequest) -> Self {
+        let choices = u64::from(request.sampling.n.unwrap_or(1).max(1));
+        Self {
+            inbound_price: request.inbound_price.clone(),
+            outbound_price: request.outbound_price.clone(),
+            max_outbound_tokens: request.max_tokens.map(|max_tokens| u64::from(max_tokens) * choices),
+        }
+    }
+
+    /// (inbound, outbound) tokens and cost in wei billed at the signed prices.
+    ///
+    /// Output beyond what the client signed for is not billed.
+    pub fn billed(&self, inbound_tokens: u64, outbound_tokens: u64) -> (u64, u64, String) {
+        let outbound_tokens = self.max_outbound_tokens.map_or(outbound_tokens, |max| outbound_tokens.min(max));
+        // check_price has refused requests whose prices do not parse
+        let parse = |price: &str| price.parse::<U256>().unwrap_or(U256::ZERO);
+        let cost = U256::from(inbound_tokens) * parse(&self.inbound_price)
+            + U256::from(outbound_tokens) * parse(&self.outbound_price);
+        (inbound_tokens, outbound_tokens, cost.to_string())
+    }
+}
+
+fn prompt_text(messages: &[ChatMessage]) -> String {
+    messages.iter().map(|message| message.content.as_str()).collect::<Vec<_>>().join("\n")
+}
+
+fn prompt_hash(messages: &[ChatMessage]) -> B256 {
+    keccak256(serde_json::to_vec(messages).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lloom_core::protocol::constants::LLM_REQUEST_VERSION;
+
+    const EXECUTOR: &str = "12D3KooWBmwkafWE2fqfzS96VoTZgpGp6aFdD7zdBUyJ1BDdyWz4";
+
+    fn quote_request() -> QuoteRequest {
+        QuoteRequest {
+            model: "gpt-4".to_string(),
+            messages: vec![ChatMessage::system("Be brief"), ChatMessage::user("Tell me a story")],
+            max_tokens: Some(100),
+            executor_address: EXECUTOR.to_string(),
+        }
+    }
+
+    /// Check and use up the quote `request` accepts, as executors serving it do
+    fn accept(book: &QuoteBook, request: &LlmRequest, client: Option<Address>, now: u64) -> anyhow::Result<Option<Quote>> {
+        let quote = book.check(request, client, now)?;
+        if let Some(quote) = &quote {
+            book.redeem(quote)?;
+        }
+        Ok(quote)
+    }
+
+    fn request(quote: &Quote) -> LlmRequest {
+        LlmRequest {
+            model: "gpt-4".to_string(),
+            prompt: "Tell me a story".to_string(),
+            system_prompt: Some("Be brief".to_string()),
+            temperature: None,
+            max_tokens: Some(100),
+            executor_address: EXECUTOR.to_string(),
+            inbound_price: quote.inbound_price.clone(),
+            outbound_price: quote.outbound_price.clone(),
+            nonce: 1,
+            deadline: 0,
+            version: LLM_REQUEST_VERSION,
+            quote_id: Some(quote.quote_id.clone()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_issue_prices_prompt_and_output() {
+        let book = QuoteBook::new(10);
+        let quote = book.issue(&quote_request(), &TokenPrices::default(), None, 60, 1000).unwrap();
+        let prompt_tokens = count_tokens("Be brief\nTell me a story", "gpt-4").unwrap() as u64;
+
+        assert_eq!(quote.prompt_tokens, prompt_tokens);
+        assert_eq!(quote.max_tokens, 100);
+        assert_eq!(quote.expires_at, 1060);
+        let max_cost = U256::from(prompt_tokens) * U256::from(500000000000000u64) + U256::from(100u64) * U256::from(1000000000000000u64);
+        assert_eq!(quote.max_cost, max_cost.to_string());
+        assert_eq!(billed(&quote, 200).2, quote.max_cost);
+
+        // Quotes carry the prices they were issued at
+        let prices = TokenPrices { input_token_price: "3".to_string(), output_token_price: "7".to_string() };
+        let quote = book.issue(&quote_request(), &prices, None, 60, 1000).unwrap();
+        assert_eq!((quote.inbound_price.as_str(), quote.outbound_price.as_str()), ("3", "7"));
+        assert_eq!(quote.max_cost, (prompt_tokens * 3 + 700).to_string());
+    }
+
+    #[test]
+    fn test_full_book_drops_the_soonest_to_expire() {
+        let book = QuoteBook::new(2);
+        let prices = TokenPrices::default();
+        let first = book.issue(&quote_request(), &prices, None, 60, 1000).unwrap();
+        let second = book.issue(&quote_request(), &prices, None, 30, 1010).unwrap();
+        let third = book.issue(&quote_request(), &prices, None, 60, 1020).unwrap();
+        assert_eq!(book.quotes.lock().unwrap().len(), 2);
+
+        assert!(accept(&book, &request(&second), None, 1020).unwrap_err().to_string().contains("Unknown"));
+        assert!(accept(&book, &request(&first), None, 1020).unwrap().is_some());
+        assert!(accept(&book, &request(&third), None, 1020).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_check_price() {
+        let prices = TokenPrices::default();
+        let mut request = LlmRequest {
+            inbound_price: prices.input_token_price.clone(),
+            outbound_price: prices.output_token_price.clone(),
+            ..Default::default()
+        };
+        assert!(check_price(&request, &prices).is_ok());
+
+        // Prices are per model
+        let dearer = TokenPrices { output_token_price: "2000000000000000".to_string(), ..prices.clone() };
+        assert_eq!(check_price(&request, &dearer).unwrap_err().0, LlmErrorCode::PriceTooLow);
+
+        request.outbound_price = "1".to_string();
+        assert_eq!(check_price(&request, &prices).unwrap_err().0, LlmErrorCode::PriceTooLow);
+        request.outbound_price = "lots".to_string();
+        assert_eq!(check_price(&request, &prices).unwrap_err().0, LlmErrorCode::InvalidRequest);
+
+        // Quoted requests are held to the quote instead
+        request.quote_id = Some("quote-1".to_string());
+        assert!(check_price(&request, &prices).is_ok());
+    }
+
+    #[test]
+    fn test_unquoted_requests_bill_signed_prices() {
+        let mut request = LlmRequest {
+            inbound_price: "3".to_string(),
+            outbound_price: "7".to_string(),
+            ..Default::default()
+        };
+        let prices = SignedPrices::of(&request);
+        assert_eq!(prices.billed(10, 20), (10, 20, (10 * 3 + 20 * 7).to_string()));
+        assert_eq!(prices.billed(0, 0).2, "0");
+
+        // Output past max_tokens for every choice is not billed
+        request.max_tokens = Some(5);
+        request.sampling.n = Some(2);
+        let prices = SignedPrices::of(&request);
+        assert_eq!(prices.max_outbound_tokens, Some(10));
+        assert_eq!(prices.billed(10, 25), (10, 10, (10 * 3 + 10 * 7).to_string()));
+        assert_eq!(prices.billed(10, 4), (10, 4, (10 * 3 + 4 * 7).to_string()));
+    }
+
+    #[test]
+    fn test_accept_checks_the_request() {
+        let book = QuoteBook::new(10);
+        let issue = |client| book.issue(&quote_request(), &TokenPrices::default(), client, 60, 1000).unwrap();
+        let client = Some(Address::repeat_byte(1));
+
+        let quote = issue(client);
+        // Checking a request leaves the quote to be used once it is served
+        assert_eq!(book.check(&request(&quote), client, 1010).unwrap(), Some(quote.clone()));
+        assert_eq!(accept(&book, &request(&quote), client, 1010).unwrap(), Some(quote.clone()));
+        // Quotes are single use
+        assert!(accept(&book, &request(&quote), client, 1010).is_err());
+        assert!(book.redeem(&quote).unwrap_err().to_string().contains("already used"));
+
+        let quote = issue(client);
+        assert!(accept(&book, &request(&quote), client, 1061).unwrap_err().to_string().contains("expired"));
+
+        let quote = issue(client);
+        assert!(accept(&book, &request(&quote), Some(Address::repeat_byte(2)), 1010).is_err());
+        // A mismatched request leaves the quote to the client it was given to
+        assert!(accept(&book, &request(&quote), client, 1010).unwrap().is_some());
+
+        let quote = issue(client);
+        let mut cheaper = request(&quote);
+        cheaper.outbound_price = "1".to_string();
+        assert!(accept(&book, &cheaper, client, 1010).is_err());
+
+        let quote = issue(client);
+        let mut longer = request(&quote);
+        longer.max_tokens = Some(101);
+        assert!(accept(&book, &longer, client, 1010).is_err());
+
+        let quote = issue(client);
+        let mut other_prompt = request(&quote);
+        other_prompt.prompt = "Tell me a much longer story".to_string();
+        assert!(accept(&book, &other_prompt, client, 1010).unwrap_err().to_string().contains("prompt"));
+
+        let mut unquoted = request(&quote);
+        unquoted.quote_id = None;
+        assert_eq!(accept(&book, &unquoted, client, 1010).unwrap(), None);
+    }
+}
@@ -0,0 +1,162 @@
+//! Spending of delegated session keys.
+//!
+//! Requests signed by a session key carry a certificate from the client's main
+//! key (see [`lloom_core::session`]). The executor bills the main address and
+//! counts what it has billed each session key, refusing further requests once
+//! the certificate's spending cap is reached. Spending is counted as requests
+//! are billed, so requests admitted under the cap can overshoot it by their own
+//! cost.
+
+use alloy::primitives::{Address, U256};
+use lloom_core::protocol::BilledRequest;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+/// What each session key has been billed, until its certificate expires
+#[derive(Clone, Default)]
+pub struct SessionBudgets {
+    sessions: Arc<Mutex<HashMap<(Address, Address), Spending>>>,
+}
+
+struct Spending {
+    spent: U256,
+    cap: U256,
+    expires_at: u64,
+}
+
+impl SessionBudgets {
+    /// Create an empty record of session spending
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check the session certificate of `request`, signed by `signer`, and
+    /// whether its session key may still spend.
+    ///
+    /// Returns the address to bill: the main address for requests carrying a
+    /// certificate, otherwise the signer.
+    pub fn admit<R: BilledRequest>(&self, request: &R, signer: Option<Address>, now: u64) -> Result<Option<Address>, String> {
+        let Some(signer) = signer else {
+            return Ok(None);
+        };
+        let Some(certificate) = request.session() else {
+            return Ok(Some(signer));
+        };
+        let account = request.account(signer, now).map_err(|e| e.to_string())?;
+        let cap = certificate.payload.spending_cap().map_err(|e| e.to_string())?;
+
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, spending| spending.expires_at > now);
+        // A newer certificate for the same key replaces the cap, not the spending
+        let spending = sessions.entry((account, signer)).or_insert(Spending {
+            spent: U256::ZERO,
+            cap,
+            expires_at: 0,
+        });
+        spending.cap = cap;
+        spending.expires_at = spending.expires_at.max(certificate.payload.expires_at);
+        if spending.spent >= spending.cap {
+            return Err(format!("Session key {} has used up its spending cap of {} wei", signer, cap));
+        }
+        Ok(Some(account))
+    }
+
+    /// Count `cost` wei billed to `account` for a request signed by `signer`
+    pub fn charge(&self, signer: Option<Address>, account: Option<Address>, cost: &str) {
+        let (Some(signer), Some(account), Ok(cost)) = (signer, account, U256::from_str(cost)) else {
+            return;
+        };
+        if let Some(spending) = self.sessions.lock().unwrap().get_mut(&(account, signer)) {
+            spending.spent = spending.spent.saturating_add(cost);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lloom_core::{EmbeddingRequest, Identity, LlmRequest, SessionKey};
+
+    fn request(session: Option<&SessionKey>) -> LlmRequest {
+        LlmRequest {
+            model: "gpt-4".to_string(),
+            prompt: "Hello".to_string(),
+            system_prompt: None,
+            temperature: None,
+            max_tokens: None,
+            executor_address: String::new(),
+            inbound_price: "1".to_string(),
+            outbound_price: "2".to_string(),
+            nonce: 1,
+            deadline: 0,
+            version: lloom_core::protocol::constants::LLM_REQUEST_VERSION,
+            session: session.map(|session| session.certificate.clone()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_session_requests_bill_the_main_address() {
+        let budgets = SessionBudgets::new();
+        let main = Identity::generate();
+        let session = SessionKey::generate(&main, 2000, U256::from(100), vec!["gpt-4".to_string()]).unwrap();
+        let session_key = session.certificate.payload.session_key;
+
+        assert_eq!(budgets.admit(&request(Some(&session)), Some(session_key), 1000).unwrap(), Some(main.evm_address));
+        assert_eq!(budgets.admit(&request(None), Some(session_key), 1000).unwrap(), Some(session_key));
+        assert_eq!(budgets.admit(&request(Some(&session)), None, 1000).unwrap(), None);
+
+        // Only the session key, before the certificate expires
+        assert!(budgets.admit(&request(Some(&session)), Some(main.evm_address), 1000).is_err());
+        assert!(budgets.admit(&request(Some(&session)), Some(session_key), 2000).is_err());
+    }
+
+    #[test]
+    fn test_spending_cap_is_enforced() {
+        let budgets = SessionBudgets::new();
+        let main = Identity::generate();
+        let session = SessionKey::generate(&main, 2000, U256::from(100), Vec::new()).unwrap();
+        let session_key = Some(session.certificate.payload.session_key);
+        let session_request = request(Some(&session));
+
+        let account = budgets.admit(&session_request, session_key, 1000).unwrap();
+        budgets.charge(session_key, account, "60");
+        assert!(budgets.admit(&session_request, session_key, 1000).is_ok());
+        budgets.charge(session_key, account, "40");
+        assert!(budgets.admit(&session_request, session_key, 1000).unwrap_err().contains("spending cap"));
+
+        // Other session keys of the same account have their own budget
+        let other = SessionKey::generate(&main, 2000, U256::from(100), Vec::new()).unwrap();
+        let other_key = Some(other.certificate.payload.session_key);
+        assert!(budgets.admit(&request(Some(&other)), other_key, 1000).is_ok());
+    }
+
+    #[test]
+    fn test_session_embedding_requests() {
+        let budgets = SessionBudgets::new();
+        let main = Identity::generate();
+        let session = SessionKey::generate(&main, 2000, U256::from(100), vec!["gpt-4".to_string()]).unwrap();
+        let session_key = Some(session.certificate.payload.session_key);
+        let embedding = |model: &str| EmbeddingRequest {
+            model: model.to_string(),
+            input: vec!["Hello".to_string()],
+            executor_address: String::new(),
+            price_per_token: "1".to_string(),
+            nonce: 1,
+            deadline: 0,
+            request_id: None,
+            session: Some(session.certificate.clone()),
+            credential: None,
+        };
+
+        // Embeddings bill the main address and share the session's cap and models
+        let account = budgets.admit(&embedding("gpt-4"), session_key, 1000).unwrap();
+        assert_eq!(account, Some(main.evm_address));
+        assert!(budgets.admit(&embedding("text-embedding-3-small"), session_key, 1000).is_err());
+        budgets.charge(session_key, account, "100");
+        assert!(budgets.admit(&request(Some(&session)), session_key, 1000).unwrap_err().contains("spending cap"));
+    }
+}
@@ -0,0 +1,367 @@
+//! Server side of the streamed LLM protocol.
+//!
+//! Every inbound stream is served on its own task. The request frame is checked
+//! the same way as a request-response `LlmRequest`, backend deltas are forwarded
+//! as [`LlmStreamFrame::Chunk`]s while they arrive, and the stream ends with the
+//! (optionally signed) final response. A client cancels by closing its side of
+//! the stream; the stopped completion is billed like a cancelled request-response
+//! call and its final response still sent, so the client learns what it owes.
+
+use crate::{config::ExecutorConfig, images::{self, ImageCache}, inflight, llm_client::{self, Completion, LlmClient}, quote::{self, QuoteBook, SignedPrices}, org::OrgMembers, sessions::SessionBudgets};
+use alloy::primitives::Address;
+use futures::{AsyncReadExt, AsyncWriteExt};
+use libp2p::{PeerId, Stream};
+use lloom_core::{
+    identity::Identity,
+    protocol::{LlmErrorCode, LlmRequest, LlmResponse, Quote, RequestMessage, ResponseMessage, UsageRecord, request_span},
+    signing::{ReplayGuard, SignableMessage},
+    streaming::{LlmStreamFrame, read_frame, write_frame},
+};
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+use tracing::{Instrument, debug, error, info, warn};
+
+/// Shared state for serving LLM streams
+pub struct StreamServer {
+    identity: Identity,
+    config: ExecutorConfig,
+    llm_clients: HashMap<String, LlmClient>,
+    images: ImageCache,
+    quotes: QuoteBook,
+    replay_guard: ReplayGuard,
+    sessions: SessionBudgets,
+    org: OrgMembers,
+    enable_signing: bool,
+}
+
+impl StreamServer {
+    /// Create a stream server over the executor's backends
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        identity: Identity,
+        config: ExecutorConfig,
+        llm_clients: HashMap<String, LlmClient>,
+        images: ImageCache,
+        quotes: QuoteBook,
+        replay_guard: ReplayGuard,
+        sessions: SessionBudgets,
+        org: OrgMembers,
+        enable_signing: bool,
+    ) -> Self {
+        Self {
+            identity,
+            config,
+            llm_clients,
+            images,
+            quotes,
+            replay_guard,
+            sessions,
+            org,
+            enable_signing,
+        }
+    }
+
+    /// Serve one inbound stream.
+    ///
+    /// Returns the usage to record once a completion has been delivered, or
+    /// once a stopped completion has been billed.
+    pub async fn serve(&self, peer: PeerId, mut stream: Stream) -> Option<UsageRecord> {
+        let request_message = match read_frame::<_, RequestMessage>(&mut stream).await {
+            Ok(Some(message)) => message,
+            Ok(None) => {
+                debug!("Stream from {} closed before sending a request", peer);
+                return None;
+            }
+            Err(e) => {
+                warn!("Invalid stream request from {}: {}", peer, e);
+                return None;
+            }
+        };
+
+        let (response, usage, signer) = match self.accept_request(peer, request_message) {
+            Ok((request, signer)) => {
+                let span = request_span(&request.id());
+                let (response, usage) = self.run_completion(peer, request, signer, &mut stream).instrument(span).await;
+                (response, usage, signer)
+            }
+            Err(error_response) => (*error_response, None, None),
+        };
+
+        let total_cost = response.total_cost.clone();
+        let final_frame = LlmStreamFrame::Final(Box::new(self.response_message(response)));
+        let usage = match write_frame(&mut stream, &final_frame).await {
+            Err(e) => {
+                error!("Failed to send final stream frame to {}: {}", peer, e);
+                // Work stopped by a disconnect is still billed, undelivered completions are not
+                usage.filter(|usage| usage.cancelled)
+            }
+            Ok(()) => {
+                let _ = stream.close().await;
+                usage
+            }
+        };
+        if let Some(usage) = &usage {
+            self.sessions.charge(signer, Some(usage.client_address), &total_cost);
+        }
+        usage
+    }
+
+    /// Check the opening frame, verifying its signature when signing is enabled
+    fn accept_request(
+        &self,
+        peer: PeerId,
+        message: RequestMessage,
+    ) -> Result<(LlmRequest, Option<Address>), Box<LlmResponse>> {
+        match message {
+            RequestMessage::LlmRequest(request) => {
+                info!("Received unsigned LLM stream request from {}: model={}", peer, request.model);
+                if self.enable_signing {
+                    warn!("⚠️  Received unsigned stream request while signing is enabled from peer: {}", peer);
+                }
+                Ok((request, None))
+            }
+            RequestMessage::SignedLlmRequest(signed_request) => {
+                info!("Received signed LLM stream request from {}: model={}", peer, signed_request.payload.model);
+                if !self.enable_signing {
+                    return Ok((signed_request.payload, None));
+                }
+                match self.replay_guard.verify(&signed_request) {
+                    Ok(signer_address) => {
+                        info!("✓ Stream request signature verified from signer: {}", signer_address);
+                        Ok((signed_request.payload, Some(signer_address)))
+                    }
+                    Err(e) => {
+                        error!("✗ Stream request signature verification failed: {}", e);
+                        Err(Box::new(error_response(
+                            &signed_request.payload,
+                            LlmErrorCode::InvalidSignature,
+                            format!("Signature verification failed: {}", e),
+                        )))
+                    }
+                }
+            }
+            _ => {
+                warn!("Unexpected message on LLM stream from {}", peer);
+                Err(Box::new(LlmResponse {
+                    content: String::new(),
+                    inbound_tokens: 0,
+                    outbound_tokens: 0,
+                    total_cost: "0".to_string(),
+                    model_used: String::new(),
+                    error: Some("Expected an LLM request".to_string()),
+                    request_id: None,
+                    error_code: Some(LlmErrorCode::InvalidRequest),
+                    tool_calls: None,
+                    choices: None,
+                }))
+            }
+        }
+    }
+
+    /// Run the completion, forwarding chunks to the client as they arrive
+    async fn run_completion(
+        &self,
+        peer: PeerId,
+        mut request: LlmRequest,
+        signer: Option<Address>,
+        stream: &mut Stream,
+    ) -> (LlmResponse, Option<UsageRecord>) {
+        if !request.is_supported_version() {
+            let error = format!("Unsupported request version {}", request.version);
+            return (error_response(&request, LlmErrorCode::UnsupportedVersion, error), None);
+        }
+        let Some(backend) = self.config.find_backend_for_model(&request.model) else {
+            let error = format!("Model {} not supported", request.model);
+            return (error_response(&request, LlmErrorCode::UnsupportedModel, error), None);
+        };
+        let Some(llm_client) = self.llm_clients.get(&backend.name) else {
+            let error = format!("Backend {} not available", backend.name);
+            return (error_response(&request, LlmErrorCode::BackendUnavailable, error), None);
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let account = match self.sessions.admit(&request, signer, now) {
+            Ok(account) => account,
+            Err(e) => return (error_response(&request, LlmErrorCode::Unauthorized, e), None),
+        };
+        if let Err(e) = self.org.admit(&request, account, now) {
+            return (error_response(&request, LlmErrorCode::Unauthorized, e), None);
+        }
+        if let Err((code, error)) = quote::check_price(&request, self.config.billing.prices(&request.model)) {
+            return (error_response(&request, code, error), None);
+        }
+        // Checked against the prompt as quoted, used up once every check has passed
+        let quote = match self.quotes.check(&request, signer, now) {
+            Ok(quote) => quote,
+            Err(e) => return (error_response(&request, LlmErrorCode::InvalidQuote, e.to_string()), None),
+        };
+        if let Err((code, error)) = inflight::check_features(&self.config, &request) {
+            return (error_response(&request, code, error), None);
+        }
+        if let Err((code, error)) = images::resolve_request_images(&self.config, &self.images, &mut request) {
+            return (error_response(&request, code, error), None);
+        }
+        if let Some(Err(e)) = quote.as_ref().map(|quote| self.quotes.redeem(quote)) {
+            return (error_response(&request, LlmErrorCode::InvalidQuote, e.to_string()), None);
+        }
+
+        let (chunk_tx, mut chunk_rx) = mpsc::unbounded_channel();
+        let completion = llm_client.complete(&request, true, chunk_tx);
+        tokio::pin!(completion);
+
+        let deadline = tokio::time::sleep(inflight::time_until(request.deadline));
+        tokio::pin!(deadline);
+
+        let mut produced = String::new();
+        // The client sends nothing after the request, so any read ends with its close
+        let mut probe = [0u8; 1];
+        let result = loop {
+            tokio::select! {
+                Some(chunk) = chunk_rx.recv() => {
+                    produced.push_str(&chunk);
+                    if let Err(e) = write_frame(stream, &LlmStreamFrame::Chunk(chunk)).await {
+                        // Dropping the completion cancels the backend request
+                        warn!("Client {} went away mid-stream: {}", peer, e);
+                        return self.stopped(&request, account, quote.as_ref(), &produced, LlmErrorCode::Cancelled, "Client disconnected");
+                    }
+                }
+                result = &mut completion => break result,
+                read = stream.read(&mut probe) => {
+                    if matches!(read, Ok(n) if n > 0) {
+                        continue; // Stray bytes carry nothing we use
+                    }
+                    info!("Client {} closed the stream", peer);
+                    return self.stopped(&request, account, quote.as_ref(), &produced, LlmErrorCode::Cancelled, "Client cancelled the stream");
+                }
+                _ = &mut deadline, if request.deadline > 0 => {
+                    warn!("Stream request from {} ran past its deadline", peer);
+                    return self.stopped(&request, account, quote.as_ref(), &produced, LlmErrorCode::DeadlineExpired, "Request deadline passed");
+                }
+            }
+        };
+        // Forward whatever arrived between the last poll and completion
+        while let Ok(chunk) = chunk_rx.try_recv() {
+            produced.push_str(&chunk);
+            if let Err(e) = write_frame(stream, &LlmStreamFrame::Chunk(chunk)).await {
+                warn!("Client {} went away mid-stream: {}", peer, e);
+                return self.stopped(&request, account, quote.as_ref(), &produced, LlmErrorCode::Cancelled, "Client disconnected");
+            }
+        }
+
+        let completion = match result {
+            Ok(completion) => completion,
+            Err(e) => {
+                error!("Streamed LLM request failed: {}", e);
+                return (error_response(&request, llm_client::error_code(&e), e.to_string()), None);
+            }
+        };
+        if let Err(reason) = inflight::check_output(&request, &completion) {
+            // The output has already been streamed, so it cannot be generated again
+            warn!("Streamed LLM request produced invalid output: {}", reason);
+            let error = format!("Output does not match the response format: {}", reason);
+            return (error_response(&request, LlmErrorCode::InvalidOutput, error), None);
+        }
+
+        let completion_tokens = u64::from(completion.completion_tokens());
+        let Completion { content, choices, tool_calls, token_count, prompt_tokens } = completion;
+        info!("Streamed LLM request completed: {} tokens used", token_count);
+        let (inbound_tokens, outbound_tokens, total_cost) = match &quote {
+            Some(quote) => quote::billed(quote, completion_tokens),
+            None => SignedPrices::of(&request).billed(u64::from(prompt_tokens), completion_tokens),
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let total_cost = self.org.bill(account, total_cost, now);
+        let response = LlmResponse {
+            content,
+            inbound_tokens,
+            outbound_tokens,
+            total_cost,
+            model_used: request.model.clone(),
+            error: None,
+            request_id: request.request_id.clone(),
+            error_code: None,
+            tool_calls,
+            choices,
+        };
+        let usage = UsageRecord {
+            client_address: account.unwrap_or(self.identity.evm_address),
+            model: request.model.clone(),
+            token_count,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            cancelled: false,
+            request_id: Some(request.id()),
+        };
+        (response, Some(usage))
+    }
+
+    /// Response and usage of a completion stopped before it finished.
+    ///
+    /// The tokens produced so far are billed if the billing policy allows it.
+    fn stopped(
+        &self,
+        request: &LlmRequest,
+        account: Option<Address>,
+        quote: Option<&Quote>,
+        produced: &str,
+        code: LlmErrorCode,
+        reason: &str,
+    ) -> (LlmResponse, Option<UsageRecord>) {
+        let mut response = error_response(request, code, reason.to_string());
+        if !self.config.billing.bill_cancelled {
+            return (response, None);
+        }
+        let (inbound_tokens, outbound_tokens) =
+            inflight::partial_tokens(&inflight::prompt_text(request), produced, &request.model, request.sampling.n.unwrap_or(1));
+        let (inbound_tokens, outbound_tokens, total_cost) = match quote {
+            Some(quote) => quote::billed(quote, outbound_tokens),
+            None => SignedPrices::of(request).billed(inbound_tokens, outbound_tokens),
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let total_cost = self.org.bill(account, total_cost, now);
+        let token_count = inbound_tokens + outbound_tokens;
+        response.content = produced.to_string();
+        response.inbound_tokens = inbound_tokens;
+        response.outbound_tokens = outbound_tokens;
+        response.total_cost = total_cost;
+        let usage = UsageRecord {
+            client_address: account.unwrap_or(self.identity.evm_address),
+            model: request.model.clone(),
+            token_count: token_count as u32,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            cancelled: true,
+            request_id: Some(request.id()),
+        };
+        (response, Some(usage))
+    }
+
+    /// Wrap a response for the final frame, signing it when enabled
+    fn response_message(&self, response: LlmResponse) -> ResponseMessage {
+        if !self.enable_signing {
+            return ResponseMessage::LlmResponse(response);
+        }
+        match response.sign_blocking(&self.identity) {
+            Ok(signed_response) => ResponseMessage::SignedLlmResponse(signed_response),
+            Err(e) => {
+                error!("Failed to sign stream response: {}, sending unsigned", e);
+                ResponseMessage::LlmResponse(response)
+            }
+        }
+    }
+}
+
+fn error_response(request: &LlmRequest, code: LlmErrorCode, error: String) -> LlmResponse {
+    LlmResponse {
+        content: String::new(),
+        inbound_tokens: 0,
+        outbound_tokens: 0,
+        total_cost: "0".to_string(),
+        model_used: request.model.clone(),
+        error: Some(error),
+        request_id: request.request_id.clone(),
+        error_code: Some(code),
+        tool_calls: None,
+        choices: None,
+    }
+}
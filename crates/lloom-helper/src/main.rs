@@ -1,8 +1,9 @@
 use clap::{Parser, Subcommand};
-use lloom_core::Identity;
+use lloom_core::{Address, Identity, Membership, SessionKey, SignableMessage, U256};
 use anyhow::{Result, Context};
 use serde::{Deserialize, Serialize};
 use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Parser)]
 #[command(name = "lloom-helper")]
@@ -41,12 +42,46 @@ enum Commands {
         #[arg(long)]
         config: String,
     },
+    /// Generate a session key authorized by the main key of a configuration file
+    NewSession {
+        /// Path to configuration file containing the main private key
+        #[arg(long)]
+        config: String,
+        /// Output file path
+        #[arg(short, long, default_value = "session.json")]
+        output: String,
+        /// How long the session key stays valid, in seconds
+        #[arg(long, default_value = "86400")]
+        expires_in: u64,
+        /// Most the session key may spend, in wei
+        #[arg(long)]
+        spending_cap: String,
+        /// Models the session key may request (all if omitted)
+        #[arg(long, value_delimiter = ',')]
+        models: Vec<String>,
+    },
+    /// Issue an org membership credential signed by the key of a configuration file
+    NewCredential {
+        /// Path to configuration file containing the org private key
+        #[arg(long)]
+        config: String,
+        /// Address of the member
+        #[arg(long)]
+        member: Address,
+        /// Output file path
+        #[arg(short, long, default_value = "credential.json")]
+        output: String,
+        /// How long the membership stays valid, in seconds
+        #[arg(long, default_value = "2592000")]
+        expires_in: u64,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
 struct ClientConfig {
     identity: IdentityConfig,
     network: NetworkConfig,
+    blockchain: ClientBlockchainConfig,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -64,6 +99,12 @@ struct NetworkConfig {
     bootstrap_nodes: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize)]
+struct ClientBlockchainConfig {
+    rpc_url: String,
+    contract_address: String,
+}
+
 const BOOTSTRAP_NODE: &str = "/ip4/34.56.189.68/tcp/5001/p2p/12D3KooWK39hN8NkmWFNRfTjJSpYW9aJvJgXVQNzVVuWpV2yCh7H";
 const ETH_RPC_URL: &str = "https://sepolia.base.org";
 const CONTRACT_ADDRESS: &str = "0x25e8c5878DdaA22d1753a9223f948B61AeAf47E6";
@@ -79,6 +120,12 @@ async fn main() -> Result<()> {
         Commands::NewExecutor { output } => generate_executor_config(&output).await,
         Commands::NewValidator { output } => generate_validator_config(&output).await,
         Commands::RequestEth { email, config } => request_eth(&email, &config).await,
+        Commands::NewSession { config, output, expires_in, spending_cap, models } => {
+            generate_session(&config, &output, expires_in, &spending_cap, models)
+        }
+        Commands::NewCredential { config, member, output, expires_in } => {
+            generate_credential(&config, member, &output, expires_in)
+        }
     }
 }
 
@@ -94,6 +141,10 @@ async fn generate_client_config(output: &str) -> Result<()> {
         network: NetworkConfig {
             bootstrap_nodes: vec![BOOTSTRAP_NODE.to_string()],
         },
+        blockchain: ClientBlockchainConfig {
+            rpc_url: ETH_RPC_URL.to_string(),
+            contract_address: CONTRACT_ADDRESS.to_string(),
+        },
     };
 
     let toml_content = toml::to_string_pretty(&config)
@@ -206,7 +257,7 @@ async fn request_eth(email: &str, config_path: &str) -> Result<()> {
     });
     
     let request_response = client
-        .post(&format!("{}/request", FAUCET_URL))
+        .post(format!("{}/request", FAUCET_URL))
         .json(&request_payload)
         .send()
         .await
@@ -236,7 +287,7 @@ async fn request_eth(email: &str, config_path: &str) -> Result<()> {
     });
     
     let redeem_response = client
-        .post(&format!("{}/redeem", FAUCET_URL))
+        .post(format!("{}/redeem", FAUCET_URL))
         .json(&redeem_payload)
         .send()
         .await
@@ -262,6 +313,63 @@ async fn request_eth(email: &str, config_path: &str) -> Result<()> {
     Ok(())
 }
 
+fn generate_session(
+    config_path: &str,
+    output: &str,
+    expires_in: u64,
+    spending_cap: &str,
+    models: Vec<String>,
+) -> Result<()> {
+    let private_key_hex = extract_private_key_from_config(config_path)
+        .context("Failed to extract private key from configuration")?;
+    let main = Identity::from_str(&private_key_hex)
+        .context("Failed to create identity from private key")?;
+    let spending_cap: U256 = spending_cap.parse()
+        .context("Spending cap must be a number of wei")?;
+    let expires_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() + expires_in;
+
+    let session = SessionKey::generate(&main, expires_at, spending_cap, models)
+        .context("Failed to authorize session key")?;
+    session.save(output)
+        .context("Failed to write session key file")?;
+
+    println!("✅ Session key generated successfully!");
+    println!("📄 File: {}", output);
+    println!("📍 Session Address: {}", session.certificate.payload.session_key);
+    println!("🏦 Billed To: {}", main.evm_address);
+    println!("⏰ Expires At: {}", expires_at);
+    println!("\n📋 Next steps:");
+    println!("   1. Copy {} to the machine sending requests", output);
+    println!("   2. Run: lloom-client --session {}", output);
+
+    Ok(())
+}
+
+fn generate_credential(config_path: &str, member: Address, output: &str, expires_in: u64) -> Result<()> {
+    let private_key_hex = extract_private_key_from_config(config_path)
+        .context("Failed to extract private key from configuration")?;
+    let org = Identity::from_str(&private_key_hex)
+        .context("Failed to create identity from private key")?;
+    let expires_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() + expires_in;
+
+    let credential = Membership { member, expires_at }.sign_blocking(&org)
+        .context("Failed to sign membership")?;
+    fs::write(output, serde_json::to_string_pretty(&credential)?)
+        .context("Failed to write credential file")?;
+
+    println!("✅ Membership credential issued successfully!");
+    println!("📄 File: {}", output);
+    println!("🏢 Org Address: {}", org.evm_address);
+    println!("📍 Member Address: {}", member);
+    println!("⏰ Expires At: {}", expires_at);
+    println!("\n📋 Next steps:");
+    println!("   1. Send {} to the member", output);
+    println!("   2. Run: lloom-client --credential {}", output);
+    println!("   3. Configure executors with [org] address = \"{}\"", org.evm_address);
+
+    Ok(())
+}
+
 fn extract_private_key_from_config(config_path: &str) -> Result<String> {
     let content = fs::read_to_string(config_path)
         .context("Failed to read configuration file")?;
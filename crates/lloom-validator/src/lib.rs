@@ -42,14 +42,14 @@ pub mod identity {
         if let Some(path) = path {
             if path.exists() {
                 let key_hex = std::fs::read_to_string(path)
-                    .map_err(|e| lloom_core::Error::Io(e))?;
+                    .map_err(lloom_core::Error::Io)?;
                 let key_hex = key_hex.trim();
                 Identity::from_str(key_hex)
             } else {
                 let identity = Identity::generate();
                 let key_hex = hex::encode(identity.wallet.to_bytes());
                 std::fs::write(path, key_hex)
-                    .map_err(|e| lloom_core::Error::Io(e))?;
+                    .map_err(lloom_core::Error::Io)?;
                 Ok(identity)
             }
         } else {
@@ -62,14 +62,14 @@ pub mod identity {
         if let Some(path) = path {
             if path.exists() {
                 let key_hex = tokio::fs::read_to_string(path).await
-                    .map_err(|e| lloom_core::Error::Io(e))?;
+                    .map_err(lloom_core::Error::Io)?;
                 let key_hex = key_hex.trim();
                 Identity::from_str(key_hex)
             } else {
                 let identity = Identity::generate();
                 let key_hex = hex::encode(identity.wallet.to_bytes());
                 tokio::fs::write(path, key_hex).await
-                    .map_err(|e| lloom_core::Error::Io(e))?;
+                    .map_err(lloom_core::Error::Io)?;
                 Ok(identity)
             }
         } else {
@@ -7,18 +7,21 @@ use anyhow::Result;
 use clap::Parser;
 use serde::Deserialize;
 use lloom_core::{
-    identity::Identity,
-    network::{LloomBehaviour, LloomEvent, helpers},
+    identity::{Identity, keystore_password},
+    network::{LloomBehaviour, LloomEvent, Transport, build_swarm, helpers},
     protocol::{
         ServiceRole, ModelAnnouncement, ModelDescriptor, AnnouncementType,
-        NetworkStatistics, ExecutorStatistics
+        NetworkStatistics, ExecutorStatistics, constants::{MAX_MESSAGE_AGE_SECS, MIN_PROTOCOL_VERSION},
+        request_span,
     },
+    signing::{DEFAULT_REPLAY_CAPACITY, LEGACY_SIGNATURE_VERSION, ReplayGuard, SIGNATURE_VERSION},
 };
 use futures::StreamExt;
 use libp2p::{
     kad::{self, QueryResult as KadQueryResult, Record},
+    request_response,
     swarm::SwarmEvent,
-    PeerId, Multiaddr, Swarm, SwarmBuilder,
+    PeerId, Multiaddr, Swarm,
 };
 use std::{
     collections::{HashMap, HashSet},
@@ -45,6 +48,8 @@ struct RegistryConfig {
     max_executors: usize,
     /// Maximum number of models per executor
     max_models_per_executor: usize,
+    /// Lowest protocol version of executors listed
+    min_protocol_version: u8,
 }
 
 impl Default for RegistryConfig {
@@ -54,6 +59,7 @@ impl Default for RegistryConfig {
             removal_timeout: 300,   // 5 minutes
             max_executors: 1000,
             max_models_per_executor: 50,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
         }
     }
 }
@@ -159,6 +165,16 @@ impl ModelRegistry {
         let peer_id = announcement.executor_peer_id.parse::<PeerId>()
             .map_err(|e| anyhow::anyhow!("Invalid peer ID in announcement: {}", e))?;
 
+        // Executors too old to speak the minimum version are not listed
+        if announcement.protocol_version < self.config.min_protocol_version {
+            if self.executor_records.contains_key(&peer_id) {
+                self.remove_executor(&peer_id)?;
+                self.update_network_stats();
+            }
+            return Err(anyhow::anyhow!("Executor {} speaks protocol version {}, below the minimum {}",
+                                     peer_id, announcement.protocol_version, self.config.min_protocol_version));
+        }
+
         match announcement.announcement_type {
             AnnouncementType::Initial => {
                 self.register_executor(&peer_id, &announcement.executor_address, &announcement.models)?;
@@ -172,6 +188,9 @@ impl ModelRegistry {
             AnnouncementType::Heartbeat => {
                 self.update_executor_heartbeat(&peer_id)?;
             }
+            AnnouncementType::Unknown => {
+                return Err(anyhow::anyhow!("Unknown announcement type from executor {}", peer_id));
+            }
         }
 
         info!("Processed {:?} announcement from executor {}", 
@@ -201,14 +220,14 @@ impl ModelRegistry {
         let mut record = ExecutorRecord::new(*peer_id, *evm_address);
         record.connection_state = ConnectionState::Connected;
 
-        // Add models to the record and index them
-        for model in models {
+        // Add models to the record and index them, leaving out org-private ones
+        for model in models.iter().filter(|model| model.org.is_none()) {
             record.models.insert(model.model_id.clone(), model.clone());
             
             // Update model-to-executor mapping
             self.model_to_executors
                 .entry(model.model_id.clone())
-                .or_insert_with(HashSet::new)
+                .or_default()
                 .insert(*peer_id);
         }
 
@@ -233,14 +252,14 @@ impl ModelRegistry {
             }
         }
 
-        // Update with new models
+        // Update with new models, leaving out org-private ones
         record.models.clear();
-        for model in models {
+        for model in models.iter().filter(|model| model.org.is_none()) {
             record.models.insert(model.model_id.clone(), model.clone());
             
             self.model_to_executors
                 .entry(model.model_id.clone())
-                .or_insert_with(HashSet::new)
+                .or_default()
                 .insert(*peer_id);
         }
 
@@ -349,7 +368,23 @@ struct ValidatorConfig {
 
 #[derive(Debug, Deserialize)]
 struct IdentityConfig {
-    private_key: String,
+    /// Private key (hex encoded)
+    #[serde(default)]
+    private_key: Option<String>,
+    /// Encrypted keystore file, used instead of the private key
+    #[serde(default)]
+    keystore: Option<PathBuf>,
+}
+
+impl IdentityConfig {
+    fn load(&self) -> Result<Identity> {
+        match (&self.keystore, &self.private_key) {
+            (Some(path), _) => load_or_generate_keystore(path),
+            (None, Some(key)) => Identity::from_str(key)
+                .map_err(|e| anyhow::anyhow!("Failed to parse identity from config: {}", e)),
+            (None, None) => Err(anyhow::anyhow!("The identity section needs a private_key or keystore")),
+        }
+    }
 }
 
 /// Command-line arguments for the Validator node
@@ -364,14 +399,39 @@ struct Args {
     #[arg(short = 'k', long, env = "VALIDATOR_PRIVATE_KEY_FILE")]
     private_key_file: Option<PathBuf>,
 
+    /// Path to an encrypted (Web3 Secret Storage) keystore file, created if
+    /// missing. The password is read from LLOOM_KEYSTORE_PASSWORD or prompted for.
+    #[arg(long, env = "VALIDATOR_KEYSTORE", conflicts_with = "private_key_file")]
+    keystore: Option<PathBuf>,
+
+    /// Envelope version to sign messages with: 2, or 1 while peers that
+    /// predate version 2 still verify messages
+    #[arg(long, default_value_t = SIGNATURE_VERSION, env = "VALIDATOR_SIGNATURE_VERSION")]
+    signature_version: u8,
+
+    /// Oldest envelope version accepted: 1 while peers upgrade, then 2 to end
+    /// the migration. Version 1 envelopes do not sign their timestamp, so they
+    /// can be replayed once forgotten.
+    #[arg(long, default_value_t = LEGACY_SIGNATURE_VERSION, env = "VALIDATOR_MIN_SIGNATURE_VERSION")]
+    min_signature_version: u8,
+
     /// Port to listen on for P2P connections
     #[arg(short = 'p', long, default_value = "9000", env = "VALIDATOR_P2P_PORT")]
     p2p_port: u16,
 
+    /// Transports to listen on: tcp, quic (/udp/<port>/quic-v1) or dual for both
+    #[arg(long, default_value = "tcp", env = "VALIDATOR_TRANSPORT")]
+    transport: Transport,
+
     /// External address for other nodes to connect to (e.g., /ip4/1.2.3.4/tcp/9000)
     #[arg(long, env = "VALIDATOR_EXTERNAL_ADDR")]
     external_addr: Option<String>,
 
+    /// Lowest protocol version of executors listed; older executors are not
+    /// offered to clients
+    #[arg(long, default_value_t = MIN_PROTOCOL_VERSION, env = "VALIDATOR_MIN_PROTOCOL_VERSION")]
+    min_protocol_version: u8,
+
     /// Enable debug logging
     #[arg(short = 'd', long, env = "VALIDATOR_DEBUG")]
     debug: bool,
@@ -402,8 +462,7 @@ async fn main() -> Result<()> {
             .map_err(|e| anyhow::anyhow!("Failed to parse TOML config: {}", e))?;
         
         info!("Loading identity from config file");
-        Identity::from_str(&config.identity.private_key)
-            .map_err(|e| anyhow::anyhow!("Failed to parse identity from config: {}", e))?
+        config.identity.load()?
     } else if std::path::Path::new("config.toml").exists() {
         info!("Automatically loading config from: config.toml");
         let config_content = std::fs::read_to_string("config.toml")
@@ -412,33 +471,25 @@ async fn main() -> Result<()> {
             .map_err(|e| anyhow::anyhow!("Failed to parse TOML config: {}", e))?;
         
         info!("Loading identity from config file");
-        Identity::from_str(&config.identity.private_key)
-            .map_err(|e| anyhow::anyhow!("Failed to parse identity from config: {}", e))?
+        config.identity.load()?
+    } else if let Some(path) = &args.keystore {
+        load_or_generate_keystore(path)?
     } else {
         // Fall back to old method for backward compatibility
         load_or_generate_identity(args.private_key_file.as_deref()).await?
     };
+    let identity = identity.with_signature_version(args.signature_version)?;
     
     info!("Node identity loaded: PeerId={}", identity.peer_id);
     info!("EVM address: {}", identity.evm_address);
 
-    // Create the network behaviour
-    let behaviour = LloomBehaviour::new(&identity)?;
-
     // Build the swarm
-    let mut swarm = SwarmBuilder::with_existing_identity(identity.p2p_keypair.clone())
-        .with_tokio()
-        .with_tcp(
-            libp2p::tcp::Config::default(),
-            libp2p::noise::Config::new,
-            libp2p::yamux::Config::default,
-        )?
-        .with_behaviour(|_| behaviour)?
-        .build();
-
-    // Listen on the specified port
-    let listen_addr: Multiaddr = format!("/ip4/0.0.0.0/tcp/{}", args.p2p_port).parse()?;
-    swarm.listen_on(listen_addr)?;
+    let mut swarm = build_swarm(&identity, args.transport)?;
+
+    // Listen on the specified port, over each selected transport
+    for listen_addr in args.transport.listen_addrs(args.p2p_port) {
+        swarm.listen_on(listen_addr)?;
+    }
 
     // Add external address if provided
     if let Some(external_addr) = args.external_addr {
@@ -479,18 +530,25 @@ async fn main() -> Result<()> {
     let mut cleanup_interval = time::interval(Duration::from_secs(30));
 
     // Initialize model registry
-    let registry_config = RegistryConfig::default();
+    let registry_config = RegistryConfig {
+        min_protocol_version: args.min_protocol_version,
+        ..Default::default()
+    };
     let model_registry = Arc::new(Mutex::new(ModelRegistry::new(registry_config)));
 
     // Track known executors with their model information (legacy tracking)
     let mut known_executors = HashSet::new();
     let mut executor_models: HashMap<libp2p::PeerId, Vec<String>> = HashMap::new();
 
+    // Signed announcements are accepted once each
+    let replay_guard = ReplayGuard::new(MAX_MESSAGE_AGE_SECS, DEFAULT_REPLAY_CAPACITY)
+        .require_version(args.min_signature_version)?;
+
     // Main event loop
     loop {
         tokio::select! {
             event = swarm.select_next_some() => {
-                handle_swarm_event(&mut swarm, event, &mut known_executors, &mut executor_models, &model_registry).await;
+                handle_swarm_event(&mut swarm, event, &mut known_executors, &mut executor_models, &model_registry, &replay_guard).await;
             }
             _ = periodic_interval.tick() => {
                 // Perform periodic maintenance
@@ -537,6 +595,20 @@ async fn load_or_generate_identity(key_file: Option<&std::path::Path>) -> Result
     }
 }
 
+/// Load identity from a keystore file, creating one with a new identity if missing
+fn load_or_generate_keystore(path: &std::path::Path) -> Result<Identity> {
+    let password = keystore_password(&format!("Password for keystore {}: ", path.display()))?;
+    if path.exists() {
+        info!("Loading identity from keystore {:?}", path);
+        Ok(Identity::from_keystore(path, &password)?)
+    } else {
+        info!("Generating new identity and saving to keystore {:?}", path);
+        let identity = Identity::generate();
+        identity.save_keystore(path, &password)?;
+        Ok(identity)
+    }
+}
+
 /// Handle swarm events
 async fn handle_swarm_event(
     swarm: &mut Swarm<LloomBehaviour>,
@@ -544,6 +616,7 @@ async fn handle_swarm_event(
     known_executors: &mut HashSet<libp2p::PeerId>,
     executor_models: &mut HashMap<libp2p::PeerId, Vec<String>>,
     model_registry: &Arc<Mutex<ModelRegistry>>,
+    replay_guard: &ReplayGuard,
 ) {
     match event {
         SwarmEvent::NewListenAddr { address, .. } => {
@@ -564,12 +637,9 @@ async fn handle_swarm_event(
                 trace!("Connected peer {} is a known executor", peer_id);
                 
                 // Update model information if we don't have it yet
-                if !executor_models.contains_key(&peer_id) {
-                    let models = discover_executor_models(&peer_id);
-                    executor_models.insert(peer_id, models);
-                }
+                executor_models.entry(peer_id).or_insert_with(|| discover_executor_models(&peer_id));
                 
-                trace_executor_models(&executor_models);
+                trace_executor_models(executor_models);
             }
         }
         SwarmEvent::ConnectionClosed { peer_id, cause, .. } => {
@@ -585,21 +655,33 @@ async fn handle_swarm_event(
                 trace!("Executor {} disconnected", peer_id);
                 // Note: We don't remove from known_executors here as they might reconnect
                 // But we could mark them as offline in a more sophisticated implementation
-                trace_executor_models(&executor_models);
+                trace_executor_models(executor_models);
             }
         }
         SwarmEvent::Behaviour(LloomEvent::Kademlia(kad::Event::OutboundQueryProgressed {
             result: KadQueryResult::GetRecord(Ok(kad::GetRecordOk::FoundRecord(record))),
             ..
         })) => {
-            if record.record.key.as_ref() == ServiceRole::Executor.to_kad_key() {
-                if let Ok(peer_id) = libp2p::PeerId::from_bytes(&record.record.value) {
+            let record = record.record;
+            if record.key.as_ref() == ServiceRole::Executor.to_kad_key() {
+                if let Ok(peer_id) = libp2p::PeerId::from_bytes(&record.value) {
                     info!("Discovered executor via Kademlia: {}", peer_id);
                     // Executors should announce their models via ModelAnnouncement messages
                     // We just note that we've discovered them here
                 }
             }
         }
+        SwarmEvent::Behaviour(LloomEvent::RequestResponse(request_response::Event::Message {
+            peer,
+            message: request_response::Message::Request { request, .. },
+            ..
+        })) => {
+            // Validators serve no LLM requests; dropping the channel tells the peer so
+            if let Some(request_id) = request.request_id() {
+                let _span = request_span(&request_id).entered();
+                warn!("Ignoring LLM request from {} sent to a validator", peer);
+            }
+        }
         SwarmEvent::Behaviour(LloomEvent::Kademlia(kad::Event::InboundRequest {
             request: kad::InboundRequest::GetRecord { .. }, 
             .. 
@@ -626,6 +708,12 @@ async fn handle_swarm_event(
                         debug!("Successfully parsed signed model announcement from {}",
                                signed_announcement.payload.executor_peer_id);
                         
+                        if let Err(e) = replay_guard.verify(&signed_announcement) {
+                            warn!("Rejected model announcement from {}: {}",
+                                  signed_announcement.payload.executor_peer_id, e);
+                            return;
+                        }
+                        
                         if let Ok(mut registry) = model_registry.lock() {
                             if let Err(e) = registry.handle_announcement(&signed_announcement.payload) {
                                 warn!("Failed to process model announcement: {}", e);
@@ -684,7 +772,7 @@ async fn handle_swarm_event(
                             let models = discover_executor_models(&peer_id);
                             executor_models.insert(peer_id, models);
                             
-                            trace_executor_models(&executor_models);
+                            trace_executor_models(executor_models);
                         }
                     }
                 }
@@ -732,7 +820,7 @@ async fn perform_periodic_tasks(
     info!("Periodic maintenance completed. Known executors: {}", known_executors.len());
     
     // Log executor model information at trace level during periodic maintenance
-    trace_executor_models(&executor_models);
+    trace_executor_models(executor_models);
 }
 
 /// Discover models supported by an executor
@@ -803,11 +891,12 @@ mod tests {
         use clap::Parser;
         
         // Test with minimal args
-        let args = Args::try_parse_from(&["validator"]).unwrap();
+        let args = Args::try_parse_from(["validator"]).unwrap();
         assert_eq!(args.p2p_port, 9000);
         assert_eq!(args.config, None);
         assert_eq!(args.private_key_file, None);
         assert_eq!(args.external_addr, None);
+        assert_eq!(args.min_signature_version, LEGACY_SIGNATURE_VERSION);
         assert!(!args.debug);
     }
 
@@ -815,14 +904,16 @@ mod tests {
     fn test_args_with_options() {
         use clap::Parser;
         
-        let args = Args::try_parse_from(&[
+        let args = Args::try_parse_from([
             "validator",
             "--p2p-port", "8000",
             "--debug",
-            "--external-addr", "/ip4/192.168.1.1/tcp/8000"
+            "--external-addr", "/ip4/192.168.1.1/tcp/8000",
+            "--min-signature-version", "2"
         ]).unwrap();
         
         assert_eq!(args.p2p_port, 8000);
+        assert_eq!(args.min_signature_version, SIGNATURE_VERSION);
         assert!(args.debug);
         assert_eq!(args.external_addr, Some("/ip4/192.168.1.1/tcp/8000".to_string()));
     }
@@ -858,6 +949,22 @@ mod tests {
         assert_eq!(identity.evm_address, identity2.evm_address);
     }
 
+    #[test]
+    fn test_load_or_generate_keystore() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("validator-keystore.json");
+        std::env::set_var(lloom_core::identity::KEYSTORE_PASSWORD_ENV, "validator password");
+        
+        let identity = load_or_generate_keystore(&path).unwrap();
+        assert!(path.exists());
+        let identity2 = load_or_generate_keystore(&path).unwrap();
+        assert_eq!(identity.peer_id, identity2.peer_id);
+        
+        // The config file can point at the keystore instead of holding the key
+        let config: ValidatorConfig = toml::from_str(&format!("[identity]\nkeystore = {:?}\n", path)).unwrap();
+        assert_eq!(config.identity.load().unwrap().evm_address, identity.evm_address);
+    }
+
     #[tokio::test]
     async fn test_load_or_generate_identity_existing_file() -> Result<(), Box<dyn std::error::Error>> {
         let mut temp_file = NamedTempFile::new()?;
@@ -933,8 +1040,13 @@ mod tests {
         let args = Args {
             config: None,
             private_key_file: None,
+            keystore: None,
+            signature_version: SIGNATURE_VERSION,
+            min_signature_version: LEGACY_SIGNATURE_VERSION,
             p2p_port: 9000,
+            transport: Transport::Tcp,
             external_addr: None,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
             debug: false,
         };
         
@@ -1000,6 +1112,7 @@ mod tests {
                     },
                     is_available: true,
                     pricing: None,
+                    org: None,
                 }
             ],
             announcement_type: AnnouncementType::Initial,
@@ -1015,6 +1128,50 @@ mod tests {
         assert!(registry.executor_records.contains_key(&peer_id));
         assert_eq!(registry.model_to_executors.len(), 1);
         assert!(registry.model_to_executors.contains_key("gpt-4"));
+
+        // Org-private models are not listed
+        let mut private_model = announcement.models[0].clone();
+        private_model.model_id = "internal-llm".to_string();
+        private_model.org = Some(announcement.executor_address);
+        let update = ModelAnnouncement {
+            models: vec![announcement.models[0].clone(), private_model],
+            announcement_type: AnnouncementType::Update,
+            nonce: 2,
+            ..announcement
+        };
+        registry.handle_announcement(&update).unwrap();
+        assert_eq!(registry.model_to_executors.len(), 1);
+        assert!(!registry.executor_records[&peer_id].models.contains_key("internal-llm"));
+    }
+
+    #[test]
+    fn test_minimum_protocol_version() {
+        let mut registry = ModelRegistry::new(RegistryConfig {
+            min_protocol_version: 2,
+            ..Default::default()
+        });
+        let peer_id = PeerId::random();
+        let announcement = ModelAnnouncement {
+            executor_peer_id: peer_id.to_string(),
+            executor_address: Address::ZERO,
+            models: vec![],
+            announcement_type: AnnouncementType::Initial,
+            timestamp: ModelRegistry::current_timestamp(),
+            nonce: 1,
+            protocol_version: 2,
+        };
+        registry.handle_announcement(&announcement).unwrap();
+        assert!(registry.executor_records.contains_key(&peer_id));
+
+        // An executor downgrading below the minimum is dropped
+        let downgraded = ModelAnnouncement {
+            announcement_type: AnnouncementType::Update,
+            protocol_version: 1,
+            nonce: 2,
+            ..announcement
+        };
+        assert!(registry.handle_announcement(&downgraded).is_err());
+        assert!(registry.executor_records.is_empty());
     }
 
     #[test]
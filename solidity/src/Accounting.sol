@@ -20,11 +20,11 @@ contract AccountingV2 {
     );
 
     bytes32 public constant LLMREQUEST_TYPEHASH = keccak256(
-        "LlmRequestCommitment(address executor,string model,bytes32 promptHash,bytes32 systemPromptHash,uint32 maxTokens,uint32 temperature,uint256 inboundPrice,uint256 outboundPrice,uint64 nonce,uint64 deadline)"
+        "LlmRequestCommitment(string requestId,address executor,string model,bytes32 promptHash,bytes32 systemPromptHash,uint32 maxTokens,uint32 temperature,bytes32 samplingHash,uint256 inboundPrice,uint256 outboundPrice,uint64 nonce,uint64 deadline)"
     );
 
     bytes32 public constant LLMRESPONSE_TYPEHASH = keccak256(
-        "LlmResponseCommitment(bytes32 requestHash,address client,string model,bytes32 contentHash,uint32 inboundTokens,uint32 outboundTokens,uint256 inboundPrice,uint256 outboundPrice,uint64 timestamp,bool success)"
+        "LlmResponseCommitment(string requestId,bytes32 requestHash,address client,string model,bytes32 contentHash,uint32 inboundTokens,uint32 outboundTokens,uint256 inboundPrice,uint256 outboundPrice,uint64 timestamp,bool success)"
     );
 
     bytes32 public immutable DOMAIN_SEPARATOR;
@@ -37,12 +37,14 @@ contract AccountingV2 {
     // =============================================================================
 
     struct LlmRequestCommitment {
+        string requestId;         // Client-generated request id
         address executor;          // Chosen executor address
         string model;             // Model identifier  
         bytes32 promptHash;       // keccak256 of prompt content
         bytes32 systemPromptHash; // keccak256 of system prompt (or zero hash if none)
         uint32 maxTokens;         // Maximum tokens to generate
         uint32 temperature;       // Temperature * 10000 (e.g., 0.7 → 7000)
+        bytes32 samplingHash;     // keccak256 of other sampling parameters (or zero hash if none)
         uint256 inboundPrice;     // Price per inbound token (wei per token)
         uint256 outboundPrice;    // Price per outbound token (wei per token)
         uint64 nonce;             // Client nonce for replay protection
@@ -50,6 +52,7 @@ contract AccountingV2 {
     }
 
     struct LlmResponseCommitment {
+        string requestId;         // Id of the answered request
         bytes32 requestHash;      // Hash of the original signed request
         address client;           // Client address who made request
         string model;             // Model actually used
@@ -177,18 +180,25 @@ contract AccountingV2 {
     // =============================================================================
 
     function getRequestMessageHash(LlmRequestCommitment memory request) public view returns (bytes32) {
-        bytes32 structHash = keccak256(abi.encode(
-            LLMREQUEST_TYPEHASH,
-            request.executor,
-            keccak256(bytes(request.model)),
-            request.promptHash,
-            request.systemPromptHash,
-            request.maxTokens,
-            request.temperature,
-            request.inboundPrice,
-            request.outboundPrice,
-            request.nonce,
-            request.deadline
+        // Encoded in two halves to stay within the stack limit
+        bytes32 structHash = keccak256(bytes.concat(
+            abi.encode(
+                LLMREQUEST_TYPEHASH,
+                keccak256(bytes(request.requestId)),
+                request.executor,
+                keccak256(bytes(request.model)),
+                request.promptHash,
+                request.systemPromptHash
+            ),
+            abi.encode(
+                request.maxTokens,
+                request.temperature,
+                request.samplingHash,
+                request.inboundPrice,
+                request.outboundPrice,
+                request.nonce,
+                request.deadline
+            )
         ));
 
         return keccak256(abi.encodePacked(
@@ -199,18 +209,24 @@ contract AccountingV2 {
     }
 
     function getResponseMessageHash(LlmResponseCommitment memory response) public view returns (bytes32) {
-        bytes32 structHash = keccak256(abi.encode(
-            LLMRESPONSE_TYPEHASH,
-            response.requestHash,
-            response.client,
-            keccak256(bytes(response.model)),
-            response.contentHash,
-            response.inboundTokens,
-            response.outboundTokens,
-            response.inboundPrice,
-            response.outboundPrice,
-            response.timestamp,
-            response.success
+        // Encoded in two halves to stay within the stack limit
+        bytes32 structHash = keccak256(bytes.concat(
+            abi.encode(
+                LLMRESPONSE_TYPEHASH,
+                keccak256(bytes(response.requestId)),
+                response.requestHash,
+                response.client,
+                keccak256(bytes(response.model)),
+                response.contentHash
+            ),
+            abi.encode(
+                response.inboundTokens,
+                response.outboundTokens,
+                response.inboundPrice,
+                response.outboundPrice,
+                response.timestamp,
+                response.success
+            )
         ));
 
         return keccak256(abi.encodePacked(
@@ -312,6 +328,10 @@ contract AccountingV2 {
 
         // Verify response matches request
         require(response.client == recoveredClient, "Client address mismatch");
+        require(
+            keccak256(bytes(response.requestId)) == keccak256(bytes(request.requestId)),
+            "Request id mismatch"
+        );
         require(
             keccak256(bytes(response.model)) == keccak256(bytes(request.model)),
             "Model mismatch"
@@ -349,6 +369,10 @@ contract AccountingV2 {
 
         // Verify response matches request
         require(response.client == recoveredClient, "Client address mismatch");
+        require(
+            keccak256(bytes(response.requestId)) == keccak256(bytes(request.requestId)),
+            "Request id mismatch"
+        );
         require(
             keccak256(bytes(response.model)) == keccak256(bytes(request.model)),
             "Model mismatch"
@@ -35,12 +35,14 @@ contract AccountingV2Test is Test {
     
     function createValidRequest() internal view returns (AccountingV2.LlmRequestCommitment memory) {
         return AccountingV2.LlmRequestCommitment({
+            requestId: "req_1",
             executor: executor,
             model: "gpt-4",
             promptHash: keccak256("test prompt"),
             systemPromptHash: keccak256("test system prompt"),
             maxTokens: 1000,
             temperature: 7000, // 0.7 * 10000
+            samplingHash: bytes32(0),
             inboundPrice: 1000000000000000, // 0.001 ETH per token
             outboundPrice: 2000000000000000, // 0.002 ETH per token
             nonce: 1,
@@ -50,6 +52,7 @@ contract AccountingV2Test is Test {
     
     function createValidResponse(bytes32 requestHash) internal view returns (AccountingV2.LlmResponseCommitment memory) {
         return AccountingV2.LlmResponseCommitment({
+            requestId: "req_1",
             requestHash: requestHash,
             client: client,
             model: "gpt-4",
@@ -130,10 +133,10 @@ contract AccountingV2Test is Test {
         
         // Verify expected values match specification
         bytes32 expectedRequestHash = keccak256(
-            "LlmRequestCommitment(address executor,string model,bytes32 promptHash,bytes32 systemPromptHash,uint32 maxTokens,uint32 temperature,uint256 inboundPrice,uint256 outboundPrice,uint64 nonce,uint64 deadline)"
+            "LlmRequestCommitment(string requestId,address executor,string model,bytes32 promptHash,bytes32 systemPromptHash,uint32 maxTokens,uint32 temperature,bytes32 samplingHash,uint256 inboundPrice,uint256 outboundPrice,uint64 nonce,uint64 deadline)"
         );
         bytes32 expectedResponseHash = keccak256(
-            "LlmResponseCommitment(bytes32 requestHash,address client,string model,bytes32 contentHash,uint32 inboundTokens,uint32 outboundTokens,uint256 inboundPrice,uint256 outboundPrice,uint64 timestamp,bool success)"
+            "LlmResponseCommitment(string requestId,bytes32 requestHash,address client,string model,bytes32 contentHash,uint32 inboundTokens,uint32 outboundTokens,uint256 inboundPrice,uint256 outboundPrice,uint64 timestamp,bool success)"
         );
         bytes32 expectedDomainHash = keccak256(
             "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)"
@@ -152,12 +155,14 @@ contract AccountingV2Test is Test {
     
     function testGetRequestMessageHash() public view {
         AccountingV2.LlmRequestCommitment memory request = AccountingV2.LlmRequestCommitment({
+            requestId: "req_1",
             executor: executor,
             model: "gpt-4",
             promptHash: keccak256("test prompt"),
             systemPromptHash: keccak256("test system prompt"),
             maxTokens: 1000,
             temperature: 7000, // 0.7 * 10000
+            samplingHash: bytes32(0),
             inboundPrice: 1000000000000000, // 0.001 ETH per token
             outboundPrice: 2000000000000000, // 0.002 ETH per token
             nonce: 1,
@@ -170,6 +175,7 @@ contract AccountingV2Test is Test {
     
     function testGetResponseMessageHash() public view {
         AccountingV2.LlmResponseCommitment memory response = AccountingV2.LlmResponseCommitment({
+            requestId: "req_1",
             requestHash: keccak256("test request"),
             client: client,
             model: "gpt-4",
@@ -242,7 +248,27 @@ contract AccountingV2Test is Test {
             "test response"
         );
     }
-    
+
+    function testProcessRequestRequestIdMismatch() public {
+        AccountingV2.LlmRequestCommitment memory request = createValidRequest();
+        bytes32 requestHash = accounting.getRequestMessageHash(request);
+        AccountingV2.LlmResponseCommitment memory response = createValidResponse(requestHash);
+        response.requestId = "req_2";
+
+        bytes memory clientSignature = signRequest(request, clientPrivateKey);
+
+        vm.prank(executor);
+        vm.expectRevert("Request id mismatch");
+        accounting.processRequest(
+            request,
+            response,
+            clientSignature,
+            "test prompt",
+            "test system prompt",
+            "test response"
+        );
+    }
+
     function testProcessRequestCorrectNonce() public {
         AccountingV2.LlmRequestCommitment memory request = createValidRequest();
         request.nonce = 1; // Should be current nonce + 1